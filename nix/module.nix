@@ -48,8 +48,11 @@ in {
       wantedBy = ["multi-user.target"];
       conflicts = map (service: "${service}.service") conflictingServices;
       serviceConfig = {
+        Type = "notify";
+        WatchdogSec = "30s";
         WorkingDirectory = "";
         ExecStart = getExe cfg.package;
+        ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
         Restart = "on-failure";
 
         RuntimeDirectory = "watt";
@@ -51,6 +51,8 @@ rustPlatform.buildRustPackage (finalAttrs: {
       # Install Watt's dbus
       install -Dm644 dbus/net.hadess.PowerProfiles.conf \
         $out/share/dbus-1/system.d/net.hadess.PowerProfiles.conf
+      install -Dm644 dbus/dev.notashelf.Watt.policy \
+        $out/share/polkit-1/actions/dev.notashelf.Watt.policy
     '';
 
   meta = {
@@ -23,13 +23,25 @@ impl Delta {
     }
   }
 
-  pub fn apply(&self) -> anyhow::Result<()> {
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      timeout_seconds:  self
+        .timeout_seconds
+        .filter(|value| that.timeout_seconds.as_ref() != Some(value)),
+      reset_controller: self
+        .reset_controller
+        .filter(|value| that.reset_controller.as_ref() != Some(value)),
+    }
+  }
+
+  pub fn apply(&self, context: &fs::ApplyContext) -> anyhow::Result<()> {
     for module in AUDIO_MODULES {
       if let Some(timeout) = self.timeout_seconds {
-        write_if_exists(module, "power_save", &timeout.to_string())?;
+        write_if_exists(context, module, "power_save", &timeout.to_string())?;
       }
       if let Some(reset) = self.reset_controller {
         write_if_exists(
+          context,
           module,
           "power_save_controller",
           if reset { "Y" } else { "N" },
@@ -42,13 +54,14 @@ impl Delta {
 }
 
 fn write_if_exists(
+  context: &fs::ApplyContext,
   module: &str,
   parameter: &str,
   value: &str,
 ) -> anyhow::Result<()> {
   let path = format!("/sys/module/{module}/parameters/{parameter}");
   if fs::exists(&path) {
-    fs::write(&path, value).with_context(|| {
+    context.write(&path, value).with_context(|| {
       format!("failed to set audio module parameter '{path}'")
     })?;
   }
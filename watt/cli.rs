@@ -0,0 +1,1324 @@
+//! Subcommands for inspecting and changing the system and configs without
+//! running the daemon.
+
+use std::{
+  collections::HashMap,
+  fmt::Write as _,
+  io::{
+    self,
+    IsTerminal as _,
+    Write as _,
+  },
+  path::{
+    Path,
+    PathBuf,
+  },
+  thread,
+  time::Duration,
+};
+
+use anyhow::{
+  Context,
+  bail,
+};
+use serde::Serialize;
+
+use crate::{
+  config,
+  cpu,
+  fs,
+  percent::Percent,
+  power_supply,
+  status,
+  system,
+};
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+  /// Inspect and change CPUs.
+  Cpu {
+    #[command(subcommand)]
+    command: CpuCommand,
+  },
+
+  /// Inspect power supplies and the platform profile.
+  Power {
+    #[command(subcommand)]
+    command: PowerCommand,
+  },
+
+  /// Work with config files.
+  Config {
+    #[command(subcommand)]
+    command: ConfigCommand,
+  },
+
+  /// Watch the values rules are evaluated against, as the daemon sees them.
+  Monitor {
+    /// How long to wait between two samples.
+    #[arg(long, default_value = "2s", value_parser = humantime::parse_duration)]
+    interval: Duration,
+
+    /// Print a single sample and exit.
+    #[arg(long)]
+    once: bool,
+
+    /// Print every sample as a line of JSON.
+    #[arg(long)]
+    json: bool,
+  },
+
+  /// Ask the running daemon what it is doing, over its status socket.
+  Status {
+    /// Print the status as JSON.
+    #[arg(long)]
+    json: bool,
+  },
+
+  /// Measure how long each phase of the scan the daemon does every poll
+  /// takes.
+  Bench {
+    /// How many times to scan.
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+
+    /// Print the timings as JSON.
+    #[arg(long)]
+    json: bool,
+  },
+
+  /// Print shell completions for watt to stdout.
+  Completions {
+    /// The shell to complete in.
+    shell: Shell,
+  },
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+  Bash,
+  Elvish,
+  Fish,
+  PowerShell,
+  Zsh,
+  Nushell,
+}
+
+impl Shell {
+  pub fn generator(&self) -> &'static dyn clap_complete::Generator {
+    match self {
+      Self::Bash => &clap_complete::Shell::Bash,
+      Self::Elvish => &clap_complete::Shell::Elvish,
+      Self::Fish => &clap_complete::Shell::Fish,
+      Self::PowerShell => &clap_complete::Shell::PowerShell,
+      Self::Zsh => &clap_complete::Shell::Zsh,
+      Self::Nushell => &clap_complete_nushell::Nushell,
+    }
+  }
+
+  /// Writes the completions of the `watt` command for this shell.
+  pub fn generate(&self, out: &mut dyn io::Write) {
+    let mut command = <crate::Cli as clap::CommandFactory>::command();
+    command.set_bin_name("watt");
+    command.build();
+
+    self.generator().generate(&command, out);
+  }
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ConfigCommand {
+  /// Compare two configs setting by setting, ignoring formatting.
+  Diff {
+    /// The config to compare against.
+    old: PathBuf,
+    /// The changed config.
+    new: PathBuf,
+  },
+
+  /// Check that a config loads and that the condition of every rule
+  /// evaluates, without reading or changing anything on the system.
+  Validate {
+    /// The config file or directory to check.
+    path: PathBuf,
+  },
+
+  /// Print a JSON Schema of the config format, for editors to complete and
+  /// validate configs with.
+  Schema,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum PowerCommand {
+  /// Print the state of every power supply and the platform profile.
+  Get {
+    /// Print the state as JSON instead of a table.
+    #[arg(long)]
+    json: bool,
+  },
+
+  /// Change power supply settings once.
+  Set {
+    /// The power supplies to change, such as `BAT0`. Every battery if
+    /// omitted.
+    #[arg(long = "for", value_delimiter = ',')]
+    for_: Option<Vec<String>>,
+
+    /// Set the percentage that the charge has to drop under for charging to
+    /// start.
+    #[arg(long)]
+    charge_threshold_start: Option<u8>,
+
+    /// Set the percentage where charging stops.
+    #[arg(long)]
+    charge_threshold_end: Option<u8>,
+
+    /// Set the charge behaviour, such as `auto`, `inhibit-charge` or
+    /// `force-discharge`.
+    #[arg(long)]
+    charge_behaviour: Option<String>,
+
+    /// Refuse `force-discharge` while the charge is below this percentage.
+    #[arg(long, default_value_t = power_supply::DEFAULT_FORCE_DISCHARGE_FLOOR)]
+    force_discharge_floor: u8,
+  },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum CpuCommand {
+  /// Print the current state of every CPU.
+  Get {
+    /// Print the state as JSON instead of a table.
+    #[arg(long)]
+    json: bool,
+  },
+
+  /// Change the settings of CPUs.
+  Set {
+    /// The CPUs to change, all of them if omitted.
+    #[arg(long = "for", value_delimiter = ',')]
+    for_: Option<Vec<u32>>,
+
+    /// Set the CPU frequency scaling governor.
+    #[arg(long)]
+    governor: Option<String>,
+
+    /// Set the CPU Energy Performance Preference (EPP).
+    #[arg(long)]
+    epp: Option<String>,
+
+    /// Set the CPU Energy Performance Bias (EPB).
+    #[arg(long)]
+    epb: Option<String>,
+
+    /// Set the minimum CPU frequency in MHz.
+    #[arg(long, conflicts_with = "frequency_khz_minimum")]
+    frequency_mhz_minimum: Option<u64>,
+
+    /// Set the maximum CPU frequency in MHz.
+    #[arg(long, conflicts_with = "frequency_khz_maximum")]
+    frequency_mhz_maximum: Option<u64>,
+
+    /// Set the minimum CPU frequency in kHz.
+    #[arg(long)]
+    frequency_khz_minimum: Option<u64>,
+
+    /// Set the maximum CPU frequency in kHz.
+    #[arg(long)]
+    frequency_khz_maximum: Option<u64>,
+
+    /// Pin the CPU frequency in MHz, switching to the userspace governor.
+    #[arg(long, conflicts_with = "governor")]
+    frequency_mhz: Option<u64>,
+
+    /// Restore the driver defaults: a dynamic governor, the full hardware
+    /// frequency range, turbo and the default EPP. Turbo is system wide, so
+    /// it is only restored without `--for`.
+    #[arg(long, conflicts_with_all = [
+      "governor",
+      "epp",
+      "epb",
+      "frequency_mhz_minimum",
+      "frequency_mhz_maximum",
+      "frequency_khz_minimum",
+      "frequency_khz_maximum",
+      "frequency_mhz",
+    ])]
+    reset: bool,
+  },
+}
+
+/// The state of a single CPU, as printed by `watt cpu get`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CpuInfo {
+  pub number:                        u32,
+  pub governor:                      Option<String>,
+  pub energy_performance_preference: Option<String>,
+  pub energy_perf_bias:              Option<String>,
+  pub frequency_mhz:                 Option<u64>,
+  pub frequency_mhz_minimum:         Option<u64>,
+  pub frequency_mhz_maximum:         Option<u64>,
+  pub temperature:                   Option<f64>,
+}
+
+impl CpuInfo {
+  pub fn new(cpu: &cpu::Cpu, temperatures: &HashMap<u32, f64>) -> Self {
+    Self {
+      number:                        cpu.number,
+      governor:                      cpu.governor.clone(),
+      energy_performance_preference: cpu.epp.clone(),
+      energy_perf_bias:              cpu.epb.clone(),
+      frequency_mhz:                 cpu.frequency_mhz,
+      frequency_mhz_minimum:         cpu.frequency_mhz_minimum,
+      frequency_mhz_maximum:         cpu.frequency_mhz_maximum,
+      temperature:                   temperatures.get(&cpu.number).copied(),
+    }
+  }
+}
+
+/// The state of a single power supply, as printed by `watt power get`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct PowerSupplyInfo {
+  pub name:                   String,
+  #[serde(rename = "type")]
+  pub type_:                  String,
+  pub charge_percent:         Option<f64>,
+  pub charge_state:           Option<String>,
+  /// The manufacturer whose charge threshold controls were detected.
+  pub threshold_manufacturer: Option<&'static str>,
+  pub charge_threshold_start: Option<u8>,
+  pub charge_threshold_end:   Option<u8>,
+  pub drain_rate_watts:       Option<f64>,
+  pub is_from_peripheral:     bool,
+}
+
+impl PowerSupplyInfo {
+  pub fn new(power_supply: &power_supply::PowerSupply) -> Self {
+    let thresholds = power_supply.threshold_config.map(|_| {
+      (
+        power_supply.charge_threshold_start,
+        power_supply.charge_threshold_end,
+      )
+    });
+
+    Self {
+      name:                   power_supply.name.clone(),
+      type_:                  power_supply.type_.clone(),
+      charge_percent:         power_supply
+        .charge_percent
+        .map(Percent::as_percent),
+      charge_state:           power_supply.charge_state.clone(),
+      threshold_manufacturer: power_supply
+        .threshold_config
+        .map(|config| config.manufacturer),
+      charge_threshold_start: thresholds
+        .and_then(|(start, _)| start.as_whole_percent()),
+      charge_threshold_end:   thresholds
+        .and_then(|(_, end)| end.as_whole_percent()),
+      drain_rate_watts:       power_supply.drain_rate_watts,
+      is_from_peripheral:     power_supply.is_from_peripheral,
+    }
+  }
+}
+
+/// Everything printed by `watt power get`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct PowerInfo {
+  pub power_supplies:              Vec<PowerSupplyInfo>,
+  pub platform_profile:            Option<String>,
+  pub available_platform_profiles: Vec<String>,
+}
+
+fn cell(value: Option<impl ToString>) -> String {
+  value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+}
+
+fn render_table<const N: usize>(
+  header: [&str; N],
+  rows: &[[String; N]],
+) -> String {
+  let mut widths = header.map(|header| header.chars().count());
+  for row in rows {
+    for (width, cell) in widths.iter_mut().zip(row) {
+      *width = (*width).max(cell.chars().count());
+    }
+  }
+
+  let mut table = String::new();
+
+  for row in
+    std::iter::once(header.map(str::to_owned)).chain(rows.iter().cloned())
+  {
+    let line = row
+      .iter()
+      .zip(widths)
+      .map(|(cell, width)| format!("{cell:<width$}"))
+      .collect::<Vec<_>>()
+      .join("  ");
+
+    let _ = writeln!(table, "{line}", line = line.trim_end());
+  }
+
+  table
+}
+
+fn render_cpu_table(cpus: &[CpuInfo]) -> String {
+  let rows: Vec<[String; 8]> = cpus
+    .iter()
+    .map(|cpu| {
+      [
+        cpu.number.to_string(),
+        cell(cpu.governor.as_ref()),
+        cell(cpu.energy_performance_preference.as_ref()),
+        cell(cpu.energy_perf_bias.as_ref()),
+        cell(cpu.frequency_mhz),
+        cell(cpu.frequency_mhz_minimum),
+        cell(cpu.frequency_mhz_maximum),
+        cell(
+          cpu
+            .temperature
+            .map(|temperature| format!("{temperature:.1}")),
+        ),
+      ]
+    })
+    .collect();
+
+  render_table(
+    [
+      "CPU", "Governor", "EPP", "EPB", "MHz", "Min MHz", "Max MHz", "Temp °C",
+    ],
+    &rows,
+  )
+}
+
+fn cpu_get(json: bool) -> anyhow::Result<()> {
+  // Temperatures are nice to have, don't refuse to print everything else.
+  let temperatures = system::cpu_temperatures().unwrap_or_else(|error| {
+    log::warn!("failed to scan CPU temperatures: {error:#}");
+    HashMap::new()
+  });
+
+  let mut cpus = cpu::Cpu::all().context("failed to scan CPUs")?;
+  cpus.sort_unstable_by_key(|cpu| cpu.number);
+
+  let cpus: Vec<CpuInfo> = cpus
+    .iter()
+    .map(|cpu| CpuInfo::new(cpu, &temperatures))
+    .collect();
+
+  if json {
+    let json = serde_json::to_string_pretty(&cpus)
+      .context("failed to serialize CPU state")?;
+    println!("{json}");
+  } else {
+    print!("{table}", table = render_cpu_table(&cpus));
+  }
+
+  Ok(())
+}
+
+fn render_power(power: &PowerInfo) -> String {
+  let rows: Vec<[String; 8]> = power
+    .power_supplies
+    .iter()
+    .map(|power_supply| {
+      [
+        power_supply.name.clone(),
+        power_supply.type_.clone(),
+        cell(
+          power_supply
+            .charge_percent
+            .map(|charge| format!("{charge:.0}%")),
+        ),
+        cell(power_supply.charge_state.as_ref()),
+        cell(
+          power_supply
+            .charge_threshold_start
+            .zip(power_supply.charge_threshold_end)
+            .map(|(start, end)| format!("{start}-{end}%")),
+        ),
+        cell(power_supply.threshold_manufacturer),
+        cell(
+          power_supply
+            .drain_rate_watts
+            .map(|watts| format!("{watts:.1}")),
+        ),
+        if power_supply.is_from_peripheral {
+          "yes".to_owned()
+        } else {
+          "no".to_owned()
+        },
+      ]
+    })
+    .collect();
+
+  let mut output = render_table(
+    [
+      "Name",
+      "Type",
+      "Charge",
+      "State",
+      "Thresholds",
+      "Manufacturer",
+      "Drain W",
+      "Peripheral",
+    ],
+    &rows,
+  );
+
+  let _ = writeln!(
+    output,
+    "\nPlatform profile: {profile}",
+    profile = power.platform_profile.as_deref().unwrap_or("-"),
+  );
+
+  if !power.available_platform_profiles.is_empty() {
+    let _ = writeln!(
+      output,
+      "Available platform profiles: {profiles}",
+      profiles = power.available_platform_profiles.join(", "),
+    );
+  }
+
+  output
+}
+
+fn power_get(json: bool) -> anyhow::Result<()> {
+  let mut power_supplies = power_supply::PowerSupply::all()
+    .context("failed to scan power supplies")?;
+  power_supplies.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+  let available_platform_profiles =
+    power_supply::PowerSupply::get_available_platform_profiles()?;
+
+  let platform_profile = power_supply::PowerSupply::platform_profile()?;
+
+  let power = PowerInfo {
+    power_supplies: power_supplies.iter().map(PowerSupplyInfo::new).collect(),
+    platform_profile,
+    available_platform_profiles,
+  };
+
+  if json {
+    let json = serde_json::to_string_pretty(&power)
+      .context("failed to serialize power state")?;
+    println!("{json}");
+  } else {
+    print!("{output}", output = render_power(&power));
+  }
+
+  Ok(())
+}
+
+fn power_set(
+  context: &fs::ApplyContext,
+  for_: Option<&[String]>,
+  delta: &power_supply::Delta,
+  force_discharge_floor: u8,
+) -> anyhow::Result<()> {
+  if *delta == power_supply::Delta::default() {
+    bail!("nothing to set, pass at least one setting");
+  }
+
+  let mut power_supplies = power_supply::PowerSupply::all()
+    .context("failed to scan power supplies")?;
+
+  match for_ {
+    Some(names) => {
+      for name in names {
+        if !power_supplies
+          .iter()
+          .any(|power_supply| power_supply.name == *name)
+        {
+          bail!("power supply '{name}' does not exist");
+        }
+      }
+
+      power_supplies.retain(|power_supply| names.contains(&power_supply.name));
+    },
+
+    None => {
+      power_supplies.retain(|power_supply| {
+        power_supply.type_ == "Battery" && !power_supply.is_from_peripheral
+      });
+    },
+  }
+
+  // Refuse before changing anything, instead of skipping like the daemon.
+  if let Some(charge_behaviour) = &delta.charge_behaviour {
+    for power_supply in &power_supplies {
+      power_supply
+        .check_charge_behaviour(charge_behaviour, force_discharge_floor)?;
+    }
+  }
+
+  for power_supply in &mut power_supplies {
+    delta.apply(context, power_supply, force_discharge_floor)?;
+  }
+
+  Ok(())
+}
+
+/// Scans the CPUs, keeping only the ones in `for_` if given.
+fn selected_cpus(for_: Option<&[u32]>) -> anyhow::Result<Vec<cpu::Cpu>> {
+  let mut cpus = cpu::Cpu::all().context("failed to scan CPUs")?;
+
+  if let Some(numbers) = for_ {
+    for number in numbers {
+      if !cpus.iter().any(|cpu| cpu.number == *number) {
+        bail!("CPU {number} does not exist");
+      }
+    }
+
+    cpus.retain(|cpu| numbers.contains(&cpu.number));
+  }
+
+  Ok(cpus)
+}
+
+fn cpu_set(
+  context: &fs::ApplyContext,
+  for_: Option<&[u32]>,
+  delta: &cpu::Delta,
+) -> anyhow::Result<()> {
+  if *delta == cpu::Delta::default() {
+    bail!("nothing to set, pass at least one setting");
+  }
+
+  for cpu in &mut selected_cpus(for_)? {
+    delta.apply(context, cpu, false)?;
+  }
+
+  Ok(())
+}
+
+/// Restores the driver defaults of the selected CPUs. Every step is best
+/// effort, a control that can't be reset is warned about and skipped.
+fn cpu_reset(
+  context: &fs::ApplyContext,
+  for_: Option<&[u32]>,
+) -> anyhow::Result<()> {
+  let mut cpus = selected_cpus(for_)?;
+
+  for cpu in &mut cpus {
+    let defaults = cpu.defaults()?;
+
+    if cpu.has_cpufreq && defaults.governor.is_none() {
+      log::warn!(
+        "none of the default governors are available for {cpu}, keeping its \
+         governor"
+      );
+    }
+
+    defaults.apply(context, cpu, true)?;
+  }
+
+  if for_.is_none()
+    && let Err(error) = cpu::Cpu::set_turbo(context, true, cpus.iter())
+  {
+    log::warn!("failed to re-enable turbo: {error:#}, skipping");
+  }
+
+  Ok(())
+}
+
+fn percent(fraction: Option<f64>) -> String {
+  cell(fraction.map(|fraction| format!("{:.0}%", fraction * 100.0)))
+}
+
+fn render_monitor_line(sample: &system::MonitorSample) -> String {
+  let [load_1min, load_5min, load_15min] = sample.load_average;
+
+  let mut line = format!(
+    "CPU {usage} (average {average}) {temperature} °C  load {load_1min:.2} \
+     {load_5min:.2} {load_15min:.2}  memory {memory}",
+    usage = percent(Some(sample.cpu_usage)),
+    average = percent(sample.cpu_usage_average),
+    temperature = cell(
+      sample
+        .cpu_temperature
+        .map(|temperature| format!("{temperature:.1}")),
+    ),
+    memory = percent(sample.memory_usage),
+  );
+
+  for battery in &sample.batteries {
+    let _ = write!(
+      line,
+      "  {name} {charge} {state}",
+      name = battery.name,
+      charge =
+        cell(battery.charge_percent.map(|charge| format!("{charge:.0}%")),),
+      state = cell(battery.charge_state.as_ref()),
+    );
+  }
+
+  if sample.discharging {
+    let _ = write!(
+      line,
+      "  draining {rate}/h {watts} W",
+      rate = percent(sample.power_supply_discharge_rate),
+      watts = cell(sample.power_draw_watts.map(|watts| format!("{watts:.1}")),),
+    );
+  }
+
+  line
+}
+
+fn monitor(
+  config_path: Option<&Path>,
+  interval: Duration,
+  once: bool,
+  json: bool,
+) -> anyhow::Result<()> {
+  let config = config::DaemonConfig::load_from(config_path)
+    .context("failed to load daemon config")?;
+  let mut monitor = system::Monitor::new(&config);
+
+  // CPU usage is measured between two scans, the first one is a baseline.
+  monitor.scan().context("failed to scan system")?;
+
+  let refresh = !json && !once && io::stdout().is_terminal();
+
+  loop {
+    thread::sleep(interval);
+
+    monitor.scan().context("failed to scan system")?;
+    let sample = monitor.sample()?;
+
+    if json {
+      let json = serde_json::to_string(&sample)
+        .context("failed to serialize monitor sample")?;
+      println!("{json}");
+    } else if refresh {
+      print!("\r\x1b[2K{line}", line = render_monitor_line(&sample));
+      io::stdout().flush().context("failed to flush stdout")?;
+    } else {
+      println!("{line}", line = render_monitor_line(&sample));
+    }
+
+    if once {
+      return Ok(());
+    }
+  }
+}
+
+fn render_status(status: &status::Status) -> String {
+  let mut output = format!(
+    "profile    {profile}\nrules      {applied} of {rule_count} \
+     applied{names}\nnext poll  {poll_interval}\n",
+    profile = status.profile,
+    applied = status.last_applied_rules.len(),
+    rule_count = status.rule_count,
+    names = if status.last_applied_rules.is_empty() {
+      String::new()
+    } else {
+      format!(": {}", status.last_applied_rules.join(", "))
+    },
+    poll_interval =
+      cell(status.poll_interval.map(|seconds| format!("{seconds:.1}s")),),
+  );
+
+  if let Some(sample) = &status.sample {
+    let _ = writeln!(output, "{line}", line = render_monitor_line(sample));
+  }
+
+  output
+}
+
+fn status(json: bool) -> anyhow::Result<()> {
+  let status = status::query(Path::new(status::SOCKET_PATH))?;
+
+  if json {
+    let json = serde_json::to_string_pretty(&status)
+      .context("failed to serialize status")?;
+    println!("{json}");
+  } else {
+    print!("{output}", output = render_status(&status));
+  }
+
+  Ok(())
+}
+
+fn render_bench(phases: &[system::BenchPhase]) -> String {
+  let milliseconds = |milliseconds: f64| format!("{milliseconds:.2}");
+
+  let rows: Vec<[String; 6]> = phases
+    .iter()
+    .map(|phase| {
+      [
+        phase.phase.to_owned(),
+        phase.samples.to_string(),
+        milliseconds(phase.minimum),
+        milliseconds(phase.median),
+        milliseconds(phase.p99),
+        milliseconds(phase.maximum),
+      ]
+    })
+    .collect();
+
+  render_table(
+    ["Phase", "Scans", "Min ms", "Median ms", "P99 ms", "Max ms"],
+    &rows,
+  )
+}
+
+fn bench(iterations: u32, json: bool) -> anyhow::Result<()> {
+  let phases = system::bench(iterations as usize)?;
+
+  if json {
+    let json = serde_json::to_string_pretty(&phases)
+      .context("failed to serialize bench timings")?;
+    println!("{json}");
+  } else {
+    print!("{table}", table = render_bench(&phases));
+  }
+
+  Ok(())
+}
+
+fn render_value(value: Option<&String>) -> &str {
+  value.map_or("(unset)", String::as_str)
+}
+
+fn render_config_diff(
+  settings: &[config::FieldChange],
+  rules: &[config::RuleChange],
+) -> String {
+  let mut diff = String::new();
+
+  for change in settings {
+    let _ = writeln!(
+      diff,
+      "~ {path}: {old} -> {new}",
+      path = change.path,
+      old = render_value(change.old.as_ref()),
+      new = render_value(change.new.as_ref()),
+    );
+  }
+
+  for change in rules {
+    match change {
+      config::RuleChange::Added { name, priority } if name.is_empty() => {
+        let _ = writeln!(diff, "+ rule (priority {priority})");
+      },
+
+      config::RuleChange::Added { name, priority } => {
+        let _ = writeln!(diff, "+ rule '{name}' (priority {priority})");
+      },
+
+      config::RuleChange::Removed { name, priority } if name.is_empty() => {
+        let _ = writeln!(diff, "- rule (priority {priority})");
+      },
+
+      config::RuleChange::Removed { name, priority } => {
+        let _ = writeln!(diff, "- rule '{name}' (priority {priority})");
+      },
+
+      config::RuleChange::Modified { name, changes } => {
+        let _ = writeln!(diff, "~ rule '{name}'");
+
+        for change in changes {
+          let _ = writeln!(
+            diff,
+            "    {path}: {old} -> {new}",
+            path = change.path,
+            old = render_value(change.old.as_ref()),
+            new = render_value(change.new.as_ref()),
+          );
+        }
+      },
+    }
+  }
+
+  diff
+}
+
+fn config_diff(old: &Path, new: &Path) -> anyhow::Result<()> {
+  let old = config::DaemonConfig::load_from(Some(old))?;
+  let new = config::DaemonConfig::load_from(Some(new))?;
+
+  let settings = old.diff_settings(&new)?;
+  let rules = old.diff_rules(&new)?;
+
+  if settings.is_empty() && rules.is_empty() {
+    println!("configs are equivalent");
+    return Ok(());
+  }
+
+  print!("{diff}", diff = render_config_diff(&settings, &rules));
+
+  Ok(())
+}
+
+fn render_config_validation(
+  checks: &[(&config::Rule, anyhow::Result<Option<bool>>)],
+) -> String {
+  let mut report = String::new();
+
+  for (rule, result) in checks {
+    let _ = match result {
+      Ok(Some(_)) => writeln!(report, "ok     rule {rule}"),
+      Ok(None) => {
+        writeln!(
+          report,
+          "ok     rule {rule}, undefined for the sample values"
+        )
+      },
+      Err(error) => writeln!(report, "error  rule {rule}: {error:#}"),
+    };
+  }
+
+  report
+}
+
+fn config_validate(path: &Path) -> anyhow::Result<()> {
+  let config = config::DaemonConfig::load_from(Some(path))?;
+  let checks = config.check_rules();
+
+  print!("{report}", report = render_config_validation(&checks));
+
+  let failed = checks.iter().filter(|(_, result)| result.is_err()).count();
+  if failed > 0 {
+    bail!(
+      "{failed} of {total} rules failed to evaluate",
+      total = checks.len(),
+    );
+  }
+
+  Ok(())
+}
+
+fn config_schema() -> anyhow::Result<()> {
+  let schema = serde_json::to_string_pretty(&config::DaemonConfig::schema())
+    .context("failed to serialize config schema")?;
+  println!("{schema}");
+
+  Ok(())
+}
+
+/// Runs `command`. `config_path` is the daemon config, for the commands that
+/// look at the system the way the daemon does.
+pub fn run(
+  command: Command,
+  context: &fs::ApplyContext,
+  config_path: Option<&Path>,
+) -> anyhow::Result<()> {
+  match command {
+    Command::Cpu {
+      command: CpuCommand::Get { json },
+    } => cpu_get(json),
+
+    Command::Cpu {
+      command:
+        CpuCommand::Set {
+          for_,
+          governor,
+          epp,
+          epb,
+          frequency_mhz_minimum,
+          frequency_mhz_maximum,
+          frequency_khz_minimum,
+          frequency_khz_maximum,
+          frequency_mhz,
+          reset,
+        },
+    } => {
+      if reset {
+        return cpu_reset(context, for_.as_deref());
+      }
+
+      cpu_set(context, for_.as_deref(), &cpu::Delta {
+        governor,
+        energy_performance_preference: epp,
+        energy_perf_bias: epb,
+        frequency_mhz_minimum,
+        frequency_mhz_maximum,
+        frequency_khz_minimum,
+        frequency_khz_maximum,
+        frequency_mhz,
+        ..cpu::Delta::default()
+      })
+    },
+
+    Command::Power {
+      command: PowerCommand::Get { json },
+    } => power_get(json),
+
+    Command::Power {
+      command:
+        PowerCommand::Set {
+          for_,
+          charge_threshold_start,
+          charge_threshold_end,
+          charge_behaviour,
+          force_discharge_floor,
+        },
+    } => {
+      power_set(
+        context,
+        for_.as_deref(),
+        &power_supply::Delta {
+          charge_threshold_start: charge_threshold_start
+            .map(|percent| Percent::from_percent(f64::from(percent))),
+          charge_threshold_end: charge_threshold_end
+            .map(|percent| Percent::from_percent(f64::from(percent))),
+          charge_behaviour,
+        },
+        force_discharge_floor,
+      )
+    },
+
+    Command::Config {
+      command: ConfigCommand::Diff { old, new },
+    } => config_diff(&old, &new),
+
+    Command::Config {
+      command: ConfigCommand::Validate { path },
+    } => config_validate(&path),
+
+    Command::Config {
+      command: ConfigCommand::Schema,
+    } => config_schema(),
+
+    Command::Monitor {
+      interval,
+      once,
+      json,
+    } => monitor(config_path, interval, once, json),
+
+    Command::Status { json } => status(json),
+
+    Command::Bench { iterations, json } => bench(iterations, json),
+
+    Command::Completions { shell } => {
+      shell.generate(&mut io::stdout().lock());
+      Ok(())
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cpu_info(number: u32) -> CpuInfo {
+    CpuInfo {
+      number,
+      governor: Some("powersave".to_owned()),
+      energy_performance_preference: None,
+      energy_perf_bias: None,
+      frequency_mhz: Some(1200),
+      frequency_mhz_minimum: Some(400),
+      frequency_mhz_maximum: Some(4700),
+      temperature: Some(48.25),
+    }
+  }
+
+  #[test]
+  fn completions_are_generated_for_every_shell() {
+    for shell in <Shell as clap::ValueEnum>::value_variants() {
+      let mut out = Vec::new();
+      shell.generate(&mut out);
+
+      let completions = String::from_utf8(out).unwrap();
+      assert!(completions.contains("completions"), "{shell:?}");
+      assert!(completions.contains("log-format"), "{shell:?}");
+    }
+  }
+
+  #[test]
+  fn table_aligns_columns_and_marks_unknown_values() {
+    let table = render_cpu_table(&[cpu_info(0), CpuInfo {
+      temperature: None,
+      ..cpu_info(10)
+    }]);
+
+    assert_eq!(table.lines().collect::<Vec<_>>(), [
+      "CPU  Governor   EPP  EPB  MHz   Min MHz  Max MHz  Temp °C",
+      "0    powersave  -    -    1200  400      4700     48.2",
+      "10   powersave  -    -    1200  400      4700     -",
+    ]);
+  }
+
+  #[test]
+  fn cpu_reset_restores_driver_defaults() {
+    let cpufreq = "sys/devices/system/cpu/cpu0/cpufreq";
+    let system = crate::harness::FakeSystem::new()
+      .cpu(0, "performance")
+      .cpu(1, "performance")
+      .file(
+        &format!("{cpufreq}/scaling_available_governors"),
+        "performance schedutil",
+      )
+      .file(&format!("{cpufreq}/scaling_min_freq"), "1200000")
+      .file(&format!("{cpufreq}/scaling_max_freq"), "2000000")
+      .file(&format!("{cpufreq}/energy_performance_preference"), "power")
+      .file(
+        &format!("{cpufreq}/energy_performance_available_preferences"),
+        "default performance power",
+      )
+      .file("sys/devices/system/cpu/intel_pstate/no_turbo", "1");
+
+    let read = |path: &str| {
+      system
+        .with_root(|| fs::read(format!("/{path}")))
+        .unwrap()
+        .unwrap()
+    };
+
+    // Turbo is system wide, so resetting some of the CPUs leaves it alone.
+    system
+      .with_root(|| cpu_reset(&fs::ApplyContext::default(), Some(&[1])))
+      .unwrap();
+    assert_eq!(read(&format!("{cpufreq}/scaling_governor")), "performance");
+    assert_eq!(read("sys/devices/system/cpu/intel_pstate/no_turbo"), "1");
+    assert_eq!(
+      read("sys/devices/system/cpu/cpu1/cpufreq/scaling_governor"),
+      "powersave",
+    );
+
+    system
+      .with_root(|| cpu_reset(&fs::ApplyContext::default(), None))
+      .unwrap();
+    assert_eq!(read(&format!("{cpufreq}/scaling_governor")), "schedutil");
+    assert_eq!(read(&format!("{cpufreq}/scaling_min_freq")), "400000");
+    assert_eq!(read(&format!("{cpufreq}/scaling_max_freq")), "4000000");
+    assert_eq!(
+      read(&format!("{cpufreq}/energy_performance_preference")),
+      "default",
+    );
+    assert_eq!(read("sys/devices/system/cpu/intel_pstate/no_turbo"), "0");
+  }
+
+  #[test]
+  fn power_set_refuses_force_discharge_below_the_floor() {
+    let charge_behaviour = "sys/class/power_supply/BAT0/charge_behaviour";
+    let system = |capacity_percent| {
+      crate::harness::FakeSystem::new()
+        .battery("BAT0", capacity_percent, "Charging")
+        .file(charge_behaviour, "[auto] inhibit-charge force-discharge")
+    };
+
+    let force_discharge = power_supply::Delta {
+      charge_behaviour: Some("force-discharge".to_owned()),
+      ..power_supply::Delta::default()
+    };
+
+    let low = system(15);
+    let error = low
+      .with_root(|| {
+        power_set(&fs::ApplyContext::default(), None, &force_discharge, 20)
+      })
+      .unwrap_err()
+      .to_string();
+    assert!(
+      error.contains("at 15%, below the floor of 20%"),
+      "unexpected error: {error}",
+    );
+    assert_eq!(
+      low
+        .with_root(|| fs::read(format!("/{charge_behaviour}")))
+        .unwrap()
+        .as_deref(),
+      Some("[auto] inhibit-charge force-discharge"),
+    );
+
+    let charged = system(80);
+    charged
+      .with_root(|| {
+        power_set(
+          &fs::ApplyContext::default(),
+          Some(&["BAT0".to_owned()]),
+          &force_discharge,
+          20,
+        )
+      })
+      .unwrap();
+    assert_eq!(
+      charged
+        .with_root(|| fs::read(format!("/{charge_behaviour}")))
+        .unwrap()
+        .as_deref(),
+      Some("force-discharge"),
+    );
+  }
+
+  #[test]
+  fn monitor_line_shows_drain_only_while_discharging() {
+    let mut sample = system::MonitorSample {
+      cpu_usage:                   0.234,
+      cpu_usage_average:           Some(0.2),
+      cpu_usage_volatility:        None,
+      cpu_temperature:             Some(54.04),
+      cpu_temperature_volatility:  None,
+      load_average:                [0.5, 0.4, 0.3],
+      memory_usage:                None,
+      discharging:                 false,
+      power_supply_charge:         Some(0.8),
+      power_supply_discharge_rate: Some(0.12),
+      power_draw_watts:            Some(7.34),
+      batteries:                   vec![system::BatterySample {
+        name:             "BAT0".to_owned(),
+        charge_percent:   Some(80.0),
+        charge_state:     Some("Charging".to_owned()),
+        drain_rate_watts: None,
+      }],
+    };
+
+    assert_eq!(
+      render_monitor_line(&sample),
+      "CPU 23% (average 20%) 54.0 °C  load 0.50 0.40 0.30  memory -  BAT0 80% \
+       Charging",
+    );
+
+    sample.discharging = true;
+    assert!(render_monitor_line(&sample).ends_with("  draining 12%/h 7.3 W"));
+  }
+
+  #[test]
+  fn status_lists_applied_rules_before_the_first_sample() {
+    let mut status = status::Status {
+      profile:            "balanced".to_owned(),
+      rule_count:         4,
+      last_applied_rules: Vec::new(),
+      poll_interval:      None,
+      sample:             None,
+    };
+
+    assert_eq!(
+      render_status(&status),
+      "profile    balanced\nrules      0 of 4 applied\nnext poll  -\n",
+    );
+
+    status.last_applied_rules = vec!["on battery".to_owned(), "10".to_owned()];
+    status.poll_interval = Some(5.0);
+    assert_eq!(
+      render_status(&status),
+      "profile    balanced\nrules      2 of 4 applied: on battery, 10\nnext \
+       poll  5.0s\n",
+    );
+  }
+
+  #[test]
+  fn json_uses_kebab_case_keys() {
+    let json = serde_json::to_value(cpu_info(3)).unwrap();
+
+    assert_eq!(json["number"], 3);
+    assert_eq!(json["frequency-mhz-maximum"], 4700);
+    assert!(json["energy-performance-preference"].is_null());
+  }
+
+  #[test]
+  fn power_lists_thresholds_and_platform_profile() {
+    let output = render_power(&PowerInfo {
+      power_supplies:              vec![
+        PowerSupplyInfo {
+          name:                   "AC".to_owned(),
+          type_:                  "Mains".to_owned(),
+          charge_percent:         None,
+          charge_state:           None,
+          threshold_manufacturer: None,
+          charge_threshold_start: None,
+          charge_threshold_end:   None,
+          drain_rate_watts:       None,
+          is_from_peripheral:     false,
+        },
+        PowerSupplyInfo {
+          name:                   "BAT0".to_owned(),
+          type_:                  "Battery".to_owned(),
+          charge_percent:         Some(81.0),
+          charge_state:           Some("Discharging".to_owned()),
+          threshold_manufacturer: Some("ThinkPad/Huawei"),
+          charge_threshold_start: Some(40),
+          charge_threshold_end:   Some(80),
+          drain_rate_watts:       Some(7.25),
+          is_from_peripheral:     false,
+        },
+      ],
+      platform_profile:            Some("balanced".to_owned()),
+      available_platform_profiles: vec![
+        "low-power".to_owned(),
+        "balanced".to_owned(),
+      ],
+    });
+
+    assert_eq!(output.lines().collect::<Vec<_>>(), [
+      "Name  Type     Charge  State        Thresholds  Manufacturer     \
+       Drain W  Peripheral",
+      "AC    Mains    -       -            -           -                -        \
+       no",
+      "BAT0  Battery  81%     Discharging  40-80%      ThinkPad/Huawei  7.2      \
+       no",
+      "",
+      "Platform profile: balanced",
+      "Available platform profiles: low-power, balanced",
+    ]);
+  }
+
+  #[test]
+  fn config_validation_reports_each_rule() {
+    let config: config::DaemonConfig = toml::from_str(
+      r#"
+        [[rule]]
+        name = "hot"
+        priority = 30
+        if = { is-more-than = 80.0, value = "$cpu-temperature" }
+
+        [[rule]]
+        name = "typo"
+        priority = 20
+        if = { is-more-than = "?discharging", value = 1.0 }
+
+        [[rule]]
+        priority = 10
+        if = { is = "powersave", value = "$cpu-governor" }
+      "#,
+    )
+    .unwrap();
+
+    let report = render_config_validation(&config.check_rules());
+
+    assert_eq!(report.lines().collect::<Vec<_>>(), [
+      "ok     rule 'hot' (priority 30)",
+      "error  rule 'typo' (priority 20): tried to cast 'Boolean(true)' to a \
+       number, failed",
+      "ok     rule with priority 10, undefined for the sample values",
+    ]);
+  }
+
+  #[test]
+  fn config_diff_marks_each_kind_of_change() {
+    let diff = render_config_diff(
+      &[config::FieldChange {
+        path: "min-write-interval".to_owned(),
+        old:  None,
+        new:  Some(r#""5s""#.to_owned()),
+      }],
+      &[
+        config::RuleChange::Removed {
+          name:     "old".to_owned(),
+          priority: 20,
+        },
+        config::RuleChange::Modified {
+          name:    "battery".to_owned(),
+          changes: vec![config::FieldChange {
+            path: "cpu.governor".to_owned(),
+            old:  Some(r#""powersave""#.to_owned()),
+            new:  Some(r#""performance""#.to_owned()),
+          }],
+        },
+      ],
+    );
+
+    assert_eq!(diff.lines().collect::<Vec<_>>(), [
+      r#"~ min-write-interval: (unset) -> "5s""#,
+      "- rule 'old' (priority 20)",
+      "~ rule 'battery'",
+      r#"    cpu.governor: "powersave" -> "performance""#,
+    ]);
+  }
+}
@@ -1,19 +1,37 @@
 #[cfg(feature = "metrics")] use std::net::IpAddr;
 use std::{
   collections::{
+    BTreeMap,
+    BTreeSet,
     HashMap,
     HashSet,
     VecDeque,
   },
+  fmt,
   fs,
-  path::Path,
-  sync::Arc,
+  iter,
+  ops::RangeInclusive,
+  path::{
+    Path,
+    PathBuf,
+  },
+  sync::{
+    Arc,
+    Mutex,
+    MutexGuard,
+  },
+  time::{
+    Duration,
+    Instant,
+    SystemTime,
+  },
 };
 
 use anyhow::{
   Context,
   bail,
 };
+use schemars::JsonSchema;
 use serde::{
   Deserialize,
   Serialize,
@@ -23,8 +41,11 @@ use crate::{
   audio,
   cpu,
   disk,
+  error::WattError,
   gpu,
+  percent::Percent,
   power_supply,
+  profile::PowerProfile,
   system,
   uncore,
   usb,
@@ -46,6 +67,64 @@ fn is_default<T: Default + PartialEq>(value: &T) -> bool {
   *value == T::default()
 }
 
+/// (De)serializes an optional duration as a human readable string, such as
+/// `"30s"` or `"1min"`.
+mod humantime_option {
+  use std::time::Duration;
+
+  use serde::{
+    Deserializer,
+    Serializer,
+  };
+
+  pub fn serialize<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    match duration {
+      Some(duration) => {
+        super::humantime_duration::serialize(duration, serializer)
+      },
+      None => serializer.serialize_none(),
+    }
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Option<Duration>, D::Error> {
+    super::humantime_duration::deserialize(deserializer).map(Some)
+  }
+}
+
+/// (De)serializes a duration as a human readable string, such as `"5min"`.
+mod humantime_duration {
+  use std::time::Duration;
+
+  use serde::{
+    Deserialize as _,
+    Deserializer,
+    Serializer,
+    de::Error as _,
+  };
+
+  pub fn serialize<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Duration, D::Error> {
+    let duration = String::deserialize(deserializer)?;
+
+    humantime::parse_duration(&duration).map_err(|error| {
+      D::Error::custom(format!("invalid duration '{duration}': {error}"))
+    })
+  }
+}
+
 /// Find a power supply entry by name, excluding peripheral batteries.
 fn find_battery<'a>(
   power_supplies: &'a HashSet<Arc<power_supply::PowerSupply>>,
@@ -73,7 +152,149 @@ pub fn find_batteries(
     .collect()
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+/// How far back trend expressions look into the CPU log.
+const TREND_WINDOW: Duration = Duration::from_secs(60);
+
+/// Fits a line through the values of the CPU log entries within
+/// [`TREND_WINDOW`] using least squares and returns its slope, in units per
+/// second. Returns `None` if there are fewer than two samples.
+fn cpu_log_trend(
+  cpu_log: &VecDeque<system::CpuLog>,
+  value: impl Fn(&system::CpuLog) -> Option<f64>,
+) -> Option<f64> {
+  let now = Instant::now();
+
+  let samples: Vec<(f64, f64)> = cpu_log
+    .iter()
+    .rev()
+    .take_while(|log| now.duration_since(log.at) < TREND_WINDOW)
+    .filter_map(|log| {
+      Some((-now.duration_since(log.at).as_secs_f64(), value(log)?))
+    })
+    .collect();
+
+  if samples.len() < 2 {
+    return None;
+  }
+
+  let count = samples.len() as f64;
+  let mean_x = samples.iter().map(|(x, _)| x).sum::<f64>() / count;
+  let mean_y = samples.iter().map(|(_, y)| y).sum::<f64>() / count;
+
+  let (covariance, variance) =
+    samples
+      .iter()
+      .fold((0.0, 0.0), |(covariance, variance), (x, y)| {
+        (
+          covariance + (x - mean_x) * (y - mean_y),
+          variance + (x - mean_x).powi(2),
+        )
+      });
+
+  if variance == 0.0 {
+    return None;
+  }
+
+  Some(covariance / variance)
+}
+
+/// The unit a frequency setting is written to sysfs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrequencyUnit {
+  Khz,
+  Mhz,
+}
+
+impl FrequencyUnit {
+  fn khz(self) -> f64 {
+    match self {
+      Self::Khz => 1.0,
+      Self::Mhz => 1000.0,
+    }
+  }
+
+  fn name(self) -> &'static str {
+    match self {
+      Self::Khz => "kHz",
+      Self::Mhz => "MHz",
+    }
+  }
+}
+
+/// Parses a frequency with a unit suffix, such as `"3.5GHz"`, into `unit`.
+fn parse_frequency(
+  frequency: &str,
+  unit: FrequencyUnit,
+) -> anyhow::Result<f64> {
+  let frequency = frequency.trim();
+
+  let split = frequency
+    .find(|char: char| char.is_ascii_alphabetic())
+    .with_context(|| {
+      format!("frequency '{frequency}' has no unit, expected GHz, MHz or kHz")
+    })?;
+  let (number, suffix) = frequency.split_at(split);
+
+  let khz = match &*suffix.to_ascii_lowercase() {
+    "ghz" => 1_000_000.0,
+    "mhz" => 1000.0,
+    "khz" => 1.0,
+    _ => {
+      bail!("invalid frequency unit '{suffix}', expected GHz, MHz or kHz")
+    },
+  };
+
+  let number: f64 = number
+    .trim()
+    .parse()
+    .with_context(|| format!("invalid frequency '{frequency}'"))?;
+
+  Ok(number * khz / unit.khz())
+}
+
+/// Evaluates a frequency setting in `unit`, rounding it to a whole number if
+/// needed. Strings with a unit suffix, such as `"3.5GHz"`, are converted.
+fn eval_frequency(
+  expression: Option<&Expression>,
+  state: &EvalState,
+  name: &str,
+  unit: FrequencyUnit,
+) -> anyhow::Result<Option<u64>> {
+  let Some(expression) = expression else {
+    return Ok(None);
+  };
+
+  let Some(frequency) = expression.eval(state)? else {
+    return Ok(None);
+  };
+
+  let frequency = match frequency {
+    Expression::String(frequency) => {
+      parse_frequency(&frequency, unit)
+        .with_context(|| format!("`{name}` was not a valid frequency"))?
+    },
+
+    frequency => {
+      frequency.try_into_number().with_context(|| {
+        format!("`{name}` was not a number or a frequency with a unit")
+      })?
+    },
+  };
+
+  if frequency.fract() != 0.0 {
+    let rounded = frequency.round() as u64;
+    log::warn!(
+      "`{name}` yielded a float value ({frequency}), rounding to {rounded}"
+    );
+    return Ok(Some(rounded));
+  }
+
+  Ok(Some(frequency as u64))
+}
+
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct CpusDelta {
   /// The CPUs to apply the changes to. When unspecified, will be applied to
@@ -89,6 +310,13 @@ pub struct CpusDelta {
   #[serde(skip_serializing_if = "is_default")]
   pub governor: Option<Expression>,
 
+  /// Set how often the `schedutil` governor may change the frequency, in
+  /// microseconds. Only applied to CPUs using `schedutil`.
+  ///
+  /// Type: `u64`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub schedutil_rate_limit_us: Option<Expression>,
+
   /// Set CPU Energy Performance Preference (EPP).
   ///
   /// Type: `String`.
@@ -111,12 +339,45 @@ pub struct CpusDelta {
   #[serde(skip_serializing_if = "is_default")]
   pub frequency_mhz_maximum: Option<Expression>,
 
+  /// Set minimum CPU frequency in kHz. Unlike `frequency_mhz_minimum`, this
+  /// is written as is, without losing precision.
+  ///
+  /// Type: `u64`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub frequency_khz_minimum: Option<Expression>,
+  /// Set maximum CPU frequency in kHz. Unlike `frequency_mhz_maximum`, this
+  /// is written as is, without losing precision.
+  ///
+  /// Type: `u64`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub frequency_khz_maximum: Option<Expression>,
+
+  /// Pin the CPU frequency in MHz, switching to the `userspace` governor.
+  ///
+  /// Type: `u64`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub frequency_mhz: Option<Expression>,
+
   /// Set turbo boost behaviour. Has to be for all CPUs.
   ///
   /// Type: `bool`.
   #[serde(skip_serializing_if = "is_default")]
   pub turbo: Option<Expression>,
 
+  /// Turn simultaneous multithreading (hyperthreading) on or off. Has to be
+  /// for all CPUs.
+  ///
+  /// Type: `bool`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub smt: Option<Expression>,
+
+  /// Turn the HWP dynamic boost of `intel_pstate` on or off. Has to be for
+  /// all CPUs.
+  ///
+  /// Type: `bool`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub hwp_dynamic_boost: Option<Expression>,
+
   /// Set Intel P-State minimum performance as a percentage.
   ///
   /// Type: `u8`.
@@ -140,9 +401,79 @@ pub struct CpusDelta {
   /// Type: `u32 | String`.
   #[serde(skip_serializing_if = "is_default")]
   pub pm_qos_resume_latency_us: Option<Expression>,
+
+  /// Bring CPUs online or take them offline. CPU 0 can't be taken offline.
+  ///
+  /// Type: `bool`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub online: Option<Expression>,
 }
 
 impl CpusDelta {
+  /// The frequencies written as plain numbers that no CPU runs at, likely
+  /// meant as GHz, such as `cpu.frequency-mhz-maximum = 2.4`. Checked when
+  /// loading, so they are reported once instead of every poll.
+  fn implausible_frequencies(&self) -> Vec<(&'static str, f64, FrequencyUnit)> {
+    fn literals(expression: &Expression, numbers: &mut Vec<f64>) {
+      match expression {
+        Expression::Number(number) => numbers.push(*number),
+
+        Expression::IfElse {
+          consequence,
+          alternative,
+          ..
+        } => {
+          literals(consequence, numbers);
+          if let Some(alternative) = alternative {
+            literals(alternative, numbers);
+          }
+        },
+
+        _ => {},
+      }
+    }
+
+    let mut implausible = Vec::new();
+
+    for (expression, name, unit) in [
+      (
+        &self.frequency_mhz_minimum,
+        "cpu.frequency-mhz-minimum",
+        FrequencyUnit::Mhz,
+      ),
+      (
+        &self.frequency_mhz_maximum,
+        "cpu.frequency-mhz-maximum",
+        FrequencyUnit::Mhz,
+      ),
+      (
+        &self.frequency_khz_minimum,
+        "cpu.frequency-khz-minimum",
+        FrequencyUnit::Khz,
+      ),
+      (
+        &self.frequency_khz_maximum,
+        "cpu.frequency-khz-maximum",
+        FrequencyUnit::Khz,
+      ),
+      (&self.frequency_mhz, "cpu.frequency-mhz", FrequencyUnit::Mhz),
+    ] {
+      let mut numbers = Vec::new();
+      if let Some(expression) = expression {
+        literals(expression, &mut numbers);
+      }
+
+      // No CPU runs below 100 MHz.
+      for frequency in numbers {
+        if frequency * unit.khz() < 100_000.0 {
+          implausible.push((name, frequency, unit));
+        }
+      }
+    }
+
+    implausible
+  }
+
   pub fn eval(&self, state: &EvalState<'_, '_>) -> CpuEvalResult {
     log::debug!("evaluating CPU deltas...");
 
@@ -197,6 +528,12 @@ impl CpusDelta {
         delta.governor = Some(governor);
       }
 
+      delta.schedutil_rate_limit_us = eval_u64(
+        &self.schedutil_rate_limit_us,
+        &state,
+        "cpu.schedutil-rate-limit-us",
+      )?;
+
       if let Some(energy_performance_preference) =
         &self.energy_performance_preference
         && let Some(energy_performance_preference) =
@@ -220,48 +557,65 @@ impl CpusDelta {
         delta.energy_perf_bias = Some(energy_perf_bias);
       }
 
-      if let Some(frequency_mhz_minimum) = &self.frequency_mhz_minimum
-        && let Some(frequency_mhz_minimum) =
-          frequency_mhz_minimum.eval(&state)?
+      delta.frequency_mhz_minimum = eval_frequency(
+        self.frequency_mhz_minimum.as_ref(),
+        &state,
+        "cpu.frequency-mhz-minimum",
+        FrequencyUnit::Mhz,
+      )?;
+      delta.frequency_mhz_maximum = eval_frequency(
+        self.frequency_mhz_maximum.as_ref(),
+        &state,
+        "cpu.frequency-mhz-maximum",
+        FrequencyUnit::Mhz,
+      )?;
+      delta.frequency_khz_minimum = eval_frequency(
+        self.frequency_khz_minimum.as_ref(),
+        &state,
+        "cpu.frequency-khz-minimum",
+        FrequencyUnit::Khz,
+      )?;
+      delta.frequency_khz_maximum = eval_frequency(
+        self.frequency_khz_maximum.as_ref(),
+        &state,
+        "cpu.frequency-khz-maximum",
+        FrequencyUnit::Khz,
+      )?;
+      delta.frequency_mhz = eval_frequency(
+        self.frequency_mhz.as_ref(),
+        &state,
+        "cpu.frequency-mhz",
+        FrequencyUnit::Mhz,
+      )?;
+
+      if delta.frequency_mhz.is_some()
+        && delta
+          .governor
+          .as_ref()
+          .is_some_and(|governor| governor != "userspace")
       {
-        let frequency_mhz_minimum = frequency_mhz_minimum
-          .try_into_number()
-          .context("`cpu.frequency-mhz-minimum` was not a number")?;
-
-        let rounded_value = if frequency_mhz_minimum.fract() != 0.0 {
-          let rounded = frequency_mhz_minimum.round() as u64;
-          log::warn!(
-            "`cpu.frequency-mhz-minimum` yielded a float value \
-             ({frequency_mhz_minimum}), rounding to {rounded}"
-          );
-          rounded
-        } else {
-          frequency_mhz_minimum as u64
-        };
-
-        delta.frequency_mhz_minimum = Some(rounded_value);
+        bail!(
+          "`cpu.frequency-mhz` requires the userspace governor and cannot be \
+           combined with another `cpu.governor`"
+        );
       }
 
-      if let Some(frequency_mhz_maximum) = &self.frequency_mhz_maximum
-        && let Some(frequency_mhz_maximum) =
-          frequency_mhz_maximum.eval(&state)?
+      if delta.frequency_mhz_minimum.is_some()
+        && delta.frequency_khz_minimum.is_some()
       {
-        let frequency_mhz_maximum = frequency_mhz_maximum
-          .try_into_number()
-          .context("`cpu.frequency-mhz-maximum` was not a number")?;
-
-        let rounded_value = if frequency_mhz_maximum.fract() != 0.0 {
-          let rounded = frequency_mhz_maximum.round() as u64;
-          log::warn!(
-            "`cpu.frequency-mhz-maximum` yielded a float value \
-             ({frequency_mhz_maximum}), rounding to {rounded}"
-          );
-          rounded
-        } else {
-          frequency_mhz_maximum as u64
-        };
+        bail!(
+          "`cpu.frequency-mhz-minimum` and `cpu.frequency-khz-minimum` cannot \
+           both be set"
+        );
+      }
 
-        delta.frequency_mhz_maximum = Some(rounded_value);
+      if delta.frequency_mhz_maximum.is_some()
+        && delta.frequency_khz_maximum.is_some()
+      {
+        bail!(
+          "`cpu.frequency-mhz-maximum` and `cpu.frequency-khz-maximum` cannot \
+           both be set"
+        );
       }
 
       if let Some(pm_qos_resume_latency_us) = &self.pm_qos_resume_latency_us
@@ -290,6 +644,20 @@ impl CpusDelta {
         });
       }
 
+      if let Some(online) = &self.online
+        && let Some(online) = online.eval(&state)?
+      {
+        let online = online
+          .try_into_boolean()
+          .context("`cpu.online` was not a boolean")?;
+
+        if !online && cpu.number == 0 {
+          bail!("`cpu.online` can't take CPU 0 offline");
+        }
+
+        delta.online = Some(online);
+      }
+
       deltas.insert(Arc::clone(&cpu), delta);
     }
 
@@ -305,8 +673,35 @@ impl CpusDelta {
       None
     };
 
+    let smt = if let Some(smt) = &self.smt
+      && let Some(smt) = smt.eval(state)?
+    {
+      Some(
+        smt
+          .try_into_boolean()
+          .context("`cpu.smt` was not a boolean")?,
+      )
+    } else {
+      None
+    };
+
+    let hwp_dynamic_boost = if let Some(hwp_dynamic_boost) =
+      &self.hwp_dynamic_boost
+      && let Some(hwp_dynamic_boost) = hwp_dynamic_boost.eval(state)?
+    {
+      Some(
+        hwp_dynamic_boost
+          .try_into_boolean()
+          .context("`cpu.hwp-dynamic-boost` was not a boolean")?,
+      )
+    } else {
+      None
+    };
+
     let global = cpu::GlobalDelta {
       turbo,
+      smt,
+      hwp_dynamic_boost,
       pstate_min_performance_percent: eval_percent(
         &self.pstate_min_performance_percent,
         state,
@@ -328,7 +723,9 @@ impl CpusDelta {
   }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct UncoresDelta {
   /// The uncore devices to apply the changes to. When unspecified, will be
@@ -410,7 +807,9 @@ impl UncoresDelta {
   }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct VmDelta {
   #[serde(skip_serializing_if = "is_default")]
@@ -467,7 +866,9 @@ impl VmDelta {
   }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct DisksDelta {
   #[serde(rename = "for", skip_serializing_if = "is_default")]
@@ -535,7 +936,9 @@ impl DisksDelta {
   }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct UsbsDelta {
   #[serde(rename = "for", skip_serializing_if = "is_default")]
@@ -599,7 +1002,9 @@ impl UsbsDelta {
   }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct AudioDelta {
   #[serde(skip_serializing_if = "is_default")]
@@ -628,7 +1033,9 @@ impl AudioDelta {
   }
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct GpusDelta {
   #[serde(rename = "for", skip_serializing_if = "is_default")]
@@ -691,22 +1098,6 @@ impl GpusDelta {
   }
 }
 
-fn eval_u32(
-  expression: &Option<Expression>,
-  state: &EvalState<'_, '_>,
-  name: &str,
-) -> anyhow::Result<Option<u32>> {
-  let Some(value) = eval_u64(expression, state, name)? else {
-    return Ok(None);
-  };
-
-  if value > u32::MAX as u64 {
-    bail!("invalid `{name}`: {value}");
-  }
-
-  Ok(Some(value as u32))
-}
-
 fn eval_i32(
   expression: &Option<Expression>,
   state: &EvalState<'_, '_>,
@@ -802,18 +1193,28 @@ fn eval_percent(
   state: &EvalState<'_, '_>,
   name: &str,
 ) -> anyhow::Result<Option<u8>> {
-  let Some(value) = eval_u32(expression, state, name)? else {
+  let Some(expression) = expression else {
     return Ok(None);
   };
+  let Some(value) = expression.eval(state)? else {
+    return Ok(None);
+  };
+
+  let value = value
+    .try_into_number()
+    .with_context(|| format!("`{name}` was not a number"))?;
 
-  if value > 100 {
-    bail!("`{name}` must be between 0 and 100, got {value}");
+  // Rejected rather than truncated, sysfs only takes whole percentages.
+  if value.fract() != 0.0 || !(0.0..=100.0).contains(&value) {
+    bail!("`{name}` must be a whole percentage between 0 and 100, got {value}");
   }
 
   Ok(Some(value as u8))
 }
 
-#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
 #[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
 pub struct PowersDelta {
   /// The power supplies to apply the changes to. When unspecified, will be
@@ -836,6 +1237,27 @@ pub struct PowersDelta {
   #[serde(skip_serializing_if = "is_default")]
   pub charge_threshold_end: Option<Expression>,
 
+  /// Set the percentage where charging will stop, and start charging
+  /// `charge-threshold-gap` percent below it. Can't be combined with
+  /// `charge-threshold-start` or `charge-threshold-end`.
+  ///
+  /// Type: `u8`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub charge_threshold_target: Option<Expression>,
+
+  /// How far below `charge-threshold-target` charging starts. Defaults to 5.
+  ///
+  /// Type: `u8`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub charge_threshold_gap: Option<Expression>,
+
+  /// Set the charge behaviour, such as `auto`, `inhibit-charge` or
+  /// `force-discharge`.
+  ///
+  /// Type: `String`.
+  #[serde(skip_serializing_if = "is_default")]
+  pub charge_behaviour: Option<Expression>,
+
   /// Set ACPI platform profile. Has to be for all power supplies.
   ///
   /// Type: `String`.
@@ -844,6 +1266,54 @@ pub struct PowersDelta {
 }
 
 impl PowersDelta {
+  /// The default of `charge-threshold-gap`.
+  pub const DEFAULT_CHARGE_THRESHOLD_GAP: u8 = 5;
+
+  /// The start and end charge thresholds, from `charge-threshold-target` if
+  /// it is set.
+  fn eval_charge_thresholds(
+    &self,
+    state: &EvalState<'_, '_>,
+  ) -> anyhow::Result<(Option<u8>, Option<u8>)> {
+    let Some(target) = eval_percent(
+      &self.charge_threshold_target,
+      state,
+      "power.charge-threshold-target",
+    )?
+    else {
+      return Ok((
+        eval_percent(
+          &self.charge_threshold_start,
+          state,
+          "power.charge-threshold-start",
+        )?,
+        eval_percent(
+          &self.charge_threshold_end,
+          state,
+          "power.charge-threshold-end",
+        )?,
+      ));
+    };
+
+    if self.charge_threshold_start.is_some()
+      || self.charge_threshold_end.is_some()
+    {
+      bail!(
+        "`power.charge-threshold-target` can't be combined with \
+         `power.charge-threshold-start` or `power.charge-threshold-end`"
+      );
+    }
+
+    let gap = eval_percent(
+      &self.charge_threshold_gap,
+      state,
+      "power.charge-threshold-gap",
+    )?
+    .unwrap_or(Self::DEFAULT_CHARGE_THRESHOLD_GAP);
+
+    Ok((Some(target.saturating_sub(gap)), Some(target)))
+  }
+
   pub fn eval(&self, state: &EvalState<'_, '_>) -> PowerSupplyEvalResult {
     log::debug!("evaluating power supply deltas...");
 
@@ -882,27 +1352,29 @@ impl PowersDelta {
 
     for power_supply in power_supplies {
       let state = state.in_context(EvalContext::PowerSupply(&power_supply));
-      let mut delta = power_supply::Delta::default();
-
-      if let Some(threshold_start) = &self.charge_threshold_start
-        && let Some(threshold_start) = threshold_start.eval(&state)?
+      let charge_behaviour = if let Some(charge_behaviour) =
+        &self.charge_behaviour
+        && let Some(charge_behaviour) = charge_behaviour.eval(&state)?
       {
-        let threshold_start = threshold_start
-          .try_into_number()
-          .context("`power.charge-threshold-start` was not a number")?;
+        let charge_behaviour = charge_behaviour
+          .try_into_string()
+          .context("`power.charge-behaviour` was not a string")?;
 
-        delta.charge_threshold_start = Some(threshold_start / 100.0);
-      }
+        Some(charge_behaviour)
+      } else {
+        None
+      };
 
-      if let Some(threshold_end) = &self.charge_threshold_end
-        && let Some(threshold_end) = threshold_end.eval(&state)?
-      {
-        let threshold_end = threshold_end
-          .try_into_number()
-          .context("`power.charge-threshold-end` was not a number")?;
+      let (charge_threshold_start, charge_threshold_end) =
+        self.eval_charge_thresholds(&state)?;
 
-        delta.charge_threshold_end = Some(threshold_end / 100.0);
-      }
+      let delta = power_supply::Delta {
+        charge_threshold_start: charge_threshold_start
+          .map(|percent| Percent::from_percent(f64::from(percent))),
+        charge_threshold_end: charge_threshold_end
+          .map(|percent| Percent::from_percent(f64::from(percent))),
+        charge_behaviour,
+      };
 
       deltas.insert(Arc::clone(&power_supply), delta);
     }
@@ -928,6 +1400,10 @@ mod expression {
   macro_rules! named {
     ($variant:ident => $value:literal) => {
       pub mod $variant {
+        pub fn schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+          schemars::json_schema!({ "const": $value })
+        }
+
         pub fn serialize<S: serde::Serializer>(
           serializer: S,
         ) -> Result<S::Ok, S::Error> {
@@ -972,11 +1448,24 @@ mod expression {
 
   named!(frequency_available => "?frequency-available");
   named!(turbo_available => "?turbo-available");
+  named!(turbo_enabled => "?turbo-enabled");
+  named!(smt_available => "?smt-available");
+  named!(hwp_dynamic_boost_available => "?hwp-dynamic-boost-available");
+  named!(charge_behaviour_available => "?charge-behaviour-available");
 
   named!(cpu_usage => "%cpu-usage");
   named!(cpu_usage_volatility => "$cpu-usage-volatility");
+  named!(cpu_usage_average => "%cpu-usage-average");
   named!(cpu_temperature => "$cpu-temperature");
   named!(cpu_temperature_volatility => "$cpu-temperature-volatility");
+  named!(cpu_temperature_rising => "?cpu-temperature-rising");
+  named!(cpu_temperature_maximum => "$cpu-temperature-max");
+  named!(cpu_temperature_minimum => "$cpu-temperature-min");
+  named!(fan_rpm_maximum => "$fan-rpm-max");
+  named!(gpu_temperature => "$gpu-temperature");
+  named!(gpu_power_watts => "$gpu-power-watts");
+  named!(disk_temperature_maximum => "$disk-temperature-max");
+  named!(cpu_usage_trend => "$cpu-usage-trend");
   named!(cpu_idle_seconds => "$cpu-idle-seconds");
   named!(cpu_frequency_maximum => "$cpu-frequency-maximum");
   named!(cpu_frequency_minimum => "$cpu-frequency-minimum");
@@ -984,23 +1473,41 @@ mod expression {
   named!(cpu_scaling_maximum => "$cpu-scaling-maximum");
 
   named!(cpu_core_count => "%cpu-core-count");
+  named!(cpu_count => "$cpu-count");
+  named!(cpu_online_count => "$cpu-online-count");
+  named!(cpu_number => "$cpu-number");
+  named!(cpu_governor => "$cpu-governor");
+
+  named!(memory_usage => "%memory-usage");
+
+  named!(cpu_pressure => "$cpu-pressure");
+  named!(cpu_pressure_avg60 => "$cpu-pressure-avg60");
+  named!(cpu_pressure_avg300 => "$cpu-pressure-avg300");
 
   named!(lid_closed => "?lid-closed");
   named!(virtual_machine => "?virtual-machine");
+  named!(platform_profile => "$platform-profile");
 
   named!(hour_of_day => "$hour-of-day");
+  named!(weekend => "?weekend");
 
   named!(power_supply_charge => "%power-supply-charge");
   named!(power_supply_discharge_rate => "%power-supply-discharge-rate");
+  named!(battery_minutes_remaining => "$battery-minutes-remaining");
+  named!(power_draw_watts => "$power-draw-watts");
+  named!(ac_power_limit_watts => "$ac-power-limit-watts");
 
   named!(battery_cycles => "$battery-cycles");
   named!(battery_health => "%battery-health");
+  named!(battery_temperature => "$battery-temperature");
 
   named!(discharging => "?discharging");
+  named!(on_ac => "?on-ac");
+  named!(desktop => "?desktop");
   named!(power_profile_preference => "$power-profile-preference");
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 #[must_use]
 pub enum Expression {
@@ -1016,6 +1523,14 @@ pub enum Expression {
     #[serde(rename = "is-energy-perf-bias-available")]
     value: Box<Expression>,
   },
+  IsScalingDriver {
+    #[serde(rename = "is-scaling-driver")]
+    value: Box<Expression>,
+  },
+  IsCoreType {
+    #[serde(rename = "is-core-type")]
+    value: Box<Expression>,
+  },
   IsPlatformProfileAvailable {
     #[serde(rename = "is-platform-profile-available")]
     value: Box<Expression>,
@@ -1053,69 +1568,219 @@ pub enum Expression {
   },
 
   #[serde(with = "expression::frequency_available")]
+  #[schemars(schema_with = "expression::frequency_available::schema")]
   FrequencyAvailable,
 
   #[serde(with = "expression::turbo_available")]
+  #[schemars(schema_with = "expression::turbo_available::schema")]
   TurboAvailable,
 
+  #[serde(with = "expression::turbo_enabled")]
+  #[schemars(schema_with = "expression::turbo_enabled::schema")]
+  TurboEnabled,
+
+  #[serde(with = "expression::smt_available")]
+  #[schemars(schema_with = "expression::smt_available::schema")]
+  SmtAvailable,
+
+  #[serde(with = "expression::hwp_dynamic_boost_available")]
+  #[schemars(schema_with = "expression::hwp_dynamic_boost_available::schema")]
+  HwpDynamicBoostAvailable,
+
+  #[serde(with = "expression::charge_behaviour_available")]
+  #[schemars(schema_with = "expression::charge_behaviour_available::schema")]
+  ChargeBehaviourAvailable,
+
   #[serde(with = "expression::cpu_usage")]
+  #[schemars(schema_with = "expression::cpu_usage::schema")]
   CpuUsage,
 
   #[serde(with = "expression::cpu_usage_volatility")]
+  #[schemars(schema_with = "expression::cpu_usage_volatility::schema")]
   CpuUsageVolatility,
 
+  #[serde(with = "expression::cpu_usage_average")]
+  #[schemars(schema_with = "expression::cpu_usage_average::schema")]
+  CpuUsageAverage,
+
   CpuUsageSince {
     #[serde(rename = "cpu-usage-since")]
     duration: Box<Expression>,
   },
 
   #[serde(with = "expression::cpu_temperature")]
+  #[schemars(schema_with = "expression::cpu_temperature::schema")]
   CpuTemperature,
 
   #[serde(with = "expression::cpu_temperature_volatility")]
+  #[schemars(schema_with = "expression::cpu_temperature_volatility::schema")]
   CpuTemperatureVolatility,
 
+  #[serde(with = "expression::cpu_temperature_rising")]
+  #[schemars(schema_with = "expression::cpu_temperature_rising::schema")]
+  CpuTemperatureRising,
+
+  #[serde(with = "expression::cpu_temperature_maximum")]
+  #[schemars(schema_with = "expression::cpu_temperature_maximum::schema")]
+  CpuTemperatureMaximum,
+
+  #[serde(with = "expression::cpu_temperature_minimum")]
+  #[schemars(schema_with = "expression::cpu_temperature_minimum::schema")]
+  CpuTemperatureMinimum,
+
+  #[serde(with = "expression::fan_rpm_maximum")]
+  #[schemars(schema_with = "expression::fan_rpm_maximum::schema")]
+  FanRpmMaximum,
+
+  #[serde(with = "expression::gpu_temperature")]
+  #[schemars(schema_with = "expression::gpu_temperature::schema")]
+  GpuTemperature,
+
+  #[serde(with = "expression::gpu_power_watts")]
+  #[schemars(schema_with = "expression::gpu_power_watts::schema")]
+  GpuPowerWatts,
+
+  #[serde(with = "expression::disk_temperature_maximum")]
+  #[schemars(schema_with = "expression::disk_temperature_maximum::schema")]
+  DiskTemperatureMaximum,
+
+  #[serde(with = "expression::cpu_usage_trend")]
+  #[schemars(schema_with = "expression::cpu_usage_trend::schema")]
+  CpuUsageTrend,
+
   #[serde(with = "expression::cpu_idle_seconds")]
+  #[schemars(schema_with = "expression::cpu_idle_seconds::schema")]
   CpuIdleSeconds,
 
   #[serde(with = "expression::cpu_frequency_maximum")]
+  #[schemars(schema_with = "expression::cpu_frequency_maximum::schema")]
   CpuFrequencyMaximum,
 
   #[serde(with = "expression::cpu_frequency_minimum")]
+  #[schemars(schema_with = "expression::cpu_frequency_minimum::schema")]
   CpuFrequencyMinimum,
 
   #[serde(with = "expression::cpu_scaling_maximum")]
+  #[schemars(schema_with = "expression::cpu_scaling_maximum::schema")]
   CpuScalingMaximum,
 
   #[serde(with = "expression::cpu_core_count")]
+  #[schemars(schema_with = "expression::cpu_core_count::schema")]
   CpuCoreCount,
 
+  #[serde(with = "expression::cpu_count")]
+  #[schemars(schema_with = "expression::cpu_count::schema")]
+  CpuCount,
+
+  #[serde(with = "expression::cpu_online_count")]
+  #[schemars(schema_with = "expression::cpu_online_count::schema")]
+  CpuOnlineCount,
+
+  #[serde(with = "expression::cpu_number")]
+  #[schemars(schema_with = "expression::cpu_number::schema")]
+  CpuNumber,
+
+  #[serde(with = "expression::cpu_governor")]
+  #[schemars(schema_with = "expression::cpu_governor::schema")]
+  CpuGovernor,
+
+  CpusOfCoreType {
+    #[serde(rename = "cpus-of-core-type")]
+    value: Box<Expression>,
+  },
+
+  Range {
+    range: Box<Range>,
+  },
+
+  Except {
+    except: Box<Except>,
+  },
+
   LoadAverageSince {
     #[serde(rename = "load-average-since")]
     duration: Box<Expression>,
   },
 
+  AverageOver {
+    #[serde(rename = "average-over")]
+    value:   Box<Expression>,
+    seconds: Box<Expression>,
+  },
+
+  MaximumOver {
+    #[serde(rename = "max-over")]
+    value:   Box<Expression>,
+    seconds: Box<Expression>,
+  },
+
+  MinimumOver {
+    #[serde(rename = "min-over")]
+    value:   Box<Expression>,
+    seconds: Box<Expression>,
+  },
+
   #[serde(with = "expression::lid_closed")]
+  #[schemars(schema_with = "expression::lid_closed::schema")]
   LidClosed,
 
   #[serde(with = "expression::virtual_machine")]
+  #[schemars(schema_with = "expression::virtual_machine::schema")]
   VirtualMachine,
 
   #[serde(with = "expression::hour_of_day")]
+  #[schemars(schema_with = "expression::hour_of_day::schema")]
   HourOfDay,
 
+  #[serde(with = "expression::weekend")]
+  #[schemars(schema_with = "expression::weekend::schema")]
+  Weekend,
+
   #[serde(with = "expression::power_supply_charge")]
+  #[schemars(schema_with = "expression::power_supply_charge::schema")]
   PowerSupplyCharge,
 
   #[serde(with = "expression::power_supply_discharge_rate")]
+  #[schemars(schema_with = "expression::power_supply_discharge_rate::schema")]
   PowerSupplyDischargeRate,
 
+  #[serde(with = "expression::battery_minutes_remaining")]
+  #[schemars(schema_with = "expression::battery_minutes_remaining::schema")]
+  BatteryMinutesRemaining,
+
+  #[serde(with = "expression::power_draw_watts")]
+  #[schemars(schema_with = "expression::power_draw_watts::schema")]
+  PowerDrawWatts,
+  #[serde(with = "expression::ac_power_limit_watts")]
+  #[schemars(schema_with = "expression::ac_power_limit_watts::schema")]
+  AcPowerLimitWatts,
+
+  #[serde(with = "expression::memory_usage")]
+  #[schemars(schema_with = "expression::memory_usage::schema")]
+  MemoryUsage,
+
+  #[serde(with = "expression::cpu_pressure")]
+  #[schemars(schema_with = "expression::cpu_pressure::schema")]
+  CpuPressure,
+  #[serde(with = "expression::cpu_pressure_avg60")]
+  #[schemars(schema_with = "expression::cpu_pressure_avg60::schema")]
+  CpuPressureAvg60,
+  #[serde(with = "expression::cpu_pressure_avg300")]
+  #[schemars(schema_with = "expression::cpu_pressure_avg300::schema")]
+  CpuPressureAvg300,
+
   #[serde(with = "expression::battery_cycles")]
+  #[schemars(schema_with = "expression::battery_cycles::schema")]
   BatteryCycles,
 
   #[serde(with = "expression::battery_health")]
+  #[schemars(schema_with = "expression::battery_health::schema")]
   BatteryHealth,
 
+  #[serde(with = "expression::battery_temperature")]
+  #[schemars(schema_with = "expression::battery_temperature::schema")]
+  BatteryTemperature,
+
   BatteryCyclesFor {
     #[serde(rename = "battery-cycles-for")]
     name: String,
@@ -1127,10 +1792,32 @@ pub enum Expression {
   },
 
   #[serde(with = "expression::discharging")]
+  #[schemars(schema_with = "expression::discharging::schema")]
   Discharging,
 
+  #[serde(with = "expression::on_ac")]
+  #[schemars(schema_with = "expression::on_ac::schema")]
+  OnAc,
+
+  #[serde(with = "expression::desktop")]
+  #[schemars(schema_with = "expression::desktop::schema")]
+  Desktop,
+
   #[serde(with = "expression::power_profile_preference")]
+  #[schemars(schema_with = "expression::power_profile_preference::schema")]
   PowerProfilePreference,
+  #[serde(with = "expression::platform_profile")]
+  #[schemars(schema_with = "expression::platform_profile::schema")]
+  PlatformProfile,
+
+  SysfsNumber {
+    #[serde(rename = "sysfs-number")]
+    path: SysfsPath,
+  },
+  SysfsString {
+    #[serde(rename = "sysfs-string")]
+    path: SysfsPath,
+  },
 
   Boolean(bool),
 
@@ -1171,11 +1858,27 @@ pub enum Expression {
     #[serde(rename = "divide")]
     b: Box<Expression>,
   },
-
-  LessThan {
+  Modulo {
     #[serde(rename = "value")]
     a: Box<Expression>,
-    #[serde(rename = "is-less-than")]
+    #[serde(rename = "modulo")]
+    b: Box<Expression>,
+  },
+
+  Round {
+    round: Box<Expression>,
+  },
+  Floor {
+    floor: Box<Expression>,
+  },
+  Ceil {
+    ceil: Box<Expression>,
+  },
+
+  LessThan {
+    #[serde(rename = "value")]
+    a: Box<Expression>,
+    #[serde(rename = "is-less-than")]
     b: Box<Expression>,
   },
   MoreThan {
@@ -1185,6 +1888,12 @@ pub enum Expression {
     b: Box<Expression>,
   },
 
+  Clamp {
+    value:   Box<Expression>,
+    minimum: Box<Expression>,
+    maximum: Box<Expression>,
+  },
+
   Minimum {
     #[serde(rename = "minimum")]
     numbers: Vec<Expression>,
@@ -1209,6 +1918,11 @@ pub enum Expression {
     a: Box<Expression>,
   },
 
+  Coalesce {
+    value:   Box<Expression>,
+    default: Box<Expression>,
+  },
+
   And {
     #[serde(rename = "value")]
     a: Box<Expression>,
@@ -1239,14 +1953,43 @@ pub enum Expression {
     a:      Box<Expression>,
     #[serde(rename = "is-equal")]
     b:      Box<Expression>,
+    /// How far apart two numbers may be and still be equal. Exact by
+    /// default.
+    #[serde(default = "literal_zero", skip_serializing_if = "literal_is_zero")]
+    leeway: Box<Expression>,
+  },
+  NotEqual {
+    #[serde(rename = "value")]
+    a:      Box<Expression>,
+    #[serde(rename = "is-not-equal")]
+    b:      Box<Expression>,
+    #[serde(default = "literal_zero", skip_serializing_if = "literal_is_zero")]
     leeway: Box<Expression>,
   },
+
+  Between {
+    between: Box<Between>,
+  },
+
+  StringEqual {
+    value: Box<Expression>,
+    is:    Box<Expression>,
+  },
+
+  In {
+    value: Box<Expression>,
+    #[serde(rename = "in")]
+    list:  Box<Expression>,
+  },
 }
 
 impl Expression {
   pub fn try_into_number(self) -> anyhow::Result<f64> {
     let Self::Number(number) = self else {
-      bail!("tried to cast '{self:?}' to a number, failed")
+      bail!(WattError::TypeMismatch {
+        expected: "a number",
+        value:    format!("{self:?}"),
+      })
     };
 
     Ok(number)
@@ -1254,7 +1997,10 @@ impl Expression {
 
   pub fn try_into_boolean(self) -> anyhow::Result<bool> {
     let Self::Boolean(boolean) = self else {
-      bail!("tried to cast '{self:?}' to a boolean, failed")
+      bail!(WattError::TypeMismatch {
+        expected: "a boolean",
+        value:    format!("{self:?}"),
+      })
     };
 
     Ok(boolean)
@@ -1262,7 +2008,10 @@ impl Expression {
 
   pub fn try_into_string(self) -> anyhow::Result<String> {
     let Self::String(string) = self else {
-      bail!("tried to cast '{self:?}' to a string, failed")
+      bail!(WattError::TypeMismatch {
+        expected: "a string",
+        value:    format!("{self:?}"),
+      })
     };
 
     Ok(string)
@@ -1270,49 +2019,144 @@ impl Expression {
 
   pub fn try_into_list(self) -> anyhow::Result<Vec<Expression>> {
     let Self::List(list) = self else {
-      bail!("tried to cast '{self:?}' to a list, failed")
+      bail!(WattError::TypeMismatch {
+        expected: "a list",
+        value:    format!("{self:?}"),
+      })
     };
 
     Ok(list)
   }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Serializes to the variables, such as for the `stats-file`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 pub struct EvalState<'peripherals, 'context> {
-  pub frequency_available: bool,
-  pub turbo_available:     bool,
+  pub frequency_available:         bool,
+  pub turbo_available:             bool,
+  /// Whether turbo is currently on, undefined without a turbo control.
+  pub turbo_enabled:               Option<bool>,
+  pub smt_available:               bool,
+  pub hwp_dynamic_boost_available: bool,
 
   pub cpu_usage:                  f64,
   pub cpu_usage_volatility:       Option<f64>,
+  pub cpu_usage_average:          Option<f64>,
   pub cpu_temperature:            Option<f64>,
   pub cpu_temperature_volatility: Option<f64>,
+  /// The hottest core, for rules that shouldn't let the average hide it.
+  pub cpu_temperature_maximum:    Option<f64>,
+  pub cpu_temperature_minimum:    Option<f64>,
+  /// The fastest fan in RPM, undefined without fan sensors.
+  pub fan_rpm_maximum:            Option<f64>,
+  /// The hottest GPU sensor, undefined without GPU sensors.
+  pub gpu_temperature:            Option<f64>,
+  pub gpu_power_watts:            Option<f64>,
+  /// The hottest storage device, undefined without disk sensors.
+  pub disk_temperature_maximum:   Option<f64>,
   pub cpu_idle_seconds:           f64,
   pub cpu_frequency_maximum:      Option<f64>,
   pub cpu_frequency_minimum:      Option<f64>,
 
-  pub lid_closed:      bool,
-  pub virtual_machine: bool,
-  pub chassis_type:    Option<&'peripherals str>,
+  /// Undefined without a lid switch.
+  pub lid_closed:       Option<bool>,
+  pub virtual_machine:  bool,
+  pub chassis_type:     Option<&'peripherals str>,
+  /// Undefined without platform profile support.
+  pub platform_profile: Option<&'peripherals str>,
 
   pub power_supply_charge:         Option<f64>,
   pub power_supply_discharge_rate: Option<f64>,
+  /// Until empty while discharging, until full otherwise.
+  pub battery_minutes_remaining:   Option<f64>,
+  /// Summed over the discharging batteries.
+  pub power_draw_watts:            Option<f64>,
+  /// The highest wattage of the plugged in adapters.
+  pub ac_power_limit_watts:        Option<f64>,
+
+  pub memory_usage: Option<f64>,
+
+  /// Undefined on kernels without pressure stall information.
+  pub cpu_pressure: Option<system::Pressure>,
 
   pub battery_cycles: Option<f64>,
   pub battery_health: Option<f64>,
 
   pub discharging: bool,
+  /// Whether the system runs on wall power, which includes a laptop that
+  /// is plugged in but not charging and desktops.
+  pub on_ac:       bool,
+  /// Whether the system has no battery.
+  pub desktop:     bool,
+
+  /// The local wall clock time of this evaluation.
+  pub local_time: jiff::civil::DateTime,
 
   pub power_profile_preference: crate::profile::PowerProfile,
 
+  #[serde(skip)]
   pub context: EvalContext<'context>,
 
+  #[serde(skip)]
   pub cpus:           &'peripherals HashSet<Arc<cpu::Cpu>>,
+  #[serde(skip)]
   pub uncores:        &'peripherals HashSet<Arc<uncore::Uncore>>,
+  #[serde(skip)]
   pub disks:          &'peripherals HashSet<Arc<disk::Disk>>,
+  #[serde(skip)]
   pub usb_devices:    &'peripherals HashSet<Arc<usb::UsbDevice>>,
+  #[serde(skip)]
   pub gpus:           &'peripherals HashSet<Arc<gpu::Gpu>>,
+  #[serde(skip)]
   pub power_supplies: &'peripherals HashSet<Arc<power_supply::PowerSupply>>,
+  #[serde(skip)]
   pub cpu_log:        &'peripherals VecDeque<system::CpuLog>,
+  #[serde(skip)]
+  pub sysfs_reads:    &'peripherals SysfsReads,
+}
+
+/// The files read by `sysfs-number` and `sysfs-string`, so every rule sees
+/// the same value of a file within one evaluation.
+#[derive(Debug, Default)]
+pub struct SysfsReads(Mutex<HashMap<PathBuf, Option<String>>>);
+
+impl SysfsReads {
+  fn values(&self) -> MutexGuard<'_, HashMap<PathBuf, Option<String>>> {
+    self
+      .0
+      .lock()
+      .unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  /// Forgets the values read so far, for the next evaluation.
+  pub fn clear(&self) {
+    self.values().clear();
+  }
+
+  fn read(&self, path: &SysfsPath) -> anyhow::Result<Option<String>> {
+    if let Some(value) = self.values().get(&path.0) {
+      return Ok(value.clone());
+    }
+
+    let value = crate::fs::read(&path.0)?;
+    self.values().insert(path.0.clone(), value.clone());
+
+    Ok(value)
+  }
+}
+
+impl Clone for SysfsReads {
+  fn clone(&self) -> Self {
+    Self(Mutex::new(self.values().clone()))
+  }
+}
+
+impl PartialEq for SysfsReads {
+  fn eq(&self, other: &Self) -> bool {
+    // Locking the same mutex twice would deadlock.
+    std::ptr::eq(self, other) || *self.values() == *other.values()
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -1377,6 +2221,79 @@ impl Expression {
       Ok(Some(strings))
     }
 
+    /// Strings are compared as they are, anything else as numbers within the
+    /// leeway.
+    fn equal(
+      a: Expression,
+      b: Expression,
+      leeway: Expression,
+    ) -> anyhow::Result<bool> {
+      if let (String(a), String(b)) = (&a, &b) {
+        return Ok(a == b);
+      }
+
+      let a = a.try_into_number()?;
+      let b = b.try_into_number()?;
+      let leeway = leeway.try_into_number()?;
+
+      let minimum = a - leeway;
+      let maximum = a + leeway;
+
+      Ok(a == b || (minimum < b && b < maximum))
+    }
+
+    /// The samples of `value` the CPU log recorded in the last `seconds`.
+    /// Undefined unless the log reaches back over the whole window with at
+    /// least two samples in it, so a freshly started daemon doesn't judge a
+    /// window from a few seconds of history.
+    fn cpu_log_window(
+      value: &Expression,
+      seconds: Expression,
+      state: &EvalState<'_, '_>,
+      name: &str,
+    ) -> anyhow::Result<Option<Vec<f64>>> {
+      let sample: fn(&system::CpuLog) -> Option<f64> = match value {
+        Expression::CpuUsage => |log| Some(log.usage),
+        Expression::CpuTemperature => |log| log.temperature,
+        _ => {
+          bail!(
+            "`{name}` only supports \"%cpu-usage\" and \"$cpu-temperature\""
+          )
+        },
+      };
+
+      let seconds = seconds
+        .try_into_number()
+        .with_context(|| format!("`seconds` of `{name}` was not a number"))?;
+      let window = Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|window| !window.is_zero())
+        .with_context(|| {
+          format!("`seconds` of `{name}` must be positive, got {seconds}")
+        })?;
+
+      let Some(oldest) = state.cpu_log.front() else {
+        return Ok(None);
+      };
+      if oldest.at.elapsed() < window {
+        return Ok(None);
+      }
+
+      let recent_logs: Vec<&system::CpuLog> = state
+        .cpu_log
+        .iter()
+        .rev()
+        .take_while(|log| log.at.elapsed() < window)
+        .collect();
+      if recent_logs.len() < 2 {
+        return Ok(None);
+      }
+
+      let samples: Vec<f64> =
+        recent_logs.into_iter().filter_map(sample).collect();
+      Ok((!samples.is_empty()).then_some(samples))
+    }
+
     fn first_available_cpu_value(
       state: &EvalState<'_, '_>,
       values: &[std::string::String],
@@ -1447,6 +2364,37 @@ impl Expression {
 
         Boolean(available)
       },
+      IsScalingDriver { value } => {
+        let value = eval!(value);
+        let value = value.try_into_string()?;
+
+        let matches = match state.context {
+          EvalContext::Cpu(cpu) => cpu.scaling_driver.as_ref() == Some(&value),
+          EvalContext::PowerSupply(_) => false,
+          EvalContext::WidestPossible => {
+            state
+              .cpus
+              .iter()
+              .any(|cpu| cpu.scaling_driver.as_ref() == Some(&value))
+          },
+        };
+
+        Boolean(matches)
+      },
+      IsCoreType { value } => {
+        let value = eval!(value);
+        let core_type: cpu::CoreType = value.try_into_string()?.parse()?;
+
+        let matches = match state.context {
+          EvalContext::Cpu(cpu) => cpu.core_type == core_type,
+          EvalContext::PowerSupply(_) => false,
+          EvalContext::WidestPossible => {
+            state.cpus.iter().any(|cpu| cpu.core_type == core_type)
+          },
+        };
+
+        Boolean(matches)
+      },
       IsPlatformProfileAvailable { value } => {
         let value = eval!(value);
         let value = value.try_into_string()?;
@@ -1532,6 +2480,24 @@ impl Expression {
       },
       FrequencyAvailable => Boolean(state.frequency_available),
       TurboAvailable => Boolean(state.turbo_available),
+      TurboEnabled => Boolean(try_ok!(state.turbo_enabled)),
+      SmtAvailable => Boolean(state.smt_available),
+      HwpDynamicBoostAvailable => Boolean(state.hwp_dynamic_boost_available),
+      ChargeBehaviourAvailable => {
+        let available = |power_supply: &power_supply::PowerSupply| {
+          !power_supply.available_charge_behaviours.is_empty()
+        };
+
+        Boolean(match state.context {
+          EvalContext::PowerSupply(power_supply) => available(power_supply),
+          EvalContext::Cpu(_) | EvalContext::WidestPossible => {
+            state
+              .power_supplies
+              .iter()
+              .any(|power_supply| available(power_supply))
+          },
+        })
+      },
 
       CpuUsage => {
         bail!(
@@ -1563,10 +2529,25 @@ impl Expression {
         )
       },
       CpuUsageVolatility => Number(try_ok!(state.cpu_usage_volatility)),
+      CpuUsageAverage => Number(try_ok!(state.cpu_usage_average)),
       CpuTemperature => Number(try_ok!(state.cpu_temperature)),
+      CpuTemperatureMaximum => Number(try_ok!(state.cpu_temperature_maximum)),
+      CpuTemperatureMinimum => Number(try_ok!(state.cpu_temperature_minimum)),
+      FanRpmMaximum => Number(try_ok!(state.fan_rpm_maximum)),
+      GpuTemperature => Number(try_ok!(state.gpu_temperature)),
+      GpuPowerWatts => Number(try_ok!(state.gpu_power_watts)),
+      DiskTemperatureMaximum => Number(try_ok!(state.disk_temperature_maximum)),
       CpuTemperatureVolatility => {
         Number(try_ok!(state.cpu_temperature_volatility))
       },
+      CpuUsageTrend => {
+        Number(try_ok!(cpu_log_trend(state.cpu_log, |log| Some(log.usage))))
+      },
+      CpuTemperatureRising => {
+        Boolean(
+          try_ok!(cpu_log_trend(state.cpu_log, |log| log.temperature)) > 0.0,
+        )
+      },
       CpuIdleSeconds => Number(state.cpu_idle_seconds),
       CpuFrequencyMaximum => Number(try_ok!(state.cpu_frequency_maximum)),
       CpuFrequencyMinimum => Number(try_ok!(state.cpu_frequency_minimum)),
@@ -1581,7 +2562,97 @@ impl Expression {
         Number(try_ok!(max))
       },
 
-      CpuCoreCount => Number(state.cpus.len() as f64),
+      CpuCoreCount | CpuCount => Number(state.cpus.len() as f64),
+      CpuOnlineCount => {
+        Number(state.cpus.iter().filter(|cpu| cpu.online).count() as f64)
+      },
+
+      CpuNumber => {
+        let EvalContext::Cpu(cpu) = state.context else {
+          return Ok(None);
+        };
+
+        Number(cpu.number as f64)
+      },
+
+      CpuGovernor => {
+        let governor = match state.context {
+          EvalContext::Cpu(cpu) => cpu.governor.clone(),
+          EvalContext::PowerSupply(_) => None,
+          // Only defined when every CPU agrees.
+          EvalContext::WidestPossible => {
+            let mut governors =
+              state.cpus.iter().filter_map(|cpu| cpu.governor.as_ref());
+
+            let first = governors.next();
+            first
+              .filter(|&first| governors.all(|governor| governor == first))
+              .cloned()
+          },
+        };
+
+        String(try_ok!(governor))
+      },
+
+      CpusOfCoreType { value } => {
+        let value = eval!(value);
+        let core_type: cpu::CoreType = value.try_into_string()?.parse()?;
+
+        let mut numbers: Vec<u32> = state
+          .cpus
+          .iter()
+          .filter(|cpu| cpu.core_type == core_type)
+          .map(|cpu| cpu.number)
+          .collect();
+        numbers.sort_unstable();
+
+        List(
+          numbers
+            .into_iter()
+            .map(|number| Number(number as f64))
+            .collect(),
+        )
+      },
+
+      Range { range } => {
+        let from = eval!(range.from).try_into_number()?;
+        let to = eval!(range.to).try_into_number()?;
+
+        for bound in [from, to] {
+          if bound.fract() != 0.0 || bound < 0.0 {
+            bail!("range bounds must be whole numbers from 0, got {bound}");
+          }
+        }
+
+        if from > to {
+          bail!("range from ({from}) must not be more than to ({to})");
+        }
+
+        if to - from >= self::Range::MAXIMUM_LEN as f64 {
+          bail!(
+            "range from {from} to {to} is longer than {maximum} items",
+            maximum = self::Range::MAXIMUM_LEN,
+          );
+        }
+
+        List(
+          (from as u32..=to as u32)
+            .map(|number| Number(number as f64))
+            .collect(),
+        )
+      },
+
+      Except { except } => {
+        let remove = eval!(except.remove).try_into_list()?;
+
+        List(
+          eval!(except.from)
+            .try_into_list()?
+            .into_iter()
+            .filter(|item| !remove.contains(item))
+            .collect(),
+        )
+      },
 
       LoadAverageSince { duration } => {
         let duration = eval!(duration).try_into_string()?;
@@ -1604,23 +2675,72 @@ impl Expression {
         )
       },
 
-      LidClosed => Boolean(state.lid_closed),
+      AverageOver { value, seconds } => {
+        let samples = try_ok!(cpu_log_window(
+          value,
+          eval!(seconds),
+          state,
+          "average-over"
+        )?);
+        Number(samples.iter().sum::<f64>() / samples.len() as f64)
+      },
+      MaximumOver { value, seconds } => {
+        let samples =
+          try_ok!(cpu_log_window(value, eval!(seconds), state, "max-over")?);
+        Number(samples.into_iter().fold(f64::NEG_INFINITY, f64::max))
+      },
+      MinimumOver { value, seconds } => {
+        let samples =
+          try_ok!(cpu_log_window(value, eval!(seconds), state, "min-over")?);
+        Number(samples.into_iter().fold(f64::INFINITY, f64::min))
+      },
+
+      LidClosed => Boolean(try_ok!(state.lid_closed)),
       VirtualMachine => Boolean(state.virtual_machine),
 
       HourOfDay => {
-        let ts = jiff::Timestamp::now()
-          .in_tz("local")
-          .context("failed to get local timezone for `$hour-of-day`")?;
-        Number(ts.hour() as f64)
+        let time = state.local_time;
+
+        Number(
+          f64::from(time.hour())
+            + f64::from(time.minute()) / 60.0
+            + f64::from(time.second()) / 3600.0,
+        )
+      },
+      Weekend => {
+        Boolean(matches!(
+          state.local_time.weekday(),
+          jiff::civil::Weekday::Saturday | jiff::civil::Weekday::Sunday
+        ))
       },
 
       PowerSupplyCharge => Number(try_ok!(state.power_supply_charge)),
       PowerSupplyDischargeRate => {
         Number(try_ok!(state.power_supply_discharge_rate))
       },
+      BatteryMinutesRemaining => {
+        Number(try_ok!(state.battery_minutes_remaining))
+      },
+      PowerDrawWatts => Number(try_ok!(state.power_draw_watts)),
+      AcPowerLimitWatts => Number(try_ok!(state.ac_power_limit_watts)),
+
+      MemoryUsage => Number(try_ok!(state.memory_usage)),
+
+      CpuPressure => Number(try_ok!(state.cpu_pressure).avg10),
+      CpuPressureAvg60 => Number(try_ok!(state.cpu_pressure).avg60),
+      CpuPressureAvg300 => Number(try_ok!(state.cpu_pressure).avg300),
 
       BatteryCycles => Number(try_ok!(state.battery_cycles)),
       BatteryHealth => Number(try_ok!(state.battery_health)),
+      BatteryTemperature => {
+        // The hottest battery is the one that needs protecting.
+        Number(try_ok!(
+          find_batteries(state.power_supplies)
+            .iter()
+            .filter_map(|battery| battery.temperature)
+            .max_by(f64::total_cmp)
+        ))
+      },
 
       BatteryCyclesFor { name } => {
         let battery = find_battery(state.power_supplies, name);
@@ -1633,11 +2753,24 @@ impl Expression {
       },
 
       Discharging => Boolean(state.discharging),
+      OnAc => Boolean(state.on_ac),
+      Desktop => Boolean(state.desktop),
 
       PowerProfilePreference => {
         String(state.power_profile_preference.as_str().to_owned())
       },
 
+      PlatformProfile => String(try_ok!(state.platform_profile).to_owned()),
+
+      SysfsNumber { path } => {
+        let value = try_ok!(state.sysfs_reads.read(path)?);
+
+        Number(value.parse().with_context(|| {
+          format!("failed to parse '{value}' read from '{path}' as a number")
+        })?)
+      },
+      SysfsString { path } => String(try_ok!(state.sysfs_reads.read(path)?)),
+
       literal @ (Boolean(_) | Number(_) | String(_)) => literal.clone(),
 
       List(items) => {
@@ -1669,6 +2802,20 @@ impl Expression {
       Divide { a, b } => {
         Number(eval!(a).try_into_number()? / eval!(b).try_into_number()?)
       },
+      Modulo { a, b } => {
+        let a = eval!(a).try_into_number()?;
+        let b = eval!(b).try_into_number()?;
+
+        if b == 0.0 {
+          bail!("cannot take {a} modulo 0");
+        }
+
+        Number(a.rem_euclid(b))
+      },
+
+      Round { round } => Number(eval!(round).try_into_number()?.round()),
+      Floor { floor } => Number(eval!(floor).try_into_number()?.floor()),
+      Ceil { ceil } => Number(eval!(ceil).try_into_number()?.ceil()),
 
       LessThan { a, b } => {
         Boolean(eval!(a).try_into_number()? < eval!(b).try_into_number()?)
@@ -1677,6 +2824,25 @@ impl Expression {
         Boolean(eval!(a).try_into_number()? > eval!(b).try_into_number()?)
       },
 
+      Clamp {
+        value,
+        minimum,
+        maximum,
+      } => {
+        let value = eval!(value).try_into_number()?;
+        let minimum = eval!(minimum).try_into_number()?;
+        let maximum = eval!(maximum).try_into_number()?;
+
+        if minimum.is_nan() || maximum.is_nan() || minimum > maximum {
+          bail!(
+            "clamp minimum ({minimum}) must not be more than maximum \
+             ({maximum})"
+          );
+        }
+
+        Number(value.clamp(minimum, maximum))
+      },
+
       Minimum { numbers } => {
         let mut evaled = Vec::with_capacity(numbers.len());
 
@@ -1710,6 +2876,13 @@ impl Expression {
 
       IsUnset { a } => Boolean(a.eval(state)?.is_none()),
 
+      Coalesce { value, default } => {
+        match value.eval(state)? {
+          Some(value) => value,
+          None => eval!(default),
+        }
+      },
+
       IfElse {
         condition,
         consequence,
@@ -1761,19 +2934,90 @@ impl Expression {
       Not { not } => Boolean(!eval!(not).try_into_boolean()?),
 
       Equal { a, b, leeway } => {
-        let a = eval!(a).try_into_number()?;
-        let b = eval!(b).try_into_number()?;
-        let leeway = eval!(leeway).try_into_number()?;
+        Boolean(equal(eval!(a), eval!(b), eval!(leeway))?)
+      },
+      NotEqual { a, b, leeway } => {
+        Boolean(!equal(eval!(a), eval!(b), eval!(leeway))?)
+      },
+
+      Between { between } => {
+        let value = eval!(between.value).try_into_number()?;
+        let minimum = eval!(between.minimum).try_into_number()?;
+        let maximum = eval!(between.maximum).try_into_number()?;
+        let inclusive = eval!(between.inclusive).try_into_boolean()?;
+
+        if minimum.is_nan() || maximum.is_nan() || minimum > maximum {
+          bail!(
+            "between minimum ({minimum}) must not be more than maximum \
+             ({maximum})"
+          );
+        }
+
+        Boolean(if inclusive {
+          minimum <= value && value <= maximum
+        } else {
+          minimum < value && value < maximum
+        })
+      },
+
+      StringEqual { value, is } => {
+        let value = eval!(value).try_into_string()?;
+        let is = eval!(is).try_into_string()?;
 
-        let minimum = a - leeway;
-        let maximum = a + leeway;
+        Boolean(value == is)
+      },
+
+      In { value, list } => {
+        let value = eval!(value).try_into_string()?;
+        let list = eval!(list)
+          .try_into_list()?
+          .into_iter()
+          .map(Expression::try_into_string)
+          .collect::<anyhow::Result<Vec<_>>>()?;
 
-        Boolean(minimum < b && b < maximum)
+        Boolean(list.contains(&value))
       },
     }))
   }
 }
 
+/// The operands of `between`, a range check that reads better than an `all`
+/// of `is-more-than` and `is-less-than`.
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Between {
+  pub value:     Expression,
+  pub minimum:   Expression,
+  pub maximum:   Expression,
+  /// Whether the bounds themselves are in the range.
+  #[serde(default = "literal_true", skip_serializing_if = "literal_is_true")]
+  pub inclusive: Expression,
+}
+
+/// The operands of `range`, the whole numbers from `from` to `to`, both
+/// included. Meant for `cpu.for`, such as `{ range = { from = 8, to = 15 } }`.
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Range {
+  pub from: Expression,
+  pub to:   Expression,
+}
+
+impl Range {
+  /// Far more than there are CPUs, to catch a typo before it allocates a
+  /// huge list.
+  const MAXIMUM_LEN: u32 = 65536;
+}
+
+/// The operands of `except`, the items of the `from` list that aren't in the
+/// `remove` list.
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Except {
+  pub from:   Expression,
+  pub remove: Expression,
+}
+
 fn literal_true() -> Expression {
   Expression::Boolean(true)
 }
@@ -1782,9 +3026,20 @@ fn literal_is_true(expression: &Expression) -> bool {
   expression == &literal_true()
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+fn literal_zero() -> Box<Expression> {
+  Box::new(Expression::Number(0.0))
+}
+
+fn literal_is_zero(expression: &Expression) -> bool {
+  expression == &Expression::Number(0.0)
+}
+
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Rule {
+  /// Used in logs and D-Bus. Optional, rules are identified by their
+  /// priority.
+  #[serde(default, skip_serializing_if = "String::is_empty")]
   pub name:     String,
   pub priority: u16,
 
@@ -1795,6 +3050,26 @@ pub struct Rule {
   )]
   pub condition: Expression,
 
+  /// How long the condition has to stay true before the rule is applied, and
+  /// false before it is released again.
+  #[serde(
+    default,
+    with = "humantime_option",
+    skip_serializing_if = "Option::is_none"
+  )]
+  #[schemars(with = "Option<String>")]
+  pub hold_for: Option<Duration>,
+
+  /// How long the settings of the rule are kept as they were applied,
+  /// instead of being evaluated again every poll, while it stays applied.
+  #[serde(
+    default,
+    with = "humantime_option",
+    skip_serializing_if = "Option::is_none"
+  )]
+  #[schemars(with = "Option<String>")]
+  pub apply_cooldown: Option<Duration>,
+
   #[serde(default, skip_serializing_if = "is_default")]
   pub cpu:    CpusDelta,
   #[serde(default, skip_serializing_if = "is_default")]
@@ -1816,36 +3091,460 @@ pub struct Rule {
 impl Default for Rule {
   fn default() -> Self {
     Self {
-      name:      String::default(),
-      priority:  u16::default(),
-      condition: literal_true(),
-      cpu:       CpusDelta::default(),
-      uncore:    UncoresDelta::default(),
-      vm:        VmDelta::default(),
-      disk:      DisksDelta::default(),
-      usb:       UsbsDelta::default(),
-      audio:     AudioDelta::default(),
-      gpu:       GpusDelta::default(),
-      power:     PowersDelta::default(),
+      name:           String::default(),
+      priority:       u16::default(),
+      condition:      literal_true(),
+      hold_for:       None,
+      apply_cooldown: None,
+      cpu:            CpusDelta::default(),
+      uncore:         UncoresDelta::default(),
+      vm:             VmDelta::default(),
+      disk:           DisksDelta::default(),
+      usb:            UsbsDelta::default(),
+      audio:          AudioDelta::default(),
+      gpu:            GpusDelta::default(),
+      power:          PowersDelta::default(),
+    }
+  }
+}
+
+impl Rule {
+  /// The name of the rule, or its priority if it doesn't have one.
+  pub fn label(&self) -> String {
+    if self.name.is_empty() {
+      format!("priority {priority}", priority = self.priority)
+    } else {
+      self.name.clone()
+    }
+  }
+
+  /// The action sections the rule sets, such as `cpu` and `power`.
+  pub fn sections(&self) -> Vec<&'static str> {
+    [
+      ("cpu", is_default(&self.cpu)),
+      ("uncore", is_default(&self.uncore)),
+      ("vm", is_default(&self.vm)),
+      ("disk", is_default(&self.disk)),
+      ("usb", is_default(&self.usb)),
+      ("audio", is_default(&self.audio)),
+      ("gpu", is_default(&self.gpu)),
+      ("power", is_default(&self.power)),
+    ]
+    .into_iter()
+    .filter(|&(_, is_default)| !is_default)
+    .map(|(section, _)| section)
+    .collect()
+  }
+}
+
+impl fmt::Display for Rule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.name.is_empty() {
+      write!(f, "with priority {priority}", priority = self.priority)
+    } else {
+      write!(
+        f,
+        "'{name}' (priority {priority})",
+        name = self.name,
+        priority = self.priority,
+      )
+    }
+  }
+}
+
+/// Actions that are applied outside of the rule evaluation, such as when the
+/// daemon stops.
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, PartialEq,
+)]
+#[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
+pub struct Actions {
+  #[serde(skip_serializing_if = "is_default")]
+  pub cpu:   CpusDelta,
+  #[serde(skip_serializing_if = "is_default")]
+  pub power: PowersDelta,
+}
+
+impl Actions {
+  /// Wraps the actions in an unconditional rule so they can be evaluated like
+  /// any other rule.
+  pub fn as_rule(&self, name: &str) -> Rule {
+    Rule {
+      name: name.to_owned(),
+      cpu: self.cpu.clone(),
+      power: self.power.clone(),
+      ..Rule::default()
     }
   }
 }
 
-#[derive(Serialize, Default, Debug, Clone, PartialEq)]
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "metrics", derive(Deserialize))]
 #[serde(default, rename_all = "kebab-case")]
 #[cfg_attr(not(feature = "metrics"), non_exhaustive)]
 pub struct DaemonConfig {
+  /// Other config files to load before this one, relative to its directory.
+  /// Only read while loading, see [`ConfigFiles`].
+  #[serde(skip_serializing)]
+  pub include: Vec<PathBuf>,
+
+  /// Every file the config was loaded from, including the included ones.
+  #[serde(skip)]
+  #[schemars(skip)]
+  pub files: Vec<PathBuf>,
+
   #[cfg(feature = "metrics")]
   #[serde(skip_serializing_if = "Option::is_none")]
   pub metrics: Option<MetricsConfig>,
 
+  /// The baseline every poll starts from. Rules override it where they set
+  /// something, so it doesn't need an always-true lowest priority rule.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub default: Option<Actions>,
+
+  /// Actions to apply when the daemon receives SIGINT or SIGTERM.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub on_stop: Option<Actions>,
+
+  /// Whether to restore the CPU and power supply settings from before the
+  /// daemon started when it stops. `[on-stop]` actions take precedence.
+  pub restore_on_exit: bool,
+
+  /// Polling interval before it is adjusted for load, battery and idle time,
+  /// unless the active profile overrides it.
+  #[serde(
+    default,
+    with = "humantime_option",
+    skip_serializing_if = "Option::is_none"
+  )]
+  #[schemars(with = "Option<String>")]
+  pub poll_interval_base:    Option<Duration>,
+  /// Shortest polling interval, unless the active profile overrides it.
+  #[serde(
+    default,
+    with = "humantime_option",
+    skip_serializing_if = "Option::is_none"
+  )]
+  #[schemars(with = "Option<String>")]
+  pub poll_interval_minimum: Option<Duration>,
+  /// Longest polling interval, unless the active profile overrides it.
+  #[serde(
+    default,
+    with = "humantime_option",
+    skip_serializing_if = "Option::is_none"
+  )]
+  #[schemars(with = "Option<String>")]
+  pub poll_interval_maximum: Option<Duration>,
+
+  /// Settings that only apply while a power profile is active.
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  pub profiles: HashMap<PowerProfile, ProfileConfig>,
+
+  /// Minimum time between two writes to the same control file.
+  #[serde(
+    default,
+    with = "humantime_option",
+    skip_serializing_if = "Option::is_none"
+  )]
+  #[schemars(with = "Option<String>")]
+  pub min_write_interval: Option<Duration>,
+
+  /// Whether the daemon reloads the config when its file changes, like on
+  /// SIGHUP.
+  pub watch_config: bool,
+
+  /// Where to write a JSON snapshot of the variables, the polling interval
+  /// and the applied rules after every poll. Nothing is written when unset.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub stats_file: Option<PathBuf>,
+
+  /// Whether a CPU control the system doesn't offer, such as turbo on a
+  /// system without a boost control, is logged and skipped instead of failing
+  /// the whole poll.
+  pub ignore_unsupported: bool,
+
+  /// The weight of the newest CPU usage sample in `%cpu-usage-average`, from
+  /// just above 0 for a slow, smooth average to 1 for the raw latest sample.
+  pub cpu_usage_smoothing: f64,
+
+  /// How far back `$cpu-usage-volatility` and
+  /// `$cpu-temperature-volatility` look.
+  #[serde(with = "humantime_duration")]
+  #[schemars(with = "String")]
+  pub volatility_window: Duration,
+  /// How long the CPU usage has to stay low for the system to count as idle.
+  #[serde(with = "humantime_duration")]
+  #[schemars(with = "String")]
+  pub idle_threshold:    Duration,
+
+  /// The charge percentage below which `power.charge-behaviour =
+  /// "force-discharge"` is refused, so a battery isn't drained too deep.
+  pub force_discharge_floor: u8,
+
+  /// The core temperature in °C above which Watt overrides every rule with
+  /// the most conservative CPU settings. Clamped to
+  /// [`Self::THERMAL_EMERGENCY_CELSIUS_RANGE`] when loading.
+  pub thermal_emergency_celsius: f64,
+
+  /// The hwmon sensors to read CPU temperatures from. Empty to detect them.
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub temperature_sensors: Vec<TemperatureSensor>,
+
+  /// Desktop notifications to send when their conditions become true.
+  #[serde(rename = "notification", skip_serializing_if = "Vec::is_empty")]
+  pub notifications: Vec<Notification>,
+
   #[serde(rename = "rule")]
   pub rules: Vec<Rule>,
 }
 
-#[cfg(feature = "metrics")]
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+impl Default for DaemonConfig {
+  fn default() -> Self {
+    Self {
+      include:                             Vec::new(),
+      files:                               Vec::new(),
+      #[cfg(feature = "metrics")]
+      metrics:                             None,
+      default:                             None,
+      on_stop:                             None,
+      restore_on_exit:                     true,
+      poll_interval_base:                  None,
+      poll_interval_minimum:               None,
+      poll_interval_maximum:               None,
+      profiles:                            HashMap::new(),
+      min_write_interval:                  None,
+      watch_config:                        false,
+      stats_file:                          None,
+      ignore_unsupported:                  true,
+      cpu_usage_smoothing:                 0.3,
+      volatility_window:                   Self::DEFAULT_WINDOW,
+      idle_threshold:                      Self::DEFAULT_WINDOW,
+      force_discharge_floor:
+        power_supply::DEFAULT_FORCE_DISCHARGE_FLOOR,
+      thermal_emergency_celsius:
+        Self::DEFAULT_THERMAL_EMERGENCY_CELSIUS,
+      temperature_sensors:                 Vec::new(),
+      notifications:                       Vec::new(),
+      rules:                               Vec::new(),
+    }
+  }
+}
+
+/// A file read by `sysfs-number` and `sysfs-string`. Only files under `/sys`
+/// and `/proc` can be read, excluding the directories of processes in
+/// `/proc`, which lead to the rest of the filesystem through links such as
+/// `/proc/self/root`.
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct SysfsPath(PathBuf);
+
+impl TryFrom<String> for SysfsPath {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    use std::path::Component;
+
+    let path = PathBuf::from(&value);
+    let mut components = path.components();
+
+    let allowed = components.next() == Some(Component::RootDir)
+      && match components.next() {
+        Some(Component::Normal(top)) if top == "sys" => true,
+        Some(Component::Normal(top)) if top == "proc" => {
+          components.clone().next().is_some_and(|component| {
+            let Component::Normal(name) = component else {
+              return false;
+            };
+            let name = name.to_string_lossy();
+
+            name != "self"
+              && name != "thread-self"
+              && !name.chars().all(|char| char.is_ascii_digit())
+          })
+        },
+        _ => false,
+      }
+      && components.all(|component| matches!(component, Component::Normal(_)));
+
+    if !allowed {
+      return Err(format!(
+        "invalid sysfs path '{value}', expected an absolute path under /sys \
+         or /proc, outside of process directories"
+      ));
+    }
+
+    Ok(Self(path))
+  }
+}
+
+impl fmt::Display for SysfsPath {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{path}", path = self.0.display())
+  }
+}
+
+impl From<SysfsPath> for String {
+  fn from(path: SysfsPath) -> Self {
+    path.to_string()
+  }
+}
+
+/// A hwmon temperature sensor, written as `"name"` for every sensor of the
+/// hwmon device called `name` or `"name:label"` for the sensors whose
+/// `tempN_label` contains `label`, such as `"k10temp:Tctl"`.
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct TemperatureSensor {
+  pub hwmon: String,
+  pub label: Option<String>,
+}
+
+impl TemperatureSensor {
+  /// Whether a sensor of the hwmon device `hwmon` with the given label, if it
+  /// has one, is selected.
+  pub fn matches(&self, hwmon: &str, label: Option<&str>) -> bool {
+    self.hwmon == hwmon
+      && match &self.label {
+        Some(wanted) => label.is_some_and(|label| label.contains(wanted)),
+        None => true,
+      }
+  }
+}
+
+impl TryFrom<String> for TemperatureSensor {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    let (hwmon, label) = match value.split_once(':') {
+      Some((hwmon, label)) => (hwmon, Some(label)),
+      None => (value.as_str(), None),
+    };
+
+    if hwmon.is_empty() || label.is_some_and(str::is_empty) {
+      return Err(format!(
+        "invalid temperature sensor '{value}', expected 'name' or 'name:label'"
+      ));
+    }
+
+    Ok(Self {
+      hwmon: hwmon.to_owned(),
+      label: label.map(ToOwned::to_owned),
+    })
+  }
+}
+
+impl fmt::Display for TemperatureSensor {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.label {
+      Some(label) => write!(f, "{hwmon}:{label}", hwmon = self.hwmon),
+      None => write!(f, "{hwmon}", hwmon = self.hwmon),
+    }
+  }
+}
+
+impl From<TemperatureSensor> for String {
+  fn from(sensor: TemperatureSensor) -> Self {
+    sensor.to_string()
+  }
+}
+
+/// A desktop notification, sent when its condition becomes true.
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Notification {
+  pub summary: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  pub body:    String,
+
+  #[serde(rename = "if")]
+  pub condition: Expression,
+
+  /// Minimum time between two of these notifications, so that a condition
+  /// flapping around a threshold doesn't send one every poll.
+  #[serde(
+    default,
+    with = "humantime_option",
+    skip_serializing_if = "Option::is_none"
+  )]
+  #[schemars(with = "Option<String>")]
+  pub cooldown: Option<Duration>,
+}
+
+impl Notification {
+  pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+
+  pub fn cooldown(&self) -> Duration {
+    self.cooldown.unwrap_or(Self::DEFAULT_COOLDOWN)
+  }
+}
+
+/// Settings for a single power profile.
+#[derive(
+  Serialize, JsonSchema, Deserialize, Default, Debug, Clone, Copy, PartialEq,
+)]
+#[serde(deny_unknown_fields, default, rename_all = "kebab-case")]
+pub struct ProfileConfig {
+  /// Polling interval before it is adjusted for load, battery and idle time.
+  #[serde(with = "humantime_option", skip_serializing_if = "Option::is_none")]
+  #[schemars(with = "Option<String>")]
+  pub poll_interval_base:    Option<Duration>,
+  /// Shortest polling interval.
+  #[serde(with = "humantime_option", skip_serializing_if = "Option::is_none")]
+  #[schemars(with = "Option<String>")]
+  pub poll_interval_minimum: Option<Duration>,
+  /// Longest polling interval.
+  #[serde(with = "humantime_option", skip_serializing_if = "Option::is_none")]
+  #[schemars(with = "Option<String>")]
+  pub poll_interval_maximum: Option<Duration>,
+}
+
+impl ProfileConfig {
+  pub const DEFAULT_POLL_INTERVAL_BASE: Duration = Duration::from_secs(5);
+  pub const DEFAULT_POLL_INTERVAL_MAXIMUM: Duration = Duration::from_secs(30);
+  pub const DEFAULT_POLL_INTERVAL_MINIMUM: Duration = Duration::from_secs(1);
+
+  pub fn poll_interval_base(&self) -> Duration {
+    self
+      .poll_interval_base
+      .unwrap_or(Self::DEFAULT_POLL_INTERVAL_BASE)
+  }
+
+  pub fn poll_interval_minimum(&self) -> Duration {
+    self
+      .poll_interval_minimum
+      .unwrap_or(Self::DEFAULT_POLL_INTERVAL_MINIMUM)
+  }
+
+  pub fn poll_interval_maximum(&self) -> Duration {
+    self
+      .poll_interval_maximum
+      .unwrap_or(Self::DEFAULT_POLL_INTERVAL_MAXIMUM)
+  }
+
+  /// Checks that the base polling interval is within the minimum and maximum.
+  pub fn validate(&self) -> anyhow::Result<()> {
+    if self.poll_interval_minimum() > self.poll_interval_maximum() {
+      bail!(
+        "`poll-interval-minimum` must not be longer than \
+         `poll-interval-maximum`"
+      );
+    }
+
+    if !(self.poll_interval_minimum()..=self.poll_interval_maximum())
+      .contains(&self.poll_interval_base())
+    {
+      bail!(
+        "`poll-interval-base` must be between `poll-interval-minimum` and \
+         `poll-interval-maximum`"
+      );
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Serialize, JsonSchema, Deserialize, Debug, Clone, PartialEq)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct MetricsConfig {
   pub listen_addr: IpAddr,
@@ -1863,6 +3562,49 @@ impl<'de> Deserialize<'de> for DaemonConfig {
     struct RawDaemonConfig {
       metrics: Option<serde::de::IgnoredAny>,
 
+      default: Option<Actions>,
+
+      on_stop: Option<Actions>,
+
+      restore_on_exit: Option<bool>,
+
+      #[serde(with = "humantime_option")]
+      poll_interval_base: Option<Duration>,
+
+      #[serde(with = "humantime_option")]
+      poll_interval_minimum: Option<Duration>,
+
+      #[serde(with = "humantime_option")]
+      poll_interval_maximum: Option<Duration>,
+
+      profiles: HashMap<PowerProfile, ProfileConfig>,
+
+      #[serde(with = "humantime_option")]
+      min_write_interval: Option<Duration>,
+
+      watch_config: bool,
+
+      stats_file: Option<PathBuf>,
+
+      ignore_unsupported: Option<bool>,
+
+      cpu_usage_smoothing: Option<f64>,
+
+      #[serde(with = "humantime_option")]
+      volatility_window: Option<Duration>,
+
+      #[serde(with = "humantime_option")]
+      idle_threshold: Option<Duration>,
+
+      force_discharge_floor: Option<u8>,
+
+      thermal_emergency_celsius: Option<f64>,
+
+      temperature_sensors: Vec<TemperatureSensor>,
+
+      #[serde(rename = "notification")]
+      notifications: Vec<Notification>,
+
       #[serde(rename = "rule")]
       rules: Vec<Rule>,
     }
@@ -1875,32 +3617,144 @@ impl<'de> Deserialize<'de> for DaemonConfig {
       ));
     }
 
-    Ok(Self { rules: raw.rules })
+    Ok(Self {
+      include:                   Vec::new(),
+      files:                     Vec::new(),
+      default:                   raw.default,
+      on_stop:                   raw.on_stop,
+      restore_on_exit:           raw.restore_on_exit.unwrap_or(true),
+      poll_interval_base:        raw.poll_interval_base,
+      poll_interval_minimum:     raw.poll_interval_minimum,
+      poll_interval_maximum:     raw.poll_interval_maximum,
+      profiles:                  raw.profiles,
+      min_write_interval:        raw.min_write_interval,
+      watch_config:              raw.watch_config,
+      stats_file:                raw.stats_file,
+      ignore_unsupported:        raw.ignore_unsupported.unwrap_or(true),
+      cpu_usage_smoothing:       raw.cpu_usage_smoothing.unwrap_or(0.3),
+      volatility_window:         raw
+        .volatility_window
+        .unwrap_or(DaemonConfig::DEFAULT_WINDOW),
+      idle_threshold:            raw
+        .idle_threshold
+        .unwrap_or(DaemonConfig::DEFAULT_WINDOW),
+      force_discharge_floor:     raw
+        .force_discharge_floor
+        .unwrap_or(power_supply::DEFAULT_FORCE_DISCHARGE_FLOOR),
+      thermal_emergency_celsius: raw
+        .thermal_emergency_celsius
+        .unwrap_or(DaemonConfig::DEFAULT_THERMAL_EMERGENCY_CELSIUS),
+      temperature_sensors:       raw.temperature_sensors,
+      notifications:             raw.notifications,
+      rules:                     raw.rules,
+    })
   }
 }
 
 impl DaemonConfig {
   const DEFAULT: &str = include_str!("config.toml");
+  const DEFAULT_WINDOW: Duration = Duration::from_secs(5 * 60);
+  const MINIMUM_LOG_CAPACITY: usize = 100;
+
+  pub const DEFAULT_THERMAL_EMERGENCY_CELSIUS: f64 = 95.0;
+  /// How far `thermal-emergency-celsius` can be moved, so the protection can
+  /// neither trigger all the time nor be configured away.
+  pub const THERMAL_EMERGENCY_CELSIUS_RANGE: RangeInclusive<f64> = 70.0..=105.0;
+
+  /// When the config at `path` was last modified. For a directory, the
+  /// latest modification of the directory or any file in it, so that editing,
+  /// adding or removing a file counts. `None` if it can't be read.
+  pub fn last_modified(path: &Path) -> Option<SystemTime> {
+    let metadata = fs::metadata(path).ok()?;
+    let mut modified = metadata.modified().ok()?;
+
+    if metadata.is_dir() {
+      for entry in fs::read_dir(path).ok()?.flatten() {
+        if let Ok(entry_modified) =
+          fs::metadata(entry.path()).and_then(|metadata| metadata.modified())
+        {
+          modified = modified.max(entry_modified);
+        }
+      }
+    }
 
+    Some(modified)
+  }
+
+  /// The latest [`Self::last_modified`] of `path` and every file this config
+  /// was loaded from, so that editing an included file counts too.
+  pub fn files_modified(&self, path: &Path) -> Option<SystemTime> {
+    iter::once(path)
+      .chain(self.files.iter().map(PathBuf::as_path))
+      .filter_map(Self::last_modified)
+      .max()
+  }
+
+  /// A JSON Schema of the config format, including the expression syntax.
+  pub fn schema() -> schemars::Schema {
+    schemars::schema_for!(DaemonConfig)
+  }
+
+  /// How many entries the CPU and power supply logs keep, enough to cover the
+  /// longest window at the shortest polling interval of any profile.
+  pub fn log_capacity(&self) -> usize {
+    let shortest_poll_interval = iter::once(None)
+      .chain(self.profiles.keys().copied().map(Some))
+      .map(|profile| self.profile_config(profile).poll_interval_minimum())
+      .min()
+      .unwrap_or(ProfileConfig::DEFAULT_POLL_INTERVAL_MINIMUM);
+
+    let longest_window = self.volatility_window.max(self.idle_threshold);
+
+    let entries = (longest_window.as_secs_f64()
+      / shortest_poll_interval.as_secs_f64().max(0.001))
+    .ceil() as usize
+      + 1;
+
+    entries.max(Self::MINIMUM_LOG_CAPACITY)
+  }
+
+  /// The settings of `profile`, falling back to the top level polling
+  /// intervals for the ones it doesn't set. The top level settings alone if
+  /// `profile` is `None`.
+  pub fn profile_config(&self, profile: Option<PowerProfile>) -> ProfileConfig {
+    let profile_config = profile
+      .and_then(|profile| self.profiles.get(&profile))
+      .copied()
+      .unwrap_or_default();
+
+    ProfileConfig {
+      poll_interval_base:    profile_config
+        .poll_interval_base
+        .or(self.poll_interval_base),
+      poll_interval_minimum: profile_config
+        .poll_interval_minimum
+        .or(self.poll_interval_minimum),
+      poll_interval_maximum: profile_config
+        .poll_interval_maximum
+        .or(self.poll_interval_maximum),
+    }
+  }
+
+  /// Loads the config from `path`, which is either a file or a directory of
+  /// drop-in `*.toml` files, or the builtin default config if it is `None`.
   pub fn load_from(path: Option<&Path>) -> anyhow::Result<Self> {
-    let contents = if let Some(path) = path {
-      log::info!("loading config from '{path}'", path = path.display());
+    let mut config: Self = match path {
+      Some(path) if path.is_dir() => Self::load_directory(path)?,
 
-      &fs::read_to_string(path).with_context(|| {
-        format!("failed to read config from '{path}'", path = path.display())
-      })?
-    } else {
-      log::info!("loading default config");
+      Some(path) => {
+        let mut files = ConfigFiles::default();
+        files.load(path)?;
+        files.into_config(path)?
+      },
 
-      Self::DEFAULT
-    };
+      None => {
+        log::info!("loading default config");
 
-    let mut config: Self = toml::from_str(contents).with_context(|| {
-      path.map_or(
-        "failed to parse builtin default config, this is a bug".to_owned(),
-        |p| format!("failed to parse file at '{path}'", path = p.display()),
-      )
-    })?;
+        toml::from_str(Self::DEFAULT)
+          .context("failed to parse builtin default config, this is a bug")?
+      },
+    };
 
     {
       let mut priorities = Vec::with_capacity(config.rules.len());
@@ -1916,6 +3770,83 @@ impl DaemonConfig {
       }
     }
 
+    // Notifications are told apart by their summary, also across reloads.
+    {
+      let mut summaries = HashSet::with_capacity(config.notifications.len());
+
+      for notification in &config.notifications {
+        if !summaries.insert(&notification.summary) {
+          bail!(
+            "notification summary '{summary}' is used twice, each \
+             notification must have a different summary",
+            summary = notification.summary,
+          );
+        }
+      }
+    }
+
+    let actions = [("default", &config.default), ("on-stop", &config.on_stop)]
+      .into_iter()
+      .filter_map(|(name, actions)| Some(actions.as_ref()?.as_rule(name)));
+
+    for rule in config.rules.iter().cloned().chain(actions) {
+      for (name, frequency, unit) in rule.cpu.implausible_frequencies() {
+        log::warn!(
+          "`{name}` of rule {rule} is {frequency} {unit}, which is \
+           implausibly low. did you mean \"{frequency}GHz\"?",
+          unit = unit.name(),
+        );
+      }
+    }
+
+    config
+      .profile_config(None)
+      .validate()
+      .context("invalid polling intervals")?;
+
+    for profile in config.profiles.keys() {
+      config
+        .profile_config(Some(*profile))
+        .validate()
+        .with_context(|| format!("invalid polling intervals of `{profile}`"))?;
+    }
+
+    if !(config.cpu_usage_smoothing > 0.0 && config.cpu_usage_smoothing <= 1.0)
+    {
+      bail!("`cpu-usage-smoothing` must be more than 0 and at most 1");
+    }
+
+    if config.volatility_window.is_zero() || config.idle_threshold.is_zero() {
+      bail!("`volatility-window` and `idle-threshold` must be longer than 0");
+    }
+
+    if config.force_discharge_floor > 100 {
+      bail!(
+        "`force-discharge-floor` must be at most 100%, got {floor}%",
+        floor = config.force_discharge_floor,
+      );
+    }
+
+    let range = Self::THERMAL_EMERGENCY_CELSIUS_RANGE;
+    if config.thermal_emergency_celsius.is_nan() {
+      bail!("`thermal-emergency-celsius` must be a number");
+    }
+    if !range.contains(&config.thermal_emergency_celsius) {
+      let clamped = config
+        .thermal_emergency_celsius
+        .clamp(*range.start(), *range.end());
+
+      log::warn!(
+        "`thermal-emergency-celsius` of {celsius}°C is outside of \
+         {start}..={end}°C, using {clamped}°C",
+        celsius = config.thermal_emergency_celsius,
+        start = range.start(),
+        end = range.end(),
+      );
+
+      config.thermal_emergency_celsius = clamped;
+    }
+
     // This is just for debug traces.
     if log::max_level() >= log::LevelFilter::Debug {
       if config.rules.is_sorted_by_key(|rule| rule.priority) {
@@ -1937,114 +3868,619 @@ impl DaemonConfig {
   }
 }
 
-#[cfg(test)]
-mod tests {
-  use std::sync::Arc;
+impl DaemonConfig {
+  /// Evaluates the condition of every rule against representative values
+  /// instead of the state of the system, to catch type errors such as
+  /// comparing a string to a number. Returns the value of each condition,
+  /// `None` if it is undefined for those values.
+  pub fn check_rules(&self) -> Vec<(&Rule, anyhow::Result<Option<bool>>)> {
+    let cpus = HashSet::new();
+    let uncores = HashSet::new();
+    let disks = HashSet::new();
+    let usb_devices = HashSet::new();
+    let gpus = HashSet::new();
+    let power_supplies = HashSet::new();
+    let cpu_log = VecDeque::new();
 
-  use proptest::prelude::*;
+    let state = EvalState {
+      frequency_available:         true,
+      turbo_available:             true,
+      turbo_enabled:               Some(true),
+      smt_available:               true,
+      hwp_dynamic_boost_available: true,
+
+      cpu_usage:                  0.5,
+      cpu_usage_volatility:       Some(0.1),
+      cpu_usage_average:          Some(0.5),
+      cpu_temperature:            Some(60.0),
+      cpu_temperature_volatility: Some(1.0),
+      cpu_temperature_maximum:    Some(65.0),
+      cpu_temperature_minimum:    Some(55.0),
+      fan_rpm_maximum:            Some(2400.0),
+      gpu_temperature:            Some(55.0),
+      gpu_power_watts:            Some(15.0),
+      disk_temperature_maximum:   Some(45.0),
+      cpu_idle_seconds:           60.0,
+      cpu_frequency_maximum:      Some(4000.0),
+      cpu_frequency_minimum:      Some(400.0),
+
+      lid_closed:       Some(false),
+      virtual_machine:  false,
+      chassis_type:     Some("laptop"),
+      platform_profile: Some("balanced"),
+
+      power_supply_charge:         Some(0.5),
+      power_supply_discharge_rate: Some(0.1),
+      battery_minutes_remaining:   Some(300.0),
+      power_draw_watts:            Some(10.0),
+      ac_power_limit_watts:        Some(65.0),
+
+      memory_usage: Some(0.5),
+
+      cpu_pressure: Some(system::Pressure {
+        avg10:  5.0,
+        avg60:  5.0,
+        avg300: 5.0,
+      }),
 
-  use super::*;
+      battery_cycles: Some(100.0),
+      battery_health: Some(0.9),
 
-  proptest! {
-    #[test]
-    fn test_multiply_float(
-      base_freq in 1000u64..10000u64,
-      multiplier in 0.1f64..1.0f64
-    ) {
-      // Create a mock CPU with all required fields
-      // FIXME: figure out if there is a less ugly way of testing this and
-      // share CPU state across tests
-      let cpu = Arc::new(cpu::Cpu {
-        number: 0,
-        has_cpufreq: true,
-        available_governors: vec![],
-        governor: None,
-        frequency_mhz: Some(base_freq),
-        frequency_mhz_minimum: Some(1000),
-        frequency_mhz_maximum: Some(base_freq),
-        available_epps: vec![],
-        epp: None,
-        available_epbs: vec![],
-        epb: None,
-        stat: cpu::CpuStat::default(),
-        previous_stat: None,
-        info: None,
-      });
+      discharging: true,
+      on_ac:       false,
+      desktop:     false,
 
-      let mut cpus = HashSet::new();
-      cpus.insert(cpu.clone());
+      local_time: jiff::Zoned::now().datetime(),
 
-      let power_supplies = HashSet::new();
-      let uncores = HashSet::new();
-      let disks = HashSet::new();
-      let usb_devices = HashSet::new();
-      let gpus = HashSet::new();
-      let cpu_log = VecDeque::new();
+      power_profile_preference: PowerProfile::Balanced,
 
-      // Create an eval state with the base frequency
-      let state = EvalState {
-        frequency_available: true,
-        turbo_available: false,
-        cpu_usage: 0.5,
-        cpu_usage_volatility: Some(0.1),
-        cpu_temperature: Some(50.0),
-        cpu_temperature_volatility: Some(5.0),
-        cpu_idle_seconds: 10.0,
-        cpu_frequency_maximum: Some(base_freq as f64),
-        cpu_frequency_minimum: Some(1000.0),
-        lid_closed: false,
-        virtual_machine: false,
-        chassis_type: None,
-        power_supply_charge: Some(0.8),
-        power_supply_discharge_rate: Some(10.0),
-        battery_cycles: Some(100.0),
-        battery_health: Some(0.95),
-        discharging: false,
-        power_profile_preference: crate::profile::PowerProfile::Balanced,
-        context: EvalContext::Cpu(&cpu),
-        cpus: &cpus,
-        uncores: &uncores,
-        disks: &disks,
-        usb_devices: &usb_devices,
-        gpus: &gpus,
-        power_supplies: &power_supplies,
-        cpu_log: &cpu_log,
-      };
+      context: EvalContext::WidestPossible,
 
-      // Create an expression like: { value = "$cpu-frequency-maximum", multiply = 0.65 }
-      let expr = Expression::Multiply {
-        a: Box::new(Expression::CpuFrequencyMaximum),
-        b: Box::new(Expression::Number(multiplier)),
-      };
+      cpus:           &cpus,
+      uncores:        &uncores,
+      disks:          &disks,
+      usb_devices:    &usb_devices,
+      gpus:           &gpus,
+      power_supplies: &power_supplies,
+      cpu_log:        &cpu_log,
+      sysfs_reads:    &SysfsReads::default(),
+    };
 
-      // Evaluate the expression
-      let result = expr.eval(&state);
+    self
+      .rules
+      .iter()
+      .map(|rule| {
+        let result = rule.condition.eval(&state).and_then(|condition| {
+          condition
+            .map(|condition| {
+              condition
+                .try_into_boolean()
+                .context("`if` was not a boolean")
+            })
+            .transpose()
+        });
 
-      // Before the fix, this would succeed but then crash when converting to u64
-      // After the fix, this should succeed and round the result
-      prop_assert!(result.is_ok());
+        (rule, result)
+      })
+      .collect()
+  }
 
-      if let Ok(Some(Expression::Number(value))) = result {
-        // The result might be a float
-        let _expected_float = base_freq as f64 * multiplier;
+  /// Loads every `*.toml` file in `directory` in order of their names. Their
+  /// rules are combined, and other settings are merged with later files
+  /// taking precedence.
+  fn load_directory(directory: &Path) -> anyhow::Result<Self> {
+    log::info!(
+      "loading config files from '{directory}'",
+      directory = directory.display(),
+    );
 
-        // Create a CpusDelta with the frequency_mhz_maximum field
-        let cpu_delta = CpusDelta {
-          for_: None,
-          governor: None,
-          energy_performance_preference: None,
-          energy_perf_bias: None,
-          frequency_mhz_minimum: None,
-          frequency_mhz_maximum: Some(Expression::Number(value)),
-          turbo: None,
-          pstate_min_performance_percent: None,
-          pstate_max_performance_percent: None,
-          dma_latency_us: None,
-          pm_qos_resume_latency_us: None,
-        };
+    let mut paths = Vec::new();
 
-        // Try to evaluate it - this should not panic after the fix
-        let eval_result = cpu_delta.eval(&state);
+    for entry in fs::read_dir(directory).with_context(|| {
+      format!(
+        "failed to read config directory '{directory}'",
+        directory = directory.display(),
+      )
+    })? {
+      let path = entry
+        .with_context(|| {
+          format!(
+            "failed to read entry of '{directory}'",
+            directory = directory.display(),
+          )
+        })?
+        .path();
+
+      if path
+        .extension()
+        .is_some_and(|extension| extension == "toml")
+        && path.is_file()
+      {
+        paths.push(path);
+      }
+    }
+
+    paths.sort();
+
+    let mut files = ConfigFiles::default();
+
+    for path in paths {
+      files.load(&path)?;
+    }
+
+    files.into_config(directory)
+  }
+}
+
+/// Config files merged into one table, following their `include` lists.
+#[derive(Default)]
+struct ConfigFiles {
+  merged:    toml::Table,
+  /// The file each rule priority came from, to name both files on a
+  /// conflict.
+  sources:   HashMap<u16, PathBuf>,
+  /// The files currently being loaded, to detect include cycles.
+  including: Vec<PathBuf>,
+  /// Every file loaded so far, so a file included twice is merged once.
+  loaded:    HashSet<PathBuf>,
+}
+
+impl ConfigFiles {
+  /// Parses the merged table, remembering the files it came from.
+  fn into_config(self, path: &Path) -> anyhow::Result<DaemonConfig> {
+    let mut config: DaemonConfig =
+      self.merged.try_into().with_context(|| {
+        format!(
+          "failed to merge the config files of '{path}'",
+          path = path.display(),
+        )
+      })?;
+
+    config.files = self.loaded.into_iter().collect();
+    config.files.sort();
+
+    Ok(config)
+  }
+
+  /// Merges the file at `path` after the files it includes, so its own
+  /// settings take precedence over theirs. Relative includes are resolved
+  /// against the directory of the including file.
+  fn load(&mut self, path: &Path) -> anyhow::Result<()> {
+    let canonical = path.canonicalize().with_context(|| {
+      format!("failed to read config from '{path}'", path = path.display())
+    })?;
+
+    if self.including.contains(&canonical) {
+      bail!(
+        "'{path}' includes itself through {chain}",
+        path = path.display(),
+        chain = self
+          .including
+          .iter()
+          .map(|path| format!("'{path}'", path = path.display()))
+          .collect::<Vec<_>>()
+          .join(" -> "),
+      );
+    }
+
+    if !self.loaded.insert(canonical.clone()) {
+      log::debug!(
+        "'{path}' was loaded already, skipping",
+        path = path.display(),
+      );
+      return Ok(());
+    }
+
+    log::info!("loading config from '{path}'", path = path.display());
+
+    let contents = fs::read_to_string(path).with_context(|| {
+      format!("failed to read config from '{path}'", path = path.display())
+    })?;
+
+    let mut table: toml::Table =
+      toml::from_str(&contents).with_context(|| {
+        format!("failed to parse file at '{path}'", path = path.display())
+      })?;
+
+    let includes: Vec<PathBuf> = table
+      .remove("include")
+      .map(toml::Value::try_into)
+      .transpose()
+      .with_context(|| {
+        format!(
+          "`include` of '{path}' must be a list of paths",
+          path = path.display(),
+        )
+      })?
+      .unwrap_or_default();
+
+    // Parse each file on its own too, so errors name the file they are in.
+    let config: DaemonConfig = table.clone().try_into().with_context(|| {
+      format!("failed to parse file at '{path}'", path = path.display())
+    })?;
+
+    self.including.push(canonical);
+
+    for include in includes {
+      let include = path
+        .parent()
+        .map_or_else(|| include.clone(), |directory| directory.join(&include));
+
+      self.load(&include).with_context(|| {
+        format!(
+          "failed to load '{include}' included by '{path}'",
+          include = include.display(),
+          path = path.display(),
+        )
+      })?;
+    }
+
+    self.including.pop();
+
+    for rule in &config.rules {
+      if let Some(other) = self.sources.get(&rule.priority)
+        && *other != path
+      {
+        bail!(
+          "rules in '{other}' and '{path}' both have priority {priority}, \
+           each config rule must have a different priority",
+          other = other.display(),
+          path = path.display(),
+          priority = rule.priority,
+        );
+      }
+
+      self.sources.insert(rule.priority, path.to_owned());
+    }
+
+    merge_tables(&mut self.merged, table);
+
+    Ok(())
+  }
+}
+
+/// Merges `table` into `into`. Rules are appended, tables are merged
+/// recursively and any other value replaces the existing one.
+fn merge_tables(into: &mut toml::Table, table: toml::Table) {
+  for (key, value) in table {
+    match (into.get_mut(&key), value) {
+      (Some(toml::Value::Array(rules)), toml::Value::Array(more_rules))
+        if key == "rule" =>
+      {
+        rules.extend(more_rules);
+      },
+
+      (Some(toml::Value::Table(into)), toml::Value::Table(table)) => {
+        merge_tables(into, table);
+      },
+
+      (_, value) => {
+        into.insert(key, value);
+      },
+    }
+  }
+}
+
+/// A change between two values of the same setting. `None` means the setting
+/// is absent on that side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+  pub path: String,
+  pub old:  Option<String>,
+  pub new:  Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleChange {
+  Added {
+    name:     String,
+    priority: u16,
+  },
+  Removed {
+    name:     String,
+    priority: u16,
+  },
+  Modified {
+    name:    String,
+    changes: Vec<FieldChange>,
+  },
+}
+
+/// Flattens a TOML value into dotted paths and their values. Arrays are kept
+/// whole, as their elements have no identity to match on.
+fn flatten(
+  value: &toml::Value,
+  path: &str,
+  fields: &mut BTreeMap<String, String>,
+) {
+  match value {
+    toml::Value::Table(table) => {
+      for (key, value) in table {
+        let path = if path.is_empty() {
+          key.clone()
+        } else {
+          format!("{path}.{key}")
+        };
+
+        flatten(value, &path, fields);
+      }
+    },
+
+    value => {
+      fields.insert(path.to_owned(), value.to_string());
+    },
+  }
+}
+
+fn diff_fields(
+  old: &impl Serialize,
+  new: &impl Serialize,
+) -> anyhow::Result<Vec<FieldChange>> {
+  let mut old_fields = BTreeMap::new();
+  flatten(
+    &toml::Value::try_from(old).context("failed to serialize old value")?,
+    "",
+    &mut old_fields,
+  );
+
+  let mut new_fields = BTreeMap::new();
+  flatten(
+    &toml::Value::try_from(new).context("failed to serialize new value")?,
+    "",
+    &mut new_fields,
+  );
+
+  let paths: BTreeSet<&String> =
+    old_fields.keys().chain(new_fields.keys()).collect();
+
+  Ok(
+    paths
+      .into_iter()
+      .filter_map(|path| {
+        let old = old_fields.get(path);
+        let new = new_fields.get(path);
+
+        (old != new).then(|| {
+          FieldChange {
+            path: path.clone(),
+            old:  old.cloned(),
+            new:  new.cloned(),
+          }
+        })
+      })
+      .collect(),
+  )
+}
+
+impl DaemonConfig {
+  /// Whether any expression of the config reads the variable `name`, such as
+  /// `$gpu-temperature`, so that sensors nothing reads aren't scanned.
+  pub fn references(&self, name: &str) -> bool {
+    fn contains(value: &toml::Value, name: &str) -> bool {
+      match value {
+        toml::Value::String(string) => string == name,
+        toml::Value::Array(values) => {
+          values.iter().any(|value| contains(value, name))
+        },
+        toml::Value::Table(table) => {
+          table.values().any(|value| contains(value, name))
+        },
+        _ => false,
+      }
+    }
+
+    // Scanning too much beats leaving a variable undefined.
+    toml::Value::try_from(self).map_or(true, |value| contains(&value, name))
+  }
+
+  /// Compares the settings other than rules, field by field.
+  pub fn diff_settings(&self, new: &Self) -> anyhow::Result<Vec<FieldChange>> {
+    let without_rules = |config: &Self| {
+      Self {
+        rules: Vec::new(),
+        ..config.clone()
+      }
+    };
+
+    diff_fields(&without_rules(self), &without_rules(new))
+  }
+
+  /// Compares the rules of two configs. Rules are matched by name, and the
+  /// remaining ones by priority.
+  pub fn diff_rules(&self, new: &Self) -> anyhow::Result<Vec<RuleChange>> {
+    let mut unmatched_new: Vec<&Rule> = new.rules.iter().collect();
+    let mut pairs = Vec::new();
+    let mut unmatched_old = Vec::new();
+
+    for old_rule in &self.rules {
+      match unmatched_new.iter().position(|new_rule| {
+        !old_rule.name.is_empty() && new_rule.name == old_rule.name
+      }) {
+        Some(index) => pairs.push((old_rule, unmatched_new.remove(index))),
+        None => unmatched_old.push(old_rule),
+      }
+    }
+
+    let mut changes = Vec::new();
+
+    for old_rule in unmatched_old {
+      match unmatched_new
+        .iter()
+        .position(|new_rule| new_rule.priority == old_rule.priority)
+      {
+        Some(index) => pairs.push((old_rule, unmatched_new.remove(index))),
+        None => {
+          changes.push(RuleChange::Removed {
+            name:     old_rule.name.clone(),
+            priority: old_rule.priority,
+          });
+        },
+      }
+    }
+
+    for (old_rule, new_rule) in pairs {
+      let field_changes = diff_fields(old_rule, new_rule)?;
+
+      if !field_changes.is_empty() {
+        changes.push(RuleChange::Modified {
+          name:    new_rule.label(),
+          changes: field_changes,
+        });
+      }
+    }
+
+    changes.extend(unmatched_new.into_iter().map(|new_rule| {
+      RuleChange::Added {
+        name:     new_rule.name.clone(),
+        priority: new_rule.priority,
+      }
+    }));
+
+    Ok(changes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use proptest::prelude::*;
+
+  use super::*;
+
+  proptest! {
+    #[test]
+    fn test_multiply_float(
+      base_freq in 1000u64..10000u64,
+      multiplier in 0.1f64..1.0f64
+    ) {
+      // Create a mock CPU with all required fields
+      // FIXME: figure out if there is a less ugly way of testing this and
+      // share CPU state across tests
+      let cpu = Arc::new(cpu::Cpu {
+        number: 0,
+        online: true,
+        has_cpufreq: true,
+        available_governors: vec![],
+        governor: None,
+        schedutil_rate_limit_us: None,
+        frequency_mhz: Some(base_freq),
+        frequency_mhz_minimum: Some(1000),
+        frequency_mhz_maximum: Some(base_freq),
+        available_epps: vec![],
+        epp: None,
+        available_epbs: vec![],
+        epb: None,
+        stat: cpu::CpuStat::default(),
+        previous_stat: None,
+        info: None,
+        core_type: cpu::CoreType::Unknown,
+        capacity: None,
+        scaling_driver: None,
+        policy_id: None,
+      });
+
+      let mut cpus = HashSet::new();
+      cpus.insert(cpu.clone());
+
+      let power_supplies = HashSet::new();
+      let uncores = HashSet::new();
+      let disks = HashSet::new();
+      let usb_devices = HashSet::new();
+      let gpus = HashSet::new();
+      let cpu_log = VecDeque::new();
+
+      // Create an eval state with the base frequency
+      let state = EvalState {
+        frequency_available: true,
+        turbo_available: false,
+        turbo_enabled: None,
+        smt_available: false,
+        hwp_dynamic_boost_available: false,
+        cpu_usage: 0.5,
+        cpu_usage_volatility: Some(0.1),
+        cpu_usage_average: None,
+        cpu_temperature: Some(50.0),
+        cpu_temperature_volatility: Some(5.0),
+        cpu_idle_seconds: 10.0,
+        cpu_frequency_maximum: Some(base_freq as f64),
+        cpu_frequency_minimum: Some(1000.0),
+        lid_closed: None,
+        virtual_machine: false,
+        chassis_type: None,
+        platform_profile: None,
+        power_supply_charge: Some(0.8),
+        power_supply_discharge_rate: Some(10.0),
+        battery_minutes_remaining: None,
+        power_draw_watts:            None,
+        ac_power_limit_watts:        None,
+        memory_usage:                None,
+        cpu_pressure:                None,
+        battery_cycles: Some(100.0),
+        battery_health: Some(0.95),
+        discharging: false,
+        on_ac:       true,
+        desktop:     false,
+        cpu_temperature_maximum: None,
+        cpu_temperature_minimum: None,
+        fan_rpm_maximum:         None,
+        gpu_temperature:         None,
+        gpu_power_watts:         None,
+        disk_temperature_maximum: None,
+        local_time: jiff::civil::date(2025, 1, 1).at(12, 0, 0, 0),
+        power_profile_preference: crate::profile::PowerProfile::Balanced,
+        context: EvalContext::Cpu(&cpu),
+        cpus: &cpus,
+        uncores: &uncores,
+        disks: &disks,
+        usb_devices: &usb_devices,
+        gpus: &gpus,
+        power_supplies: &power_supplies,
+        cpu_log: &cpu_log,
+        sysfs_reads: &SysfsReads::default(),
+      };
+
+      // Create an expression like: { value = "$cpu-frequency-maximum", multiply = 0.65 }
+      let expr = Expression::Multiply {
+        a: Box::new(Expression::CpuFrequencyMaximum),
+        b: Box::new(Expression::Number(multiplier)),
+      };
+
+      // Evaluate the expression
+      let result = expr.eval(&state);
+
+      // Before the fix, this would succeed but then crash when converting to u64
+      // After the fix, this should succeed and round the result
+      prop_assert!(result.is_ok());
+
+      if let Ok(Some(Expression::Number(value))) = result {
+        // The result might be a float
+        let _expected_float = base_freq as f64 * multiplier;
+
+        // Create a CpusDelta with the frequency_mhz_maximum field
+        let cpu_delta = CpusDelta {
+          for_: None,
+          governor: None,
+          schedutil_rate_limit_us: None,
+          energy_performance_preference: None,
+          energy_perf_bias: None,
+          frequency_mhz_minimum: None,
+          frequency_mhz_maximum: Some(Expression::Number(value)),
+          frequency_khz_minimum: None,
+          frequency_khz_maximum: None,
+          frequency_mhz: None,
+          turbo: None,
+          smt: None,
+          hwp_dynamic_boost: None,
+          pstate_min_performance_percent: None,
+          pstate_max_performance_percent: None,
+          dma_latency_us: None,
+          pm_qos_resume_latency_us: None,
+          online: None,
+        };
+
+        // Try to evaluate it - this should not panic after the fix
+        let eval_result = cpu_delta.eval(&state);
 
         // This test should pass after the fix is applied
         prop_assert!(
@@ -2061,20 +4497,26 @@ mod tests {
   #[test]
   fn test_rounding() {
     let cpu = Arc::new(cpu::Cpu {
-      number:                0,
-      has_cpufreq:           true,
-      available_governors:   vec![],
-      governor:              None,
-      frequency_mhz:         Some(3333),
-      frequency_mhz_minimum: Some(1000),
-      frequency_mhz_maximum: Some(3333),
-      available_epps:        vec![],
-      epp:                   None,
-      available_epbs:        vec![],
-      epb:                   None,
-      stat:                  cpu::CpuStat::default(),
-      previous_stat:         None,
-      info:                  None,
+      number:                  0,
+      online:                  true,
+      has_cpufreq:             true,
+      available_governors:     vec![],
+      governor:                None,
+      schedutil_rate_limit_us: None,
+      frequency_mhz:           Some(3333),
+      frequency_mhz_minimum:   Some(1000),
+      frequency_mhz_maximum:   Some(3333),
+      available_epps:          vec![],
+      epp:                     None,
+      available_epbs:          vec![],
+      epb:                     None,
+      stat:                    cpu::CpuStat::default(),
+      previous_stat:           None,
+      info:                    None,
+      core_type:               cpu::CoreType::Unknown,
+      capacity:                None,
+      scaling_driver:          None,
+      policy_id:               None,
     });
 
     let mut cpus = HashSet::new();
@@ -2090,21 +4532,41 @@ mod tests {
     let state = EvalState {
       frequency_available:         true,
       turbo_available:             false,
+      turbo_enabled:               None,
+      smt_available:               false,
+      hwp_dynamic_boost_available: false,
       cpu_usage:                   0.5,
       cpu_usage_volatility:        Some(0.1),
+      cpu_usage_average:           None,
       cpu_temperature:             Some(50.0),
       cpu_temperature_volatility:  Some(5.0),
       cpu_idle_seconds:            10.0,
       cpu_frequency_maximum:       Some(3333.0),
       cpu_frequency_minimum:       Some(1000.0),
-      lid_closed:                  false,
+      lid_closed:                  None,
       virtual_machine:             false,
       chassis_type:                None,
+      platform_profile:            None,
       power_supply_charge:         Some(0.8),
       power_supply_discharge_rate: Some(10.0),
+      battery_minutes_remaining:   None,
+      power_draw_watts:            None,
+      ac_power_limit_watts:        None,
+      memory_usage:                None,
+      cpu_pressure:                None,
       battery_cycles:              Some(100.0),
       battery_health:              Some(0.95),
       discharging:                 false,
+      on_ac:                       true,
+      desktop:                     false,
+      cpu_temperature_maximum:     None,
+      cpu_temperature_minimum:     None,
+      fan_rpm_maximum:             None,
+      gpu_temperature:             None,
+      gpu_power_watts:             None,
+      disk_temperature_maximum:    None,
+      local_time:                  jiff::civil::date(2025, 1, 1)
+        .at(12, 0, 0, 0),
       power_profile_preference:    crate::profile::PowerProfile::Balanced,
       context:                     EvalContext::Cpu(&cpu),
       cpus:                        &cpus,
@@ -2114,12 +4576,14 @@ mod tests {
       gpus:                        &gpus,
       power_supplies:              &power_supplies,
       cpu_log:                     &cpu_log,
+      sysfs_reads:                 &SysfsReads::default(),
     };
 
     // 3333 * 0.65 = 2166.45
     let cpu_delta = CpusDelta {
       for_:                           None,
       governor:                       None,
+      schedutil_rate_limit_us:        None,
       energy_performance_preference:  None,
       energy_perf_bias:               None,
       frequency_mhz_minimum:          None,
@@ -2127,11 +4591,17 @@ mod tests {
         a: Box::new(Expression::CpuFrequencyMaximum),
         b: Box::new(Expression::Number(0.65)),
       }),
+      frequency_khz_minimum:          None,
+      frequency_khz_maximum:          None,
+      frequency_mhz:                  None,
       turbo:                          None,
+      smt:                            None,
+      hwp_dynamic_boost:              None,
       pstate_min_performance_percent: None,
       pstate_max_performance_percent: None,
       dma_latency_us:                 None,
       pm_qos_resume_latency_us:       None,
+      online:                         None,
     };
 
     // Previously this would bail! with "invalid number for ...". With the
@@ -2151,23 +4621,142 @@ mod tests {
     }
   }
 
+  #[test]
+  fn log_capacity_covers_longest_window_at_fastest_polling() {
+    let config: DaemonConfig = toml::from_str(
+      r#"
+        volatility-window = "10min"
+        idle-threshold = "2min"
+
+        [profiles.performance]
+        poll-interval-minimum = "500ms"
+        poll-interval-base = "1s"
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.volatility_window, Duration::from_secs(600));
+    assert_eq!(config.log_capacity(), 1201);
+
+    // Never fewer entries than before the windows were configurable.
+    assert_eq!(DaemonConfig::default().log_capacity(), 301);
+    let config: DaemonConfig =
+      toml::from_str("volatility-window = \"10s\"\nidle-threshold = \"10s\"")
+        .unwrap();
+    assert_eq!(config.log_capacity(), 100);
+  }
+
+  #[test]
+  fn references_finds_variables_in_rules() {
+    let config: DaemonConfig = toml::from_str(
+      r#"
+        [[rule]]
+        priority = 10
+        if = { is-more-than = 70.0, value = "$gpu-temperature" }
+        cpu.turbo = false
+      "#,
+    )
+    .unwrap();
+
+    assert!(config.references("$gpu-temperature"));
+    assert!(!config.references("$disk-temperature-max"));
+    assert!(!DaemonConfig::default().references("$gpu-power-watts"));
+  }
+
+  #[test]
+  fn frequencies_accept_unit_suffixes() {
+    let peripherals = Peripherals::with_cpus(0..1);
+
+    let cpu_delta = CpusDelta {
+      frequency_mhz_maximum: Some(Expression::String("3.5GHz".to_owned())),
+      frequency_khz_minimum: Some(Expression::String("800 MHz".to_owned())),
+      frequency_mhz: Some(Expression::Number(1200.0)),
+      ..CpusDelta::default()
+    };
+
+    let (deltas, _) = cpu_delta.eval(&peripherals.state()).unwrap();
+    let delta = deltas.values().next().unwrap();
+    assert_eq!(delta.frequency_mhz_maximum, Some(3500));
+    assert_eq!(delta.frequency_khz_minimum, Some(800_000));
+    assert_eq!(delta.frequency_mhz, Some(1200));
+
+    assert_eq!(
+      parse_frequency("2400000kHz", FrequencyUnit::Mhz).unwrap(),
+      2400.0
+    );
+    assert!(parse_frequency("3500", FrequencyUnit::Mhz).is_err());
+    assert!(parse_frequency("3.5 GB", FrequencyUnit::Mhz).is_err());
+  }
+
+  #[test]
+  fn notification_summaries_must_be_unique() {
+    let path = std::env::temp_dir().join(format!(
+      "watt-config-notifications-{}.toml",
+      std::process::id()
+    ));
+    fs::write(
+      &path,
+      r#"
+        [[notification]]
+        summary = "Battery low"
+        if = "?discharging"
+
+        [[notification]]
+        summary = "Battery low"
+        if = "?lid-closed"
+      "#,
+    )
+    .unwrap();
+
+    let error = DaemonConfig::load_from(Some(&path))
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("used twice"), "{error}");
+
+    let _ = fs::remove_file(&path);
+  }
+
+  #[test]
+  fn implausible_frequencies_are_found_in_literals() {
+    let cpu_delta: CpusDelta = toml::from_str(
+      r#"
+        frequency-mhz-maximum = { if = "?discharging", then = 1.8, else = 2400 }
+        frequency-khz-minimum = 800000
+        frequency-mhz = "$cpu-frequency-minimum"
+      "#,
+    )
+    .unwrap();
+
+    assert!(matches!(cpu_delta.implausible_frequencies()[..], [(
+      "cpu.frequency-mhz-maximum",
+      1.8,
+      FrequencyUnit::Mhz,
+    )]));
+  }
+
   #[test]
   fn test_volatility_expressions_with_insufficient_data() {
     let cpu = Arc::new(cpu::Cpu {
-      number:                0,
-      has_cpufreq:           true,
-      available_governors:   vec![],
-      governor:              None,
-      frequency_mhz:         Some(3333),
-      frequency_mhz_minimum: Some(1000),
-      frequency_mhz_maximum: Some(3333),
-      available_epps:        vec![],
-      epp:                   None,
-      available_epbs:        vec![],
-      epb:                   None,
-      stat:                  cpu::CpuStat::default(),
-      previous_stat:         None,
-      info:                  None,
+      number:                  0,
+      online:                  true,
+      has_cpufreq:             true,
+      available_governors:     vec![],
+      governor:                None,
+      schedutil_rate_limit_us: None,
+      frequency_mhz:           Some(3333),
+      frequency_mhz_minimum:   Some(1000),
+      frequency_mhz_maximum:   Some(3333),
+      available_epps:          vec![],
+      epp:                     None,
+      available_epbs:          vec![],
+      epb:                     None,
+      stat:                    cpu::CpuStat::default(),
+      previous_stat:           None,
+      info:                    None,
+      core_type:               cpu::CoreType::Unknown,
+      capacity:                None,
+      scaling_driver:          None,
+      policy_id:               None,
     });
 
     let mut cpus = HashSet::new();
@@ -2183,21 +4772,41 @@ mod tests {
     let state = EvalState {
       frequency_available:         true,
       turbo_available:             false,
+      turbo_enabled:               None,
+      smt_available:               false,
+      hwp_dynamic_boost_available: false,
       cpu_usage:                   0.0,
       cpu_usage_volatility:        None,
+      cpu_usage_average:           None,
       cpu_temperature:             None,
       cpu_temperature_volatility:  None,
       cpu_idle_seconds:            0.0,
       cpu_frequency_maximum:       Some(3333.0),
       cpu_frequency_minimum:       Some(1000.0),
-      lid_closed:                  false,
+      lid_closed:                  None,
       virtual_machine:             false,
       chassis_type:                None,
+      platform_profile:            None,
       power_supply_charge:         None,
       power_supply_discharge_rate: None,
+      battery_minutes_remaining:   None,
+      power_draw_watts:            None,
+      ac_power_limit_watts:        None,
+      memory_usage:                None,
+      cpu_pressure:                None,
       battery_cycles:              None,
       battery_health:              None,
       discharging:                 false,
+      on_ac:                       true,
+      desktop:                     false,
+      cpu_temperature_maximum:     None,
+      cpu_temperature_minimum:     None,
+      fan_rpm_maximum:             None,
+      gpu_temperature:             None,
+      gpu_power_watts:             None,
+      disk_temperature_maximum:    None,
+      local_time:                  jiff::civil::date(2025, 1, 1)
+        .at(12, 0, 0, 0),
       power_profile_preference:    crate::profile::PowerProfile::Balanced,
       context:                     EvalContext::Cpu(&cpu),
       cpus:                        &cpus,
@@ -2207,6 +4816,7 @@ mod tests {
       gpus:                        &gpus,
       power_supplies:              &power_supplies,
       cpu_log:                     &cpu_log,
+      sysfs_reads:                 &SysfsReads::default(),
     };
 
     let result = Expression::CpuUsageVolatility.eval(&state);
@@ -2231,23 +4841,29 @@ mod tests {
   #[test]
   fn first_available_governor_selects_first_supported_value() {
     let cpu = Arc::new(cpu::Cpu {
-      number:                0,
-      has_cpufreq:           true,
-      available_governors:   vec![
+      number:                  0,
+      online:                  true,
+      has_cpufreq:             true,
+      available_governors:     vec![
         "powersave".to_owned(),
         "schedutil".to_owned(),
       ],
-      governor:              None,
-      frequency_mhz:         Some(3333),
-      frequency_mhz_minimum: Some(1000),
-      frequency_mhz_maximum: Some(3333),
-      available_epps:        vec![],
-      epp:                   None,
-      available_epbs:        vec![],
-      epb:                   None,
-      stat:                  cpu::CpuStat::default(),
-      previous_stat:         None,
-      info:                  None,
+      governor:                None,
+      schedutil_rate_limit_us: None,
+      frequency_mhz:           Some(3333),
+      frequency_mhz_minimum:   Some(1000),
+      frequency_mhz_maximum:   Some(3333),
+      available_epps:          vec![],
+      epp:                     None,
+      available_epbs:          vec![],
+      epb:                     None,
+      stat:                    cpu::CpuStat::default(),
+      previous_stat:           None,
+      info:                    None,
+      core_type:               cpu::CoreType::Unknown,
+      capacity:                None,
+      scaling_driver:          None,
+      policy_id:               None,
     });
 
     let mut cpus = HashSet::new();
@@ -2263,21 +4879,41 @@ mod tests {
     let state = EvalState {
       frequency_available:         true,
       turbo_available:             false,
+      turbo_enabled:               None,
+      smt_available:               false,
+      hwp_dynamic_boost_available: false,
       cpu_usage:                   0.0,
       cpu_usage_volatility:        None,
+      cpu_usage_average:           None,
       cpu_temperature:             None,
       cpu_temperature_volatility:  None,
       cpu_idle_seconds:            0.0,
       cpu_frequency_maximum:       Some(3333.0),
       cpu_frequency_minimum:       Some(1000.0),
-      lid_closed:                  false,
+      lid_closed:                  None,
       virtual_machine:             false,
       chassis_type:                None,
+      platform_profile:            None,
       power_supply_charge:         None,
       power_supply_discharge_rate: None,
+      battery_minutes_remaining:   None,
+      power_draw_watts:            None,
+      ac_power_limit_watts:        None,
+      memory_usage:                None,
+      cpu_pressure:                None,
       battery_cycles:              None,
       battery_health:              None,
       discharging:                 false,
+      on_ac:                       true,
+      desktop:                     false,
+      cpu_temperature_maximum:     None,
+      cpu_temperature_minimum:     None,
+      fan_rpm_maximum:             None,
+      gpu_temperature:             None,
+      gpu_power_watts:             None,
+      disk_temperature_maximum:    None,
+      local_time:                  jiff::civil::date(2025, 1, 1)
+        .at(12, 0, 0, 0),
       power_profile_preference:    crate::profile::PowerProfile::Balanced,
       context:                     EvalContext::Cpu(&cpu),
       cpus:                        &cpus,
@@ -2287,6 +4923,7 @@ mod tests {
       gpus:                        &gpus,
       power_supplies:              &power_supplies,
       cpu_log:                     &cpu_log,
+      sysfs_reads:                 &SysfsReads::default(),
     };
 
     let result = Expression::FirstAvailableGovernor {
@@ -2301,4 +4938,1282 @@ mod tests {
 
     assert_eq!(result, Some(Expression::String("schedutil".to_owned())));
   }
+
+  /// Owns the peripherals an [`EvalState`] borrows from.
+  #[derive(Default)]
+  struct Peripherals {
+    cpus:           HashSet<Arc<cpu::Cpu>>,
+    uncores:        HashSet<Arc<uncore::Uncore>>,
+    disks:          HashSet<Arc<disk::Disk>>,
+    usb_devices:    HashSet<Arc<usb::UsbDevice>>,
+    gpus:           HashSet<Arc<gpu::Gpu>>,
+    power_supplies: HashSet<Arc<power_supply::PowerSupply>>,
+    cpu_log:        VecDeque<system::CpuLog>,
+    sysfs_reads:    SysfsReads,
+  }
+
+  impl Peripherals {
+    fn with_cpus(numbers: impl IntoIterator<Item = u32>) -> Self {
+      Self {
+        cpus: numbers
+          .into_iter()
+          .map(|number| {
+            Arc::new(cpu::Cpu {
+              number,
+              online: true,
+              ..Default::default()
+            })
+          })
+          .collect(),
+        ..Default::default()
+      }
+    }
+
+    fn state(&self) -> EvalState<'_, 'static> {
+      EvalState {
+        frequency_available:         false,
+        turbo_available:             false,
+        turbo_enabled:               None,
+        smt_available:               false,
+        hwp_dynamic_boost_available: false,
+        cpu_usage:                   0.0,
+        cpu_usage_volatility:        None,
+        cpu_usage_average:           None,
+        cpu_temperature:             None,
+        cpu_temperature_volatility:  None,
+        cpu_idle_seconds:            0.0,
+        cpu_frequency_maximum:       None,
+        cpu_frequency_minimum:       None,
+        lid_closed:                  None,
+        virtual_machine:             false,
+        chassis_type:                None,
+        platform_profile:            None,
+        power_supply_charge:         None,
+        power_supply_discharge_rate: None,
+        battery_minutes_remaining:   None,
+        power_draw_watts:            None,
+        ac_power_limit_watts:        None,
+        memory_usage:                None,
+        cpu_pressure:                None,
+        battery_cycles:              None,
+        battery_health:              None,
+        discharging:                 false,
+        on_ac:                       true,
+        desktop:                     false,
+        cpu_temperature_maximum:     None,
+        cpu_temperature_minimum:     None,
+        fan_rpm_maximum:             None,
+        gpu_temperature:             None,
+        gpu_power_watts:             None,
+        disk_temperature_maximum:    None,
+        local_time:                  jiff::civil::date(2025, 1, 1)
+          .at(12, 0, 0, 0),
+        power_profile_preference:    crate::profile::PowerProfile::Balanced,
+        context:                     EvalContext::WidestPossible,
+        cpus:                        &self.cpus,
+        uncores:                     &self.uncores,
+        disks:                       &self.disks,
+        usb_devices:                 &self.usb_devices,
+        gpus:                        &self.gpus,
+        power_supplies:              &self.power_supplies,
+        cpu_log:                     &self.cpu_log,
+        sysfs_reads:                 &self.sysfs_reads,
+      }
+    }
+  }
+
+  #[test]
+  fn cpu_number_matches_iterated_cpu() {
+    let peripherals = Peripherals::with_cpus([0, 1, 5]);
+    let state = peripherals.state();
+
+    assert_eq!(Expression::CpuNumber.eval(&state).unwrap(), None);
+
+    let cpu_delta = CpusDelta {
+      frequency_mhz_maximum: Some(Expression::Plus {
+        a: Box::new(Expression::CpuNumber),
+        b: Box::new(Expression::Number(1000.0)),
+      }),
+      ..Default::default()
+    };
+
+    let (deltas, _) = cpu_delta.eval(&state).unwrap();
+
+    assert_eq!(deltas.len(), 3);
+    for (cpu, delta) in deltas {
+      assert_eq!(delta.frequency_mhz_maximum, Some(1000 + cpu.number as u64));
+    }
+  }
+
+  #[test]
+  fn is_unset_sees_through_nested_undefined_values() {
+    let peripherals = Peripherals::default();
+    let state = peripherals.state();
+
+    let condition: Expression = toml::from_str::<toml::Table>(
+      r#"
+        if.any = [
+          { is-unset = { value = "%power-supply-discharge-rate", multiply = 100 } },
+          { is-more-than = 0.1, value = "%power-supply-discharge-rate" },
+        ]
+      "#,
+    )
+    .unwrap()["if"]
+      .clone()
+      .try_into()
+      .unwrap();
+
+    assert_eq!(
+      condition.eval(&state).unwrap(),
+      Some(Expression::Boolean(true))
+    );
+
+    let state = EvalState {
+      power_supply_discharge_rate: Some(0.05),
+      ..state
+    };
+
+    assert_eq!(
+      condition.eval(&state).unwrap(),
+      Some(Expression::Boolean(false)),
+    );
+  }
+
+  #[test]
+  fn coalesce_falls_back_to_default_only_when_undefined() {
+    let peripherals = Peripherals::default();
+    let state = peripherals.state();
+
+    let expression = Expression::Coalesce {
+      value:   Box::new(Expression::PowerSupplyDischargeRate),
+      default: Box::new(Expression::Number(0.0)),
+    };
+
+    assert_eq!(
+      expression.eval(&state).unwrap(),
+      Some(Expression::Number(0.0)),
+    );
+
+    let state = EvalState {
+      power_supply_discharge_rate: Some(0.3),
+      ..state
+    };
+
+    assert_eq!(
+      expression.eval(&state).unwrap(),
+      Some(Expression::Number(0.3)),
+    );
+  }
+
+  #[test]
+  fn modulo_and_rounding_handle_negative_operands() {
+    let peripherals = Peripherals::default();
+    let state = peripherals.state();
+
+    let eval = |expression: &str| {
+      toml::from_str::<Rule>(&format!("priority = 0\nif = {expression}"))
+        .unwrap()
+        .condition
+        .eval(&state)
+    };
+    let number = |expression| eval(expression).unwrap();
+
+    assert_eq!(
+      number("{ value = 3456.0, modulo = 100.0 }"),
+      Some(Expression::Number(56.0)),
+    );
+    assert_eq!(
+      number("{ value = -7.0, modulo = 3.0 }"),
+      Some(Expression::Number(2.0)),
+    );
+    assert_eq!(
+      number("{ value = -7.0, modulo = -3.0 }"),
+      Some(Expression::Number(2.0)),
+    );
+    assert!(eval("{ value = 7.0, modulo = 0.0 }").is_err());
+
+    assert_eq!(number("{ round = -2.5 }"), Some(Expression::Number(-3.0)));
+    assert_eq!(number("{ floor = -2.5 }"), Some(Expression::Number(-3.0)));
+    assert_eq!(number("{ ceil = -2.5 }"), Some(Expression::Number(-2.0)));
+    assert_eq!(
+      number("{ floor = { value = 3456.0, divide = 100.0 } }"),
+      Some(Expression::Number(34.0)),
+    );
+  }
+
+  #[test]
+  fn clamp_bounds_value_and_rejects_inverted_range() {
+    let peripherals = Peripherals::default();
+    let state = peripherals.state();
+
+    let clamp = |value: &str| {
+      toml::from_str::<Rule>(&format!(
+        "priority = 0\nif = {{ value = {value}, minimum = 800.0, maximum = \
+         4000.0 }}",
+      ))
+      .unwrap()
+      .condition
+    };
+
+    assert_eq!(
+      clamp("{ value = 1000.0, multiply = 5.0 }")
+        .eval(&state)
+        .unwrap(),
+      Some(Expression::Number(4000.0)),
+    );
+    assert_eq!(
+      clamp("500.0").eval(&state).unwrap(),
+      Some(Expression::Number(800.0)),
+    );
+    assert_eq!(
+      clamp("\"%power-supply-discharge-rate\"")
+        .eval(&state)
+        .unwrap(),
+      None
+    );
+
+    let inverted = Expression::Clamp {
+      value:   Box::new(Expression::Number(1.0)),
+      minimum: Box::new(Expression::Number(2.0)),
+      maximum: Box::new(Expression::Number(0.0)),
+    };
+    assert!(inverted.eval(&state).is_err());
+  }
+
+  #[test]
+  fn between_checks_inclusive_and_exclusive_ranges() {
+    let peripherals = Peripherals::default();
+    let state = peripherals.state();
+
+    let between = |operands: &str| {
+      toml::from_str::<Rule>(&format!(
+        "priority = 0\nif = {{ between = {{ minimum = 50.0, maximum = 80.0, \
+         {operands} }} }}",
+      ))
+      .unwrap()
+      .condition
+      .eval(&state)
+      .unwrap()
+    };
+
+    assert_eq!(between("value = 50.0"), Some(Expression::Boolean(true)));
+    assert_eq!(between("value = 65.0"), Some(Expression::Boolean(true)));
+    assert_eq!(between("value = 90.0"), Some(Expression::Boolean(false)));
+    assert_eq!(
+      between("value = 80.0, inclusive = false"),
+      Some(Expression::Boolean(false)),
+    );
+    assert_eq!(between("value = \"$cpu-temperature\""), None);
+
+    let not_equal = |value: f64| {
+      Expression::NotEqual {
+        a:      Box::new(Expression::Number(12.0)),
+        b:      Box::new(Expression::Number(value)),
+        leeway: Box::new(Expression::Number(0.5)),
+      }
+      .eval(&state)
+      .unwrap()
+    };
+
+    assert_eq!(not_equal(12.2), Some(Expression::Boolean(false)));
+    assert_eq!(not_equal(13.0), Some(Expression::Boolean(true)));
+  }
+
+  #[test]
+  fn equal_is_exact_without_leeway_and_compares_strings() {
+    let peripherals = Peripherals::default();
+    let state = peripherals.state();
+
+    let condition = |condition: &str| {
+      toml::from_str::<Rule>(&format!("priority = 0\nif = {condition}"))
+        .unwrap()
+        .condition
+        .eval(&state)
+        .unwrap()
+    };
+
+    assert_eq!(
+      condition("{ value = 12.0, is-equal = 12.0 }"),
+      Some(Expression::Boolean(true)),
+    );
+    assert_eq!(
+      condition("{ value = 12.0, is-equal = 12.1 }"),
+      Some(Expression::Boolean(false)),
+    );
+    assert_eq!(
+      condition("{ value = 12.0, is-not-equal = 12.1 }"),
+      Some(Expression::Boolean(true)),
+    );
+    assert_eq!(
+      condition("{ value = 12.0, is-equal = 12.1, leeway = 0.5 }"),
+      Some(Expression::Boolean(true)),
+    );
+
+    assert_eq!(
+      condition(r#"{ value = "powersave", is-equal = "powersave" }"#),
+      Some(Expression::Boolean(true)),
+    );
+    assert_eq!(
+      condition(r#"{ value = "powersave", is-not-equal = "performance" }"#),
+      Some(Expression::Boolean(true)),
+    );
+  }
+
+  /// Builds a CPU log with one entry every 5 seconds, ending now.
+  fn cpu_log(
+    entries: impl ExactSizeIterator<Item = (f64, Option<f64>)>,
+  ) -> VecDeque<system::CpuLog> {
+    let now = Instant::now();
+    let len = entries.len();
+
+    entries
+      .enumerate()
+      .map(|(index, (usage, temperature))| {
+        system::CpuLog {
+          at: now - Duration::from_secs(5 * (len - index) as u64),
+          usage,
+          temperature,
+          load_average: 0.0,
+        }
+      })
+      .collect()
+  }
+
+  fn temperature_log(temperatures: &[f64]) -> VecDeque<system::CpuLog> {
+    cpu_log(
+      temperatures
+        .iter()
+        .map(|&temperature| (0.0, Some(temperature))),
+    )
+  }
+
+  #[test]
+  fn cpu_temperature_rising_follows_trend() {
+    let mut peripherals = Peripherals::default();
+    assert_eq!(
+      Expression::CpuTemperatureRising
+        .eval(&peripherals.state())
+        .unwrap(),
+      None,
+    );
+
+    peripherals.cpu_log = temperature_log(&[50.0, 52.0, 55.0, 61.0]);
+    assert_eq!(
+      Expression::CpuTemperatureRising
+        .eval(&peripherals.state())
+        .unwrap(),
+      Some(Expression::Boolean(true)),
+    );
+
+    peripherals.cpu_log = temperature_log(&[61.0, 55.0, 52.0, 50.0]);
+    assert_eq!(
+      Expression::CpuTemperatureRising
+        .eval(&peripherals.state())
+        .unwrap(),
+      Some(Expression::Boolean(false)),
+    );
+  }
+
+  #[test]
+  fn aggregates_cover_only_the_window() {
+    let peripherals = Peripherals {
+      cpu_log: cpu_log(
+        [
+          (0.9, Some(90.0)),
+          (0.1, Some(60.0)),
+          (0.3, None),
+          (0.5, Some(70.0)),
+        ]
+        .into_iter(),
+      ),
+      ..Peripherals::default()
+    };
+
+    let aggregate = |expression: &str| {
+      let expression: Expression =
+        toml::from_str::<toml::Table>(&format!("value = {expression}"))
+          .unwrap()["value"]
+          .clone()
+          .try_into()
+          .unwrap();
+      expression.eval(&peripherals.state()).unwrap()
+    };
+
+    // The log is 20 seconds old, so only the last three samples are within
+    // 17 seconds.
+    assert_eq!(
+      aggregate(r#"{ average-over = "%cpu-usage", seconds = 17 }"#),
+      Some(Expression::Number(0.3)),
+    );
+    assert_eq!(
+      aggregate(r#"{ max-over = "$cpu-temperature", seconds = 17 }"#),
+      Some(Expression::Number(70.0)),
+    );
+    assert_eq!(
+      aggregate(r#"{ min-over = "$cpu-temperature", seconds = 17 }"#),
+      Some(Expression::Number(60.0)),
+    );
+    assert_eq!(
+      aggregate(r#"{ max-over = "$cpu-temperature", seconds = 60 }"#),
+      None,
+    );
+    assert_eq!(
+      aggregate(r#"{ min-over = "$cpu-temperature", seconds = 7 }"#),
+      None,
+    );
+
+    let expression: Expression = toml::from_str::<toml::Table>(
+      r#"value = { max-over = "$cpu-frequency-maximum", seconds = 10 }"#,
+    )
+    .unwrap()["value"]
+      .clone()
+      .try_into()
+      .unwrap();
+    assert!(expression.eval(&peripherals.state()).is_err());
+  }
+
+  #[test]
+  fn type_mismatches_are_typed_through_context() {
+    let error = Expression::String("powersave".to_owned())
+      .try_into_number()
+      .context("`if` of rule 'test' was not a number")
+      .unwrap_err();
+
+    assert!(matches!(
+      error.downcast_ref::<WattError>(),
+      Some(WattError::TypeMismatch {
+        expected: "a number",
+        ..
+      }),
+    ));
+  }
+
+  #[test]
+  fn cpu_count_is_number_of_tracked_cpus() {
+    let peripherals = Peripherals::with_cpus(0..4);
+
+    let expression: Expression = toml::Value::String("$cpu-count".to_owned())
+      .try_into()
+      .unwrap();
+    assert_eq!(expression, Expression::CpuCount);
+    assert_eq!(
+      expression.eval(&peripherals.state()).unwrap(),
+      Some(Expression::Number(4.0)),
+    );
+  }
+
+  #[test]
+  fn platform_profile_is_undefined_without_support() {
+    let peripherals = Peripherals::default();
+
+    let expression: Expression =
+      toml::Value::String("$platform-profile".to_owned())
+        .try_into()
+        .unwrap();
+    assert_eq!(expression, Expression::PlatformProfile);
+    assert_eq!(expression.eval(&peripherals.state()).unwrap(), None);
+
+    let state = EvalState {
+      platform_profile: Some("quiet"),
+      ..peripherals.state()
+    };
+    assert_eq!(
+      expression.eval(&state).unwrap(),
+      Some(Expression::String("quiet".to_owned())),
+    );
+  }
+
+  #[test]
+  fn sysfs_values_are_read_once_per_evaluation() {
+    let peripherals = Peripherals::default();
+    let state = peripherals.state();
+
+    let parse = |kind: &str, path: &str| {
+      toml::from_str::<Rule>(&format!(
+        "priority = 0\nif = {{ {kind} = \"{path}\" }}"
+      ))
+      .map(|rule| rule.condition)
+    };
+
+    for path in [
+      "/etc/shadow",
+      "sys/class/power_supply",
+      "/sys/../etc/shadow",
+      "/proc/self/root/etc/shadow",
+      "/proc/1/environ",
+    ] {
+      assert!(parse("sysfs-string", path).is_err(), "{path}");
+    }
+
+    let number =
+      parse("sysfs-number", "/sys/devices/platform/fan/mode").unwrap();
+    let string = parse("sysfs-string", "/proc/sys/kernel/osrelease").unwrap();
+    let missing = parse("sysfs-number", "/sys/devices/platform/none").unwrap();
+
+    let system = crate::harness::FakeSystem::new()
+      .file("sys/devices/platform/fan/mode", "2\n")
+      .file("proc/sys/kernel/osrelease", "6.12.0\n");
+
+    let (first, cached, cleared, string, missing) = system.with_root(|| {
+      let first = number.eval(&state);
+      crate::fs::write("/sys/devices/platform/fan/mode", "3").unwrap();
+      let cached = number.eval(&state);
+      state.sysfs_reads.clear();
+      let cleared = number.eval(&state);
+
+      (
+        first,
+        cached,
+        cleared,
+        string.eval(&state),
+        missing.eval(&state),
+      )
+    });
+
+    assert_eq!(first.unwrap(), Some(Expression::Number(2.0)));
+    assert_eq!(cached.unwrap(), Some(Expression::Number(2.0)));
+    assert_eq!(cleared.unwrap(), Some(Expression::Number(3.0)));
+    assert_eq!(
+      string.unwrap(),
+      Some(Expression::String("6.12.0".to_owned())),
+    );
+    assert_eq!(missing.unwrap(), None);
+  }
+
+  #[test]
+  fn cpu_online_count_skips_offline_cpus() {
+    let mut peripherals = Peripherals::with_cpus(0..4);
+    peripherals.cpus.replace(Arc::new(cpu::Cpu {
+      number: 3,
+      ..Default::default()
+    }));
+
+    assert_eq!(
+      Expression::CpuOnlineCount
+        .eval(&peripherals.state())
+        .unwrap(),
+      Some(Expression::Number(3.0)),
+    );
+
+    let cpu_delta = CpusDelta {
+      online: Some(Expression::Boolean(false)),
+      ..CpusDelta::default()
+    };
+    assert!(cpu_delta.eval(&peripherals.state()).is_err());
+
+    let cpu_delta = CpusDelta {
+      for_: Some(Expression::List(vec![Expression::Number(3.0)])),
+      online: Some(Expression::Boolean(true)),
+      ..CpusDelta::default()
+    };
+    let (deltas, _) = cpu_delta.eval(&peripherals.state()).unwrap();
+    assert_eq!(deltas.values().next().unwrap().online, Some(true));
+  }
+
+  #[test]
+  fn frequency_khz_is_exact_while_mhz_rounds() {
+    let peripherals = Peripherals::with_cpus(0..1);
+
+    let cpu_delta = CpusDelta {
+      frequency_khz_maximum: Some(Expression::Number(2_345_678.0)),
+      ..CpusDelta::default()
+    };
+    let (deltas, _) = cpu_delta.eval(&peripherals.state()).unwrap();
+    let delta = deltas.values().next().unwrap();
+    assert_eq!(delta.frequency_khz_maximum, Some(2_345_678));
+    assert_eq!(delta.frequency_mhz_maximum, None);
+
+    let cpu_delta = CpusDelta {
+      frequency_mhz_maximum: Some(Expression::Number(2_345.678)),
+      ..CpusDelta::default()
+    };
+    let (deltas, _) = cpu_delta.eval(&peripherals.state()).unwrap();
+    let delta = deltas.values().next().unwrap();
+    assert_eq!(delta.frequency_mhz_maximum, Some(2_346));
+    assert_eq!(delta.frequency_khz_maximum, None);
+
+    let cpu_delta = CpusDelta {
+      frequency_mhz_maximum: Some(Expression::Number(2_345.0)),
+      frequency_khz_maximum: Some(Expression::Number(2_345_678.0)),
+      ..CpusDelta::default()
+    };
+    assert!(cpu_delta.eval(&peripherals.state()).is_err());
+  }
+
+  #[test]
+  fn is_scaling_driver_matches_cpu_driver() {
+    let mut peripherals = Peripherals::with_cpus(0..2);
+    peripherals.cpus = peripherals
+      .cpus
+      .into_iter()
+      .map(|cpu| {
+        Arc::new(cpu::Cpu {
+          scaling_driver: (cpu.number == 1)
+            .then(|| "amd-pstate-epp".to_owned()),
+          ..(*cpu).clone()
+        })
+      })
+      .collect();
+
+    let expression: Expression = toml::from_str::<toml::Table>(
+      r#"if.is-scaling-driver = "amd-pstate-epp""#,
+    )
+    .unwrap()["if"]
+      .clone()
+      .try_into()
+      .unwrap();
+
+    let state = peripherals.state();
+    assert_eq!(
+      expression.eval(&state).unwrap(),
+      Some(Expression::Boolean(true))
+    );
+
+    let cpus: Vec<_> = peripherals.cpus.iter().cloned().collect();
+    for cpu in &cpus {
+      let state = state.in_context(EvalContext::Cpu(cpu));
+      assert_eq!(
+        expression.eval(&state).unwrap(),
+        Some(Expression::Boolean(cpu.number == 1)),
+      );
+    }
+  }
+
+  #[test]
+  fn cpus_of_core_type_targets_efficiency_cores() {
+    let mut peripherals = Peripherals::with_cpus(0..4);
+    peripherals.cpus = peripherals
+      .cpus
+      .into_iter()
+      .map(|cpu| {
+        Arc::new(cpu::Cpu {
+          core_type: if cpu.number >= 2 {
+            cpu::CoreType::Efficiency
+          } else {
+            cpu::CoreType::Performance
+          },
+          ..(*cpu).clone()
+        })
+      })
+      .collect();
+
+    let cpu_delta: CpusDelta = toml::from_str(
+      r#"
+        for = { cpus-of-core-type = "efficiency" }
+        frequency-mhz-maximum = 800
+      "#,
+    )
+    .unwrap();
+
+    let (deltas, _) = cpu_delta.eval(&peripherals.state()).unwrap();
+    let mut numbers: Vec<u32> = deltas.keys().map(|cpu| cpu.number).collect();
+    numbers.sort_unstable();
+    assert_eq!(numbers, [2, 3]);
+
+    let expression: Expression =
+      toml::from_str::<toml::Table>(r#"if.is-core-type = "performance""#)
+        .unwrap()["if"]
+        .clone()
+        .try_into()
+        .unwrap();
+
+    let state = peripherals.state();
+    let cpus: Vec<_> = peripherals.cpus.iter().cloned().collect();
+    for cpu in &cpus {
+      let state = state.in_context(EvalContext::Cpu(cpu));
+      assert_eq!(
+        expression.eval(&state).unwrap(),
+        Some(Expression::Boolean(cpu.number < 2)),
+      );
+    }
+
+    let expression: Expression =
+      toml::from_str::<toml::Table>(r#"if.is-core-type = "little""#).unwrap()
+        ["if"]
+        .clone()
+        .try_into()
+        .unwrap();
+    assert!(expression.eval(&state).is_err());
+  }
+
+  #[test]
+  fn cpu_for_takes_ranges_and_exceptions() {
+    let peripherals = Peripherals::with_cpus(0..8);
+    let state = peripherals.state();
+
+    let numbers = |for_: &str| {
+      let cpu_delta: CpusDelta =
+        toml::from_str(&format!("for = {for_}\nfrequency-mhz-maximum = 800"))
+          .unwrap();
+
+      cpu_delta.eval(&state).map(|(deltas, _)| {
+        let mut numbers: Vec<u32> =
+          deltas.keys().map(|cpu| cpu.number).collect();
+        numbers.sort_unstable();
+        numbers
+      })
+    };
+
+    assert_eq!(numbers("{ range = { from = 2, to = 5 } }").unwrap(), [
+      2, 3, 4, 5
+    ]);
+    assert_eq!(
+      numbers(
+        "{ except = { from = { range = { from = 0, to = 7 } }, remove = [0, \
+         4] } }"
+      )
+      .unwrap(),
+      [1, 2, 3, 5, 6, 7],
+    );
+
+    assert!(numbers("{ range = { from = 5, to = 2 } }").is_err());
+    assert!(numbers("{ range = { from = 0.5, to = 2 } }").is_err());
+  }
+
+  #[test]
+  fn poll_intervals_fall_back_to_top_level() {
+    let config: DaemonConfig = toml::from_str(
+      r#"
+        poll-interval-minimum = "500ms"
+        poll-interval-maximum = "1min"
+
+        [profiles.performance]
+        poll-interval-maximum = "5s"
+      "#,
+    )
+    .unwrap();
+
+    let performance = config.profile_config(Some(PowerProfile::Performance));
+    assert_eq!(
+      performance.poll_interval_minimum(),
+      Duration::from_millis(500)
+    );
+    assert_eq!(performance.poll_interval_maximum(), Duration::from_secs(5));
+    assert_eq!(
+      config
+        .profile_config(Some(PowerProfile::Balanced))
+        .poll_interval_maximum(),
+      Duration::from_secs(60),
+    );
+    assert!(performance.validate().is_ok());
+
+    let base_too_long = ProfileConfig {
+      poll_interval_base: Some(Duration::from_secs(10)),
+      ..performance
+    };
+    assert!(base_too_long.validate().is_err());
+  }
+
+  #[test]
+  fn last_modified_covers_files_in_directories() {
+    let directory = std::env::temp_dir()
+      .join(format!("watt-config-modified-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+
+    let file = directory.join("10-rules.toml");
+    fs::write(&file, "").unwrap();
+    let set_modified = |modified: SystemTime| {
+      fs::File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    };
+
+    let later = SystemTime::now() + Duration::from_secs(60);
+    set_modified(later);
+    assert_eq!(DaemonConfig::last_modified(&file), Some(later));
+    assert_eq!(DaemonConfig::last_modified(&directory), Some(later));
+
+    // Editing a file in the directory doesn't touch the directory itself.
+    let even_later = later + Duration::from_secs(60);
+    set_modified(even_later);
+    assert_eq!(DaemonConfig::last_modified(&directory), Some(even_later));
+
+    assert_eq!(
+      DaemonConfig::last_modified(&directory.join("missing")),
+      None
+    );
+
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn directory_configs_are_merged() {
+    let directory = std::env::temp_dir()
+      .join(format!("watt-config-directory-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+
+    let write = |name: &str, contents: &str| {
+      fs::write(directory.join(name), contents).unwrap();
+    };
+
+    write(
+      "10-defaults.toml",
+      r#"
+        min-write-interval = "5s"
+        profiles.power-saver.poll-interval-base = "15s"
+
+        [[rule]]
+        name = "default"
+        priority = 10
+      "#,
+    );
+    write(
+      "20-local.toml",
+      r#"
+        restore-on-exit = false
+        profiles.power-saver.poll-interval-maximum = "2min"
+
+        [[rule]]
+        name = "local"
+        priority = 5
+      "#,
+    );
+    write("README", "not a config");
+
+    let config = DaemonConfig::load_from(Some(&directory)).unwrap();
+
+    assert_eq!(
+      config
+        .rules
+        .iter()
+        .map(|rule| rule.name.as_str())
+        .collect::<Vec<_>>(),
+      ["local", "default"],
+    );
+    assert_eq!(config.min_write_interval, Some(Duration::from_secs(5)));
+    assert!(!config.restore_on_exit);
+    assert_eq!(
+      config.profiles[&PowerProfile::PowerSaver].poll_interval_maximum(),
+      Duration::from_secs(120),
+    );
+
+    write(
+      "30-conflict.toml",
+      r#"
+        [[rule]]
+        name = "conflict"
+        priority = 10
+      "#,
+    );
+
+    let error = DaemonConfig::load_from(Some(&directory))
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("10-defaults.toml"), "{error}");
+    assert!(error.contains("30-conflict.toml"), "{error}");
+
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn included_configs_are_merged() {
+    let directory = std::env::temp_dir()
+      .join(format!("watt-config-include-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(directory.join("common")).unwrap();
+
+    let write = |name: &str, contents: &str| {
+      fs::write(directory.join(name), contents).unwrap();
+    };
+
+    write(
+      "common/rules.toml",
+      r#"
+        min-write-interval = "5s"
+
+        [[rule]]
+        name = "common"
+        priority = 10
+      "#,
+    );
+    write(
+      "watt.toml",
+      r#"
+        include = ["common/rules.toml"]
+        min-write-interval = "10s"
+
+        [[rule]]
+        name = "local"
+        priority = 20
+      "#,
+    );
+
+    let config =
+      DaemonConfig::load_from(Some(&directory.join("watt.toml"))).unwrap();
+    assert_eq!(
+      config
+        .rules
+        .iter()
+        .map(|rule| rule.name.as_str())
+        .collect::<Vec<_>>(),
+      ["common", "local"],
+    );
+    // The including file takes precedence.
+    assert_eq!(config.min_write_interval, Some(Duration::from_secs(10)));
+
+    // Editing only the included file counts as a change.
+    let included = directory.join("common/rules.toml");
+    let later = SystemTime::now() + Duration::from_secs(60);
+    fs::File::options()
+      .write(true)
+      .open(&included)
+      .unwrap()
+      .set_modified(later)
+      .unwrap();
+    assert_eq!(
+      config.files_modified(&directory.join("watt.toml")),
+      Some(later)
+    );
+
+    write(
+      "conflict.toml",
+      r#"
+        include = ["common/rules.toml"]
+
+        [[rule]]
+        name = "conflict"
+        priority = 10
+      "#,
+    );
+    let error = DaemonConfig::load_from(Some(&directory.join("conflict.toml")))
+      .unwrap_err()
+      .to_string();
+    assert!(error.contains("rules.toml"), "{error}");
+    assert!(error.contains("conflict.toml"), "{error}");
+
+    write("a.toml", r#"include = ["b.toml"]"#);
+    write("b.toml", r#"include = ["a.toml"]"#);
+    let error =
+      DaemonConfig::load_from(Some(&directory.join("a.toml"))).unwrap_err();
+    assert!(
+      format!("{error:#}").contains("includes itself"),
+      "{error:#}"
+    );
+
+    let _ = fs::remove_dir_all(&directory);
+  }
+
+  #[test]
+  fn schema_describes_rules_and_expressions() {
+    let schema = serde_json::to_value(DaemonConfig::schema()).unwrap();
+
+    assert_eq!(
+      schema["$defs"]["Rule"]["required"],
+      serde_json::json!(["priority"]),
+    );
+
+    let expressions =
+      schema["$defs"]["Expression"]["anyOf"].as_array().unwrap();
+
+    for named in ["$cpu-temperature", "?discharging", "%battery-health"] {
+      assert!(
+        expressions
+          .iter()
+          .any(|expression| expression["const"] == named),
+        "{named} is missing from the schema",
+      );
+    }
+
+    assert!(expressions.iter().any(|expression| {
+      expression["required"] == serde_json::json!(["value", "is-more-than"])
+    }));
+
+    assert!(schema["properties"]["include"].is_object());
+    assert!(schema["properties"]["files"].is_null());
+  }
+
+  #[test]
+  fn rules_may_be_unnamed() {
+    let config: DaemonConfig = toml::from_str(
+      r#"
+        [[rule]]
+        priority = 10
+        cpu.governor = "powersave"
+        power.platform-profile = "low-power"
+
+        [[rule]]
+        name = "always"
+        priority = 20
+      "#,
+    )
+    .unwrap();
+
+    let [unnamed, named] = &config.rules[..] else {
+      panic!("expected two rules");
+    };
+
+    assert_eq!(unnamed.label(), "priority 10");
+    assert_eq!(unnamed.to_string(), "with priority 10");
+    assert_eq!(unnamed.sections(), ["cpu", "power"]);
+
+    assert_eq!(named.label(), "always");
+    assert_eq!(named.to_string(), "'always' (priority 20)");
+    assert_eq!(named.sections(), Vec::<&str>::new());
+  }
+
+  /// Peripherals with a single battery, `BAT0`, that has no thresholds set.
+  fn battery_peripherals() -> Peripherals {
+    Peripherals {
+      power_supplies: HashSet::from([Arc::new(power_supply::PowerSupply {
+        type_: "Battery".to_owned(),
+        ..power_supply::PowerSupply::unscanned(
+          "BAT0".to_owned(),
+          "/sys/class/power_supply/BAT0".into(),
+        )
+      })]),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn charge_thresholds_are_whole_percentages() {
+    let peripherals = battery_peripherals();
+
+    let eval = |end: f64| {
+      PowersDelta {
+        charge_threshold_end: Some(Expression::Number(end)),
+        ..PowersDelta::default()
+      }
+      .eval(&peripherals.state())
+      .map(|(deltas, _)| deltas.into_values().next().unwrap())
+    };
+
+    assert_eq!(
+      eval(80.0).unwrap().charge_threshold_end,
+      Some(Percent::from_percent(80.0)),
+    );
+    // Used to be truncated to 79.
+    assert!(eval(79.5).is_err());
+    assert!(eval(101.0).is_err());
+    assert!(eval(-1.0).is_err());
+  }
+
+  #[test]
+  fn charge_threshold_target_sets_both_thresholds() {
+    let peripherals = battery_peripherals();
+
+    let eval = |delta: &str| {
+      toml::from_str::<PowersDelta>(delta)
+        .unwrap()
+        .eval(&peripherals.state())
+        .map(|(deltas, _)| {
+          let delta = deltas.into_values().next().unwrap();
+          (delta.charge_threshold_start, delta.charge_threshold_end)
+        })
+    };
+
+    assert_eq!(
+      eval("charge-threshold-target = 80").unwrap(),
+      (
+        Some(Percent::from_percent(75.0)),
+        Some(Percent::from_percent(80.0))
+      ),
+    );
+    assert_eq!(
+      eval("charge-threshold-target = 3\ncharge-threshold-gap = 10").unwrap(),
+      (Some(Percent::ZERO), Some(Percent::from_percent(3.0))),
+    );
+    assert!(
+      eval("charge-threshold-target = 80\ncharge-threshold-end = 90").is_err()
+    );
+  }
+
+  #[test]
+  fn string_equality_and_membership_match_cpu_governor() {
+    let mut peripherals = Peripherals::default();
+    let with_governors = |governors: [&str; 2]| {
+      (0..2)
+        .map(|number| {
+          Arc::new(cpu::Cpu {
+            number,
+            governor: Some(governors[number as usize].to_owned()),
+            ..cpu::Cpu::default()
+          })
+        })
+        .collect()
+    };
+
+    let is_powersave: Expression = toml::from_str::<toml::Table>(
+      r#"if = { value = "$cpu-governor", is = "powersave" }"#,
+    )
+    .unwrap()["if"]
+      .clone()
+      .try_into()
+      .unwrap();
+    let is_known: Expression = toml::from_str::<toml::Table>(
+      r#"if = { value = "$cpu-governor", in = ["powersave", "schedutil"] }"#,
+    )
+    .unwrap()["if"]
+      .clone()
+      .try_into()
+      .unwrap();
+
+    peripherals.cpus = with_governors(["powersave", "powersave"]);
+    let state = peripherals.state();
+    assert_eq!(
+      is_powersave.eval(&state).unwrap(),
+      Some(Expression::Boolean(true)),
+    );
+    assert_eq!(
+      is_known.eval(&state).unwrap(),
+      Some(Expression::Boolean(true))
+    );
+
+    // Undefined while the CPUs disagree, but defined for each of them.
+    peripherals.cpus = with_governors(["performance", "powersave"]);
+    let state = peripherals.state();
+    assert_eq!(is_powersave.eval(&state).unwrap(), None);
+
+    let cpus: Vec<_> = peripherals.cpus.iter().cloned().collect();
+    for cpu in &cpus {
+      let state = state.in_context(EvalContext::Cpu(cpu));
+      assert_eq!(
+        is_known.eval(&state).unwrap(),
+        Some(Expression::Boolean(cpu.number == 1)),
+      );
+    }
+  }
+
+  #[test]
+  fn durations_are_parsed_from_human_readable_strings() {
+    let config: DaemonConfig = toml::from_str(
+      r#"
+        min-write-interval = "2s"
+
+        [[rule]]
+        name = "debounced"
+        priority = 0
+        hold-for = "1min 30s"
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.min_write_interval, Some(Duration::from_secs(2)));
+    assert!(config.restore_on_exit);
+    assert_eq!(config.rules[0].hold_for, Some(Duration::from_secs(90)));
+
+    assert!(
+      toml::from_str::<DaemonConfig>(r#"min-write-interval = "soon""#).is_err()
+    );
+  }
+
+  #[test]
+  fn cpu_usage_trend_is_slope_of_usage() {
+    let mut peripherals = Peripherals::default();
+    assert_eq!(
+      Expression::CpuUsageTrend
+        .eval(&peripherals.state())
+        .unwrap(),
+      None,
+    );
+
+    // 0.05 more usage every 5 seconds.
+    peripherals.cpu_log = cpu_log(
+      [0.2, 0.25, 0.3, 0.35, 0.4]
+        .into_iter()
+        .map(|usage| (usage, None)),
+    );
+
+    let Some(Expression::Number(trend)) = Expression::CpuUsageTrend
+      .eval(&peripherals.state())
+      .unwrap()
+    else {
+      panic!("expected a number");
+    };
+    assert!((trend - 0.01).abs() < 1e-9, "unexpected trend: {trend}");
+  }
+
+  #[test]
+  fn hour_of_day_and_weekend_follow_local_time() {
+    let peripherals = Peripherals::default();
+
+    // A Saturday.
+    let state = EvalState {
+      local_time: jiff::civil::date(2025, 3, 8).at(23, 45, 0, 0),
+      ..peripherals.state()
+    };
+    assert_eq!(
+      Expression::HourOfDay.eval(&state).unwrap(),
+      Some(Expression::Number(23.75)),
+    );
+    assert_eq!(
+      Expression::Weekend.eval(&state).unwrap(),
+      Some(Expression::Boolean(true)),
+    );
+
+    // The Monday after.
+    let state = EvalState {
+      local_time: jiff::civil::date(2025, 3, 10).at(6, 30, 0, 0),
+      ..state
+    };
+    assert_eq!(
+      Expression::HourOfDay.eval(&state).unwrap(),
+      Some(Expression::Number(6.5)),
+    );
+    assert_eq!(
+      Expression::Weekend.eval(&state).unwrap(),
+      Some(Expression::Boolean(false)),
+    );
+  }
+
+  #[test]
+  fn diff_rules_reports_field_level_changes() {
+    let old: DaemonConfig = toml::from_str(
+      r#"
+        [[rule]]
+        name = "battery"
+        priority = 10
+        if = "?discharging"
+        cpu.governor = "powersave"
+
+        [[rule]]
+        name = "old"
+        priority = 20
+        cpu.turbo = false
+      "#,
+    )
+    .unwrap();
+
+    let new: DaemonConfig = toml::from_str(
+      r#"
+        [[rule]]
+        name     = "battery"
+        priority = 10
+        if       = "?discharging"
+
+        cpu.governor = "performance"
+
+        [[rule]]
+        name = "new"
+        priority = 30
+      "#,
+    )
+    .unwrap();
+
+    assert_eq!(old.diff_rules(&new).unwrap(), [
+      RuleChange::Removed {
+        name:     "old".to_owned(),
+        priority: 20,
+      },
+      RuleChange::Modified {
+        name:    "battery".to_owned(),
+        changes: vec![FieldChange {
+          path: "cpu.governor".to_owned(),
+          old:  Some(r#""powersave""#.to_owned()),
+          new:  Some(r#""performance""#.to_owned()),
+        }],
+      },
+      RuleChange::Added {
+        name:     "new".to_owned(),
+        priority: 30,
+      },
+    ]);
+
+    assert_eq!(old.diff_settings(&new).unwrap(), []);
+  }
 }
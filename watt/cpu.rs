@@ -1,11 +1,18 @@
 use std::{
   cell::OnceCell,
-  collections::HashMap,
+  collections::{
+    HashMap,
+    HashSet,
+  },
   fmt,
   fs::OpenOptions,
   hash,
   io::Write,
   mem,
+  path::{
+    Path,
+    PathBuf,
+  },
   string::ToString,
   sync::Arc,
 };
@@ -17,7 +24,10 @@ use anyhow::{
 };
 use yansi::Paint as _;
 
-use crate::fs;
+use crate::{
+  error::WattError,
+  fs,
+};
 
 #[derive(Default, Debug, Clone, PartialEq)]
 struct CpuScanCache {
@@ -67,14 +77,117 @@ impl CpuStat {
   }
 }
 
+/// The kind of core on hybrid CPUs, such as Intel chips with P-cores and
+/// E-cores or ARM big.LITTLE designs.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreType {
+  Performance,
+  Efficiency,
+  #[default]
+  Unknown,
+}
+
+impl CoreType {
+  pub const ALL: [CoreType; 3] = [
+    CoreType::Performance,
+    CoreType::Efficiency,
+    CoreType::Unknown,
+  ];
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      CoreType::Performance => "performance",
+      CoreType::Efficiency => "efficiency",
+      CoreType::Unknown => "unknown",
+    }
+  }
+}
+
+impl fmt::Display for CoreType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl std::str::FromStr for CoreType {
+  type Err = anyhow::Error;
+
+  fn from_str(value: &str) -> anyhow::Result<Self> {
+    Self::ALL
+      .into_iter()
+      .find(|core_type| core_type.as_str() == value)
+      .with_context(|| {
+        format!(
+          "invalid core type '{value}', expected one of: performance, \
+           efficiency, unknown"
+        )
+      })
+  }
+}
+
+/// Cores below this fraction of the fastest core are efficiency cores.
+/// Performance cores can differ slightly among themselves, for example with
+/// Intel Turbo Boost Max 3.0 favoring a few cores.
+const EFFICIENCY_CORE_RATIO: f64 = 0.85;
+
+/// Classifies every CPU by comparing its `cpu_capacity` against the largest
+/// one, falling back to `cpuinfo_max_freq` when no CPU exposes a capacity.
+/// CPUs that don't report the compared value, such as offline ones, stay
+/// [`CoreType::Unknown`].
+fn classify_core_types(cpus: &mut [Cpu]) {
+  let key: fn(&Cpu) -> Option<u64> =
+    if cpus.iter().any(|cpu| cpu.capacity.is_some()) {
+      |cpu| cpu.capacity
+    } else {
+      |cpu| cpu.frequency_mhz_maximum
+    };
+
+  let Some(highest) = cpus.iter().filter_map(key).max() else {
+    log::debug!(
+      "cannot classify core types, capacities and maximum frequencies are \
+       unknown"
+    );
+    return;
+  };
+
+  for cpu in cpus.iter_mut() {
+    cpu.core_type = match key(cpu) {
+      Some(key) if (key as f64) < highest as f64 * EFFICIENCY_CORE_RATIO => {
+        CoreType::Efficiency
+      },
+      Some(_) => CoreType::Performance,
+      None => CoreType::Unknown,
+    };
+
+    log::trace!("{cpu} is a {core_type} core", core_type = cpu.core_type);
+  }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct Cpu {
   pub number: u32,
 
-  pub has_cpufreq: bool,
-
-  pub available_governors: Vec<String>,
-  pub governor:            Option<String>,
+  /// Whether the CPU is online. Offline CPUs have no cpufreq controls and no
+  /// usage statistics.
+  pub online:         bool,
+  pub has_cpufreq:    bool,
+  /// The cpufreq policy this CPU belongs to. CPUs in the same policy share
+  /// their scaling settings.
+  pub policy_id:      Option<u32>,
+  /// The cpufreq driver, such as `amd-pstate-epp` or `intel_pstate`.
+  pub scaling_driver: Option<String>,
+  /// The relative compute capacity from `cpu_capacity`, where the fastest core
+  /// is 1024. Only exposed on some hybrid systems.
+  pub capacity:       Option<u64>,
+  /// Whether this is a performance or efficiency core, see
+  /// [`classify_core_types`].
+  pub core_type:      CoreType,
+
+  pub available_governors:     Vec<String>,
+  pub governor:                Option<String>,
+  /// The `rate_limit_us` tunable of the `schedutil` governor, `None` with
+  /// other governors.
+  pub schedutil_rate_limit_us: Option<u64>,
 
   pub frequency_mhz:         Option<u64>,
   pub frequency_mhz_minimum: Option<u64>,
@@ -115,8 +228,8 @@ impl fmt::Display for Cpu {
 }
 
 impl Cpu {
-  /// Returns current CPU usage based on delta from previous reading.
-  /// Returns 0.0 on first reading when no previous stat is available.
+  /// Returns current CPU usage based on delta from the previous scan.
+  /// Returns 0.0 on the first scan, when no previous stat is available.
   pub fn current_usage(&self) -> f64 {
     match &self.previous_stat {
       Some(prev) => self.stat.usage_delta(prev),
@@ -125,15 +238,33 @@ impl Cpu {
   }
   /// Get all CPUs.
   pub fn all() -> anyhow::Result<Vec<Cpu>> {
-    fn from_number(number: u32, cache: &CpuScanCache) -> anyhow::Result<Cpu> {
+    Self::rescan_all(&HashSet::new())
+  }
+
+  /// Get all CPUs, reusing the attributes that don't change while the system
+  /// runs, such as the available governors, from the `previous` scan. Usage
+  /// is measured since the `previous` scan, not since boot.
+  pub fn rescan_all(previous: &HashSet<Arc<Cpu>>) -> anyhow::Result<Vec<Cpu>> {
+    let from_number = |number: u32, cache: &CpuScanCache| {
       let mut cpu = Cpu {
         number,
         ..Cpu::default()
       };
+
+      if let Some(previous) =
+        previous.iter().find(|previous| previous.number == number)
+      {
+        cpu.available_governors = previous.available_governors.clone();
+        cpu.available_epps = previous.available_epps.clone();
+        cpu.available_epbs = previous.available_epbs.clone();
+        cpu.capacity = previous.capacity;
+        cpu.previous_stat = Some(previous.stat.clone());
+      }
+
       cpu.scan(cache)?;
 
-      Ok(cpu)
-    }
+      anyhow::Ok(cpu)
+    };
 
     const PATH: &str = "/sys/devices/system/cpu";
 
@@ -177,6 +308,8 @@ impl Cpu {
       }
     }
 
+    classify_core_types(&mut cpus);
+
     log::info!("detected {len} CPUs", len = cpus.len());
 
     Ok(cpus)
@@ -186,12 +319,25 @@ impl Cpu {
   fn scan(&mut self, cache: &CpuScanCache) -> anyhow::Result<()> {
     log::debug!("scanning CPU {number}", number = self.number);
 
-    let Self { number, .. } = self;
+    let Self { number, .. } = *self;
 
     if !fs::exists(format!("/sys/devices/system/cpu/cpu{number}")) {
       bail!("{self} does not exist");
     }
 
+    // CPUs that can't be taken offline, such as CPU 0, have no `online` file.
+    self.online =
+      fs::read_n::<u64>(format!("/sys/devices/system/cpu/cpu{number}/online"))
+        .with_context(|| format!("failed to read whether {self} is online"))?
+        != Some(0);
+
+    if !self.online {
+      log::debug!("{self} is offline, skipping the rest of its scan");
+
+      self.has_cpufreq = false;
+      return Ok(());
+    }
+
     self.has_cpufreq =
       fs::exists(format!("/sys/devices/system/cpu/cpu{number}/cpufreq"));
 
@@ -202,29 +348,170 @@ impl Cpu {
     );
 
     if self.has_cpufreq {
+      self.scan_policy()?;
+      self.scan_driver()?;
       self.scan_governor()?;
+      self.scan_schedutil_rate_limit()?;
       self.scan_frequency()?;
       self.scan_epp()?;
       self.scan_epb()?;
     }
 
+    self.scan_capacity()?;
     self.scan_stat(cache)?;
     self.scan_info(cache)?;
 
     Ok(())
   }
 
+  fn scan_capacity(&mut self) -> anyhow::Result<()> {
+    log::trace!("scanning capacity for CPU {number}", number = self.number);
+
+    if self.capacity.is_some() {
+      return Ok(());
+    }
+
+    let Self { number, .. } = *self;
+
+    self.capacity = fs::read_n::<u64>(format!(
+      "/sys/devices/system/cpu/cpu{number}/cpu_capacity"
+    ))
+    .with_context(|| format!("failed to parse {self} capacity"))?;
+
+    Ok(())
+  }
+
+  fn scan_policy(&mut self) -> anyhow::Result<()> {
+    log::trace!("scanning policy for CPU {number}", number = self.number);
+
+    let Self { number, .. } = *self;
+
+    // The cpufreq directory is a symlink to `../cpufreq/policyN`.
+    self.policy_id =
+      fs::read_link(format!("/sys/devices/system/cpu/cpu{number}/cpufreq"))
+        .with_context(|| format!("failed to read {self} cpufreq policy"))?
+        .and_then(|target| {
+          target
+            .file_name()?
+            .to_str()?
+            .strip_prefix("policy")?
+            .parse()
+            .ok()
+        });
+
+    Ok(())
+  }
+
+  /// Returns a delta that restores the current settings of this CPU.
+  pub fn snapshot(&self) -> anyhow::Result<Delta> {
+    let Self { number, .. } = *self;
+
+    let online =
+      fs::exists(format!("/sys/devices/system/cpu/cpu{number}/online"))
+        .then_some(self.online);
+
+    if !self.has_cpufreq {
+      return Ok(Delta {
+        online,
+        ..Delta::default()
+      });
+    }
+
+    let frequency_khz_minimum = fs::read_n::<u64>(format!(
+      "/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_min_freq"
+    ))
+    .with_context(|| format!("failed to read {self} minimum frequency"))?;
+
+    let frequency_khz_maximum = fs::read_n::<u64>(format!(
+      "/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_max_freq"
+    ))
+    .with_context(|| format!("failed to read {self} maximum frequency"))?;
+
+    Ok(Delta {
+      governor: self.governor.clone(),
+      schedutil_rate_limit_us: self.schedutil_rate_limit_us,
+      energy_performance_preference: self.epp.clone(),
+      energy_perf_bias: self.epb.clone(),
+      frequency_khz_minimum,
+      frequency_khz_maximum,
+      online,
+      ..Delta::default()
+    })
+  }
+
+  /// Returns a delta that restores the settings of this CPU to the driver
+  /// defaults: a dynamic governor, the full hardware frequency range and the
+  /// `default` EPP. Settings without a known default are left unset.
+  pub fn defaults(&self) -> anyhow::Result<Delta> {
+    let Self { number, .. } = *self;
+
+    if !self.has_cpufreq {
+      return Ok(Delta::default());
+    }
+
+    let governor = DEFAULT_GOVERNORS
+      .iter()
+      .find(|governor| {
+        self
+          .available_governors
+          .iter()
+          .any(|available| available == *governor)
+      })
+      .map(|governor| (*governor).to_owned());
+
+    let frequency_khz_minimum = fs::read_n::<u64>(format!(
+      "/sys/devices/system/cpu/cpu{number}/cpufreq/cpuinfo_min_freq"
+    ))
+    .with_context(|| {
+      format!("failed to read {self} hardware minimum frequency")
+    })?;
+
+    let frequency_khz_maximum = fs::read_n::<u64>(format!(
+      "/sys/devices/system/cpu/cpu{number}/cpufreq/cpuinfo_max_freq"
+    ))
+    .with_context(|| {
+      format!("failed to read {self} hardware maximum frequency")
+    })?;
+
+    let energy_performance_preference = self
+      .available_epps
+      .iter()
+      .any(|epp| epp == "default")
+      .then(|| "default".to_owned());
+
+    Ok(Delta {
+      governor,
+      energy_performance_preference,
+      frequency_khz_minimum,
+      frequency_khz_maximum,
+      ..Delta::default()
+    })
+  }
+
+  fn scan_driver(&mut self) -> anyhow::Result<()> {
+    log::trace!("scanning driver for CPU {number}", number = self.number);
+
+    let Self { number, .. } = *self;
+
+    self.scaling_driver = fs::read_first_line(format!(
+      "/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_driver"
+    ))
+    .with_context(|| format!("failed to read {self} scaling driver"))?;
+
+    Ok(())
+  }
+
   fn scan_governor(&mut self) -> anyhow::Result<()> {
     log::trace!("scanning governor for CPU {number}", number = self.number);
 
     let Self { number, .. } = *self;
 
-    self.governor = fs::read(format!(
+    self.governor = fs::read_first_line(format!(
       "/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_governor"
     ))
     .with_context(|| format!("failed to read {self} scaling governor"))?;
 
-    if self.governor.is_some() {
+    if self.governor.is_some() && self.available_governors.is_empty() {
       self.available_governors = 'available_governors: {
         let Some(content) = fs::read(format!(
           "/sys/devices/system/cpu/cpu{number}/cpufreq/\
@@ -247,6 +534,35 @@ impl Cpu {
     Ok(())
   }
 
+  fn scan_schedutil_rate_limit(&mut self) -> anyhow::Result<()> {
+    self.schedutil_rate_limit_us =
+      if self.governor.as_deref() == Some("schedutil") {
+        fs::read_n::<u64>(self.schedutil_rate_limit_path()).with_context(
+          || format!("failed to read {self} schedutil rate limit"),
+        )?
+      } else {
+        None
+      };
+
+    Ok(())
+  }
+
+  /// The `schedutil` tunables live in the cpufreq policy of the CPU, or in a
+  /// single global directory on systems without per-policy governors.
+  fn schedutil_rate_limit_path(&self) -> PathBuf {
+    let Self { number, .. } = *self;
+
+    let per_policy = PathBuf::from(format!(
+      "/sys/devices/system/cpu/cpu{number}/cpufreq/schedutil/rate_limit_us"
+    ));
+
+    if fs::exists(&per_policy) {
+      per_policy
+    } else {
+      PathBuf::from("/sys/devices/system/cpu/cpufreq/schedutil/rate_limit_us")
+    }
+  }
+
   fn scan_frequency(&mut self) -> anyhow::Result<()> {
     log::trace!("scanning frequency for CPU {number}", number = self.number);
 
@@ -277,44 +593,50 @@ impl Cpu {
 
     let Self { number, .. } = *self;
 
-    self.epp = fs::read(format!(
+    self.epp = fs::read_first_line(format!(
       "/sys/devices/system/cpu/cpu{number}/cpufreq/\
        energy_performance_preference"
     ))
     .with_context(|| format!("failed to read {self} EPP"))?;
 
-    if self.epp.is_some() {
-      self.available_epps = 'available_epps: {
-        let Some(content) = fs::read(format!(
-          "/sys/devices/system/cpu/cpu{number}/cpufreq/\
-           energy_performance_available_preferences"
-        ))
-        .with_context(|| format!("failed to read {self} available EPPs"))?
-        else {
-          break 'available_epps Vec::new();
-        };
-
-        content
-          .split_whitespace()
-          .map(ToString::to_string)
-          .collect()
-      };
+    if self.epp.is_some() && self.available_epps.is_empty() {
+      self.available_epps = self.read_available_epps()?;
     }
 
     Ok(())
   }
 
+  fn read_available_epps(&self) -> anyhow::Result<Vec<String>> {
+    let Self { number, .. } = *self;
+
+    let Some(content) = fs::read(format!(
+      "/sys/devices/system/cpu/cpu{number}/cpufreq/\
+       energy_performance_available_preferences"
+    ))
+    .with_context(|| format!("failed to read {self} available EPPs"))?
+    else {
+      return Ok(Vec::new());
+    };
+
+    Ok(
+      content
+        .split_whitespace()
+        .map(ToString::to_string)
+        .collect(),
+    )
+  }
+
   fn scan_epb(&mut self) -> anyhow::Result<()> {
     log::trace!("scanning EPB for CPU {number}", number = self.number);
 
     let Self { number, .. } = self;
 
-    self.epb = fs::read(format!(
+    self.epb = fs::read_first_line(format!(
       "/sys/devices/system/cpu/cpu{number}/power/energy_perf_bias"
     ))
     .with_context(|| format!("failed to read {self} EPB"))?;
 
-    if self.epb.is_some() {
+    if self.epb.is_some() && self.available_epbs.is_empty() {
       self.available_epbs = vec![
         "0".to_owned(),
         "1".to_owned(),
@@ -386,10 +708,6 @@ impl Cpu {
       },
     };
 
-    // Store current stat as previous before updating to enable delta
-    // calculation
-    self.previous_stat = Some(self.stat.clone());
-
     self.stat = stat
       .get(&self.number)
       .with_context(|| format!("failed to get stat of {self}"))?
@@ -457,34 +775,46 @@ impl Cpu {
     Ok(())
   }
 
-  pub fn set_governor(&mut self, governor: &str) -> anyhow::Result<()> {
+  pub fn set_governor(
+    &mut self,
+    context: &fs::ApplyContext,
+    governor: &str,
+  ) -> anyhow::Result<()> {
     let Self {
       number,
       available_governors: ref governors,
       ..
     } = *self;
 
+    if governors.is_empty() {
+      bail!(WattError::UnsupportedControl {
+        control: format!("governor control of {self}"),
+      });
+    }
+
     if !governors
       .iter()
       .any(|avail_governor| avail_governor == governor)
     {
-      bail!(
-        "governor '{governor}' is not available for {self}. available \
-         governors: {governors}",
-        governors = governors.join(", "),
-      );
+      bail!(WattError::InvalidValue {
+        control:   "governor".to_owned(),
+        value:     governor.to_owned(),
+        target:    self.to_string(),
+        available: governors.clone(),
+      });
     }
 
-    fs::write(
-      format!("/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_governor"),
-      governor,
-    )
-    .with_context(|| {
-      format!(
-        "this probably means that {self} doesn't exist or doesn't support \
-         changing governors"
+    context
+      .write(
+        format!("/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_governor"),
+        governor,
       )
-    })?;
+      .with_context(|| {
+        format!(
+          "this probably means that {self} doesn't exist or doesn't support \
+           changing governors"
+        )
+      })?;
 
     self.governor = Some(governor.to_owned());
 
@@ -496,34 +826,63 @@ impl Cpu {
     Ok(())
   }
 
-  pub fn set_epp(&mut self, epp: &str) -> anyhow::Result<()> {
+  pub fn set_epp(
+    &mut self,
+    context: &fs::ApplyContext,
+    epp: &str,
+  ) -> anyhow::Result<()> {
+    // The available EPPs change when the scaling driver switches modes, such
+    // as amd_pstate going from guided to active, so the cached list may be
+    // stale.
+    if !self.available_epps.iter().any(|avail_epp| avail_epp == epp) {
+      let available_epps = self.read_available_epps()?;
+
+      if available_epps != self.available_epps {
+        log::info!(
+          "available EPP values of {self} changed from [{old}] to [{new}]",
+          old = self.available_epps.join(", "),
+          new = available_epps.join(", "),
+        );
+
+        self.available_epps = available_epps;
+      }
+    }
+
     let Self {
       number,
       available_epps: ref epps,
       ..
     } = *self;
 
+    if epps.is_empty() {
+      bail!(WattError::UnsupportedControl {
+        control: format!("EPP control of {self}"),
+      });
+    }
+
     if !epps.iter().any(|avail_epp| avail_epp == epp) {
-      bail!(
-        "EPP value '{epp}' is not available for {self}. available EPP values: \
-         {epps}",
-        epps = epps.join(", "),
-      );
+      bail!(WattError::InvalidValue {
+        control:   "EPP value".to_owned(),
+        value:     epp.to_owned(),
+        target:    self.to_string(),
+        available: epps.clone(),
+      });
     }
 
-    fs::write(
-      format!(
-        "/sys/devices/system/cpu/cpu{number}/cpufreq/\
-         energy_performance_preference"
-      ),
-      epp,
-    )
-    .with_context(|| {
-      format!(
-        "this probably means that {self} doesn't exist or doesn't support \
-         changing EPP"
+    context
+      .write(
+        format!(
+          "/sys/devices/system/cpu/cpu{number}/cpufreq/\
+           energy_performance_preference"
+        ),
+        epp,
       )
-    })?;
+      .with_context(|| {
+        format!(
+          "this probably means that {self} doesn't exist or doesn't support \
+           changing EPP"
+        )
+      })?;
 
     self.epp = Some(epp.to_owned());
 
@@ -532,31 +891,43 @@ impl Cpu {
     Ok(())
   }
 
-  pub fn set_epb(&mut self, epb: &str) -> anyhow::Result<()> {
+  pub fn set_epb(
+    &mut self,
+    context: &fs::ApplyContext,
+    epb: &str,
+  ) -> anyhow::Result<()> {
     let Self {
       number,
       available_epbs: ref epbs,
       ..
     } = *self;
 
+    if epbs.is_empty() {
+      bail!(WattError::UnsupportedControl {
+        control: format!("EPB control of {self}"),
+      });
+    }
+
     if !epbs.iter().any(|avail_epb| avail_epb == epb) {
-      bail!(
-        "EPB value '{epb}' is not available for {self}. available EPB values: \
-         {valid}",
-        valid = epbs.join(", "),
-      );
+      bail!(WattError::InvalidValue {
+        control:   "EPB value".to_owned(),
+        value:     epb.to_owned(),
+        target:    self.to_string(),
+        available: epbs.clone(),
+      });
     }
 
-    fs::write(
-      format!("/sys/devices/system/cpu/cpu{number}/power/energy_perf_bias"),
-      epb,
-    )
-    .with_context(|| {
-      format!(
-        "this probably means that {self} doesn't exist or doesn't support \
-         changing EPB"
+    context
+      .write(
+        format!("/sys/devices/system/cpu/cpu{number}/power/energy_perf_bias"),
+        epb,
       )
-    })?;
+      .with_context(|| {
+        format!(
+          "this probably means that {self} doesn't exist or doesn't support \
+           changing EPB"
+        )
+      })?;
 
     self.epb = Some(epb.to_owned());
 
@@ -567,133 +938,192 @@ impl Cpu {
 
   pub fn set_frequency_mhz_minimum(
     &self,
+    context: &fs::ApplyContext,
     frequency_mhz: u64,
+  ) -> anyhow::Result<()> {
+    // We use u64 for the intermediate calculation to prevent overflow
+    self.set_frequency_khz_minimum(context, frequency_mhz * 1000)
+  }
+
+  pub fn set_frequency_khz_minimum(
+    &self,
+    context: &fs::ApplyContext,
+    frequency_khz: u64,
   ) -> anyhow::Result<()> {
     let Self { number, .. } = *self;
 
-    self.validate_frequency_mhz_minimum(frequency_mhz)?;
+    self.validate_frequency_khz_minimum(frequency_khz)?;
 
-    // We use u64 for the intermediate calculation to prevent overflow
-    let frequency_khz = frequency_mhz * 1000;
-    let frequency_khz = frequency_khz.to_string();
-
-    fs::write(
-      format!("/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_min_freq"),
-      &frequency_khz,
-    )
-    .with_context(|| {
-      format!(
-        "this probably means that {self} doesn't exist or doesn't support \
-         changing minimum frequency"
+    context
+      .write(
+        format!("/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_min_freq"),
+        &frequency_khz.to_string(),
       )
-    })?;
+      .with_context(|| {
+        format!(
+          "this probably means that {self} doesn't exist or doesn't support \
+           changing minimum frequency"
+        )
+      })?;
 
     log::info!(
-      "CPU {number} min frequency set to {frequency_mhz} MHz",
+      "CPU {number} min frequency set to {frequency_khz} kHz",
       number = self.number,
     );
 
     Ok(())
   }
 
-  fn validate_frequency_mhz_minimum(
+  fn validate_frequency_khz_minimum(
     &self,
-    new_frequency_mhz: u64,
+    new_frequency_khz: u64,
   ) -> anyhow::Result<()> {
     let Self { number, .. } = self;
 
-    let Some(minimum_frequency_khz) = fs::read_n::<u64>(format!(
-      "/sys/devices/system/cpu/cpu{number}/cpufreq/cpuinfo_min_freq"
-    ))
-    .with_context(|| format!("failed to read {self} minimum frequency"))?
-    else {
-      // Just let it pass if we can't find anything.
-      return Ok(());
-    };
-
-    if new_frequency_mhz * 1000 < minimum_frequency_khz {
-      bail!(
-        "new software minimum frequency ({new_frequency_mhz} MHz) cannot be \
-         lower than the hardware minimum frequency ({mhz} MHz) for {self}",
-        mhz = minimum_frequency_khz / 1000,
-      );
-    }
-
-    Ok(())
+    validate_frequency_khz_minimum(
+      format!("/sys/devices/system/cpu/cpu{number}/cpufreq/cpuinfo_min_freq"),
+      new_frequency_khz,
+    )
+    .with_context(|| format!("invalid minimum frequency for {self}"))
   }
 
   pub fn set_frequency_mhz_maximum(
     &self,
+    context: &fs::ApplyContext,
     frequency_mhz: u64,
   ) -> anyhow::Result<()> {
-    let Self { number, .. } = *self;
+    // We use u64 for the intermediate calculation to prevent overflow
+    self.set_frequency_khz_maximum(context, frequency_mhz * 1000)
+  }
 
-    self.validate_frequency_mhz_maximum(frequency_mhz)?;
+  pub fn set_frequency_khz_maximum(
+    &self,
+    context: &fs::ApplyContext,
+    frequency_khz: u64,
+  ) -> anyhow::Result<()> {
+    let Self { number, .. } = *self;
 
-    // We use u64 for the intermediate calculation to prevent overflow
-    let frequency_khz = frequency_mhz * 1000;
-    let frequency_khz = frequency_khz.to_string();
+    self.validate_frequency_khz_maximum(frequency_khz)?;
 
-    fs::write(
-      format!("/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_max_freq"),
-      &frequency_khz,
-    )
-    .with_context(|| {
-      format!(
-        "this probably means that {self} doesn't exist or doesn't support \
-         changing maximum frequency"
+    context
+      .write(
+        format!("/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_max_freq"),
+        &frequency_khz.to_string(),
       )
-    })?;
+      .with_context(|| {
+        format!(
+          "this probably means that {self} doesn't exist or doesn't support \
+           changing maximum frequency"
+        )
+      })?;
 
     log::info!(
-      "CPU {number} max frequency set to {frequency_mhz} MHz",
+      "CPU {number} max frequency set to {frequency_khz} kHz",
       number = self.number,
     );
 
     Ok(())
   }
 
-  fn validate_frequency_mhz_maximum(
+  fn validate_frequency_khz_maximum(
     &self,
-    new_frequency_mhz: u64,
+    new_frequency_khz: u64,
   ) -> anyhow::Result<()> {
     let Self { number, .. } = self;
 
-    let Some(maximum_frequency_khz) = fs::read_n::<u64>(format!(
-      "/sys/devices/system/cpu/cpu{number}/cpufreq/cpuinfo_max_freq"
-    ))
-    .with_context(|| {
-      format!("failed to read {self} hardware maximum frequency")
-    })?
-    else {
-      // Just let it pass if we can't find anything.
-      return Ok(());
-    };
+    validate_frequency_khz_maximum(
+      format!("/sys/devices/system/cpu/cpu{number}/cpufreq/cpuinfo_max_freq"),
+      new_frequency_khz,
+    )
+    .with_context(|| format!("invalid maximum frequency for {self}"))
+  }
 
-    if new_frequency_mhz * 1000 > maximum_frequency_khz {
-      bail!(
-        "new software maximum frequency ({new_frequency_mhz} MHz) cannot be \
-         higher than the hardware maximum frequency ({mhz} MHz) for {self}",
-        mhz = maximum_frequency_khz / 1000,
-      );
+  /// Pins the frequency by writing `scaling_setspeed`, switching to the
+  /// `userspace` governor first if another one is active.
+  pub fn set_frequency_mhz_exact(
+    &mut self,
+    context: &fs::ApplyContext,
+    frequency_mhz: u64,
+  ) -> anyhow::Result<()> {
+    let frequency_khz = frequency_mhz * 1000;
+
+    self.validate_frequency_khz_minimum(frequency_khz)?;
+    self.validate_frequency_khz_maximum(frequency_khz)?;
+
+    if self.governor.as_deref() != Some("userspace") {
+      self.set_governor(context, "userspace").with_context(|| {
+        format!(
+          "the userspace governor is needed to pin the frequency of {self}"
+        )
+      })?;
     }
 
+    let Self { number, .. } = *self;
+
+    context
+      .write(
+        format!("/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_setspeed"),
+        &frequency_khz.to_string(),
+      )
+      .with_context(|| {
+        format!(
+          "this probably means that {self} doesn't exist or doesn't support \
+           setting an exact frequency"
+        )
+      })?;
+
+    log::info!("CPU {number} frequency pinned to {frequency_mhz} MHz");
+
+    Ok(())
+  }
+
+  /// Sets how often `schedutil` may change the frequency of this CPU.
+  /// Shorter limits react faster to load, longer ones save power.
+  pub fn set_schedutil_rate_limit(
+    &mut self,
+    context: &fs::ApplyContext,
+    rate_limit_us: u64,
+  ) -> anyhow::Result<()> {
+    if self.governor.as_deref() != Some("schedutil") {
+      bail!("the schedutil rate limit of {self} needs the schedutil governor");
+    }
+
+    let Self { number, .. } = *self;
+
+    context
+      .write(self.schedutil_rate_limit_path(), &rate_limit_us.to_string())
+      .with_context(|| {
+        format!(
+          "this probably means that {self} doesn't exist or doesn't support \
+           changing the schedutil rate limit"
+        )
+      })?;
+
+    self.schedutil_rate_limit_us = Some(rate_limit_us);
+
+    log::info!("CPU {number} schedutil rate limit set to {rate_limit_us} us");
+
     Ok(())
   }
 
   pub fn set_pm_qos_resume_latency_us(
     &self,
+    context: &fs::ApplyContext,
     latency: &str,
   ) -> anyhow::Result<()> {
     let Self { number, .. } = *self;
 
-    fs::write(
-      format!(
-        "/sys/devices/system/cpu/cpu{number}/power/pm_qos_resume_latency_us"
-      ),
-      latency,
-    )
-    .with_context(|| {
+    let path = format!(
+      "/sys/devices/system/cpu/cpu{number}/power/pm_qos_resume_latency_us"
+    );
+
+    if !fs::exists(&path) {
+      bail!(WattError::UnsupportedControl {
+        control: format!("PM QoS resume latency of {self}"),
+      });
+    }
+
+    context.write(&path, latency).with_context(|| {
       format!(
         "this probably means that {self} doesn't exist or doesn't support \
          changing PM QoS resume latency"
@@ -708,31 +1138,113 @@ impl Cpu {
     Ok(())
   }
 
-  pub fn set_pstate_min_performance_percent(percent: u8) -> anyhow::Result<()> {
-    fs::write(
-      "/sys/devices/system/cpu/intel_pstate/min_perf_pct",
-      &percent.to_string(),
-    )
-    .context("failed to set Intel P-State minimum performance percent")?;
+  pub fn set_pstate_min_performance_percent(
+    context: &fs::ApplyContext,
+    percent: u8,
+  ) -> anyhow::Result<()> {
+    Self::validate_pstate_performance_percent(percent)?;
+
+    context
+      .write(
+        "/sys/devices/system/cpu/intel_pstate/min_perf_pct",
+        &percent.to_string(),
+      )
+      .context("failed to set Intel P-State minimum performance percent")?;
 
     log::info!("Intel P-State minimum performance set to {percent}%");
 
     Ok(())
   }
 
-  pub fn set_pstate_max_performance_percent(percent: u8) -> anyhow::Result<()> {
-    fs::write(
-      "/sys/devices/system/cpu/intel_pstate/max_perf_pct",
-      &percent.to_string(),
-    )
-    .context("failed to set Intel P-State maximum performance percent")?;
+  pub fn set_pstate_max_performance_percent(
+    context: &fs::ApplyContext,
+    percent: u8,
+  ) -> anyhow::Result<()> {
+    Self::validate_pstate_performance_percent(percent)?;
+
+    context
+      .write(
+        "/sys/devices/system/cpu/intel_pstate/max_perf_pct",
+        &percent.to_string(),
+      )
+      .context("failed to set Intel P-State maximum performance percent")?;
 
     log::info!("Intel P-State maximum performance set to {percent}%");
 
     Ok(())
   }
 
+  fn validate_pstate_performance_percent(percent: u8) -> anyhow::Result<()> {
+    if !Self::is_intel_pstate() {
+      bail!(WattError::UnsupportedControl {
+        control: "Intel P-State performance percent".to_owned(),
+      });
+    }
+
+    if percent > 100 {
+      bail!(
+        "Intel P-State performance percent must be at most 100%, got \
+         {percent}%"
+      );
+    }
+
+    Ok(())
+  }
+
+  /// The current `min_perf_pct` of Intel P-State, `None` without it.
+  pub fn pstate_min_performance_percent() -> anyhow::Result<Option<u8>> {
+    fs::read_n::<u8>("/sys/devices/system/cpu/intel_pstate/min_perf_pct")
+      .context("failed to read Intel P-State minimum performance percent")
+  }
+
+  /// The current `max_perf_pct` of Intel P-State, `None` without it.
+  pub fn pstate_max_performance_percent() -> anyhow::Result<Option<u8>> {
+    fs::read_n::<u8>("/sys/devices/system/cpu/intel_pstate/max_perf_pct")
+      .context("failed to read Intel P-State maximum performance percent")
+  }
+
+  /// Brings the CPU online or takes it offline.
+  pub fn set_online(
+    &mut self,
+    context: &fs::ApplyContext,
+    on: bool,
+  ) -> anyhow::Result<()> {
+    let Self { number, .. } = *self;
+
+    let path = format!("/sys/devices/system/cpu/cpu{number}/online");
+
+    if !fs::exists(&path) {
+      // Without the file, the CPU is always online.
+      if on {
+        return Ok(());
+      }
+
+      bail!(WattError::UnsupportedControl {
+        control: format!("taking {self} offline"),
+      });
+    }
+
+    context
+      .write(&path, if on { "1" } else { "0" })
+      .with_context(|| {
+        format!(
+          "failed to take {self} {state}",
+          state = if on { "online" } else { "offline" },
+        )
+      })?;
+
+    self.online = on;
+
+    log::info!(
+      "{self} set to {state}",
+      state = if on { "online" } else { "offline" }
+    );
+
+    Ok(())
+  }
+
   pub fn set_turbo<'a>(
+    context: &fs::ApplyContext,
     on: bool,
     mut cpus: impl Iterator<Item = &'a Self>,
   ) -> anyhow::Result<()> {
@@ -759,16 +1271,19 @@ impl Cpu {
     let generic_boost_path = "/sys/devices/system/cpu/cpufreq/boost";
 
     // Try each boost control path in order of specificity
-    if fs::write(intel_boost_path_negated, value_boost_negated).is_ok() {
+    if context
+      .write(intel_boost_path_negated, value_boost_negated)
+      .is_ok()
+    {
       return Ok(());
     }
-    if fs::write(amd_boost_path, value_boost).is_ok() {
+    if context.write(amd_boost_path, value_boost).is_ok() {
       return Ok(());
     }
-    if fs::write(msr_boost_path, value_boost).is_ok() {
+    if context.write(msr_boost_path, value_boost).is_ok() {
       return Ok(());
     }
-    if fs::write(generic_boost_path, value_boost).is_ok() {
+    if context.write(generic_boost_path, value_boost).is_ok() {
       return Ok(());
     }
 
@@ -776,16 +1291,88 @@ impl Cpu {
     if cpus.any(|cpu| {
       let Cpu { number, .. } = cpu;
 
-      fs::write(
-        format!("/sys/devices/system/cpu/cpu{number}/cpufreq/boost"),
-        value_boost,
-      )
-      .is_ok()
+      context
+        .write(
+          format!("/sys/devices/system/cpu/cpu{number}/cpufreq/boost"),
+          value_boost,
+        )
+        .is_ok()
     }) {
       return Ok(());
     }
 
-    bail!("no supported CPU boost control mechanism found");
+    bail!(WattError::UnsupportedControl {
+      control: "CPU boost control".to_owned(),
+    });
+  }
+
+  /// Turns simultaneous multithreading on or off for every core.
+  pub fn set_smt(context: &fs::ApplyContext, on: bool) -> anyhow::Result<()> {
+    if !Self::smt_available()? {
+      bail!(WattError::UnsupportedControl {
+        control: "SMT control".to_owned(),
+      });
+    }
+
+    log::info!("setting CPU SMT to {on}");
+
+    context
+      .write(SMT_CONTROL_PATH, if on { "on" } else { "off" })
+      .context("failed to set CPU SMT")
+  }
+
+  /// Whether SMT is active, `None` on systems without SMT support.
+  pub fn smt() -> anyhow::Result<Option<bool>> {
+    log::trace!("reading SMT status");
+
+    Ok(
+      fs::read_n::<u64>("/sys/devices/system/cpu/smt/active")
+        .context("failed to read CPU SMT status")?
+        .map(|active| active == 1),
+    )
+  }
+
+  /// Whether SMT can be turned on and off. CPUs without SMT, and systems
+  /// where it was disabled in firmware or on the kernel command line, report
+  /// a control state other than `on` or `off`.
+  pub fn smt_available() -> anyhow::Result<bool> {
+    Ok(matches!(
+      fs::read_first_line(SMT_CONTROL_PATH)
+        .context("failed to read CPU SMT control")?
+        .as_deref(),
+      Some("on" | "off"),
+    ))
+  }
+
+  /// Turns the HWP dynamic boost of `intel_pstate` on or off, which raises
+  /// the minimum performance of a CPU while it waits on I/O.
+  pub fn set_hwp_dynamic_boost(
+    context: &fs::ApplyContext,
+    on: bool,
+  ) -> anyhow::Result<()> {
+    if Self::hwp_dynamic_boost()?.is_none() {
+      bail!(WattError::UnsupportedControl {
+        control: "HWP dynamic boost".to_owned(),
+      });
+    }
+
+    log::info!("setting HWP dynamic boost to {on}");
+
+    context
+      .write(HWP_DYNAMIC_BOOST_PATH, if on { "1" } else { "0" })
+      .context("failed to set HWP dynamic boost")
+  }
+
+  /// Whether HWP dynamic boost is on, `None` on systems without it, such as
+  /// ones not using `intel_pstate` with HWP.
+  pub fn hwp_dynamic_boost() -> anyhow::Result<Option<bool>> {
+    log::trace!("reading HWP dynamic boost status");
+
+    Ok(
+      fs::read_n::<u64>(HWP_DYNAMIC_BOOST_PATH)
+        .context("failed to read HWP dynamic boost status")?
+        .map(|boost| boost == 1),
+    )
   }
 
   pub fn hardware_frequency_mhz_maximum() -> anyhow::Result<Option<u64>> {
@@ -827,6 +1414,61 @@ impl Cpu {
   }
 }
 
+const SMT_CONTROL_PATH: &str = "/sys/devices/system/cpu/smt/control";
+
+const HWP_DYNAMIC_BOOST_PATH: &str =
+  "/sys/devices/system/cpu/intel_pstate/hwp_dynamic_boost";
+
+/// Governors that `watt cpu set --reset` falls back to, in order of
+/// preference. `powersave` is the default of `intel_pstate` and
+/// `amd-pstate-epp` in active mode, which offer no dynamic governors.
+const DEFAULT_GOVERNORS: &[&str] = &["schedutil", "ondemand", "powersave"];
+
+/// Checks a new software minimum frequency against the hardware minimum read
+/// from `cpuinfo_min_freq`. Passes if the hardware minimum is unknown.
+fn validate_frequency_khz_minimum(
+  cpuinfo_min_freq: impl AsRef<Path>,
+  new_frequency_khz: u64,
+) -> anyhow::Result<()> {
+  let Some(minimum_frequency_khz) = fs::read_n::<u64>(cpuinfo_min_freq)
+    .context("failed to read hardware minimum frequency")?
+  else {
+    return Ok(());
+  };
+
+  if new_frequency_khz < minimum_frequency_khz {
+    bail!(
+      "new software minimum frequency ({new_frequency_khz} kHz) cannot be \
+       lower than the hardware minimum frequency ({minimum_frequency_khz} kHz)",
+    );
+  }
+
+  Ok(())
+}
+
+/// Checks a new software maximum frequency against the hardware maximum read
+/// from `cpuinfo_max_freq`. Passes if the hardware maximum is unknown.
+fn validate_frequency_khz_maximum(
+  cpuinfo_max_freq: impl AsRef<Path>,
+  new_frequency_khz: u64,
+) -> anyhow::Result<()> {
+  let Some(maximum_frequency_khz) = fs::read_n::<u64>(cpuinfo_max_freq)
+    .context("failed to read hardware maximum frequency")?
+  else {
+    return Ok(());
+  };
+
+  if new_frequency_khz > maximum_frequency_khz {
+    bail!(
+      "new software maximum frequency ({new_frequency_khz} kHz) cannot be \
+       higher than the hardware maximum frequency ({maximum_frequency_khz} \
+       kHz)",
+    );
+  }
+
+  Ok(())
+}
+
 #[derive(Default, Debug, Clone, PartialEq)]
 #[must_use]
 pub struct Delta {
@@ -835,7 +1477,17 @@ pub struct Delta {
   pub energy_perf_bias:              Option<String>,
   pub frequency_mhz_minimum:         Option<u64>,
   pub frequency_mhz_maximum:         Option<u64>,
+  /// Takes precedence over [`Self::frequency_mhz_minimum`].
+  pub frequency_khz_minimum:         Option<u64>,
+  /// Takes precedence over [`Self::frequency_mhz_maximum`].
+  pub frequency_khz_maximum:         Option<u64>,
+  /// Pinned with the `userspace` governor, which replaces
+  /// [`Self::governor`].
+  pub frequency_mhz:                 Option<u64>,
   pub pm_qos_resume_latency_us:      Option<String>,
+  pub online:                        Option<bool>,
+  /// Only applied while the governor is `schedutil`.
+  pub schedutil_rate_limit_us:       Option<u64>,
 }
 
 impl Delta {
@@ -843,75 +1495,311 @@ impl Delta {
     self.governor.is_some()
       && self.energy_performance_preference.is_some()
       && self.energy_perf_bias.is_some()
-      && self.frequency_mhz_minimum.is_some()
-      && self.frequency_mhz_maximum.is_some()
+      && (self.frequency_mhz_minimum.is_some()
+        || self.frequency_khz_minimum.is_some())
+      && (self.frequency_mhz_maximum.is_some()
+        || self.frequency_khz_maximum.is_some())
+      && self.frequency_mhz.is_some()
       && self.pm_qos_resume_latency_us.is_some()
+      && self.online.is_some()
+      && self.schedutil_rate_limit_us.is_some()
   }
 
   pub fn or(self, that: &Self) -> Self {
+    // The MHz and kHz variants of a bound are one setting, so a rule setting
+    // either one of them shadows both of them from lower priority rules.
+    let (frequency_mhz_minimum, frequency_khz_minimum) =
+      if self.frequency_mhz_minimum.is_some()
+        || self.frequency_khz_minimum.is_some()
+      {
+        (self.frequency_mhz_minimum, self.frequency_khz_minimum)
+      } else {
+        (that.frequency_mhz_minimum, that.frequency_khz_minimum)
+      };
+
+    let (frequency_mhz_maximum, frequency_khz_maximum) =
+      if self.frequency_mhz_maximum.is_some()
+        || self.frequency_khz_maximum.is_some()
+      {
+        (self.frequency_mhz_maximum, self.frequency_khz_maximum)
+      } else {
+        (that.frequency_mhz_maximum, that.frequency_khz_maximum)
+      };
+
+    // Pinning the frequency switches to the userspace governor, which would
+    // override the governor of a higher priority rule.
+    let frequency_mhz = if self.governor.is_some() {
+      self.frequency_mhz
+    } else {
+      self.frequency_mhz.or(that.frequency_mhz)
+    };
+
     Self {
-      governor:                      self
-        .governor
-        .or_else(|| that.governor.clone()),
+      governor: self.governor.or_else(|| that.governor.clone()),
       energy_performance_preference: self
         .energy_performance_preference
         .or_else(|| that.energy_performance_preference.clone()),
-      energy_perf_bias:              self
+      energy_perf_bias: self
         .energy_perf_bias
         .or_else(|| that.energy_perf_bias.clone()),
+      frequency_mhz_minimum,
+      frequency_mhz_maximum,
+      frequency_khz_minimum,
+      frequency_khz_maximum,
+      frequency_mhz,
+      pm_qos_resume_latency_us: self
+        .pm_qos_resume_latency_us
+        .or_else(|| that.pm_qos_resume_latency_us.clone()),
+      online: self.online.or(that.online),
+      schedutil_rate_limit_us: self
+        .schedutil_rate_limit_us
+        .or(that.schedutil_rate_limit_us),
+    }
+  }
+
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      governor:                      self
+        .governor
+        .filter(|value| that.governor.as_ref() != Some(value)),
+      energy_performance_preference: self.energy_performance_preference.filter(
+        |value| that.energy_performance_preference.as_ref() != Some(value),
+      ),
+      energy_perf_bias:              self
+        .energy_perf_bias
+        .filter(|value| that.energy_perf_bias.as_ref() != Some(value)),
       frequency_mhz_minimum:         self
         .frequency_mhz_minimum
-        .or(that.frequency_mhz_minimum),
+        .filter(|value| that.frequency_mhz_minimum.as_ref() != Some(value)),
       frequency_mhz_maximum:         self
         .frequency_mhz_maximum
-        .or(that.frequency_mhz_maximum),
+        .filter(|value| that.frequency_mhz_maximum.as_ref() != Some(value)),
+      frequency_khz_minimum:         self
+        .frequency_khz_minimum
+        .filter(|value| that.frequency_khz_minimum.as_ref() != Some(value)),
+      frequency_khz_maximum:         self
+        .frequency_khz_maximum
+        .filter(|value| that.frequency_khz_maximum.as_ref() != Some(value)),
+      frequency_mhz:                 self
+        .frequency_mhz
+        .filter(|value| that.frequency_mhz.as_ref() != Some(value)),
       pm_qos_resume_latency_us:      self
         .pm_qos_resume_latency_us
-        .or_else(|| that.pm_qos_resume_latency_us.clone()),
+        .filter(|value| that.pm_qos_resume_latency_us.as_ref() != Some(value)),
+      online:                        self
+        .online
+        .filter(|value| that.online.as_ref() != Some(value)),
+      schedutil_rate_limit_us:       self
+        .schedutil_rate_limit_us
+        .filter(|value| that.schedutil_rate_limit_us.as_ref() != Some(value)),
     }
   }
 
-  pub fn apply(&self, cpu: &mut Cpu) -> anyhow::Result<()> {
-    if let Some(governor) = &self.governor {
-      cpu.set_governor(governor)?;
+  /// Applies the delta to `cpu`. With `ignore_unsupported`, a control that
+  /// fails to be set is logged and skipped instead of failing the rest.
+  pub fn apply(
+    &self,
+    context: &fs::ApplyContext,
+    cpu: &mut Cpu,
+    ignore_unsupported: bool,
+  ) -> anyhow::Result<()> {
+    // The controls of a CPU that was just brought online only show up in the
+    // next scan, and an offline CPU has none to set.
+    if let Some(online) = self.online
+      && online != cpu.online
+    {
+      return skip_unsupported(
+        cpu.set_online(context, online),
+        ignore_unsupported,
+      );
     }
 
-    if let Some(epp) = &self.energy_performance_preference {
-      cpu.set_epp(epp)?;
+    if !cpu.online {
+      log::debug!("{cpu} is offline, skipping its controls");
+      return Ok(());
+    }
+
+    // Online CPUs can still lack cpufreq, for example when no scaling driver
+    // claimed them.
+    if cpu.has_cpufreq {
+      self.apply_cpufreq(context, cpu, ignore_unsupported)?;
+    } else {
+      log::debug!("{cpu} has no cpufreq, skipping its frequency controls");
     }
 
     if let Some(epb) = &self.energy_perf_bias {
-      cpu.set_epb(epb)?;
+      skip_unsupported(cpu.set_epb(context, epb), ignore_unsupported)?;
     }
 
-    if let Some(mhz_minimum) = self.frequency_mhz_minimum {
-      cpu.set_frequency_mhz_minimum(mhz_minimum)?;
+    if let Some(latency) = &self.pm_qos_resume_latency_us {
+      skip_unsupported(
+        cpu.set_pm_qos_resume_latency_us(context, latency),
+        ignore_unsupported,
+      )?;
     }
 
-    if let Some(mhz_maximum) = self.frequency_mhz_maximum {
-      cpu.set_frequency_mhz_maximum(mhz_maximum)?;
+    Ok(())
+  }
+
+  fn apply_cpufreq(
+    &self,
+    context: &fs::ApplyContext,
+    cpu: &mut Cpu,
+    ignore_unsupported: bool,
+  ) -> anyhow::Result<()> {
+    if let Some(governor) = &self.governor {
+      skip_unsupported(
+        cpu.set_governor(context, governor),
+        ignore_unsupported,
+      )?;
     }
 
-    if let Some(latency) = &self.pm_qos_resume_latency_us {
-      cpu.set_pm_qos_resume_latency_us(latency)?;
+    if let Some(epp) = &self.energy_performance_preference {
+      skip_unsupported(cpu.set_epp(context, epp), ignore_unsupported)?;
+    }
+
+    let khz_minimum = self
+      .frequency_khz_minimum
+      .or(self.frequency_mhz_minimum.map(|mhz| mhz * 1000));
+    let khz_maximum = self
+      .frequency_khz_maximum
+      .or(self.frequency_mhz_maximum.map(|mhz| mhz * 1000));
+
+    // The kernel rejects a minimum above the current maximum and the other
+    // way around, so when both move, the one that makes room goes first.
+    let maximum_first = match (khz_minimum, khz_maximum) {
+      (Some(khz_minimum), Some(khz_maximum)) => {
+        if khz_minimum > khz_maximum {
+          bail!(
+            "minimum frequency of {khz_minimum} kHz is above the maximum \
+             frequency of {khz_maximum} kHz for {cpu}"
+          );
+        }
+
+        let Cpu { number, .. } = *cpu;
+        let current_khz_maximum = fs::read_n::<u64>(format!(
+          "/sys/devices/system/cpu/cpu{number}/cpufreq/scaling_max_freq"
+        ))
+        .with_context(|| format!("failed to read {cpu} maximum frequency"))?;
+
+        current_khz_maximum.is_some_and(|current| khz_minimum > current)
+      },
+
+      _ => false,
+    };
+
+    let set_minimum = || {
+      match khz_minimum {
+        Some(khz_minimum) => {
+          skip_unsupported(
+            cpu.set_frequency_khz_minimum(context, khz_minimum),
+            ignore_unsupported,
+          )
+        },
+        None => Ok(()),
+      }
+    };
+    let set_maximum = || {
+      match khz_maximum {
+        Some(khz_maximum) => {
+          skip_unsupported(
+            cpu.set_frequency_khz_maximum(context, khz_maximum),
+            ignore_unsupported,
+          )
+        },
+        None => Ok(()),
+      }
+    };
+
+    if maximum_first {
+      set_maximum()?;
+      set_minimum()?;
+    } else {
+      set_minimum()?;
+      set_maximum()?;
+    }
+
+    if let Some(frequency_mhz) = self.frequency_mhz {
+      skip_unsupported(
+        cpu.set_frequency_mhz_exact(context, frequency_mhz),
+        ignore_unsupported,
+      )?;
+    }
+
+    if let Some(rate_limit_us) = self.schedutil_rate_limit_us {
+      if cpu.governor.as_deref() == Some("schedutil") {
+        skip_unsupported(
+          cpu.set_schedutil_rate_limit(context, rate_limit_us),
+          ignore_unsupported,
+        )?;
+      } else {
+        log::debug!(
+          "{cpu} doesn't use the schedutil governor, skipping its rate limit"
+        );
+      }
     }
 
     Ok(())
   }
 }
 
+/// Turns a control the system doesn't offer into a warning if
+/// `ignore_unsupported` is set, so the remaining controls still get applied.
+/// Any other failure, such as a rejected value, is returned.
+fn skip_unsupported(
+  result: anyhow::Result<()>,
+  ignore_unsupported: bool,
+) -> anyhow::Result<()> {
+  match result {
+    Err(error)
+      if ignore_unsupported
+        && matches!(
+          error.downcast_ref::<WattError>(),
+          Some(WattError::UnsupportedControl { .. }),
+        ) =>
+    {
+      log::warn!("{error:#}, skipping");
+      Ok(())
+    },
+
+    result => result,
+  }
+}
+
 #[derive(Default, Debug, Clone, PartialEq)]
 #[must_use]
 pub struct GlobalDelta {
   pub turbo:                          Option<bool>,
+  pub smt:                            Option<bool>,
+  pub hwp_dynamic_boost:              Option<bool>,
   pub pstate_min_performance_percent: Option<u8>,
   pub pstate_max_performance_percent: Option<u8>,
   pub dma_latency_us:                 Option<i32>,
 }
 
 impl GlobalDelta {
+  /// Returns a delta that restores the current global CPU settings. SMT is
+  /// left out where it can't be switched.
+  pub fn snapshot() -> anyhow::Result<Self> {
+    Ok(Self {
+      turbo:                          Cpu::turbo()?,
+      smt:                            if Cpu::smt_available()? {
+        Cpu::smt()?
+      } else {
+        None
+      },
+      hwp_dynamic_boost:              Cpu::hwp_dynamic_boost()?,
+      pstate_min_performance_percent: Cpu::pstate_min_performance_percent()?,
+      pstate_max_performance_percent: Cpu::pstate_max_performance_percent()?,
+      // The latency request ends with the daemon anyway.
+      dma_latency_us:                 None,
+    })
+  }
+
   pub fn is_some(&self) -> bool {
     self.turbo.is_some()
+      && self.smt.is_some()
+      && self.hwp_dynamic_boost.is_some()
       && self.pstate_min_performance_percent.is_some()
       && self.pstate_max_performance_percent.is_some()
       && self.dma_latency_us.is_some()
@@ -920,6 +1808,10 @@ impl GlobalDelta {
   pub fn or(self, that: &Self) -> Self {
     Self {
       turbo:                          self.turbo.or(that.turbo),
+      smt:                            self.smt.or(that.smt),
+      hwp_dynamic_boost:              self
+        .hwp_dynamic_boost
+        .or(that.hwp_dynamic_boost),
       pstate_min_performance_percent: self
         .pstate_min_performance_percent
         .or(that.pstate_min_performance_percent),
@@ -932,24 +1824,116 @@ impl GlobalDelta {
     }
   }
 
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      turbo:                          self
+        .turbo
+        .filter(|value| that.turbo.as_ref() != Some(value)),
+      smt:                            self
+        .smt
+        .filter(|value| that.smt.as_ref() != Some(value)),
+      hwp_dynamic_boost:              self
+        .hwp_dynamic_boost
+        .filter(|value| that.hwp_dynamic_boost.as_ref() != Some(value)),
+      pstate_min_performance_percent: self
+        .pstate_min_performance_percent
+        .filter(|value| {
+          that.pstate_min_performance_percent.as_ref() != Some(value)
+        }),
+      pstate_max_performance_percent: self
+        .pstate_max_performance_percent
+        .filter(|value| {
+          that.pstate_max_performance_percent.as_ref() != Some(value)
+        }),
+      // Unset releases the request, and an unchanged one isn't made again
+      // anyway.
+      dma_latency_us:                 self.dma_latency_us,
+    }
+  }
+
+  /// Applies the delta. With `ignore_unsupported`, a control that fails to be
+  /// set is logged and skipped instead of failing the rest.
   pub fn apply<'a>(
     &self,
+    context: &fs::ApplyContext,
     cpus: impl Iterator<Item = &'a Cpu>,
     dma_latency: &mut DmaLatency,
+    ignore_unsupported: bool,
   ) -> anyhow::Result<()> {
-    if let Some(percent) = self.pstate_min_performance_percent {
-      Cpu::set_pstate_min_performance_percent(percent)?;
-    }
+    // Like the frequency limits, the kernel rejects a minimum above the
+    // current maximum, so a maximum that makes room goes first.
+    let maximum_first = match (
+      self.pstate_min_performance_percent,
+      self.pstate_max_performance_percent,
+    ) {
+      (Some(minimum), Some(maximum)) => {
+        if minimum > maximum {
+          bail!(
+            "Intel P-State minimum performance of {minimum}% is above the \
+             maximum of {maximum}%"
+          );
+        }
+
+        Cpu::pstate_max_performance_percent()?
+          .is_some_and(|current| minimum > current)
+      },
+
+      _ => false,
+    };
+
+    let set_minimum = || {
+      match self.pstate_min_performance_percent {
+        Some(percent) => {
+          skip_unsupported(
+            Cpu::set_pstate_min_performance_percent(context, percent),
+            ignore_unsupported,
+          )
+        },
+        None => Ok(()),
+      }
+    };
+    let set_maximum = || {
+      match self.pstate_max_performance_percent {
+        Some(percent) => {
+          skip_unsupported(
+            Cpu::set_pstate_max_performance_percent(context, percent),
+            ignore_unsupported,
+          )
+        },
+        None => Ok(()),
+      }
+    };
 
-    if let Some(percent) = self.pstate_max_performance_percent {
-      Cpu::set_pstate_max_performance_percent(percent)?;
+    if maximum_first {
+      set_maximum()?;
+      set_minimum()?;
+    } else {
+      set_minimum()?;
+      set_maximum()?;
     }
 
     if let Some(turbo) = self.turbo {
-      Cpu::set_turbo(turbo, cpus)?;
+      skip_unsupported(
+        Cpu::set_turbo(context, turbo, cpus),
+        ignore_unsupported,
+      )?;
+    }
+
+    if let Some(smt) = self.smt {
+      skip_unsupported(Cpu::set_smt(context, smt), ignore_unsupported)?;
+    }
+
+    if let Some(hwp_dynamic_boost) = self.hwp_dynamic_boost {
+      skip_unsupported(
+        Cpu::set_hwp_dynamic_boost(context, hwp_dynamic_boost),
+        ignore_unsupported,
+      )?;
     }
 
-    dma_latency.apply(self.dma_latency_us)?;
+    skip_unsupported(
+      dma_latency.apply(context, self.dma_latency_us),
+      ignore_unsupported,
+    )?;
 
     Ok(())
   }
@@ -962,11 +1946,21 @@ pub struct DmaLatency {
 }
 
 impl DmaLatency {
-  pub fn apply(&mut self, latency_us: Option<i32>) -> anyhow::Result<()> {
+  pub fn apply(
+    &mut self,
+    context: &fs::ApplyContext,
+    latency_us: Option<i32>,
+  ) -> anyhow::Result<()> {
     if self.current == latency_us {
       return Ok(());
     }
 
+    if context.dry_run {
+      log::info!("dry run: would request CPU DMA latency of {latency_us:?}");
+      self.current = latency_us;
+      return Ok(());
+    }
+
     if let Some(latency_us) = latency_us {
       let mut file = OpenOptions::new()
         .write(true)
@@ -989,3 +1983,286 @@ impl DmaLatency {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    env,
+    fs,
+    path::PathBuf,
+    process,
+    sync::atomic::{
+      AtomicU64,
+      Ordering,
+    },
+  };
+
+  use super::*;
+
+  static NEXT_TEMP_FILE: AtomicU64 = AtomicU64::new(0);
+
+  struct ValueFixture {
+    path: PathBuf,
+  }
+
+  impl ValueFixture {
+    fn new(value: &str) -> Self {
+      let counter = NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed);
+      let path =
+        env::temp_dir().join(format!("watt-cpu-{}-{counter}", process::id()));
+
+      fs::write(&path, value).expect("write value fixture");
+
+      Self { path }
+    }
+  }
+
+  impl Drop for ValueFixture {
+    fn drop(&mut self) {
+      let _ = fs::remove_file(&self.path);
+    }
+  }
+
+  #[test]
+  fn frequency_minimum_is_validated_against_hardware_minimum() {
+    let cpuinfo_min_freq = ValueFixture::new("400000\n");
+
+    assert!(
+      validate_frequency_khz_minimum(&cpuinfo_min_freq.path, 400_000).is_ok()
+    );
+    assert!(
+      validate_frequency_khz_minimum(&cpuinfo_min_freq.path, 1_200_000).is_ok()
+    );
+    assert!(
+      validate_frequency_khz_minimum(&cpuinfo_min_freq.path, 399_999).is_err()
+    );
+  }
+
+  #[test]
+  fn frequency_maximum_is_validated_against_hardware_maximum() {
+    let cpuinfo_max_freq = ValueFixture::new("4700000\n");
+
+    // Raising the cap up to the hardware maximum has to be possible, no matter
+    // what the current software maximum is.
+    assert!(
+      validate_frequency_khz_maximum(&cpuinfo_max_freq.path, 4_700_000).is_ok()
+    );
+    assert!(
+      validate_frequency_khz_maximum(&cpuinfo_max_freq.path, 1_200_000).is_ok()
+    );
+    assert!(
+      validate_frequency_khz_maximum(&cpuinfo_max_freq.path, 4_700_001)
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn frequency_validation_passes_without_hardware_limits() {
+    let missing = env::temp_dir().join("watt-cpu-missing-cpuinfo-freq");
+
+    assert!(validate_frequency_khz_minimum(&missing, 1).is_ok());
+    assert!(validate_frequency_khz_maximum(&missing, u64::MAX).is_ok());
+  }
+
+  #[test]
+  fn governor_shadows_lower_priority_exact_frequency() {
+    let higher = Delta {
+      governor: Some("powersave".to_owned()),
+      ..Delta::default()
+    };
+    let lower = Delta {
+      governor: Some("userspace".to_owned()),
+      frequency_mhz: Some(1200),
+      ..Delta::default()
+    };
+
+    let merged = higher.or(&lower);
+    assert_eq!(merged.governor.as_deref(), Some("powersave"));
+    assert_eq!(merged.frequency_mhz, None);
+
+    // Without a governor, the exact frequency still comes through.
+    let merged = Delta::default().or(&lower);
+    assert_eq!(merged.frequency_mhz, Some(1200));
+  }
+
+  #[test]
+  fn ignore_unsupported_only_skips_missing_controls() {
+    let context = crate::fs::ApplyContext::default();
+    let mut cpu = Cpu {
+      online: true,
+      has_cpufreq: true,
+      ..Cpu::default()
+    };
+    let delta = Delta {
+      governor: Some("powersave".to_owned()),
+      ..Delta::default()
+    };
+
+    // Without any governors, the control doesn't exist.
+    delta.apply(&context, &mut cpu, true).unwrap();
+    assert!(delta.apply(&context, &mut cpu, false).is_err());
+
+    cpu.available_governors = vec!["performance".to_owned()];
+    let error = delta.apply(&context, &mut cpu, true).unwrap_err();
+    assert!(matches!(
+      error.downcast_ref::<WattError>(),
+      Some(WattError::InvalidValue { .. }),
+    ));
+  }
+
+  #[test]
+  fn set_governor_suggests_closest_governor() {
+    let mut cpu = Cpu {
+      available_governors: vec![
+        "performance".to_owned(),
+        "powersave".to_owned(),
+      ],
+      ..Cpu::default()
+    };
+
+    let error = cpu
+      .set_governor(&crate::fs::ApplyContext::default(), "powersafe")
+      .unwrap_err();
+    assert!(matches!(
+      error.downcast_ref::<WattError>(),
+      Some(WattError::InvalidValue { value, .. }) if value == "powersafe",
+    ));
+
+    let error = error.to_string();
+    assert!(
+      error.contains("did you mean 'powersave'?"),
+      "unexpected error: {error}",
+    );
+    assert_eq!(cpu.governor, None);
+  }
+
+  fn core_types_of(cpus: &mut [Cpu]) -> Vec<CoreType> {
+    classify_core_types(cpus);
+    cpus.iter().map(|cpu| cpu.core_type).collect()
+  }
+
+  #[test]
+  fn core_types_are_classified_by_capacity() {
+    let mut cpus = [1024, 1024, 1010, 420].map(|capacity| {
+      Cpu {
+        capacity: Some(capacity),
+        // Capacity takes precedence over frequency.
+        frequency_mhz_maximum: Some(4000),
+        ..Cpu::default()
+      }
+    });
+
+    assert_eq!(core_types_of(&mut cpus), [
+      CoreType::Performance,
+      CoreType::Performance,
+      CoreType::Performance,
+      CoreType::Efficiency,
+    ]);
+  }
+
+  #[test]
+  fn core_types_fall_back_to_maximum_frequency() {
+    let mut cpus = [5000, 4700, 3800, 3800].map(|frequency| {
+      Cpu {
+        frequency_mhz_maximum: Some(frequency),
+        ..Cpu::default()
+      }
+    });
+
+    assert_eq!(core_types_of(&mut cpus), [
+      CoreType::Performance,
+      CoreType::Performance,
+      CoreType::Efficiency,
+      CoreType::Efficiency,
+    ]);
+
+    // An offline CPU reports neither, which leaves only itself unknown.
+    let mut cpus = [Some(5000), None, Some(3800)].map(|frequency| {
+      Cpu {
+        frequency_mhz_maximum: frequency,
+        ..Cpu::default()
+      }
+    });
+
+    assert_eq!(core_types_of(&mut cpus), [
+      CoreType::Performance,
+      CoreType::Unknown,
+      CoreType::Efficiency,
+    ]);
+  }
+
+  #[test]
+  fn rescan_reuses_available_governors() {
+    let system = crate::harness::FakeSystem::new().cpu(0, "performance");
+
+    let previous = system
+      .with_root(Cpu::all)
+      .unwrap()
+      .into_iter()
+      .map(Arc::new)
+      .collect::<HashSet<_>>();
+
+    let system = system
+      .file(
+        "sys/devices/system/cpu/cpu0/cpufreq/scaling_available_governors",
+        "schedutil",
+      )
+      .file(
+        "sys/devices/system/cpu/cpu0/cpufreq/scaling_governor",
+        "powersave",
+      );
+
+    let cpus = system.with_root(|| Cpu::rescan_all(&previous)).unwrap();
+
+    assert_eq!(cpus[0].available_governors, ["performance", "powersave"]);
+    assert_eq!(cpus[0].governor.as_deref(), Some("powersave"));
+  }
+
+  #[test]
+  fn set_epp_rereads_available_epps() {
+    let epp =
+      "sys/devices/system/cpu/cpu0/cpufreq/energy_performance_preference";
+    let available_epps = "sys/devices/system/cpu/cpu0/cpufreq/\
+                          energy_performance_available_preferences";
+
+    let system = crate::harness::FakeSystem::new()
+      .cpu(0, "performance")
+      .file(epp, "performance")
+      .file(available_epps, "performance");
+
+    let mut cpu = system.with_root(Cpu::all).unwrap().remove(0);
+    assert_eq!(cpu.available_epps, ["performance"]);
+
+    let system = system.file(available_epps, "performance power");
+
+    system
+      .with_root(|| cpu.set_epp(&crate::fs::ApplyContext::default(), "power"))
+      .unwrap();
+    assert_eq!(cpu.available_epps, ["performance", "power"]);
+    assert!(
+      system
+        .with_root(|| {
+          cpu.set_epp(&crate::fs::ApplyContext::default(), "balance_power")
+        })
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn usage_is_measured_since_previous_scan() {
+    let system = crate::harness::FakeSystem::new().cpu(0, "performance");
+
+    let cpus = system.with_root(Cpu::all).unwrap();
+    assert_eq!(cpus[0].current_usage(), 0.0);
+
+    let previous = cpus.into_iter().map(Arc::new).collect::<HashSet<_>>();
+
+    let system = system.file(
+      "proc/stat",
+      "cpu  0 0 0 0 0 0 0 0\ncpu0 600 0 100 1300 0 0 0 0\n",
+    );
+
+    let cpus = system.with_root(|| Cpu::rescan_all(&previous)).unwrap();
+    assert_eq!(cpus[0].current_usage(), 0.5);
+  }
+}
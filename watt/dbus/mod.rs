@@ -1,3 +1,75 @@
+use std::collections::HashMap;
+
+use zbus::{
+  Connection,
+  fdo,
+  message::Header,
+  names::BusName,
+  zvariant::Value,
+};
+
+pub mod notifications;
 pub mod ppd;
 pub mod server;
 pub mod watt;
+
+/// The polkit action that lets users other than root rescan the system and
+/// apply the rules over D-Bus.
+const REEVALUATE_ACTION: &str = "dev.notashelf.Watt.reevaluate";
+
+/// The polkit action that lets users other than root switch the power profile
+/// over D-Bus, through Watt's own interface or the power-profiles-daemon one.
+const SET_PROFILE_ACTION: &str = "dev.notashelf.Watt.set-profile";
+
+/// Fails unless the sender of the message is root or polkit authorizes
+/// `action` for it.
+async fn authorize(
+  connection: &Connection,
+  header: Option<&Header<'_>>,
+  action: &str,
+) -> fdo::Result<()> {
+  let sender = header.and_then(Header::sender).ok_or_else(|| {
+    fdo::Error::AccessDenied("message has no sender".to_owned())
+  })?;
+
+  let uid = fdo::DBusProxy::new(connection)
+    .await?
+    .get_connection_unix_user(BusName::from(sender.clone()))
+    .await?;
+
+  if uid == 0 {
+    return Ok(());
+  }
+
+  let subject = (
+    "system-bus-name",
+    HashMap::from([("name", Value::from(sender.as_str()))]),
+  );
+
+  // Flag 1 lets polkit ask the user to authenticate.
+  let reply = connection
+    .call_method(
+      Some("org.freedesktop.PolicyKit1"),
+      "/org/freedesktop/PolicyKit1/Authority",
+      Some("org.freedesktop.PolicyKit1.Authority"),
+      "CheckAuthorization",
+      &(subject, action, HashMap::<&str, &str>::new(), 1u32, ""),
+    )
+    .await
+    .map_err(|error| {
+      fdo::Error::AccessDenied(format!(
+        "only root may call this without polkit: {error}"
+      ))
+    })?;
+
+  let (authorized, ..): (bool, bool, HashMap<String, String>) =
+    reply.body().deserialize()?;
+
+  if !authorized {
+    return Err(fdo::Error::AccessDenied(format!(
+      "not authorized for {action}"
+    )));
+  }
+
+  Ok(())
+}
@@ -0,0 +1,60 @@
+//! Desktop notifications through `org.freedesktop.Notifications` on the
+//! session bus.
+
+use std::collections::HashMap;
+
+use zbus::{
+  Connection,
+  proxy,
+  zvariant::Value,
+};
+
+#[proxy(
+  interface = "org.freedesktop.Notifications",
+  default_service = "org.freedesktop.Notifications",
+  default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+  #[allow(clippy::too_many_arguments)]
+  fn notify(
+    &self,
+    app_name: &str,
+    replaces_id: u32,
+    app_icon: &str,
+    summary: &str,
+    body: &str,
+    actions: &[&str],
+    hints: HashMap<&str, Value<'_>>,
+    expire_timeout: i32,
+  ) -> zbus::Result<u32>;
+}
+
+/// Shows a notification with `summary` and `body`. Does nothing if there is
+/// no session bus, such as when watt runs as a system service.
+pub async fn send(summary: &str, body: &str) {
+  let connection = match Connection::session().await {
+    Ok(connection) => connection,
+
+    Err(error) => {
+      log::debug!(
+        "no session bus to send notification '{summary}' on: {error}"
+      );
+      return;
+    },
+  };
+
+  let result = async {
+    NotificationsProxy::new(&connection)
+      .await?
+      .notify("watt", 0, "", summary, body, &[], HashMap::new(), -1)
+      .await
+  }
+  .await;
+
+  match result {
+    Ok(_) => log::info!("sent notification '{summary}'"),
+    Err(error) => {
+      log::warn!("failed to send notification '{summary}': {error}");
+    },
+  }
+}
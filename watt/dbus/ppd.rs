@@ -6,12 +6,18 @@ use std::{
 
 use tokio::sync::RwLock;
 use zbus::{
+  Connection,
   fdo,
   interface,
+  message::Header,
   object_server::SignalEmitter,
   zvariant::Value,
 };
 
+use super::{
+  SET_PROFILE_ACTION,
+  authorize,
+};
 use crate::{
   profile::PowerProfile,
   system::DaemonState,
@@ -40,7 +46,14 @@ impl PowerProfilesInterface {
   }
 
   #[zbus(property)]
-  async fn set_active_profile(&self, profile: &str) -> zbus::Result<()> {
+  async fn set_active_profile(
+    &self,
+    profile: &str,
+    #[zbus(connection)] connection: &Connection,
+    #[zbus(header)] header: Option<Header<'_>>,
+  ) -> zbus::Result<()> {
+    authorize(connection, header.as_ref(), SET_PROFILE_ACTION).await?;
+
     let profile = match PowerProfile::from_str(profile) {
       Ok(profile) => profile,
       Err(_) => {
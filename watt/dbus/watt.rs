@@ -1,15 +1,28 @@
 use std::{
   collections::HashMap,
+  str::FromStr as _,
   sync::Arc,
 };
 
 use tokio::sync::RwLock;
 use zbus::{
+  Connection,
+  fdo,
   interface,
+  message::Header,
+  object_server::SignalEmitter,
   zvariant::Value,
 };
 
-use crate::system::DaemonState;
+use super::{
+  REEVALUATE_ACTION,
+  SET_PROFILE_ACTION,
+  authorize,
+};
+use crate::{
+  profile::PowerProfile,
+  system::DaemonState,
+};
 
 pub struct WattInterface {
   state: Arc<RwLock<DaemonState>>,
@@ -40,6 +53,65 @@ impl WattInterface {
     state.cpu_count() as u32
   }
 
+  #[zbus(property)]
+  async fn active_profile(&self) -> String {
+    let state = self.state.read().await;
+    state.active_profile().as_str().to_owned()
+  }
+
+  /// Sets the preferred power profile and applies the rules right away if
+  /// that changes the active profile. Only root, or users polkit authorizes,
+  /// may call it.
+  async fn set_profile(
+    &self,
+    #[zbus(connection)] connection: &Connection,
+    #[zbus(header)] header: Header<'_>,
+    #[zbus(signal_emitter)] signal_emitter: SignalEmitter<'_>,
+    profile: String,
+  ) -> fdo::Result<()> {
+    authorize(connection, Some(&header), SET_PROFILE_ACTION).await?;
+
+    let profile = PowerProfile::from_str(&profile).map_err(|_| {
+      fdo::Error::InvalidArgs(format!(
+        "invalid profile: {profile}, valid: performance, balanced, power-saver"
+      ))
+    })?;
+
+    let mut state = self.state.write().await;
+    let changed = state.set_active_profile(profile);
+
+    log::info!(
+      "D-Bus: preferred profile set to {profile}",
+      profile = profile.as_str()
+    );
+
+    drop(state);
+
+    if changed
+      && let Err(e) = self.active_profile_changed(&signal_emitter).await
+    {
+      log::warn!("failed to emit ActiveProfile change signal: {e}");
+    }
+
+    Ok(())
+  }
+
+  /// Rescans the system and applies the rules without waiting for the next
+  /// poll. Only root, or users polkit authorizes, may call it.
+  async fn reevaluate(
+    &self,
+    #[zbus(connection)] connection: &Connection,
+    #[zbus(header)] header: Header<'_>,
+  ) -> fdo::Result<()> {
+    authorize(connection, Some(&header), REEVALUATE_ACTION).await?;
+
+    log::info!("D-Bus: reevaluating rules");
+
+    self.state.read().await.request_poll();
+
+    Ok(())
+  }
+
   async fn get_status(&self) -> HashMap<String, Value<'_>> {
     let state = self.state.read().await;
     let mut status = HashMap::new();
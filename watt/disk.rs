@@ -67,27 +67,55 @@ impl Disk {
     Ok(disks)
   }
 
-  pub fn set_scheduler(&self, value: &str) -> anyhow::Result<()> {
-    fs::write(self.path.join("queue/scheduler"), value)
+  pub fn set_scheduler(
+    &self,
+    context: &fs::ApplyContext,
+    value: &str,
+  ) -> anyhow::Result<()> {
+    context
+      .write(self.path.join("queue/scheduler"), value)
       .with_context(|| format!("failed to set scheduler for {self}"))
   }
 
-  pub fn set_readahead_kib(&self, value: u64) -> anyhow::Result<()> {
-    fs::write(self.path.join("queue/read_ahead_kb"), &value.to_string())
+  pub fn set_readahead_kib(
+    &self,
+    context: &fs::ApplyContext,
+    value: u64,
+  ) -> anyhow::Result<()> {
+    context
+      .write(self.path.join("queue/read_ahead_kb"), &value.to_string())
       .with_context(|| format!("failed to set readahead for {self}"))
   }
 
-  pub fn set_apm(&self, value: u8) -> anyhow::Result<()> {
-    run_hdparm(self, format!("-B{value}"))
+  pub fn set_apm(
+    &self,
+    context: &fs::ApplyContext,
+    value: u8,
+  ) -> anyhow::Result<()> {
+    run_hdparm(context, self, format!("-B{value}"))
   }
 
-  pub fn set_spindown(&self, value: u8) -> anyhow::Result<()> {
-    run_hdparm(self, format!("-S{value}"))
+  pub fn set_spindown(
+    &self,
+    context: &fs::ApplyContext,
+    value: u8,
+  ) -> anyhow::Result<()> {
+    run_hdparm(context, self, format!("-S{value}"))
   }
 }
 
-fn run_hdparm(disk: &Disk, option: String) -> anyhow::Result<()> {
+fn run_hdparm(
+  context: &fs::ApplyContext,
+  disk: &Disk,
+  option: String,
+) -> anyhow::Result<()> {
   let device = format!("/dev/{name}", name = disk.name);
+
+  if context.dry_run {
+    log::info!("dry run: would run 'hdparm {option} {device}'");
+    return Ok(());
+  }
+
   let status = Command::new("hdparm")
     .arg(option)
     .arg(&device)
@@ -101,7 +129,10 @@ fn run_hdparm(disk: &Disk, option: String) -> anyhow::Result<()> {
   Ok(())
 }
 
-pub fn set_alpm(policy: &str) -> anyhow::Result<()> {
+pub fn set_alpm(
+  context: &fs::ApplyContext,
+  policy: &str,
+) -> anyhow::Result<()> {
   let Some(entries) = fs::read_dir("/sys/class/scsi_host")? else {
     return Ok(());
   };
@@ -120,7 +151,7 @@ pub fn set_alpm(policy: &str) -> anyhow::Result<()> {
     }
 
     if policy_path.exists() {
-      fs::write(&policy_path, policy).with_context(|| {
+      context.write(&policy_path, policy).with_context(|| {
         format!(
           "failed to set ALPM policy at '{path}'",
           path = policy_path.display()
@@ -133,7 +164,8 @@ pub fn set_alpm(policy: &str) -> anyhow::Result<()> {
 }
 
 fn is_external_sata_port(host_path: &std::path::Path) -> anyhow::Result<bool> {
-  let Some(value) = fs::read(host_path.join("ahci_port_cmd"))? else {
+  let Some(value) = fs::read_first_line(host_path.join("ahci_port_cmd"))?
+  else {
     return Ok(false);
   };
   let value = value.trim_start_matches("0x");
@@ -176,18 +208,37 @@ impl Delta {
     }
   }
 
-  pub fn apply(&self, disk: &Disk) -> anyhow::Result<()> {
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      scheduler:     self
+        .scheduler
+        .filter(|value| that.scheduler.as_ref() != Some(value)),
+      readahead_kib: self
+        .readahead_kib
+        .filter(|value| that.readahead_kib.as_ref() != Some(value)),
+      apm:           self.apm.filter(|value| that.apm.as_ref() != Some(value)),
+      spindown:      self
+        .spindown
+        .filter(|value| that.spindown.as_ref() != Some(value)),
+    }
+  }
+
+  pub fn apply(
+    &self,
+    context: &fs::ApplyContext,
+    disk: &Disk,
+  ) -> anyhow::Result<()> {
     if let Some(value) = &self.scheduler {
-      disk.set_scheduler(value)?;
+      disk.set_scheduler(context, value)?;
     }
     if let Some(value) = self.readahead_kib {
-      disk.set_readahead_kib(value)?;
+      disk.set_readahead_kib(context, value)?;
     }
     if let Some(value) = self.apm {
-      disk.set_apm(value)?;
+      disk.set_apm(context, value)?;
     }
     if let Some(value) = self.spindown {
-      disk.set_spindown(value)?;
+      disk.set_spindown(context, value)?;
     }
 
     Ok(())
@@ -211,9 +262,15 @@ impl GlobalDelta {
     }
   }
 
-  pub fn apply(&self) -> anyhow::Result<()> {
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      alpm: self.alpm.filter(|value| that.alpm.as_ref() != Some(value)),
+    }
+  }
+
+  pub fn apply(&self, context: &fs::ApplyContext) -> anyhow::Result<()> {
     if let Some(value) = &self.alpm {
-      set_alpm(value)?;
+      set_alpm(context, value)?;
     }
 
     Ok(())
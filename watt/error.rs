@@ -0,0 +1,99 @@
+use std::{
+  error,
+  fmt,
+  io,
+  path::PathBuf,
+};
+
+use crate::suggest;
+
+/// The errors callers may need to tell apart, such as to skip an unsupported
+/// control or to report a typo in the config. They are wrapped in
+/// [`anyhow::Error`] with context like every other error, and found again with
+/// [`anyhow::Error::downcast_ref`].
+#[derive(Debug)]
+pub enum WattError {
+  /// The system doesn't offer a control at all, such as turbo on a system
+  /// without a boost control.
+  UnsupportedControl { control: String },
+
+  /// A value the control doesn't accept, such as a governor the driver
+  /// doesn't offer.
+  InvalidValue {
+    /// What the value is for, such as `governor`.
+    control:   String,
+    value:     String,
+    /// What the control belongs to, such as `CPU 0`.
+    target:    String,
+    available: Vec<String>,
+  },
+
+  /// Reading or writing a sysfs file failed.
+  SysfsIo {
+    path:   PathBuf,
+    /// The value that was being written, `None` for reads.
+    value:  Option<String>,
+    source: io::Error,
+  },
+
+  /// An expression evaluated to a value of the wrong type.
+  TypeMismatch {
+    /// Such as `a number`.
+    expected: &'static str,
+    value:    String,
+  },
+}
+
+impl fmt::Display for WattError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UnsupportedControl { control } => {
+        write!(f, "{control} is not available on this system")
+      },
+
+      Self::InvalidValue {
+        control,
+        value,
+        target,
+        available,
+      } => {
+        write!(
+          f,
+          "{control} '{value}' is not available for {target}.{hint} available \
+           {control}s: {available}",
+          hint = suggest::did_you_mean(value, available),
+          available = available.join(", "),
+        )
+      },
+
+      Self::SysfsIo {
+        path, value: None, ..
+      } => write!(f, "failed to read '{path}'", path = path.display()),
+
+      Self::SysfsIo {
+        path,
+        value: Some(value),
+        ..
+      } => {
+        write!(
+          f,
+          "failed to write '{value}' to '{path}'",
+          path = path.display(),
+        )
+      },
+
+      Self::TypeMismatch { expected, value } => {
+        write!(f, "tried to cast '{value}' to {expected}, failed")
+      },
+    }
+  }
+}
+
+impl error::Error for WattError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      Self::SysfsIo { source, .. } => Some(source),
+      _ => None,
+    }
+  }
+}
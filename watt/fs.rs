@@ -1,23 +1,66 @@
 use std::{
+  borrow::Cow,
+  cell::RefCell,
+  collections::HashMap,
   error,
   fs,
-  io,
-  path::Path,
+  io::{
+    self,
+    Write as _,
+  },
+  path::{
+    Path,
+    PathBuf,
+  },
   str,
+  sync::{
+    Mutex,
+    MutexGuard,
+  },
+  time::{
+    Duration,
+    Instant,
+  },
 };
 
 use anyhow::Context;
 
+use crate::error::WattError;
+
+thread_local! {
+  static ROOT: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+/// Resolves absolute paths against `root` instead of `/` for every function
+/// in this module on the current thread, so the daemon can run against a fake
+/// sysfs in tests. `None` restores the real root.
+pub fn set_root(root: Option<PathBuf>) {
+  ROOT.set(root);
+}
+
+fn resolve(path: &Path) -> Cow<'_, Path> {
+  ROOT.with_borrow(|root| {
+    match root {
+      // Paths built from directory entries already point into the root.
+      Some(root) if path.is_absolute() && !path.starts_with(root) => {
+        Cow::Owned(root.join(path.strip_prefix("/").unwrap_or(path)))
+      },
+
+      _ => Cow::Borrowed(path),
+    }
+  })
+}
+
 pub fn exists(path: impl AsRef<Path>) -> bool {
   let path = path.as_ref();
 
-  path.exists()
+  resolve(path).exists()
 }
 
 pub fn read_dir(path: impl AsRef<Path>) -> anyhow::Result<Option<fs::ReadDir>> {
   let path = path.as_ref();
 
-  match fs::read_dir(path) {
+  match fs::read_dir(resolve(path)) {
     Ok(entries) => Ok(Some(entries)),
 
     Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
@@ -31,17 +74,59 @@ pub fn read_dir(path: impl AsRef<Path>) -> anyhow::Result<Option<fs::ReadDir>> {
   }
 }
 
+/// Reads the whole file, trimming whitespace only from its edges. Use this for
+/// files with several values or lines that the caller splits itself.
 pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Option<String>> {
   let path = path.as_ref();
 
-  match fs::read_to_string(path) {
+  match fs::read_to_string(resolve(path)) {
     Ok(string) => Ok(Some(string.trim().to_owned())),
 
     Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
 
     Err(error) => {
-      Err(error)
-        .context(format!("failed to read '{path}'", path = path.display()))
+      Err(
+        WattError::SysfsIo {
+          path:   path.to_owned(),
+          value:  None,
+          source: error,
+        }
+        .into(),
+      )
+    },
+  }
+}
+
+/// Reads the first line of a file holding a single value, trimmed.
+pub fn read_first_line(
+  path: impl AsRef<Path>,
+) -> anyhow::Result<Option<String>> {
+  Ok(read(path)?.map(|content| {
+    content.lines().next().unwrap_or_default().trim().to_owned()
+  }))
+}
+
+pub fn read_link(path: impl AsRef<Path>) -> anyhow::Result<Option<PathBuf>> {
+  let path = path.as_ref();
+
+  match fs::read_link(resolve(path)) {
+    Ok(target) => Ok(Some(target)),
+
+    // EINVAL means that the path exists but isn't a symlink.
+    Err(error)
+      if matches!(
+        error.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::InvalidInput
+      ) =>
+    {
+      Ok(None)
+    },
+
+    Err(error) => {
+      Err(error).context(format!(
+        "failed to read link '{path}'",
+        path = path.display(),
+      ))
     },
   }
 }
@@ -54,9 +139,9 @@ where
 {
   let path = path.as_ref();
 
-  match read(path)? {
+  match read_first_line(path)? {
     Some(content) => {
-      Ok(Some(content.trim().parse().with_context(|| {
+      Ok(Some(content.parse().with_context(|| {
         format!(
           "failed to parse contents of '{path}' as a unsigned number",
           path = path.display(),
@@ -68,13 +153,366 @@ where
   }
 }
 
+/// Enforces a minimum interval between writes to the same file. Some embedded
+/// controllers misbehave when their controls are written to in quick
+/// succession.
+#[derive(Debug)]
+pub struct WriteLimiter {
+  interval:    Duration,
+  last_writes: HashMap<PathBuf, (Instant, String)>,
+  /// Values that were written within the interval, to be written once it has
+  /// passed.
+  queued:      HashMap<PathBuf, String>,
+}
+
+impl WriteLimiter {
+  pub fn new(interval: Duration) -> Self {
+    Self {
+      interval,
+      last_writes: HashMap::new(),
+      queued: HashMap::new(),
+    }
+  }
+
+  /// Writes `value` to `path`, unless the last write to it was less than the
+  /// interval ago. Returns whether the write happened. Writing the same value
+  /// again within the interval is dropped, while a different value is queued
+  /// for [`WriteLimiter::flush`].
+  pub fn write(
+    &mut self,
+    path: impl AsRef<Path>,
+    value: &str,
+  ) -> anyhow::Result<bool> {
+    let path = path.as_ref();
+
+    if let Some((last_write, last_value)) = self.last_writes.get(path)
+      && last_write.elapsed() < self.interval
+    {
+      if last_value == value {
+        log::debug!(
+          "skipping write of '{value}' to '{path}', it was just written",
+          path = path.display(),
+        );
+        self.queued.remove(path);
+      } else {
+        log::debug!(
+          "queueing write of '{value}' to '{path}', it was written to less \
+           than {interval} ago",
+          path = path.display(),
+          interval = humantime::format_duration(self.interval),
+        );
+        self.queued.insert(path.to_owned(), value.to_owned());
+      }
+
+      return Ok(false);
+    }
+
+    self.write_now(path, value)?;
+
+    Ok(true)
+  }
+
+  /// Writes `value` to `path` regardless of the interval, replacing any
+  /// queued value.
+  pub fn write_now(&mut self, path: &Path, value: &str) -> anyhow::Result<()> {
+    self.queued.remove(path);
+    write_unlimited(path, value)?;
+    self
+      .last_writes
+      .insert(path.to_owned(), (Instant::now(), value.to_owned()));
+
+    Ok(())
+  }
+
+  /// When the earliest queued write is allowed to happen.
+  pub fn next_flush(&self) -> Option<Instant> {
+    self
+      .queued
+      .keys()
+      .filter_map(|path| self.last_writes.get(path))
+      .map(|(last_write, _)| *last_write + self.interval)
+      .min()
+  }
+
+  /// Writes the queued values whose interval has passed. Every one of them is
+  /// attempted, the first error is returned.
+  pub fn flush(&mut self) -> anyhow::Result<()> {
+    let due = self
+      .queued
+      .keys()
+      .filter(|path| {
+        self
+          .last_writes
+          .get(*path)
+          .is_none_or(|(last_write, _)| last_write.elapsed() >= self.interval)
+      })
+      .cloned()
+      .collect::<Vec<_>>();
+
+    let mut result = Ok(());
+
+    for path in due {
+      let Some(value) = self.queued.remove(&path) else {
+        continue;
+      };
+
+      if let Err(error) = self.write_now(&path, &value)
+        && result.is_ok()
+      {
+        result = Err(error);
+      }
+    }
+
+    result
+  }
+}
+
+static WRITE_LIMITER: Mutex<Option<WriteLimiter>> = Mutex::new(None);
+
+/// Sets the minimum interval between writes to the same file through
+/// [`write`]. `None` disables the limit, which is the default.
+pub fn set_min_write_interval(interval: Option<Duration>) {
+  *write_limiter() = interval.map(WriteLimiter::new);
+}
+
+fn write_unlimited(path: &Path, value: &str) -> anyhow::Result<()> {
+  let resolved = resolve(path);
+
+  // Kernel attributes are never created, writing one that doesn't exist
+  // fails so that callers fall back, under a fake root as well.
+  let result = fs::OpenOptions::new()
+    .write(true)
+    .truncate(true)
+    .open(&resolved)
+    .and_then(|mut file| file.write_all(value.as_bytes()));
+
+  result.map_err(|error| {
+    WattError::SysfsIo {
+      path:   path.to_owned(),
+      value:  Some(value.to_owned()),
+      source: error,
+    }
+    .into()
+  })
+}
+
+fn write_limiter() -> MutexGuard<'static, Option<WriteLimiter>> {
+  WRITE_LIMITER
+    .lock()
+    .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
 pub fn write(path: impl AsRef<Path>, value: &str) -> anyhow::Result<()> {
   let path = path.as_ref();
 
-  fs::write(path, value).with_context(|| {
-    format!(
-      "failed to write '{value}' to '{path}'",
-      path = path.display(),
-    )
-  })
+  match &mut *write_limiter() {
+    Some(limiter) => limiter.write(path, value).map(|_| ()),
+    None => write_unlimited(path, value),
+  }
+}
+
+/// Like [`write`], but ignores the minimum write interval.
+pub fn write_now(path: impl AsRef<Path>, value: &str) -> anyhow::Result<()> {
+  let path = path.as_ref();
+
+  match &mut *write_limiter() {
+    Some(limiter) => limiter.write_now(path, value),
+    None => write_unlimited(path, value),
+  }
+}
+
+/// When the earliest write queued by [`write`] is due, if any.
+pub fn next_queued_write() -> Option<Instant> {
+  write_limiter().as_ref().and_then(WriteLimiter::next_flush)
+}
+
+/// Performs the writes queued by [`write`] that are due.
+pub fn flush_queued_writes() -> anyhow::Result<()> {
+  write_limiter().as_mut().map_or(Ok(()), WriteLimiter::flush)
+}
+
+/// How settings get applied, passed down to every function that writes a
+/// control.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ApplyContext {
+  /// Log what would be written instead of writing it.
+  pub dry_run:               bool,
+  /// Write even if the control was written to less than the minimum write
+  /// interval ago, for settings that have to be in place right away.
+  pub ignore_write_interval: bool,
+  /// Log a device that fails to apply and go on with the others, failing only
+  /// once everything was attempted.
+  pub keep_going:            bool,
+}
+
+impl ApplyContext {
+  pub fn write(
+    &self,
+    path: impl AsRef<Path>,
+    value: &str,
+  ) -> anyhow::Result<()> {
+    let path = path.as_ref();
+
+    if self.dry_run {
+      log::info!(
+        "dry run: would write '{value}' to '{path}'",
+        path = path.display(),
+      );
+      return Ok(());
+    }
+
+    if self.ignore_write_interval {
+      write_now(path, value)
+    } else {
+      write(path, value)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    env,
+    process,
+    thread,
+  };
+
+  use super::*;
+
+  #[test]
+  fn io_errors_keep_the_path() {
+    // Reading a directory fails with something other than `NotFound`.
+    let error = read(env::temp_dir()).unwrap_err();
+
+    assert!(matches!(
+      error.downcast_ref::<WattError>(),
+      Some(WattError::SysfsIo { path, value: None, .. }) if *path == env::temp_dir(),
+    ));
+  }
+
+  #[test]
+  fn read_trims_edges_and_read_first_line_takes_the_first_line() {
+    let path =
+      env::temp_dir().join(format!("watt-fs-read-lines-{}", process::id()));
+
+    fs::write(&path, "\n  powersave \n\nperformance\n\n").unwrap();
+    let whole = read(&path);
+    let first_line = read_first_line(&path);
+
+    fs::write(&path, "42\n\n").unwrap();
+    let number = read_n::<u32>(&path);
+
+    fs::write(&path, "").unwrap();
+    let empty = read_first_line(&path);
+
+    let _ = fs::remove_file(&path);
+
+    assert_eq!(whole.unwrap().as_deref(), Some("powersave \n\nperformance"),);
+    assert_eq!(first_line.unwrap().as_deref(), Some("powersave"));
+    assert_eq!(number.unwrap(), Some(42));
+    assert_eq!(empty.unwrap().as_deref(), Some(""));
+  }
+
+  #[test]
+  fn write_limiter_merges_repeats_and_queues_changes() {
+    let path =
+      env::temp_dir().join(format!("watt-fs-write-limiter-{}", process::id()));
+    let other_path = env::temp_dir()
+      .join(format!("watt-fs-write-limiter-other-{}", process::id()));
+
+    let mut limiter = WriteLimiter::new(Duration::from_secs(3600));
+
+    // Like kernel attributes, the files have to exist to be written.
+    assert!(limiter.write(&path, "first").is_err());
+    fs::write(&path, "").unwrap();
+    fs::write(&other_path, "").unwrap();
+
+    let mut limiter = WriteLimiter::new(Duration::from_secs(3600));
+
+    assert!(limiter.write(&path, "first").unwrap());
+    fs::write(&path, "changed behind our back").unwrap();
+    assert!(!limiter.write(&path, "first").unwrap());
+    assert_eq!(
+      read(&path).unwrap().as_deref(),
+      Some("changed behind our back"),
+    );
+    assert_eq!(limiter.next_flush(), None);
+
+    // A different value isn't lost, it waits for the interval.
+    assert!(!limiter.write(&path, "second").unwrap());
+    assert!(limiter.next_flush().is_some());
+    limiter.flush().unwrap();
+    assert_eq!(
+      read(&path).unwrap().as_deref(),
+      Some("changed behind our back"),
+    );
+
+    // Going back to the written value drops the queued one.
+    assert!(!limiter.write(&path, "first").unwrap());
+    assert_eq!(limiter.next_flush(), None);
+
+    // Other paths have their own interval.
+    assert!(limiter.write(&other_path, "other").unwrap());
+
+    limiter.write_now(&path, "now").unwrap();
+    assert_eq!(read(&path).unwrap().as_deref(), Some("now"));
+
+    let mut limiter = WriteLimiter::new(Duration::from_millis(50));
+
+    assert!(limiter.write(&path, "first").unwrap());
+    assert!(!limiter.write(&path, "second").unwrap());
+    assert_eq!(read(&path).unwrap().as_deref(), Some("first"));
+
+    thread::sleep(Duration::from_millis(60));
+    limiter.flush().unwrap();
+    assert_eq!(read(&path).unwrap().as_deref(), Some("second"));
+    assert_eq!(limiter.next_flush(), None);
+
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&other_path);
+  }
+
+  #[test]
+  fn root_redirects_absolute_paths() {
+    let root = env::temp_dir().join(format!("watt-fs-root-{}", process::id()));
+    fs::create_dir_all(root.join("sys")).unwrap();
+    fs::write(root.join("sys/value"), "").unwrap();
+
+    set_root(Some(root.clone()));
+    let written = write("/sys/value", "42");
+    // Like sysfs, the root doesn't let new files be created.
+    let created = write("/sys/other", "42");
+    let value = read_n::<u32>("/sys/value");
+    let entries = read_dir("/sys").map(|entries| {
+      entries
+        .into_iter()
+        .flatten()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>()
+    });
+    set_root(None);
+
+    written.unwrap();
+    assert!(created.is_err());
+    assert_eq!(value.unwrap(), Some(42));
+    assert_eq!(entries.unwrap(), [root.join("sys/value")]);
+    // Paths already inside the root are left alone.
+    assert_eq!(resolve(&root.join("sys/value")), root.join("sys/value"));
+
+    let _ = fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn dry_run_skips_writes() {
+    let path =
+      env::temp_dir().join(format!("watt-fs-dry-run-{}", process::id()));
+
+    let context = ApplyContext {
+      dry_run: true,
+      ..ApplyContext::default()
+    };
+
+    context.write(&path, "value").unwrap();
+    assert!(!exists(&path));
+  }
 }
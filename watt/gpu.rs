@@ -72,7 +72,11 @@ impl Gpu {
     path.join("device/power_method")
   }
 
-  pub fn set_panel_power_savings(&self, value: u8) -> anyhow::Result<()> {
+  pub fn set_panel_power_savings(
+    &self,
+    context: &fs::ApplyContext,
+    value: u8,
+  ) -> anyhow::Result<()> {
     if value > 4 {
       bail!("GPU panel power savings must be between 0 and 4, got {value}");
     }
@@ -83,11 +87,16 @@ impl Gpu {
       return Ok(());
     }
 
-    fs::write(path, &value.to_string())
+    context
+      .write(path, &value.to_string())
       .with_context(|| format!("failed to set panel power savings for {self}"))
   }
 
-  pub fn set_radeon_powersave(&self, value: &str) -> anyhow::Result<()> {
+  pub fn set_radeon_powersave(
+    &self,
+    context: &fs::ApplyContext,
+    value: &str,
+  ) -> anyhow::Result<()> {
     let method = Self::radeon_power_method_path(&self.path);
     if !method.exists() {
       log::debug!("{self} does not support Radeon power profiles");
@@ -96,23 +105,28 @@ impl Gpu {
 
     match value {
       "default" | "auto" | "low" | "mid" | "high" => {
-        fs::write(&method, "profile")
+        context
+          .write(&method, "profile")
           .with_context(|| format!("failed to set power method for {self}"))?;
-        fs::write(self.path.join("device/power_profile"), value)
+        context
+          .write(self.path.join("device/power_profile"), value)
           .with_context(|| format!("failed to set power profile for {self}"))?;
       },
       "dynpm" => {
-        fs::write(&method, "dynpm")
+        context
+          .write(&method, "dynpm")
           .with_context(|| format!("failed to set power method for {self}"))?
       },
       "dpm-battery" | "dpm-balanced" | "dpm-performance" => {
-        fs::write(&method, "dpm")
+        context
+          .write(&method, "dpm")
           .with_context(|| format!("failed to set power method for {self}"))?;
-        fs::write(
-          self.path.join("device/power_dpm_state"),
-          value.trim_start_matches("dpm-"),
-        )
-        .with_context(|| format!("failed to set DPM state for {self}"))?;
+        context
+          .write(
+            self.path.join("device/power_dpm_state"),
+            value.trim_start_matches("dpm-"),
+          )
+          .with_context(|| format!("failed to set DPM state for {self}"))?;
       },
       _ => bail!("invalid Radeon powersave value: {value}"),
     }
@@ -144,12 +158,27 @@ impl Delta {
     }
   }
 
-  pub fn apply(&self, gpu: &Gpu) -> anyhow::Result<()> {
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      panel_power_savings: self
+        .panel_power_savings
+        .filter(|value| that.panel_power_savings.as_ref() != Some(value)),
+      radeon_powersave:    self
+        .radeon_powersave
+        .filter(|value| that.radeon_powersave.as_ref() != Some(value)),
+    }
+  }
+
+  pub fn apply(
+    &self,
+    context: &fs::ApplyContext,
+    gpu: &Gpu,
+  ) -> anyhow::Result<()> {
     if let Some(value) = self.panel_power_savings {
-      gpu.set_panel_power_savings(value)?;
+      gpu.set_panel_power_savings(context, value)?;
     }
     if let Some(value) = &self.radeon_powersave {
-      gpu.set_radeon_powersave(value)?;
+      gpu.set_radeon_powersave(context, value)?;
     }
 
     Ok(())
@@ -0,0 +1,1274 @@
+//! Runs the daemon once against a fake `/sys` and `/proc` and reports the
+//! files it wrote, so its behavior can be tested end to end.
+
+use std::{
+  collections::BTreeMap,
+  env,
+  fs,
+  path::{
+    Path,
+    PathBuf,
+  },
+  process,
+  sync::atomic::{
+    AtomicU64,
+    Ordering,
+  },
+};
+
+use crate::{
+  config,
+  system,
+};
+
+static NEXT_ROOT: AtomicU64 = AtomicU64::new(0);
+
+/// A fake root filesystem with just enough of `/sys` and `/proc` for the
+/// daemon to scan. Dropping it removes it.
+pub struct FakeSystem {
+  root: PathBuf,
+}
+
+impl FakeSystem {
+  pub fn new() -> Self {
+    let counter = NEXT_ROOT.fetch_add(1, Ordering::Relaxed);
+    let root =
+      env::temp_dir().join(format!("watt-harness-{}-{counter}", process::id()));
+
+    let _ = fs::remove_dir_all(&root);
+
+    let system = Self { root };
+
+    for directory in [
+      "sys/devices/system/cpu",
+      "sys/class/power_supply",
+      "sys/class/hwmon",
+    ] {
+      fs::create_dir_all(system.root.join(directory))
+        .expect("create fake system directory");
+    }
+
+    system
+      .file("proc/stat", "cpu  0 0 0 0 0 0 0 0\n")
+      .file("proc/cpuinfo", "")
+      .file("proc/loadavg", "0.50 0.40 0.30 1/100 1000\n")
+  }
+
+  /// Creates a file, along with its parent directories.
+  pub fn file(self, path: &str, content: &str) -> Self {
+    let path = self.root.join(path);
+
+    fs::create_dir_all(path.parent().expect("file has a parent"))
+      .expect("create fake system file parent");
+    fs::write(&path, content).expect("write fake system file");
+
+    self
+  }
+
+  /// Reads a file of the fake system, such as one a poll wrote to.
+  pub fn read(&self, path: &str) -> String {
+    fs::read_to_string(self.root.join(path)).expect("read fake system file")
+  }
+
+  /// Adds a CPU with cpufreq support using the given governor.
+  pub fn cpu(self, number: u32, governor: &str) -> Self {
+    let stat = fs::read_to_string(self.root.join("proc/stat"))
+      .expect("read fake CPU stat");
+    let cpufreq = format!("sys/devices/system/cpu/cpu{number}/cpufreq");
+
+    self
+      .file(
+        "proc/stat",
+        &format!("{stat}cpu{number} 100 0 100 800 0 0 0 0\n"),
+      )
+      .file(&format!("{cpufreq}/scaling_governor"), governor)
+      .file(
+        &format!("{cpufreq}/scaling_available_governors"),
+        "performance powersave",
+      )
+      .file(&format!("{cpufreq}/cpuinfo_min_freq"), "400000")
+      .file(&format!("{cpufreq}/cpuinfo_max_freq"), "4000000")
+      .file(&format!("{cpufreq}/scaling_min_freq"), "400000")
+      .file(&format!("{cpufreq}/scaling_max_freq"), "4000000")
+  }
+
+  /// Adds an Intel turbo control, with turbo enabled.
+  pub fn intel_turbo(self) -> Self {
+    self.file("sys/devices/system/cpu/intel_pstate/no_turbo", "0")
+  }
+
+  /// Adds a battery with the given charge and `status`, such as
+  /// `Discharging`.
+  pub fn battery(self, name: &str, capacity_percent: u8, status: &str) -> Self {
+    let path = format!("sys/class/power_supply/{name}");
+
+    self
+      .file(&format!("{path}/type"), "Battery")
+      .file(&format!("{path}/status"), status)
+      .file(&format!("{path}/capacity"), &capacity_percent.to_string())
+  }
+
+  /// Adds the hwmon device `hwmonN` called `name`, with a sensor for each
+  /// label and temperature pair.
+  pub fn hwmon(self, number: u32, name: &str, sensors: &[(&str, f64)]) -> Self {
+    let path = format!("sys/class/hwmon/hwmon{number}");
+
+    let mut system = self.file(&format!("{path}/name"), name);
+
+    for (index, (label, celsius)) in sensors.iter().enumerate() {
+      let index = index + 1;
+
+      system = system
+        .file(&format!("{path}/temp{index}_label"), label)
+        .file(
+          &format!("{path}/temp{index}_input"),
+          &((celsius * 1000.0) as i64).to_string(),
+        );
+    }
+
+    system
+  }
+
+  /// Adds a `coretemp` sensor reporting the temperature of core 0.
+  pub fn cpu_temperature(self, celsius: f64) -> Self {
+    self.hwmon(0, "coretemp", &[("Core 0", celsius)])
+  }
+
+  fn contents(&self) -> BTreeMap<PathBuf, String> {
+    fn walk(path: &Path, contents: &mut BTreeMap<PathBuf, String>) {
+      for entry in fs::read_dir(path).expect("read fake system directory") {
+        let path = entry.expect("read fake system entry").path();
+
+        if path.is_dir() {
+          walk(&path, contents);
+        } else {
+          let content =
+            fs::read_to_string(&path).expect("read fake system file");
+          contents.insert(path, content);
+        }
+      }
+    }
+
+    let mut contents = BTreeMap::new();
+    walk(&self.root, &mut contents);
+    contents
+  }
+
+  /// Runs `f` with every path resolved inside the fake system.
+  pub fn with_root<T>(&self, f: impl FnOnce() -> T) -> T {
+    struct ResetRoot;
+
+    impl Drop for ResetRoot {
+      fn drop(&mut self) {
+        crate::fs::set_root(None);
+      }
+    }
+
+    crate::fs::set_root(Some(self.root.clone()));
+    let _reset_root = ResetRoot;
+
+    f()
+  }
+
+  /// Loads `config` and applies its rules once, returning the new contents of
+  /// every file that changed, keyed by its absolute path on a real system.
+  pub fn run_once(
+    &self,
+    config: &str,
+  ) -> anyhow::Result<BTreeMap<String, String>> {
+    let config_path = self.root.join("watt.toml");
+    fs::write(&config_path, config).expect("write fake system config");
+    let config = config::DaemonConfig::load_from(Some(&config_path))?;
+
+    let before = self.contents();
+
+    self.with_root(|| system::run_once(config))?;
+
+    Ok(
+      self
+        .contents()
+        .into_iter()
+        .filter(|(path, content)| before.get(path) != Some(content))
+        .map(|(path, content)| {
+          let path = path
+            .strip_prefix(&self.root)
+            .expect("fake system file is in the root");
+
+          (format!("/{path}", path = path.display()), content)
+        })
+        .collect(),
+    )
+  }
+}
+
+impl Drop for FakeSystem {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.root);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const THROTTLE_WHEN_HOT_ON_BATTERY: &str = r#"
+    [[rule]]
+    name = "hot on battery"
+    priority = 90
+    if.all = ["?discharging", { is-more-than = 80.0, value = "$cpu-temperature" }]
+    cpu.governor = "powersave"
+    cpu.turbo = false
+  "#;
+
+  fn laptop(status: &str, celsius: f64) -> FakeSystem {
+    FakeSystem::new()
+      .cpu(0, "performance")
+      .cpu(1, "performance")
+      .intel_turbo()
+      .battery("BAT0", 60, status)
+      .cpu_temperature(celsius)
+  }
+
+  #[test]
+  fn throttles_when_hot_on_battery() {
+    let writes = laptop("Discharging", 92.0)
+      .run_once(THROTTLE_WHEN_HOT_ON_BATTERY)
+      .unwrap();
+
+    assert_eq!(
+      writes,
+      BTreeMap::from(
+        [
+          (
+            "/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor",
+            "powersave",
+          ),
+          (
+            "/sys/devices/system/cpu/cpu1/cpufreq/scaling_governor",
+            "powersave",
+          ),
+          ("/sys/devices/system/cpu/intel_pstate/no_turbo", "1"),
+        ]
+        .map(|(path, content)| (path.to_owned(), content.to_owned()))
+      ),
+    );
+  }
+
+  #[test]
+  fn leaves_settings_alone_when_cool_or_charging() {
+    for system in [laptop("Discharging", 55.0), laptop("Charging", 92.0)] {
+      let writes = system.run_once(THROTTLE_WHEN_HOT_ON_BATTERY).unwrap();
+
+      assert_eq!(writes, BTreeMap::new());
+    }
+  }
+
+  #[test]
+  fn temperature_sensors_replace_detection() {
+    let system = || {
+      FakeSystem::new()
+        .cpu(0, "performance")
+        .intel_turbo()
+        .battery("BAT0", 60, "Discharging")
+        .hwmon(0, "coretemp", &[("Package id 0", 92.0), ("Core 0", 50.0)])
+        .hwmon(1, "k10temp", &[("Tctl", 50.0)])
+    };
+
+    let config = |sensors: &str| {
+      format!("temperature-sensors = {sensors}\n{THROTTLE_WHEN_HOT_ON_BATTERY}")
+    };
+
+    let writes = system()
+      .run_once(&config(r#"["coretemp:Package"]"#))
+      .unwrap();
+    assert_eq!(
+      writes
+        .get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .map(String::as_str),
+      Some("powersave"),
+    );
+
+    // Detection skips the package sensor, its label isn't a core.
+    assert_eq!(system().run_once(&config("[]")).unwrap(), BTreeMap::new());
+
+    assert!(system().run_once(&config(r#"["coretemp:"]"#)).is_err());
+  }
+
+  #[test]
+  fn fan_rpm_is_the_fastest_fan() {
+    const POWERSAVE_WITH_FANS_MAXED: &str = r#"
+      [[rule]]
+      name = "fans maxed"
+      priority = 50
+      if = { is-more-than = 4000.0, value = "$fan-rpm-max" }
+      cpu.governor = "powersave"
+    "#;
+
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .hwmon(0, "thinkpad", &[])
+      .file("sys/class/hwmon/hwmon0/fan1_input", "1200")
+      // Limits aren't speeds.
+      .file("sys/class/hwmon/hwmon0/fan1_max", "6000")
+      .hwmon(1, "nct6775", &[])
+      .file("sys/class/hwmon/hwmon1/fan2_input", "4800")
+      // A directory stands in for a fan that fails to read, which is skipped.
+      .file("sys/class/hwmon/hwmon1/fan3_input/unreadable", "")
+      .run_once(POWERSAVE_WITH_FANS_MAXED)
+      .unwrap();
+    assert_eq!(
+      writes
+        .get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .map(String::as_str),
+      Some("powersave"),
+    );
+
+    // Without fan sensors the variable is undefined.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .cpu_temperature(50.0)
+      .run_once(POWERSAVE_WITH_FANS_MAXED)
+      .unwrap();
+    assert_eq!(writes, BTreeMap::new());
+  }
+
+  #[test]
+  fn schedutil_rate_limit_is_only_set_with_schedutil() {
+    let writes = FakeSystem::new()
+      .cpu(0, "schedutil")
+      .file(
+        "sys/devices/system/cpu/cpu0/cpufreq/schedutil/rate_limit_us",
+        "1000",
+      )
+      .cpu(1, "performance")
+      .run_once(
+        r#"
+          [[rule]]
+          priority = 50
+          cpu.schedutil-rate-limit-us = 5000
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(
+      writes,
+      BTreeMap::from([(
+        "/sys/devices/system/cpu/cpu0/cpufreq/schedutil/rate_limit_us"
+          .to_owned(),
+        "5000".to_owned(),
+      )]),
+    );
+  }
+
+  #[test]
+  fn unsupported_controls_are_skipped() {
+    // No turbo control, so `cpu.turbo` can't be applied.
+    let system = || {
+      FakeSystem::new()
+        .cpu(0, "performance")
+        .battery("BAT0", 60, "Discharging")
+        .cpu_temperature(92.0)
+    };
+
+    let writes = system().run_once(THROTTLE_WHEN_HOT_ON_BATTERY).unwrap();
+    assert_eq!(
+      writes
+        .get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .map(String::as_str),
+      Some("powersave"),
+    );
+
+    assert!(
+      system()
+        .run_once(&format!(
+          "ignore-unsupported = false\n{THROTTLE_WHEN_HOT_ON_BATTERY}"
+        ))
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn frequency_is_pinned_with_the_userspace_governor() {
+    let cpufreq = "sys/devices/system/cpu/cpu0/cpufreq";
+
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .file(
+        &format!("{cpufreq}/scaling_available_governors"),
+        "performance powersave userspace",
+      )
+      .file(&format!("{cpufreq}/scaling_setspeed"), "<unsupported>")
+      .run_once(
+        r#"
+          [[rule]]
+          name = "benchmark"
+          priority = 50
+          cpu.frequency-mhz = 2000
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(
+      writes,
+      BTreeMap::from(
+        [
+          (format!("/{cpufreq}/scaling_governor"), "userspace"),
+          (format!("/{cpufreq}/scaling_setspeed"), "2000000"),
+        ]
+        .map(|(path, content)| (path, content.to_owned()))
+      ),
+    );
+  }
+
+  #[test]
+  fn power_draw_is_summed_over_discharging_batteries() {
+    const POWERSAVE_ABOVE_20_WATTS: &str = r#"
+      [[rule]]
+      name = "heavy draw"
+      priority = 50
+      if = { is-more-than = 20.0, value = "$power-draw-watts" }
+      cpu.governor = "powersave"
+    "#;
+
+    let system = |bat1_status: &str| {
+      FakeSystem::new()
+        .cpu(0, "performance")
+        .battery("BAT0", 60, "Discharging")
+        .file("sys/class/power_supply/BAT0/power_now", "12000000")
+        .battery("BAT1", 60, bat1_status)
+        .file("sys/class/power_supply/BAT1/power_now", "9000000")
+    };
+
+    let governor = |writes: BTreeMap<String, String>| {
+      writes
+        .get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .cloned()
+    };
+
+    assert_eq!(
+      governor(
+        system("Discharging")
+          .run_once(POWERSAVE_ABOVE_20_WATTS)
+          .unwrap()
+      ),
+      Some("powersave".to_owned()),
+    );
+    assert_eq!(
+      governor(system("Full").run_once(POWERSAVE_ABOVE_20_WATTS).unwrap()),
+      None,
+    );
+
+    // Without a reading the variable is undefined and the rule is skipped.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .battery("BAT0", 60, "Discharging")
+      .run_once(POWERSAVE_ABOVE_20_WATTS)
+      .unwrap();
+    assert_eq!(writes, BTreeMap::new());
+  }
+
+  #[test]
+  fn battery_cycles_is_the_highest_cycle_count() {
+    const POWERSAVE_ABOVE_500_CYCLES: &str = r#"
+      [[rule]]
+      name = "worn battery"
+      priority = 50
+      if = { is-more-than = 500.0, value = "$battery-cycles" }
+      cpu.governor = "powersave"
+    "#;
+
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .battery("BAT0", 60, "Discharging")
+      .file("sys/class/power_supply/BAT0/cycle_count", "800")
+      .battery("BAT1", 60, "Discharging")
+      .file("sys/class/power_supply/BAT1/cycle_count", "100")
+      .run_once(POWERSAVE_ABOVE_500_CYCLES)
+      .unwrap();
+    assert_eq!(
+      writes
+        .get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .map(String::as_str),
+      Some("powersave"),
+    );
+
+    // Without a cycle count the variable is undefined and the rule is skipped.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .battery("BAT0", 60, "Discharging")
+      .run_once(POWERSAVE_ABOVE_500_CYCLES)
+      .unwrap();
+    assert_eq!(writes, BTreeMap::new());
+  }
+
+  #[test]
+  fn charge_is_weighted_by_energy_and_skips_peripherals() {
+    const POWERSAVE_ABOVE_60_PERCENT: &str = r#"
+      [[rule]]
+      name = "mostly charged"
+      priority = 50
+      if = { is-more-than = 0.6, value = "%power-supply-charge" }
+      cpu.governor = "powersave"
+    "#;
+
+    // 54 of 60 Wh and 2 of 20 Wh are 70% together, not 50%.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .battery("BAT0", 90, "Discharging")
+      .file("sys/class/power_supply/BAT0/energy_now", "54000000")
+      .file("sys/class/power_supply/BAT0/energy_full", "60000000")
+      .battery("BAT1", 10, "Discharging")
+      .file("sys/class/power_supply/BAT1/energy_now", "2000000")
+      .file("sys/class/power_supply/BAT1/energy_full", "20000000")
+      .battery("hid-mouse-battery", 0, "Discharging")
+      .run_once(POWERSAVE_ABOVE_60_PERCENT)
+      .unwrap();
+
+    assert_eq!(
+      writes
+        .get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .map(String::as_str),
+      Some("powersave"),
+    );
+  }
+
+  #[test]
+  fn smt_is_turned_off_only_where_available() {
+    const SMT_OFF: &str = r#"
+      [[rule]]
+      name = "no smt"
+      priority = 50
+      if = "?smt-available"
+      cpu.smt = false
+    "#;
+
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .file("sys/devices/system/cpu/smt/control", "on")
+      .run_once(SMT_OFF)
+      .unwrap();
+    assert_eq!(
+      writes
+        .get("/sys/devices/system/cpu/smt/control")
+        .map(String::as_str),
+      Some("off"),
+    );
+
+    // CPUs without SMT report `notsupported`.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .file("sys/devices/system/cpu/smt/control", "notsupported")
+      .run_once(SMT_OFF)
+      .unwrap();
+    assert_eq!(writes, BTreeMap::new());
+  }
+
+  #[test]
+  fn lid_state_is_undefined_without_a_lid_switch() {
+    const POWERSAVE_WHEN_LID_CLOSED: &str = r#"
+      [[rule]]
+      name = "lid closed"
+      priority = 50
+      if = "?lid-closed"
+      cpu.governor = "powersave"
+
+      [[rule]]
+      name = "lid open"
+      priority = 40
+      if = { not = "?lid-closed" }
+      cpu.governor = "performance"
+    "#;
+
+    let system = || FakeSystem::new().cpu(0, "schedutil");
+    let governor = |writes: BTreeMap<String, String>| {
+      writes
+        .get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .cloned()
+    };
+
+    assert_eq!(
+      governor(
+        system()
+          .file("proc/acpi/button/lid/LID0/state", "state:      closed\n")
+          .run_once(POWERSAVE_WHEN_LID_CLOSED)
+          .unwrap()
+      ),
+      Some("powersave".to_owned()),
+    );
+    assert_eq!(
+      governor(
+        system()
+          .file("proc/acpi/button/lid/LID0/state", "state:      open\n")
+          .run_once(POWERSAVE_WHEN_LID_CLOSED)
+          .unwrap()
+      ),
+      Some("performance".to_owned()),
+    );
+    assert_eq!(
+      governor(system().run_once(POWERSAVE_WHEN_LID_CLOSED).unwrap()),
+      None,
+    );
+  }
+
+  #[test]
+  fn platform_profile_falls_back_to_synonyms() {
+    const QUIET: &str = r#"
+      [[rule]]
+      name = "quiet"
+      priority = 50
+      power.platform-profile = "quiet"
+    "#;
+
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .file(
+        "sys/firmware/acpi/platform_profile_choices",
+        "low-power balanced",
+      )
+      .file("sys/firmware/acpi/platform_profile", "balanced")
+      .run_once(QUIET)
+      .unwrap();
+    assert_eq!(
+      writes
+        .get("/sys/firmware/acpi/platform_profile")
+        .map(String::as_str),
+      Some("low-power"),
+    );
+
+    // Without the profile or a synonym the write is skipped, not an error.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .file(
+        "sys/firmware/acpi/platform_profile_choices",
+        "balanced performance",
+      )
+      .file("sys/firmware/acpi/platform_profile", "balanced")
+      .run_once(QUIET)
+      .unwrap();
+    assert_eq!(writes, BTreeMap::new());
+  }
+
+  #[test]
+  fn charge_behaviour_is_set_where_available() {
+    const FORCE_DISCHARGE: &str = r#"
+      [[rule]]
+      name = "storage"
+      priority = 50
+      if = "?charge-behaviour-available"
+      power.charge-behaviour = "force-discharge"
+    "#;
+
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .battery("BAT0", 80, "Charging")
+      .file(
+        "sys/class/power_supply/BAT0/charge_behaviour",
+        "[auto] inhibit-charge force-discharge",
+      )
+      .run_once(FORCE_DISCHARGE)
+      .unwrap();
+    assert_eq!(
+      writes
+        .get("/sys/class/power_supply/BAT0/charge_behaviour")
+        .map(String::as_str),
+      Some("force-discharge"),
+    );
+
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .battery("BAT0", 80, "Charging")
+      .run_once(FORCE_DISCHARGE)
+      .unwrap();
+    assert_eq!(writes, BTreeMap::new());
+  }
+
+  #[test]
+  fn force_discharge_is_skipped_below_the_floor() {
+    let system = |floor: &str| {
+      FakeSystem::new()
+        .cpu(0, "performance")
+        .battery("BAT0", 25, "Charging")
+        .file(
+          "sys/class/power_supply/BAT0/charge_behaviour",
+          "[auto] inhibit-charge force-discharge",
+        )
+        .run_once(&format!(
+          r#"
+            {floor}
+
+            [[rule]]
+            priority = 50
+            power.charge-behaviour = "force-discharge"
+          "#
+        ))
+        .unwrap()
+    };
+
+    // The default floor of 20% lets a battery at 25% discharge.
+    assert_eq!(
+      system("")
+        .get("/sys/class/power_supply/BAT0/charge_behaviour")
+        .map(String::as_str),
+      Some("force-discharge"),
+    );
+
+    assert_eq!(system("force-discharge-floor = 30"), BTreeMap::new());
+  }
+
+  #[test]
+  fn force_discharge_below_the_floor_switches_back_to_auto() {
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .battery("BAT0", 15, "Discharging")
+      .file(
+        "sys/class/power_supply/BAT0/charge_behaviour",
+        "auto inhibit-charge [force-discharge]",
+      )
+      .run_once(
+        r#"
+          [[rule]]
+          priority = 50
+          power.charge-behaviour = "force-discharge"
+        "#,
+      )
+      .unwrap();
+
+    assert_eq!(
+      writes
+        .get("/sys/class/power_supply/BAT0/charge_behaviour")
+        .map(String::as_str),
+      Some("auto"),
+    );
+  }
+
+  #[test]
+  fn ac_power_limit_is_read_from_plugged_in_adapters() {
+    const POWERSAVE_ON_WEAK_CHARGER: &str = r#"
+      [[rule]]
+      name = "weak charger"
+      priority = 50
+      if = { is-less-than = 90.0, value = "$ac-power-limit-watts" }
+      cpu.governor = "powersave"
+    "#;
+
+    let governor = |writes: BTreeMap<String, String>| {
+      writes
+        .get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+        .cloned()
+    };
+
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .file("sys/class/power_supply/ADP1/type", "Mains")
+      .file("sys/class/power_supply/ADP1/online", "1")
+      .file("sys/class/power_supply/ADP1/input_power_limit", "65000000")
+      .run_once(POWERSAVE_ON_WEAK_CHARGER)
+      .unwrap();
+    assert_eq!(governor(writes).as_deref(), Some("powersave"));
+
+    // 20V at 2.25A negotiated over USB-PD.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .file("sys/class/power_supply/USBC1/type", "USB_PD")
+      .file("sys/class/power_supply/USBC1/voltage_max", "20000000")
+      .file("sys/class/power_supply/USBC1/current_max", "2250000")
+      .run_once(POWERSAVE_ON_WEAK_CHARGER)
+      .unwrap();
+    assert_eq!(governor(writes).as_deref(), Some("powersave"));
+
+    // Unplugged adapters don't count, so the variable is undefined.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .file("sys/class/power_supply/ADP1/type", "Mains")
+      .file("sys/class/power_supply/ADP1/online", "0")
+      .file("sys/class/power_supply/ADP1/input_power_limit", "65000000")
+      .run_once(POWERSAVE_ON_WEAK_CHARGER)
+      .unwrap();
+    assert_eq!(governor(writes), None);
+  }
+
+  #[test]
+  fn higher_priority_rules_win_per_setting() {
+    const DEFAULTS_AND_OVERRIDE: &str = r#"
+      [[rule]]
+      name = "defaults"
+      priority = 0
+      cpu.governor = "performance"
+      cpu.turbo = false
+
+      [[rule]]
+      name = "on battery"
+      priority = 50
+      if = "?discharging"
+      cpu.governor = "powersave"
+    "#;
+
+    // The override only replaces the governor, the turbo setting still comes
+    // from the catch-all rule.
+    let writes = FakeSystem::new()
+      .cpu(0, "performance")
+      .intel_turbo()
+      .battery("BAT0", 60, "Discharging")
+      .run_once(DEFAULTS_AND_OVERRIDE)
+      .unwrap();
+    assert_eq!(
+      writes,
+      BTreeMap::from(
+        [
+          (
+            "/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor",
+            "powersave",
+          ),
+          ("/sys/devices/system/cpu/intel_pstate/no_turbo", "1"),
+        ]
+        .map(|(path, content)| (path.to_owned(), content.to_owned()))
+      ),
+    );
+  }
+
+  #[test]
+  fn thermal_emergency_overrides_every_rule() {
+    const FULL_SPEED: &str = r#"
+      [[rule]]
+      name = "full speed"
+      priority = 100
+      cpu.governor = "performance"
+      cpu.frequency-mhz-maximum = 4000
+      cpu.turbo = true
+    "#;
+
+    let writes = laptop("Charging", 99.0).run_once(FULL_SPEED).unwrap();
+    assert_eq!(
+      writes,
+      BTreeMap::from(
+        [
+          (
+            "/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor",
+            "powersave",
+          ),
+          (
+            "/sys/devices/system/cpu/cpu0/cpufreq/scaling_max_freq",
+            "400000",
+          ),
+          (
+            "/sys/devices/system/cpu/cpu1/cpufreq/scaling_governor",
+            "powersave",
+          ),
+          (
+            "/sys/devices/system/cpu/cpu1/cpufreq/scaling_max_freq",
+            "400000",
+          ),
+          ("/sys/devices/system/cpu/intel_pstate/no_turbo", "1"),
+        ]
+        .map(|(path, content)| (path.to_owned(), content.to_owned()))
+      ),
+    );
+
+    // The threshold can't be raised past the clamped range.
+    let writes = laptop("Charging", 110.0)
+      .run_once(&format!("thermal-emergency-celsius = 200\n{FULL_SPEED}"))
+      .unwrap();
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+      Some(&"powersave".to_owned()),
+    );
+
+    // Below the threshold, the rules apply as usual.
+    let writes = laptop("Charging", 90.0).run_once(FULL_SPEED).unwrap();
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+      None,
+    );
+  }
+
+  #[test]
+  fn turbo_enabled_follows_the_current_state() {
+    const POWERSAVE_WITH_TURBO: &str = r#"
+      [[rule]]
+      priority = 10
+      if = "?turbo-enabled"
+      cpu.governor = "powersave"
+    "#;
+
+    let governor = |system: FakeSystem| {
+      system
+        .run_once(POWERSAVE_WITH_TURBO)
+        .unwrap()
+        .remove("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+    };
+
+    let system = || FakeSystem::new().cpu(0, "performance");
+    assert_eq!(
+      governor(system().intel_turbo()),
+      Some("powersave".to_owned()),
+    );
+    assert_eq!(
+      governor(
+        system().file("sys/devices/system/cpu/intel_pstate/no_turbo", "1")
+      ),
+      None,
+    );
+    // Undefined without a turbo control.
+    assert_eq!(governor(system()), None);
+  }
+
+  #[test]
+  fn stats_file_has_the_variables_and_applied_rules() {
+    let system = laptop("Discharging", 92.0);
+    let stats_file = system.root.join("stats.json");
+
+    system
+      .run_once(&format!(
+        "stats-file = \"{path}\"\n{THROTTLE_WHEN_HOT_ON_BATTERY}",
+        path = stats_file.display(),
+      ))
+      .unwrap();
+
+    let stats: serde_json::Value =
+      serde_json::from_str(&fs::read_to_string(&stats_file).unwrap()).unwrap();
+    assert_eq!(stats["variables"]["cpu-temperature"], 92.0);
+    assert_eq!(stats["variables"]["discharging"], true);
+    assert_eq!(
+      stats["applied-rules"],
+      serde_json::json!(["hot on battery"])
+    );
+    assert!(stats["poll-interval-seconds"].as_f64().unwrap() > 0.0);
+
+    // Written through a temporary file that is renamed over it.
+    assert!(!system.root.join("stats.json.tmp").exists());
+  }
+
+  #[test]
+  fn on_ac_and_desktop_are_distinct_from_discharging() {
+    let applies = |system: FakeSystem, condition: &str| {
+      system
+        .run_once(&format!(
+          "[[rule]]\npriority = 10\nif = \"{condition}\"\ncpu.governor = \
+           \"powersave\"\n"
+        ))
+        .unwrap()
+        .contains_key("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+    };
+
+    let laptop = |status: &str, online: &str| {
+      FakeSystem::new()
+        .cpu(0, "performance")
+        .battery("BAT0", 80, status)
+        .file("sys/class/power_supply/AC/type", "Mains")
+        .file("sys/class/power_supply/AC/online", online)
+    };
+
+    // Plugged in, but held at the charge threshold.
+    assert!(applies(laptop("Not charging", "1"), "?on-ac"));
+    assert!(!applies(laptop("Not charging", "1"), "?discharging"));
+
+    // The adapter stays around while unplugged.
+    assert!(!applies(laptop("Discharging", "0"), "?on-ac"));
+    assert!(!applies(laptop("Discharging", "0"), "?desktop"));
+
+    let desktop = || FakeSystem::new().cpu(0, "performance");
+    assert!(applies(desktop(), "?desktop"));
+    assert!(applies(desktop(), "?on-ac"));
+    assert!(!applies(desktop(), "?discharging"));
+  }
+
+  #[test]
+  fn gpu_sensors_are_kept_apart_from_the_cpu_ones() {
+    let applies = |condition: &str| {
+      FakeSystem::new()
+        .cpu(0, "performance")
+        .cpu_temperature(50.0)
+        .hwmon(1, "amdgpu", &[("edge", 70.0), ("junction", 85.0)])
+        .file("sys/class/hwmon/hwmon1/power1_average", "45000000")
+        // Sensors that fail to read are skipped. A directory stands in for
+        // one that returns EIO.
+        .file("sys/class/hwmon/hwmon1/temp3_input/unreadable", "")
+        .file("sys/class/hwmon/hwmon1/power2_average/unreadable", "")
+        .run_once(&format!(
+          "[[rule]]\npriority = 10\nif = {condition}\ncpu.governor = \
+           \"powersave\"\n"
+        ))
+        .unwrap()
+        .contains_key("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+    };
+
+    assert!(applies(
+      r#"{ value = "$gpu-temperature", is-equal = 85.0 }"#
+    ));
+    assert!(applies(
+      r#"{ value = "$gpu-power-watts", is-equal = 45.0 }"#
+    ));
+    assert!(applies(
+      r#"{ value = "$cpu-temperature", is-equal = 50.0 }"#
+    ));
+  }
+
+  #[test]
+  fn default_is_the_baseline_rules_override() {
+    const DEFAULT_WITH_RULE: &str = r#"
+      [default]
+      cpu.governor = "powersave"
+      cpu.frequency-mhz-maximum = 2000
+
+      [[rule]]
+      priority = 10
+      if = "?discharging"
+      cpu.governor = "performance"
+    "#;
+
+    let writes = laptop("Charging", 50.0)
+      .run_once(DEFAULT_WITH_RULE)
+      .unwrap();
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+      Some(&"powersave".to_owned()),
+    );
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_max_freq"),
+      Some(&"2000000".to_owned()),
+    );
+
+    // The rule wins where it sets something, the default fills in the rest.
+    let writes = laptop("Discharging", 50.0)
+      .run_once(DEFAULT_WITH_RULE)
+      .unwrap();
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor"),
+      None,
+    );
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_max_freq"),
+      Some(&"2000000".to_owned()),
+    );
+  }
+
+  #[test]
+  fn frequency_minimum_above_maximum_is_refused() {
+    let run = |minimum: u64, maximum: u64| {
+      FakeSystem::new().cpu(0, "performance").run_once(&format!(
+        "[[rule]]\npriority = 10\ncpu.frequency-mhz-minimum = \
+         {minimum}\ncpu.frequency-mhz-maximum = {maximum}\n"
+      ))
+    };
+
+    let error = run(3000, 2000).unwrap_err();
+    let message = format!("{error:#}");
+    assert!(message.contains("3000000 kHz"), "{message}");
+    assert!(message.contains("2000000 kHz"), "{message}");
+
+    let writes = run(2000, 3000).unwrap();
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_min_freq"),
+      Some(&"2000000".to_owned()),
+    );
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/cpu0/cpufreq/scaling_max_freq"),
+      Some(&"3000000".to_owned()),
+    );
+  }
+
+  #[test]
+  fn hwp_dynamic_boost_is_only_set_where_available() {
+    const BOOST: &str = r#"
+      [[rule]]
+      priority = 10
+      cpu.hwp-dynamic-boost = { if = "?hwp-dynamic-boost-available", then = true }
+    "#;
+    const PATH: &str = "sys/devices/system/cpu/intel_pstate/hwp_dynamic_boost";
+
+    let writes = FakeSystem::new()
+      .cpu(0, "powersave")
+      .file(PATH, "0")
+      .run_once(BOOST)
+      .unwrap();
+    assert_eq!(writes.get(&format!("/{PATH}")), Some(&"1".to_owned()));
+
+    let writes = FakeSystem::new()
+      .cpu(0, "powersave")
+      .run_once(BOOST)
+      .unwrap();
+    assert_eq!(writes.get(&format!("/{PATH}")), None);
+  }
+
+  #[test]
+  fn pstate_performance_percent_minimum_stays_below_maximum() {
+    let run = |minimum: u8, maximum: u8| {
+      FakeSystem::new()
+        .cpu(0, "powersave")
+        .intel_turbo()
+        .file("sys/devices/system/cpu/intel_pstate/min_perf_pct", "20")
+        .file("sys/devices/system/cpu/intel_pstate/max_perf_pct", "50")
+        .run_once(&format!(
+          "[[rule]]\npriority = 10\ncpu.pstate-min-performance-percent = \
+           {minimum}\ncpu.pstate-max-performance-percent = {maximum}\n"
+        ))
+    };
+
+    let error = run(80, 60).unwrap_err();
+    assert!(format!("{error:#}").contains("80%"), "{error:#}");
+
+    // Raised past the current maximum of 50%.
+    let writes = run(70, 90).unwrap();
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/intel_pstate/min_perf_pct"),
+      Some(&"70".to_owned()),
+    );
+    assert_eq!(
+      writes.get("/sys/devices/system/cpu/intel_pstate/max_perf_pct"),
+      Some(&"90".to_owned()),
+    );
+  }
+
+  #[test]
+  fn disk_temperature_is_the_hottest_storage_sensor() {
+    let applies = |system: FakeSystem, condition: &str| {
+      system
+        .run_once(&format!(
+          "[[rule]]\npriority = 10\nif = {condition}\ncpu.governor = \
+           \"powersave\"\n"
+        ))
+        .unwrap()
+        .contains_key("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+    };
+
+    let system = || {
+      FakeSystem::new()
+        .cpu(0, "performance")
+        .cpu_temperature(50.0)
+        .hwmon(1, "nvme", &[("Composite", 48.0), ("Sensor 1", 61.0)])
+        .hwmon(2, "drivetemp", &[("", 40.0)])
+    };
+
+    assert!(applies(
+      system(),
+      r#"{ value = "$disk-temperature-max", is-equal = 61.0 }"#,
+    ));
+    assert!(applies(
+      system(),
+      r#"{ value = "$cpu-temperature", is-equal = 50.0 }"#,
+    ));
+
+    // Undefined without disk sensors, so the rule is skipped.
+    assert!(!applies(
+      FakeSystem::new().cpu(0, "performance"),
+      r#"{ value = "$disk-temperature-max", is-less-than = 1000.0 }"#,
+    ));
+  }
+
+  #[test]
+  fn offline_and_cpufreq_less_cpus_are_skipped() {
+    let cpu = "sys/devices/system/cpu";
+
+    // CPU 1 is offline and has no cpufreq directory, CPU 3 is online without
+    // a scaling driver, and there is no CPU 4 onwards.
+    let system = FakeSystem::new()
+      .cpu(0, "powersave")
+      .file(&format!("{cpu}/cpu1/online"), "0")
+      .cpu(2, "powersave")
+      .file(&format!("{cpu}/cpu2/online"), "1")
+      .file(&format!("{cpu}/cpu3/online"), "1");
+    let stat = system.read("proc/stat");
+
+    let writes = system
+      .file("proc/stat", &format!("{stat}cpu3 100 0 100 800 0 0 0 0\n"))
+      .run_once(
+        "ignore-unsupported = false\n[[rule]]\npriority = 10\ncpu.governor = \
+         \"performance\"\n",
+      )
+      .unwrap();
+
+    assert_eq!(
+      writes,
+      BTreeMap::from([
+        (
+          format!("/{cpu}/cpu0/cpufreq/scaling_governor"),
+          "performance".to_owned(),
+        ),
+        (
+          format!("/{cpu}/cpu2/cpufreq/scaling_governor"),
+          "performance".to_owned(),
+        ),
+      ]),
+    );
+  }
+
+  #[test]
+  fn desktop_is_detected_from_the_chassis_and_system_batteries() {
+    let on_ac = |system: FakeSystem| {
+      system
+        .cpu(0, "performance")
+        .run_once(
+          "[[rule]]\npriority = 10\nif = \"?on-ac\"\ncpu.governor = \
+           \"powersave\"\n",
+        )
+        .unwrap()
+        .contains_key("/sys/devices/system/cpu/cpu0/cpufreq/scaling_governor")
+    };
+
+    // Desktops have `intel_pstate` parameters too.
+    assert!(on_ac(
+      FakeSystem::new()
+        .file("sys/class/dmi/id/chassis_type", "3")
+        .file("sys/module/intel_pstate/parameters/no_hwp", "0"),
+    ));
+    assert!(!on_ac(
+      FakeSystem::new().file("sys/class/dmi/id/chassis_type", "10"),
+    ));
+
+    // Without a chassis type, a battery by any name is a laptop's, unless it
+    // belongs to a peripheral.
+    assert!(!on_ac(FakeSystem::new().battery("CMB0", 60, "Discharging")));
+    assert!(on_ac(
+      FakeSystem::new()
+        .battery("hidpp_battery_0", 60, "Discharging")
+        .file(
+          "sys/class/power_supply/hidpp_battery_0/energy_full",
+          "1000000"
+        ),
+    ));
+    assert!(on_ac(
+      FakeSystem::new().file("sys/module/intel_pstate/parameters/no_hwp", "0"),
+    ));
+  }
+
+  #[test]
+  fn charge_thresholds_keep_start_below_end() {
+    let run = |actions: &str| {
+      FakeSystem::new()
+        .cpu(0, "performance")
+        .battery("BAT0", 60, "Charging")
+        .file(
+          "sys/class/power_supply/BAT0/charge_control_start_threshold",
+          "40",
+        )
+        .file(
+          "sys/class/power_supply/BAT0/charge_control_end_threshold",
+          "60",
+        )
+        .run_once(&format!("[[rule]]\npriority = 10\n{actions}"))
+    };
+
+    assert_eq!(
+      run("power.charge-threshold-target = 90\n").unwrap(),
+      BTreeMap::from([
+        (
+          "/sys/class/power_supply/BAT0/charge_control_end_threshold"
+            .to_owned(),
+          "90".to_owned(),
+        ),
+        (
+          "/sys/class/power_supply/BAT0/charge_control_start_threshold"
+            .to_owned(),
+          "85".to_owned(),
+        ),
+      ]),
+    );
+
+    let error = run(
+      "power.charge-threshold-start = 80\npower.charge-threshold-end = 70\n",
+    )
+    .unwrap_err();
+    let message = format!("{error:#}");
+    assert!(message.contains("is not below the end"), "{message}");
+  }
+}
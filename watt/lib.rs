@@ -14,15 +14,27 @@ pub mod uncore;
 pub mod usb;
 pub mod vm;
 
+pub mod error;
 pub mod fs;
+pub mod percent;
+
+pub mod suggest;
 
 pub mod config;
 
 pub mod lock;
 
+pub mod preflight;
+
+pub mod cli;
+
+#[cfg(test)] mod harness;
+
 pub mod dbus;
 #[cfg(feature = "metrics")] pub mod metrics;
 pub mod profile;
+pub mod status;
+pub mod systemd;
 
 #[derive(clap::Parser, Debug)]
 #[command(version, about)]
@@ -30,9 +42,49 @@ pub struct Cli {
   #[command(flatten)]
   verbosity: clap_verbosity_flag::Verbosity<clap_verbosity_flag::InfoLevel>,
 
+  /// The format of log lines.
+  #[arg(long, value_enum, default_value_t = LogFormat::Human)]
+  log_format: LogFormat,
+
   /// The daemon config path.
   #[arg(long, env = "WATT_CONFIG")]
   config: Option<PathBuf>,
+
+  /// Report which of the controls watt writes to are writable and exit.
+  #[arg(long)]
+  check_permissions: bool,
+
+  /// Log the values watt would write instead of writing them.
+  #[arg(long)]
+  dry_run: bool,
+
+  /// Stop the instance holding the daemon lock and take the lock over.
+  #[arg(long)]
+  force: bool,
+
+  /// Run a one-off command instead of the daemon.
+  #[command(subcommand)]
+  command: Option<cli::Command>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+  /// Plain lines meant to be read by people.
+  Human,
+  /// One JSON object per line, for log collectors.
+  Json,
+}
+
+/// Formats a log record as a single line JSON object with its timestamp,
+/// level, module and message.
+fn json_log_line(timestamp: jiff::Timestamp, record: &log::Record) -> String {
+  serde_json::json!({
+    "timestamp": timestamp.to_string(),
+    "level": record.level().as_str(),
+    "module": record.module_path().unwrap_or(record.target()),
+    "message": record.args().to_string(),
+  })
+  .to_string()
 }
 
 pub fn main() -> anyhow::Result<()> {
@@ -40,11 +92,40 @@ pub fn main() -> anyhow::Result<()> {
 
   yansi::whenever(yansi::Condition::TTY_AND_COLOR);
 
-  env_logger::Builder::new()
-    .filter_level(cli.verbosity.log_level_filter())
-    .format_timestamp(None)
-    .format_module_path(false)
-    .init();
+  let mut logger = env_logger::Builder::new();
+  logger.filter_level(cli.verbosity.log_level_filter());
+
+  match cli.log_format {
+    LogFormat::Human => {
+      logger.format_timestamp(None).format_module_path(false);
+    },
+
+    LogFormat::Json => {
+      // Messages contain ANSI color codes when stderr is a terminal.
+      yansi::disable();
+
+      logger.format(|buf, record| {
+        use std::io::Write as _;
+
+        writeln!(buf, "{}", json_log_line(jiff::Timestamp::now(), record))
+      });
+    },
+  }
+
+  logger.init();
+
+  let apply_context = fs::ApplyContext {
+    dry_run: cli.dry_run,
+    ..fs::ApplyContext::default()
+  };
+
+  if cli.check_permissions {
+    return preflight::run();
+  }
+
+  if let Some(command) = cli.command {
+    return cli::run(command, &apply_context, cli.config.as_deref());
+  }
 
   let config = config::DaemonConfig::load_from(cli.config.as_deref())
     .context("failed to load daemon config")?;
@@ -52,12 +133,41 @@ pub fn main() -> anyhow::Result<()> {
   log::info!("starting watt daemon");
 
   let lock_path = PathBuf::from("/run/watt/lock");
-  let _lock = lock::LockFile::acquire(&lock_path)?;
+  let _lock = lock::LockFile::acquire(&lock_path, cli.force)?;
 
   let runtime = RuntimeBuilder::new_multi_thread()
     .enable_all()
     .build()
     .context("failed to build tokio runtime")?;
 
-  runtime.block_on(system::run_daemon(config))
+  runtime.block_on(system::run_daemon(config, cli.config, apply_context))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn json_log_line_has_the_record_fields() {
+    let timestamp: jiff::Timestamp = "2025-01-01T12:00:00Z".parse().unwrap();
+    let line = json_log_line(
+      timestamp,
+      &log::Record::builder()
+        .args(format_args!("applied rule \"battery\""))
+        .level(log::Level::Info)
+        .module_path(Some("watt::system"))
+        .build(),
+    );
+
+    assert!(!line.contains('\n'));
+    assert_eq!(
+      serde_json::from_str::<serde_json::Value>(&line).unwrap(),
+      serde_json::json!({
+        "timestamp": "2025-01-01T12:00:00Z",
+        "level": "INFO",
+        "module": "watt::system",
+        "message": "applied rule \"battery\"",
+      }),
+    );
+  }
 }
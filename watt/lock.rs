@@ -16,11 +16,25 @@ use std::{
     Path,
     PathBuf,
   },
+  process,
+  thread,
+  time::{
+    Duration,
+    Instant,
+  },
 };
 
-#[cfg(unix)] use nix::fcntl::{
-  Flock,
-  FlockArg,
+#[cfg(unix)]
+use nix::{
+  fcntl::{
+    Flock,
+    FlockArg,
+  },
+  sys::signal::{
+    self,
+    Signal,
+  },
+  unistd::Pid,
 };
 
 #[cfg(not(unix))]
@@ -35,6 +49,8 @@ pub struct LockFile {
 pub struct LockFileError {
   pub path:    PathBuf,
   pub message: Option<String>,
+  /// The PID of the instance holding the lock, if it is held.
+  pub pid:     Option<u32>,
 }
 
 impl fmt::Display for LockFileError {
@@ -71,6 +87,78 @@ fn lock_contention_message(lock_path: &Path) -> String {
   message
 }
 
+/// How long the holder of the lock gets to release it after being asked to
+/// stop.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn process_exists(pid: u32) -> bool {
+  let Ok(pid) = i32::try_from(pid) else {
+    return false;
+  };
+
+  // Signal 0 only checks whether the process exists. EPERM means it does, but
+  // belongs to someone else.
+  !matches!(
+    signal::kill(Pid::from_raw(pid), None),
+    Err(nix::errno::Errno::ESRCH),
+  )
+}
+
+/// Sends SIGTERM to `pid`, which holds the lock on `file`, and waits for it to
+/// release the lock.
+fn stop_holder(
+  mut file: File,
+  lock_path: &Path,
+  pid: u32,
+) -> Result<Flock<File>, LockFileError> {
+  let error = |message: String| {
+    LockFileError {
+      path:    lock_path.to_owned(),
+      message: Some(message),
+      pid:     Some(pid),
+    }
+  };
+
+  if pid == process::id() {
+    return Err(error("the lock is held by this process".to_owned()));
+  }
+
+  log::warn!(
+    "asking pid {pid} holding the lock on {path} to stop",
+    path = lock_path.display(),
+  );
+
+  let raw_pid = i32::try_from(pid)
+    .map_err(|_| error(format!("pid {pid} is out of range")))?;
+
+  signal::kill(Pid::from_raw(raw_pid), Signal::SIGTERM)
+    .map_err(|errno| error(format!("failed to stop pid {pid}: {errno}")))?;
+
+  let deadline = Instant::now() + TAKEOVER_TIMEOUT;
+
+  loop {
+    match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+      Ok(lock) => return Ok(lock),
+
+      Err((returned, nix::errno::Errno::EWOULDBLOCK))
+        if Instant::now() < deadline =>
+      {
+        file = returned;
+        thread::sleep(Duration::from_millis(100));
+      },
+
+      Err((_, nix::errno::Errno::EWOULDBLOCK)) => {
+        return Err(error(format!(
+          "pid {pid} did not release the lock within {seconds} seconds",
+          seconds = TAKEOVER_TIMEOUT.as_secs(),
+        )));
+      },
+
+      Err((_, errno)) => return Err(error(errno.to_string())),
+    }
+  }
+}
+
 impl ops::Deref for LockFile {
   type Target = File;
 
@@ -90,7 +178,10 @@ impl LockFile {
     &self.path
   }
 
-  pub fn acquire(lock_path: &Path) -> Result<Self, LockFileError> {
+  /// Locks `lock_path` and writes our PID to it. If another instance holds the
+  /// lock and `force` is set, that instance is asked to stop and the lock is
+  /// taken once it has released it, instead of failing.
+  pub fn acquire(lock_path: &Path, force: bool) -> Result<Self, LockFileError> {
     // Ensure parent directory exists with proper permissions
     if let Some(parent) = lock_path.parent()
       && !parent.exists()
@@ -110,6 +201,7 @@ impl LockFile {
               "cannot create directory {parent}: {error}",
               parent = parent.display(),
             )),
+            pid:     None,
           }
         })?;
     }
@@ -129,24 +221,63 @@ impl LockFile {
         LockFileError {
           path:    lock_path.to_owned(),
           message: Some(error.to_string()),
+          pid:     None,
         }
       })?;
 
-    let mut lock = Flock::lock(file, FlockArg::LockExclusiveNonblock).map_err(
-      |(_, error)| {
-        let message = if error == nix::errno::Errno::EWOULDBLOCK {
-          Some(lock_contention_message(lock_path))
+    let mut lock = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+      Ok(lock) => lock,
+
+      Err((file, nix::errno::Errno::EWOULDBLOCK)) if force => {
+        match read_lock_pid(lock_path) {
+          Some(pid) if process_exists(pid) => {
+            stop_holder(file, lock_path, pid)?
+          },
+
+          // The lock is still held, by a child the recorded holder left it
+          // to or by a process we can't see. Replacing the file would let two
+          // instances run at once.
+          pid => {
+            let message = format!(
+              "the lock is still held, but {holder} is not running and cannot \
+               be asked to stop",
+              holder = pid.map_or_else(
+                || "the process recorded in it".to_owned(),
+                |pid| format!("pid {pid}"),
+              ),
+            );
+            log::error!(
+              "{message} (lock held on {path})",
+              path = lock_path.display(),
+            );
+
+            return Err(LockFileError {
+              path: lock_path.to_owned(),
+              message: Some(message),
+              pid,
+            });
+          },
+        }
+      },
+
+      Err((_, error)) => {
+        let (message, pid) = if error == nix::errno::Errno::EWOULDBLOCK {
+          (
+            Some(lock_contention_message(lock_path)),
+            read_lock_pid(lock_path),
+          )
         } else {
           log::error!("failed to acquire lock: {error}");
-          Some(error.to_string())
+          (Some(error.to_string()), None)
         };
 
-        LockFileError {
+        return Err(LockFileError {
           path: lock_path.to_owned(),
           message,
-        }
+          pid,
+        });
       },
-    )?;
+    };
 
     lock.set_len(0).map_err(|error| {
       log::error!(
@@ -156,6 +287,7 @@ impl LockFile {
       LockFileError {
         path:    lock_path.to_owned(),
         message: Some(error.to_string()),
+        pid:     None,
       }
     })?;
 
@@ -167,6 +299,7 @@ impl LockFile {
       LockFileError {
         path:    lock_path.to_owned(),
         message: Some(error.to_string()),
+        pid:     None,
       }
     })?;
 
@@ -178,6 +311,7 @@ impl LockFile {
       LockFileError {
         path:    lock_path.to_owned(),
         message: Some(error.to_string()),
+        pid:     None,
       }
     })?;
 
@@ -187,3 +321,39 @@ impl LockFile {
     })
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::env;
+
+  use super::*;
+
+  #[test]
+  fn second_acquire_reports_holder_pid() {
+    let path = env::temp_dir()
+      .join(format!("watt-lock-test-{}", process::id()))
+      .join("lock");
+
+    let lock = LockFile::acquire(&path, false).unwrap();
+
+    let error = LockFile::acquire(&path, false).err().unwrap();
+    assert_eq!(error.pid, Some(process::id()));
+
+    // Taking over never signals our own process.
+    let error = LockFile::acquire(&path, true).err().unwrap();
+    assert_eq!(error.pid, Some(process::id()));
+
+    // A lock that is still held is never replaced, even if the pid recorded
+    // in it is gone.
+    let mut exited = process::Command::new("true").spawn().unwrap();
+    exited.wait().unwrap();
+    fs::write(&path, exited.id().to_string()).unwrap();
+
+    let error = LockFile::acquire(&path, true).err().unwrap();
+    assert_eq!(error.pid, Some(exited.id()));
+    assert!(LockFile::acquire(&path, false).is_err());
+
+    drop(lock);
+    let _ = fs::remove_dir_all(path.parent().unwrap());
+  }
+}
@@ -114,6 +114,26 @@ fn render_metrics(state: &RwLock<DaemonState>) -> String {
     }
   }
 
+  let cpu_temperatures = state.cpu_temperatures();
+  if !cpu_temperatures.is_empty() {
+    metric_help(
+      &mut metrics,
+      "watt_cpu_core_temperature_celsius",
+      "CPU core temperature in degrees Celsius labelled by core.",
+    );
+    metric_type(&mut metrics, "watt_cpu_core_temperature_celsius", "gauge");
+
+    for (core, temperature) in cpu_temperatures {
+      labelled_metric(
+        &mut metrics,
+        "watt_cpu_core_temperature_celsius",
+        "core",
+        &core.to_string(),
+        temperature,
+      );
+    }
+  }
+
   metric_help(
     &mut metrics,
     "watt_power_supply_discharging",
@@ -126,6 +146,70 @@ fn render_metrics(state: &RwLock<DaemonState>) -> String {
     u8::from(state.is_discharging()) as f64,
   );
 
+  let batteries = state.batteries();
+  if !batteries.is_empty() {
+    metric_help(
+      &mut metrics,
+      "watt_battery_charge_ratio",
+      "Battery charge ratio from 0 to 1 labelled by battery.",
+    );
+    metric_type(&mut metrics, "watt_battery_charge_ratio", "gauge");
+    for battery in &batteries {
+      if let Some(charge) = battery.charge_percent {
+        labelled_metric(
+          &mut metrics,
+          "watt_battery_charge_ratio",
+          "battery",
+          &battery.name,
+          charge.as_fraction(),
+        );
+      }
+    }
+
+    metric_help(
+      &mut metrics,
+      "watt_battery_drain_watts",
+      "Power drawn from the battery in watts labelled by battery.",
+    );
+    metric_type(&mut metrics, "watt_battery_drain_watts", "gauge");
+    for battery in &batteries {
+      if let Some(drain_rate_watts) = battery.drain_rate_watts {
+        labelled_metric(
+          &mut metrics,
+          "watt_battery_drain_watts",
+          "battery",
+          &battery.name,
+          drain_rate_watts,
+        );
+      }
+    }
+  }
+
+  if let Some(poll_interval) = state.poll_interval() {
+    metric_help(
+      &mut metrics,
+      "watt_poll_interval_seconds",
+      "Delay until the next poll in seconds.",
+    );
+    metric_type(&mut metrics, "watt_poll_interval_seconds", "gauge");
+    metric(
+      &mut metrics,
+      "watt_poll_interval_seconds",
+      poll_interval.as_secs_f64(),
+    );
+  }
+
+  metric_help(
+    &mut metrics,
+    "watt_rule_applied",
+    "Rules applied by the last poll labelled by rule name, or priority if \
+     unnamed.",
+  );
+  metric_type(&mut metrics, "watt_rule_applied", "gauge");
+  for rule in state.last_applied_rules() {
+    labelled_metric(&mut metrics, "watt_rule_applied", "rule", &rule, 1.0);
+  }
+
   metric_help(
     &mut metrics,
     "watt_active_profile",
@@ -167,5 +251,48 @@ fn labelled_metric(
   label_value: &str,
   value: f64,
 ) {
+  // Rule names come from the config and may contain anything.
+  let label_value = label_value
+    .replace('\\', r"\\")
+    .replace('"', r#"\""#)
+    .replace('\n', r"\n");
+
   let _ = writeln!(metrics, r#"{name}{{{label}="{label_value}"}} {value}"#);
 }
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+
+  #[test]
+  fn render_exports_the_system_and_escapes_rule_names() {
+    let system = crate::harness::FakeSystem::new()
+      .cpu(0, "powersave")
+      .battery("BAT0", 80, "Discharging")
+      .file("sys/class/power_supply/BAT0/power_now", "12500000")
+      .cpu_temperature(60.0);
+
+    let state = system
+      .with_root(|| {
+        DaemonState::polled(
+          vec!["quote \" backslash \\ newline \n end".to_owned()],
+          Duration::from_secs(5),
+        )
+      })
+      .unwrap();
+
+    let metrics = render_metrics(&RwLock::new(state));
+
+    for line in [
+      r#"watt_cpu_core_temperature_celsius{core="0"} 60"#,
+      r#"watt_battery_charge_ratio{battery="BAT0"} 0.8"#,
+      r#"watt_battery_drain_watts{battery="BAT0"} 12.5"#,
+      "watt_poll_interval_seconds 5",
+      r#"watt_rule_applied{rule="quote \" backslash \\ newline \n end"} 1"#,
+    ] {
+      assert!(metrics.lines().any(|metric| metric == line), "{line}");
+    }
+  }
+}
@@ -0,0 +1,57 @@
+/// A percentage. Stored as a fraction from 0 to 1, and only ever built and
+/// read through the explicitly named conversions, so that a value on the 0 to
+/// 100 scale can't be mistaken for a fraction or the other way around.
+#[derive(Default, Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Percent(f64);
+
+impl Percent {
+  pub const ZERO: Self = Self::from_fraction(0.0);
+  pub const FULL: Self = Self::from_fraction(1.0);
+
+  /// From a fraction, where 1 is 100%.
+  pub const fn from_fraction(fraction: f64) -> Self {
+    Self(fraction)
+  }
+
+  /// From a percentage, where 100 is 100%.
+  pub const fn from_percent(percent: f64) -> Self {
+    Self(percent / 100.0)
+  }
+
+  /// As a fraction, where 1 is 100%.
+  pub const fn as_fraction(self) -> f64 {
+    self.0
+  }
+
+  /// As a percentage, where 100 is 100%.
+  pub const fn as_percent(self) -> f64 {
+    self.0 * 100.0
+  }
+
+  /// As the nearest whole percentage, which is what sysfs takes. `None`
+  /// outside of 0 to 100%.
+  pub fn as_whole_percent(self) -> Option<u8> {
+    let percent = self.as_percent().round();
+
+    (0.0..=100.0).contains(&percent).then_some(percent as u8)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn conversions_round_trip() {
+    let percent = Percent::from_percent(57.0);
+
+    assert_eq!(percent.as_fraction(), 0.57);
+    // 57 / 100 * 100 isn't exactly 57 in floating point.
+    assert_eq!(percent.as_whole_percent(), Some(57));
+    assert_eq!(Percent::from_fraction(0.8).as_whole_percent(), Some(80));
+
+    assert_eq!(Percent::FULL.as_whole_percent(), Some(100));
+    assert_eq!(Percent::from_percent(101.0).as_whole_percent(), None);
+    assert_eq!(Percent::from_percent(-1.0).as_whole_percent(), None);
+  }
+}
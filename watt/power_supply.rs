@@ -1,17 +1,27 @@
 use std::{
+  collections::HashSet,
   fmt,
   hash,
   path::PathBuf,
+  sync::Arc,
 };
 
 use anyhow::{
   Context,
-  anyhow,
   bail,
 };
 use yansi::Paint as _;
 
-use crate::fs;
+use crate::{
+  error::WattError,
+  fs,
+  percent::Percent,
+  suggest,
+};
+
+/// The charge percentage below which forcing a battery to discharge is
+/// refused by default.
+pub const DEFAULT_FORCE_DISCHARGE_FLOOR: u8 = 20;
 
 /// Represents a pattern of path suffixes used to control charge thresholds
 /// for different device vendors.
@@ -23,7 +33,8 @@ pub struct PowerSupplyThresholdConfig {
 }
 
 /// Power supply threshold configs.
-const POWER_SUPPLY_THRESHOLD_CONFIGS: &[PowerSupplyThresholdConfig] = &[
+pub(crate) const POWER_SUPPLY_THRESHOLD_CONFIGS:
+  &[PowerSupplyThresholdConfig] = &[
   PowerSupplyThresholdConfig {
     manufacturer: "Standard",
     path_start:   "charge_control_start_threshold",
@@ -58,16 +69,36 @@ pub struct PowerSupply {
   pub is_from_peripheral: bool,
 
   pub charge_state:   Option<String>,
-  pub charge_percent: Option<f64>,
+  pub charge_percent: Option<Percent>,
+
+  /// The energy left and the energy when full in Wh, if the battery reports
+  /// energy rather than charge counters.
+  pub energy_now_wh:  Option<f64>,
+  pub energy_full_wh: Option<f64>,
 
   pub cycles: Option<u64>,
   pub health: Option<f64>,
 
-  pub charge_threshold_start: f64,
-  pub charge_threshold_end:   f64,
+  /// Battery temperature in °C.
+  pub temperature: Option<f64>,
+
+  /// Charge thresholds, 0 and 100% if they can't be changed.
+  pub charge_threshold_start: Percent,
+  pub charge_threshold_end:   Percent,
+
+  /// The `charge_behaviour`, such as `auto` or `inhibit-charge`. `None` and
+  /// empty if it can't be changed.
+  pub charge_behaviour:            Option<String>,
+  pub available_charge_behaviours: Vec<String>,
 
   pub drain_rate_watts: Option<f64>,
 
+  /// Whether an adapter is plugged in, from `online`. `None` for batteries
+  /// and adapters that don't report it.
+  pub online:                  Option<bool>,
+  /// The most power a plugged in adapter can deliver in W.
+  pub input_power_limit_watts: Option<f64>,
+
   pub threshold_config: Option<PowerSupplyThresholdConfig>,
 }
 
@@ -122,7 +153,50 @@ impl fmt::Display for PowerSupply {
 const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
 
 impl PowerSupply {
+  /// The power supply called `name` at `path`, before anything about it is
+  /// scanned.
+  pub(crate) fn unscanned(name: String, path: PathBuf) -> Self {
+    Self {
+      name,
+      path,
+      type_: String::new(),
+
+      charge_state: None,
+      charge_percent: None,
+      energy_now_wh: None,
+      energy_full_wh: None,
+
+      cycles: None,
+      health: None,
+
+      temperature: None,
+
+      charge_threshold_start: Percent::ZERO,
+      charge_threshold_end: Percent::FULL,
+
+      charge_behaviour: None,
+      available_charge_behaviours: Vec::new(),
+
+      drain_rate_watts: None,
+
+      online: None,
+      input_power_limit_watts: None,
+
+      is_from_peripheral: false,
+
+      threshold_config: None,
+    }
+  }
+
   pub fn all() -> anyhow::Result<Vec<PowerSupply>> {
+    Self::rescan_all(&HashSet::new())
+  }
+
+  /// Get all power supplies, reusing the attributes that don't change while
+  /// the system runs, such as the threshold config, from the `previous` scan.
+  pub fn rescan_all(
+    previous: &HashSet<Arc<PowerSupply>>,
+  ) -> anyhow::Result<Vec<PowerSupply>> {
     log::info!("detecting power supplies...");
 
     let mut power_supplies = Vec::new();
@@ -145,38 +219,20 @@ impl PowerSupply {
       };
       let path = entry.path();
 
-      let mut power_supply = PowerSupply {
-        name: path
-          .file_name()
-          .with_context(|| {
-            format!(
-              "failed to get file name of '{path}'",
-              path = path.display(),
-            )
-          })?
-          .to_string_lossy()
-          .to_string(),
-
-        path,
-        type_: String::new(),
-
-        charge_state: None,
-        charge_percent: None,
-
-        cycles: None,
-        health: None,
-
-        charge_threshold_start: 0.0,
-        charge_threshold_end: 1.0,
-
-        drain_rate_watts: None,
+      let name = path
+        .file_name()
+        .with_context(|| {
+          format!("failed to get file name of '{path}'", path = path.display())
+        })?
+        .to_string_lossy()
+        .to_string();
 
-        is_from_peripheral: false,
+      let mut power_supply = PowerSupply::unscanned(name, path);
 
-        threshold_config: None,
-      };
-
-      power_supply.scan()?;
+      let previous = previous
+        .iter()
+        .find(|previous| previous.name == power_supply.name);
+      power_supply.scan_reusing(previous.map(|previous| &**previous))?;
 
       power_supplies.push(power_supply);
     }
@@ -186,7 +242,14 @@ impl PowerSupply {
     Ok(power_supplies)
   }
 
+  #[cfg(test)]
   fn scan(&mut self) -> anyhow::Result<()> {
+    self.scan_reusing(None)
+  }
+
+  /// Scans the power supply, taking the static attributes from `previous`
+  /// instead of detecting them again if it is given.
+  fn scan_reusing(&mut self, previous: Option<&Self>) -> anyhow::Result<()> {
     log::trace!("scanning power supply '{name}'", name = self.name);
 
     if !self.path.exists() {
@@ -196,7 +259,7 @@ impl PowerSupply {
     self.type_ = {
       let type_path = self.path.join("type");
 
-      fs::read(&type_path)
+      fs::read_first_line(&type_path)
         .with_context(|| {
           format!("failed to read '{path}'", path = type_path.display())
         })?
@@ -206,6 +269,10 @@ impl PowerSupply {
     };
 
     self.is_from_peripheral = 'is_from_peripheral: {
+      if let Some(previous) = previous {
+        break 'is_from_peripheral previous.is_from_peripheral;
+      }
+
       let name_lower = self.name.to_lowercase();
 
       log::trace!(
@@ -240,8 +307,9 @@ impl PowerSupply {
         }
       }
       // Check for model name that indicates a peripheral
-      if let Some(model_name) = fs::read(self.path.join("model_name"))
-        .with_context(|| format!("failed to read the model name of {self}"))?
+      if let Some(model_name) =
+        fs::read_first_line(self.path.join("model_name"))
+          .with_context(|| format!("failed to read the model name of {self}"))?
       {
         let model_name_lower = model_name.to_lowercase();
         if model_name_lower.contains("bluetooth")
@@ -255,12 +323,20 @@ impl PowerSupply {
     };
 
     if self.type_ == "Battery" {
-      self.charge_state = fs::read(self.path.join("status"))
+      self.charge_state = fs::read_first_line(self.path.join("status"))
         .with_context(|| format!("failed to read {self} charge status"))?;
 
       self.charge_percent = fs::read_n::<u64>(self.path.join("capacity"))
         .with_context(|| format!("failed to read {self} charge percent"))?
-        .map(|percent| percent as f64 / 100.0);
+        .map(|percent| Percent::from_percent(percent as f64));
+
+      self.energy_now_wh = fs::read_n::<u64>(self.path.join("energy_now"))
+        .with_context(|| format!("failed to read {self} energy"))?
+        .map(|energy| energy as f64 / 1e6);
+
+      self.energy_full_wh = fs::read_n::<u64>(self.path.join("energy_full"))
+        .with_context(|| format!("failed to read {self} full energy"))?
+        .map(|energy| energy as f64 / 1e6);
 
       self.cycles = fs::read_n::<u64>(self.path.join("cycle_count"))
         .with_context(|| format!("failed to read {self} cycle count"))?;
@@ -273,53 +349,90 @@ impl PowerSupply {
       {
         Some(health as f64 / 100.0)
       } else {
-        // Try to calculate health from energy_full vs energy_full_design
-        let energy_full = fs::read_n::<u64>(self.path.join("energy_full"))
-          .with_context(|| format!("failed to read {self} energy_full"))?;
-
-        let energy_full_design =
-          fs::read_n::<u64>(self.path.join("energy_full_design"))
-            .with_context(|| {
-              format!("failed to read {self} energy_full_design")
-            })?;
-
-        match (energy_full, energy_full_design) {
-          (Some(full), Some(design)) if design > 0 => {
-            Some(full as f64 / design as f64)
-          },
-          _ => None,
+        // Try to calculate health from the full vs design capacity. Batteries
+        // report either energy (µWh) or charge (µAh) counters, never both.
+        let mut health = None;
+
+        for (full, design) in [
+          ("energy_full", "energy_full_design"),
+          ("charge_full", "charge_full_design"),
+        ] {
+          let full = fs::read_n::<u64>(self.path.join(full))
+            .with_context(|| format!("failed to read {self} {full}"))?;
+
+          let design = fs::read_n::<u64>(self.path.join(design))
+            .with_context(|| format!("failed to read {self} {design}"))?;
+
+          if let (Some(full), Some(design)) = (full, design)
+            && design > 0
+          {
+            health = Some(full as f64 / design as f64);
+            break;
+          }
         }
+
+        health
       };
 
-      self.threshold_config = POWER_SUPPLY_THRESHOLD_CONFIGS
-        .iter()
-        .find(|config| {
-          self.path.join(config.path_start).exists()
-            && self.path.join(config.path_end).exists()
-        })
-        .copied();
+      // The ABI specifies tenths of a degree, but some drivers report
+      // millidegrees. Batteries don't survive 100°C, so anything above that
+      // has to be millidegrees.
+      self.temperature = fs::read_n::<i64>(self.path.join("temp"))
+        .with_context(|| format!("failed to read {self} temperature"))?
+        .map(|temperature| {
+          if temperature.abs() >= 1000 {
+            temperature as f64 / 1000.0
+          } else {
+            temperature as f64 / 10.0
+          }
+        });
+
+      self.threshold_config = match previous {
+        Some(previous) => previous.threshold_config,
+
+        None => {
+          POWER_SUPPLY_THRESHOLD_CONFIGS
+            .iter()
+            .find(|config| {
+              self.path.join(config.path_start).exists()
+                && self.path.join(config.path_end).exists()
+            })
+            .copied()
+        },
+      };
 
       self.charge_threshold_start = if let Some(config) = self.threshold_config
       {
-        fs::read_n::<u64>(self.path.join(config.path_start))
+        fs::read_n::<u8>(self.path.join(config.path_start))
           .with_context(|| {
             format!("failed to read {self} charge threshold start")
           })?
-          .map_or(0.0, |percent| percent as f64 / 100.0)
+          .map_or(Percent::ZERO, |percent| {
+            Percent::from_percent(f64::from(percent))
+          })
       } else {
-        0.0
+        Percent::ZERO
       };
 
       self.charge_threshold_end = if let Some(config) = self.threshold_config {
-        fs::read_n::<u64>(self.path.join(config.path_end))
+        fs::read_n::<u8>(self.path.join(config.path_end))
           .with_context(|| {
             format!("failed to read {self} charge threshold end")
           })?
-          .map_or(1.0, |percent| percent as f64 / 100.0)
+          .map_or(Percent::FULL, |percent| {
+            Percent::from_percent(f64::from(percent))
+          })
       } else {
-        1.0
+        Percent::FULL
       };
 
+      (self.available_charge_behaviours, self.charge_behaviour) =
+        fs::read(self.path.join("charge_behaviour"))
+          .with_context(|| format!("failed to read {self} charge behaviour"))?
+          .as_deref()
+          .map(parse_charge_behaviours)
+          .unwrap_or_default();
+
       self.drain_rate_watts =
         match fs::read_n::<i64>(self.path.join("power_now"))
           .with_context(|| format!("failed to read {self} power drain"))?
@@ -350,9 +463,51 @@ impl PowerSupply {
       );
     }
 
+    self.online = if self.is_ac() {
+      fs::read_n::<u8>(self.path.join("online"))
+        .with_context(|| format!("failed to read whether {self} is online"))?
+        .map(|online| online != 0)
+    } else {
+      None
+    };
+
+    self.input_power_limit_watts = if self.is_ac() {
+      self.read_input_power_limit()?
+    } else {
+      None
+    };
+
     Ok(())
   }
 
+  /// Reads the adapter wattage from `input_power_limit`, or from the maximum
+  /// voltage and current that USB-PD negotiated. `None` while it isn't
+  /// plugged in.
+  fn read_input_power_limit(&self) -> anyhow::Result<Option<f64>> {
+    if self.online == Some(false) {
+      return Ok(None);
+    }
+
+    if let Some(limit) = fs::read_n::<u64>(self.path.join("input_power_limit"))
+      .with_context(|| format!("failed to read {self} input power limit"))?
+    {
+      return Ok(Some(limit as f64 / 1e6));
+    }
+
+    let voltage_uv = fs::read_n::<u64>(self.path.join("voltage_max"))
+      .with_context(|| format!("failed to read {self} maximum voltage"))?;
+
+    let current_ua = fs::read_n::<u64>(self.path.join("current_max"))
+      .with_context(|| format!("failed to read {self} maximum current"))?;
+
+    Ok(
+      voltage_uv
+        .zip(current_ua)
+        .map(|(voltage, current)| voltage as f64 * current as f64 / 1e12)
+        .filter(|&watts| watts > 0.0),
+    )
+  }
+
   pub fn charge_threshold_path_start(&self) -> Option<PathBuf> {
     self
       .threshold_config
@@ -365,60 +520,160 @@ impl PowerSupply {
       .map(|config| self.path.join(config.path_end))
   }
 
-  pub fn set_charge_threshold_start(
+  pub fn set_charge_threshold_start_percent(
     &mut self,
-    charge_threshold_start: f64,
+    context: &fs::ApplyContext,
+    charge_threshold_start: Percent,
   ) -> anyhow::Result<()> {
-    fs::write(
-      &self.charge_threshold_path_start().ok_or_else(|| {
-        anyhow!(
-          "power supply '{name}' does not support changing charge threshold \
-           levels",
-          name = self.name,
-        )
-      })?,
-      &((charge_threshold_start * 100.0) as u8).to_string(),
-    )
-    .with_context(|| {
-      format!("failed to set charge threshold start for {self}")
-    })?;
+    let Some(percent) = charge_threshold_start.as_whole_percent() else {
+      bail!(
+        "charge threshold start for {self} must be between 0 and 100%, got \
+         {percent}%",
+        percent = charge_threshold_start.as_percent(),
+      );
+    };
+
+    context
+      .write(
+        &self.charge_threshold_path_start().ok_or_else(|| {
+          WattError::UnsupportedControl {
+            control: format!("charge threshold control of {self}"),
+          }
+        })?,
+        &percent.to_string(),
+      )
+      .with_context(|| {
+        format!("failed to set charge threshold start for {self}")
+      })?;
 
     self.charge_threshold_start = charge_threshold_start;
 
-    log::info!(
-      "set battery threshold start for {self} to {charge_threshold_start}%"
-    );
+    log::info!("set battery threshold start for {self} to {percent}%");
 
     Ok(())
   }
 
-  pub fn set_charge_threshold_end(
+  pub fn set_charge_threshold_end_percent(
     &mut self,
-    charge_threshold_end: f64,
+    context: &fs::ApplyContext,
+    charge_threshold_end: Percent,
   ) -> anyhow::Result<()> {
-    fs::write(
-      &self.charge_threshold_path_end().ok_or_else(|| {
-        anyhow!(
-          "power supply '{name}' does not support changing charge threshold \
-           levels",
-          name = self.name,
-        )
-      })?,
-      &((charge_threshold_end * 100.0) as u8).to_string(),
-    )
-    .with_context(|| {
-      format!("failed to set charge threshold end for {self}")
-    })?;
+    let Some(percent) = charge_threshold_end.as_whole_percent() else {
+      bail!(
+        "charge threshold end for {self} must be between 0 and 100%, got \
+         {percent}%",
+        percent = charge_threshold_end.as_percent(),
+      );
+    };
+
+    context
+      .write(
+        &self.charge_threshold_path_end().ok_or_else(|| {
+          WattError::UnsupportedControl {
+            control: format!("charge threshold control of {self}"),
+          }
+        })?,
+        &percent.to_string(),
+      )
+      .with_context(|| {
+        format!("failed to set charge threshold end for {self}")
+      })?;
 
     self.charge_threshold_end = charge_threshold_end;
 
-    log::info!(
-      "set battery threshold end for {self} to {charge_threshold_end}%"
-    );
+    log::info!("set battery threshold end for {self} to {percent}%");
+
+    Ok(())
+  }
+
+  /// Sets the `charge_behaviour`, such as `force-discharge` to drain the
+  /// battery while plugged in.
+  pub fn set_charge_behaviour(
+    &mut self,
+    context: &fs::ApplyContext,
+    charge_behaviour: &str,
+  ) -> anyhow::Result<()> {
+    let behaviours = &self.available_charge_behaviours;
+
+    if behaviours.is_empty() {
+      bail!(WattError::UnsupportedControl {
+        control: format!("charge behaviour control of {self}"),
+      });
+    }
+
+    if !behaviours
+      .iter()
+      .any(|behaviour| behaviour == charge_behaviour)
+    {
+      bail!(WattError::InvalidValue {
+        control:   "charge behaviour".to_owned(),
+        value:     charge_behaviour.to_owned(),
+        target:    self.to_string(),
+        available: behaviours.clone(),
+      });
+    }
+
+    context
+      .write(self.path.join("charge_behaviour"), charge_behaviour)
+      .with_context(|| format!("failed to set charge behaviour for {self}"))?;
+
+    self.charge_behaviour = Some(charge_behaviour.to_owned());
+
+    log::info!("set charge behaviour for {self} to {charge_behaviour}");
 
     Ok(())
   }
 
+  /// Refuses to force the battery to discharge while its charge is below
+  /// `floor` percent, or unknown. Other behaviours always pass.
+  pub fn check_charge_behaviour(
+    &self,
+    charge_behaviour: &str,
+    floor: u8,
+  ) -> anyhow::Result<()> {
+    if charge_behaviour != "force-discharge" {
+      return Ok(());
+    }
+
+    let Some(charge) = self.charge_percent else {
+      bail!(
+        "refusing to force {self} to discharge, its charge is unknown and may \
+         be below the floor of {floor}%"
+      );
+    };
+
+    let charge = charge.as_percent();
+
+    if charge < f64::from(floor) {
+      bail!(
+        "refusing to force {self} to discharge at {charge:.0}%, below the \
+         floor of {floor}%"
+      );
+    }
+
+    Ok(())
+  }
+
+  /// Returns a delta that restores the current charge thresholds and charge
+  /// behaviour.
+  pub fn snapshot(&self) -> Delta {
+    let (charge_threshold_start, charge_threshold_end) =
+      if self.threshold_config.is_some() {
+        (
+          Some(self.charge_threshold_start),
+          Some(self.charge_threshold_end),
+        )
+      } else {
+        (None, None)
+      };
+
+    Delta {
+      charge_threshold_start,
+      charge_threshold_end,
+      charge_behaviour: self.charge_behaviour.clone(),
+    }
+  }
+
   pub fn get_available_platform_profiles() -> anyhow::Result<Vec<String>> {
     log::trace!("reading available platform profiles");
 
@@ -442,50 +697,119 @@ impl PowerSupply {
   /// This changes the system performance, temperature, fan, and other hardware
   /// related characteristics.
   ///
+  /// Falls back to a synonym of the profile if only that is available, and
+  /// skips the write with a warning if neither is.
+  ///
   /// Also see [`The Kernel docs`] for this.
   ///
   /// [`The Kernel docs`]: <https://docs.kernel.org/userspace-api/sysfs-platform_profile.html>
-  pub fn set_platform_profile(profile: &str) -> anyhow::Result<()> {
-    log::info!("setting platform profile to '{profile}'");
-
+  pub fn set_platform_profile(
+    context: &fs::ApplyContext,
+    profile: &str,
+  ) -> anyhow::Result<()> {
     let profiles = Self::get_available_platform_profiles()?;
 
-    if !profiles
-      .iter()
-      .any(|avail_profile| avail_profile == profile)
-    {
-      bail!(
-        "profile '{profile}' is not available for system. valid profiles: \
-         {profiles}",
+    let Some(available) = resolve_platform_profile(profile, &profiles) else {
+      log::warn!(
+        "platform profile '{profile}' is not available for system, not \
+         setting it.{hint} valid profiles: {profiles}",
+        hint = suggest::did_you_mean(profile, &profiles),
         profiles = profiles.join(", "),
       );
+      return Ok(());
+    };
+
+    if available != profile {
+      log::info!(
+        "platform profile '{profile}' is not available, using its synonym \
+         '{available}'"
+      );
     }
 
-    fs::write("/sys/firmware/acpi/platform_profile", profile).context(
-      "this probably means that your system does not support changing ACPI \
-       profiles",
-    )
+    log::info!("setting platform profile to '{available}'");
+
+    context
+      .write("/sys/firmware/acpi/platform_profile", available)
+      .context(
+        "this probably means that your system does not support changing ACPI \
+         profiles",
+      )
   }
 
-  pub fn platform_profile() -> anyhow::Result<String> {
+  /// The current platform profile, `None` if the system doesn't support them.
+  pub fn platform_profile() -> anyhow::Result<Option<String>> {
     log::trace!("reading current platform profile");
 
-    fs::read("/sys/firmware/acpi/platform_profile")
-      .context("failed to read platform profile")?
-      .context("failed to find platform profile")
+    fs::read_first_line("/sys/firmware/acpi/platform_profile")
+      .context("failed to read platform profile")
+  }
+}
+
+/// Parses the `charge_behaviour` file, which lists the available behaviours
+/// with the current one in brackets, such as `auto [inhibit-charge]
+/// force-discharge`. Returns the available behaviours and the current one.
+fn parse_charge_behaviours(content: &str) -> (Vec<String>, Option<String>) {
+  let mut current = None;
+
+  let available = content
+    .split_whitespace()
+    .map(|behaviour| {
+      match behaviour
+        .strip_prefix('[')
+        .and_then(|behaviour| behaviour.strip_suffix(']'))
+      {
+        Some(behaviour) => {
+          current = Some(behaviour.to_owned());
+          behaviour.to_owned()
+        },
+        None => behaviour.to_owned(),
+      }
+    })
+    .collect();
+
+  (available, current)
+}
+
+/// Profiles that the kernel documents as meaning the same thing, where
+/// drivers only offer one of them.
+const PLATFORM_PROFILE_SYNONYMS: &[&[&str]] =
+  &[&["low-power", "quiet", "cool"]];
+
+/// Picks `profile` if it is available, or else the first available synonym.
+fn resolve_platform_profile<'a>(
+  profile: &'a str,
+  available: &'a [String],
+) -> Option<&'a str> {
+  if available.iter().any(|available| available == profile) {
+    return Some(profile);
   }
+
+  let synonyms = PLATFORM_PROFILE_SYNONYMS
+    .iter()
+    .find(|synonyms| synonyms.contains(&profile))?;
+
+  synonyms.iter().find_map(|&synonym| {
+    available
+      .iter()
+      .find(|available| *available == synonym)
+      .map(String::as_str)
+  })
 }
 
 #[derive(Default, Debug, Clone, PartialEq)]
 #[must_use]
 pub struct Delta {
-  pub charge_threshold_start: Option<f64>,
-  pub charge_threshold_end:   Option<f64>,
+  pub charge_threshold_start: Option<Percent>,
+  pub charge_threshold_end:   Option<Percent>,
+
+  pub charge_behaviour: Option<String>,
 }
 
 impl Delta {
   pub fn is_some(&self) -> bool {
-    self.charge_threshold_start.is_some() && self.charge_threshold_end.is_some()
+    self.charge_threshold_start.is_some()
+      && self.charge_threshold_end.is_some()
+      && self.charge_behaviour.is_some()
   }
 
   pub fn or(self, that: &Self) -> Self {
@@ -496,16 +820,98 @@ impl Delta {
       charge_threshold_end:   self
         .charge_threshold_end
         .or(that.charge_threshold_end),
+      charge_behaviour:       self
+        .charge_behaviour
+        .or_else(|| that.charge_behaviour.clone()),
+    }
+  }
+
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      charge_threshold_start: self
+        .charge_threshold_start
+        .filter(|value| that.charge_threshold_start.as_ref() != Some(value)),
+      charge_threshold_end:   self
+        .charge_threshold_end
+        .filter(|value| that.charge_threshold_end.as_ref() != Some(value)),
+      charge_behaviour:       self
+        .charge_behaviour
+        .filter(|value| that.charge_behaviour.as_ref() != Some(value)),
     }
   }
 
-  pub fn apply(&self, power_supply: &mut PowerSupply) -> anyhow::Result<()> {
-    if let Some(charge_threshold_start) = self.charge_threshold_start {
-      power_supply.set_charge_threshold_start(charge_threshold_start)?;
+  /// Applies the delta to `power_supply`. Forcing it to discharge below
+  /// `force_discharge_floor` percent is logged and skipped, see
+  /// [`PowerSupply::check_charge_behaviour`].
+  pub fn apply(
+    &self,
+    context: &fs::ApplyContext,
+    power_supply: &mut PowerSupply,
+    force_discharge_floor: u8,
+  ) -> anyhow::Result<()> {
+    if let (Some(start), Some(end)) =
+      (self.charge_threshold_start, self.charge_threshold_end)
+      && start >= end
+    {
+      bail!(
+        "charge threshold start of {start}% is not below the end of {end}% \
+         for {power_supply}",
+        start = start.as_percent(),
+        end = end.as_percent(),
+      );
+    }
+
+    // Firmware rejects a start threshold at or above the current end, so when
+    // both move up, the end goes first.
+    let end_first = self
+      .charge_threshold_start
+      .is_some_and(|start| start >= power_supply.charge_threshold_end);
+
+    let set_start = |power_supply: &mut PowerSupply| {
+      match self.charge_threshold_start {
+        Some(start) => {
+          power_supply.set_charge_threshold_start_percent(context, start)
+        },
+        None => Ok(()),
+      }
+    };
+    let set_end = |power_supply: &mut PowerSupply| {
+      match self.charge_threshold_end {
+        Some(end) => {
+          power_supply.set_charge_threshold_end_percent(context, end)
+        },
+        None => Ok(()),
+      }
+    };
+
+    if end_first {
+      set_end(power_supply)?;
+      set_start(power_supply)?;
+    } else {
+      set_start(power_supply)?;
+      set_end(power_supply)?;
     }
 
-    if let Some(charge_threshold_end) = self.charge_threshold_end {
-      power_supply.set_charge_threshold_end(charge_threshold_end)?;
+    if let Some(charge_behaviour) = &self.charge_behaviour {
+      match power_supply
+        .check_charge_behaviour(charge_behaviour, force_discharge_floor)
+      {
+        Ok(()) => {
+          power_supply.set_charge_behaviour(context, charge_behaviour)?
+        },
+
+        // Skipping would leave a battery that is already being forced to
+        // discharge draining past the floor.
+        Err(error)
+          if power_supply.charge_behaviour.as_deref()
+            == Some("force-discharge") =>
+        {
+          log::warn!("{error:#}, switching it back to auto");
+          power_supply.set_charge_behaviour(context, "auto")?;
+        },
+
+        Err(error) => log::warn!("{error:#}, skipping"),
+      }
     }
 
     Ok(())
@@ -525,7 +931,11 @@ mod tests {
     },
   };
 
-  use super::PowerSupply;
+  use super::{
+    PowerSupply,
+    WattError,
+    parse_charge_behaviours,
+  };
 
   static NEXT_TEMP_DIR: AtomicU64 = AtomicU64::new(0);
 
@@ -560,20 +970,7 @@ mod tests {
     }
 
     fn power_supply(&self) -> PowerSupply {
-      PowerSupply {
-        name:                   "BAT0".to_owned(),
-        path:                   self.path.clone(),
-        type_:                  String::new(),
-        is_from_peripheral:     false,
-        charge_state:           None,
-        charge_percent:         None,
-        cycles:                 None,
-        health:                 None,
-        charge_threshold_start: 0.0,
-        charge_threshold_end:   1.0,
-        drain_rate_watts:       None,
-        threshold_config:       None,
-      }
+      PowerSupply::unscanned("BAT0".to_owned(), self.path.clone())
     }
   }
 
@@ -610,4 +1007,93 @@ mod tests {
 
     assert_eq!(power_supply.health, Some(0.82));
   }
+
+  #[test]
+  fn scan_health_falls_back_to_charge_ratio_without_energy_counters() {
+    let fixture = BatteryFixture::new();
+    fixture.write("charge_full", "4500000");
+    fixture.write("charge_full_design", "5000000");
+
+    let mut power_supply = fixture.power_supply();
+    power_supply.scan().expect("scan battery fixture");
+
+    assert_eq!(power_supply.health, Some(0.9));
+  }
+
+  #[test]
+  fn scan_health_is_undefined_without_design_capacity() {
+    let fixture = BatteryFixture::new();
+    fixture.write("charge_full", "4500000");
+    fixture.write("charge_full_design", "0");
+
+    let mut power_supply = fixture.power_supply();
+    power_supply.scan().expect("scan battery fixture");
+
+    assert_eq!(power_supply.health, None);
+  }
+
+  #[test]
+  fn scan_temperature_handles_tenths_and_millidegrees() {
+    let fixture = BatteryFixture::new();
+    let mut power_supply = fixture.power_supply();
+
+    power_supply.scan().expect("scan battery fixture");
+    assert_eq!(power_supply.temperature, None);
+
+    fixture.write("temp", "315");
+    power_supply.scan().expect("scan battery fixture");
+    assert_eq!(power_supply.temperature, Some(31.5));
+
+    fixture.write("temp", "31500");
+    power_supply.scan().expect("scan battery fixture");
+    assert_eq!(power_supply.temperature, Some(31.5));
+  }
+
+  #[test]
+  fn charge_behaviours_mark_the_current_one_in_brackets() {
+    assert_eq!(
+      parse_charge_behaviours("auto [inhibit-charge] force-discharge"),
+      (
+        vec![
+          "auto".to_owned(),
+          "inhibit-charge".to_owned(),
+          "force-discharge".to_owned(),
+        ],
+        Some("inhibit-charge".to_owned()),
+      ),
+    );
+  }
+
+  #[test]
+  fn set_charge_behaviour_rejects_unavailable_behaviours() {
+    let fixture = BatteryFixture::new();
+    fixture.write("charge_behaviour", "[auto] inhibit-charge");
+
+    let mut power_supply = fixture.power_supply();
+    power_supply.scan().expect("scan battery fixture");
+    assert_eq!(power_supply.charge_behaviour.as_deref(), Some("auto"));
+
+    let error = power_supply
+      .set_charge_behaviour(
+        &crate::fs::ApplyContext::default(),
+        "force-discharge",
+      )
+      .unwrap_err()
+      .to_string();
+    assert!(
+      error.contains("available charge behaviours: auto, inhibit-charge"),
+      "unexpected error: {error}",
+    );
+
+    // Without a `charge_behaviour` file, the control is unsupported rather
+    // than the value invalid.
+    power_supply.available_charge_behaviours.clear();
+    let error = power_supply
+      .set_charge_behaviour(&crate::fs::ApplyContext::default(), "auto")
+      .unwrap_err();
+    assert!(matches!(
+      error.downcast_ref::<WattError>(),
+      Some(WattError::UnsupportedControl { .. }),
+    ));
+  }
 }
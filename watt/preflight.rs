@@ -0,0 +1,282 @@
+//! Checks whether the controls watt writes to are actually writable, so that
+//! missing permissions can be reported before the daemon starts.
+
+use std::{
+  fmt,
+  os::unix::fs::PermissionsExt as _,
+  path::{
+    Path,
+    PathBuf,
+  },
+};
+
+use anyhow::{
+  Context,
+  bail,
+};
+use nix::unistd::{
+  AccessFlags,
+  access,
+};
+use yansi::Paint as _;
+
+use crate::{
+  fs,
+  power_supply,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+  Writable,
+  ReadOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Control {
+  pub kind:   &'static str,
+  pub path:   PathBuf,
+  pub access: Access,
+}
+
+impl fmt::Display for Control {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let access = match self.access {
+      Access::Writable => "writable".green(),
+      Access::ReadOnly => "read-only".red(),
+    };
+
+    write!(
+      f,
+      "{kind:<18} {access:<9} {path}",
+      kind = self.kind,
+      path = self.path.display(),
+    )
+  }
+}
+
+/// Whether the file can be written to. Files without any write permission bits
+/// are read-only even for root, as sysfs rejects writes to them.
+fn access_of(path: &Path) -> anyhow::Result<Access> {
+  let metadata = std::fs::metadata(path).with_context(|| {
+    format!("failed to stat '{path}'", path = path.display())
+  })?;
+
+  if metadata.permissions().mode() & 0o222 == 0
+    || access(path, AccessFlags::W_OK).is_err()
+  {
+    return Ok(Access::ReadOnly);
+  }
+
+  Ok(Access::Writable)
+}
+
+/// Finds the controls watt may write to under `root`, which is `/` outside of
+/// tests. Controls that don't exist on this system are left out.
+pub fn check(root: &Path) -> anyhow::Result<Vec<Control>> {
+  let mut candidates: Vec<(&'static str, PathBuf)> = Vec::new();
+
+  let cpu_path = root.join("sys/devices/system/cpu");
+
+  let mut cpus = Vec::new();
+  for entry in fs::read_dir(&cpu_path)?.into_iter().flatten() {
+    let entry = entry.with_context(|| {
+      format!(
+        "failed to read entry of '{path}'",
+        path = cpu_path.display()
+      )
+    })?;
+
+    let name = entry.file_name();
+    let Some(number) = name
+      .to_str()
+      .and_then(|name| name.strip_prefix("cpu"))
+      .and_then(|number| number.parse::<u32>().ok())
+    else {
+      continue;
+    };
+
+    cpus.push((number, entry.path()));
+  }
+  cpus.sort_unstable_by_key(|&(number, _)| number);
+
+  for (_, path) in &cpus {
+    candidates.extend([
+      ("governor", path.join("cpufreq/scaling_governor")),
+      ("EPP", path.join("cpufreq/energy_performance_preference")),
+      ("EPB", path.join("power/energy_perf_bias")),
+      ("minimum frequency", path.join("cpufreq/scaling_min_freq")),
+      ("maximum frequency", path.join("cpufreq/scaling_max_freq")),
+    ]);
+  }
+
+  candidates.extend([
+    ("turbo", cpu_path.join("intel_pstate/no_turbo")),
+    ("turbo", cpu_path.join("cpufreq/boost")),
+    ("turbo", cpu_path.join("amd_pstate/cpufreq/boost")),
+    ("turbo", cpu_path.join("cpufreq/amd_pstate_enable_boost")),
+  ]);
+
+  let power_supply_path = root.join("sys/class/power_supply");
+
+  let mut power_supplies = Vec::new();
+  for entry in fs::read_dir(&power_supply_path)?.into_iter().flatten() {
+    let entry = entry.with_context(|| {
+      format!(
+        "failed to read entry of '{path}'",
+        path = power_supply_path.display(),
+      )
+    })?;
+
+    power_supplies.push(entry.path());
+  }
+  power_supplies.sort_unstable();
+
+  for path in &power_supplies {
+    for config in power_supply::POWER_SUPPLY_THRESHOLD_CONFIGS {
+      candidates.extend([
+        ("charge threshold", path.join(config.path_start)),
+        ("charge threshold", path.join(config.path_end)),
+      ]);
+    }
+  }
+
+  candidates.push((
+    "platform profile",
+    root.join("sys/firmware/acpi/platform_profile"),
+  ));
+
+  let mut controls = Vec::new();
+
+  for (kind, path) in candidates {
+    if !fs::exists(&path) {
+      continue;
+    }
+
+    controls.push(Control {
+      kind,
+      access: access_of(&path)?,
+      path,
+    });
+  }
+
+  Ok(controls)
+}
+
+/// Prints which controls are writable, failing if none of them are.
+pub fn run() -> anyhow::Result<()> {
+  let controls = check(Path::new("/"))?;
+
+  for control in &controls {
+    println!("{control}");
+  }
+
+  let writable = controls
+    .iter()
+    .filter(|control| control.access == Access::Writable)
+    .count();
+
+  println!(
+    "{writable} of {total} controls are writable",
+    total = controls.len(),
+  );
+
+  if writable == 0 {
+    bail!(
+      "none of the controls watt uses are writable, make sure watt runs as \
+       root"
+    );
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    env,
+    fs::Permissions,
+    process,
+    sync::atomic::{
+      AtomicU64,
+      Ordering,
+    },
+  };
+
+  use super::*;
+
+  static NEXT_TEMP_DIR: AtomicU64 = AtomicU64::new(0);
+
+  struct RootFixture {
+    path: PathBuf,
+  }
+
+  impl RootFixture {
+    fn new() -> Self {
+      let counter = NEXT_TEMP_DIR.fetch_add(1, Ordering::Relaxed);
+      let path = env::temp_dir()
+        .join(format!("watt-preflight-{}-{counter}", process::id()));
+
+      let _ = std::fs::remove_dir_all(&path);
+      std::fs::create_dir(&path).expect("create root fixture directory");
+
+      Self { path }
+    }
+
+    fn create(&self, path: &str, mode: u32) {
+      let path = self.path.join(path);
+      std::fs::create_dir_all(path.parent().unwrap())
+        .expect("create root fixture parent");
+      std::fs::write(&path, "").expect("write root fixture file");
+      std::fs::set_permissions(&path, Permissions::from_mode(mode))
+        .expect("set root fixture file mode");
+    }
+  }
+
+  impl Drop for RootFixture {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.path);
+    }
+  }
+
+  #[test]
+  fn check_reports_mixed_access() {
+    let root = RootFixture::new();
+
+    root.create(
+      "sys/devices/system/cpu/cpu0/cpufreq/scaling_governor",
+      0o644,
+    );
+    root.create(
+      "sys/devices/system/cpu/cpu1/cpufreq/scaling_governor",
+      0o444,
+    );
+    root.create("sys/devices/system/cpu/intel_pstate/no_turbo", 0o644);
+    root.create(
+      "sys/class/power_supply/BAT0/charge_control_end_threshold",
+      0o444,
+    );
+    root.create("sys/firmware/acpi/platform_profile", 0o644);
+
+    let controls = check(&root.path).unwrap();
+
+    let summary: Vec<(&str, Access)> = controls
+      .iter()
+      .map(|control| (control.kind, control.access))
+      .collect();
+
+    assert_eq!(summary, [
+      ("governor", Access::Writable),
+      ("governor", Access::ReadOnly),
+      ("turbo", Access::Writable),
+      ("charge threshold", Access::ReadOnly),
+      ("platform profile", Access::Writable),
+    ]);
+  }
+
+  #[test]
+  fn check_skips_missing_controls() {
+    let root = RootFixture::new();
+
+    assert_eq!(check(&root.path).unwrap(), []);
+  }
+}
@@ -5,12 +5,15 @@ use std::{
   time::Instant,
 };
 
+use schemars::JsonSchema;
 use serde::{
   Deserialize,
   Serialize,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema,
+)]
 #[serde(rename_all = "kebab-case")]
 pub enum PowerProfile {
   Performance,
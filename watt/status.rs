@@ -0,0 +1,262 @@
+//! A Unix socket that `watt status` queries the running daemon over, for
+//! systems without a system bus.
+//!
+//! Every message is a big endian `u32` length followed by that many bytes of
+//! JSON. The client sends a [`Request`] and the daemon answers with a
+//! [`Response`].
+
+use std::{
+  fs,
+  io::{
+    self,
+    Read,
+    Write,
+  },
+  os::unix::{
+    fs::PermissionsExt as _,
+    net::{
+      UnixListener,
+      UnixStream,
+    },
+  },
+  path::Path,
+  sync::Arc,
+  thread,
+  time::Duration,
+};
+
+use anyhow::{
+  Context as _,
+  bail,
+};
+use serde::{
+  Deserialize,
+  Serialize,
+  de::DeserializeOwned,
+};
+use tokio::sync::RwLock;
+
+use crate::system::{
+  DaemonState,
+  MonitorSample,
+};
+
+pub const SOCKET_PATH: &str = "/run/watt/status.sock";
+
+/// Messages larger than this are rejected instead of being allocated.
+const MAX_MESSAGE_LENGTH: u32 = 1 << 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum Request {
+  Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "response", rename_all = "kebab-case")]
+pub enum Response {
+  Status(Box<Status>),
+  Error { message: String },
+}
+
+/// What the daemon is doing, as printed by `watt status`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Status {
+  pub profile:            String,
+  pub rule_count:         usize,
+  pub last_applied_rules: Vec<String>,
+  /// Seconds until the next poll, `None` before the first one.
+  pub poll_interval:      Option<f64>,
+  /// `None` before the first poll.
+  pub sample:             Option<MonitorSample>,
+}
+
+impl Status {
+  fn new(state: &DaemonState) -> Self {
+    Self {
+      profile:            state.active_profile().as_str().to_owned(),
+      rule_count:         state.rule_count(),
+      last_applied_rules: state.last_applied_rules(),
+      poll_interval:      state
+        .poll_interval()
+        .map(|delay| delay.as_secs_f64()),
+      sample:             state.monitor_sample(),
+    }
+  }
+}
+
+fn read_message<T: DeserializeOwned>(
+  stream: &mut impl Read,
+) -> anyhow::Result<T> {
+  let mut length = [0; 4];
+  stream
+    .read_exact(&mut length)
+    .context("failed to read message length")?;
+
+  let length = u32::from_be_bytes(length);
+  if length > MAX_MESSAGE_LENGTH {
+    bail!("message of {length} bytes is too long");
+  }
+
+  let mut message = vec![0; length as usize];
+  stream
+    .read_exact(&mut message)
+    .context("failed to read message")?;
+
+  serde_json::from_slice(&message).context("failed to parse message")
+}
+
+fn write_message(
+  stream: &mut impl Write,
+  message: &impl Serialize,
+) -> anyhow::Result<()> {
+  let message =
+    serde_json::to_vec(message).context("failed to serialize message")?;
+  let length =
+    u32::try_from(message.len()).context("message is too long to send")?;
+
+  stream
+    .write_all(&length.to_be_bytes())
+    .and_then(|()| stream.write_all(&message))
+    .and_then(|()| stream.flush())
+    .context("failed to write message")
+}
+
+/// Listens on `path` in a background thread, answering with the current
+/// `state`. Replaces a socket left behind by a daemon that didn't exit
+/// cleanly.
+pub fn start(
+  path: &Path,
+  state: Arc<RwLock<DaemonState>>,
+) -> anyhow::Result<()> {
+  match fs::remove_file(path) {
+    Ok(()) => {},
+    Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+    Err(error) => {
+      return Err(error).with_context(|| {
+        format!(
+          "failed to remove stale socket '{path}'",
+          path = path.display()
+        )
+      });
+    },
+  }
+
+  let listener = UnixListener::bind(path).with_context(|| {
+    format!(
+      "failed to bind status socket '{path}'",
+      path = path.display()
+    )
+  })?;
+
+  // The status is read only, anyone may ask for it.
+  fs::set_permissions(path, fs::Permissions::from_mode(0o666)).with_context(
+    || {
+      format!(
+        "failed to set permissions of status socket '{path}'",
+        path = path.display(),
+      )
+    },
+  )?;
+
+  thread::Builder::new()
+    .name("watt-status".to_owned())
+    .spawn(move || serve(listener, state))
+    .context("failed to spawn status socket thread")?;
+
+  log::info!("serving status at '{path}'", path = path.display());
+
+  Ok(())
+}
+
+fn serve(listener: UnixListener, state: Arc<RwLock<DaemonState>>) {
+  for stream in listener.incoming() {
+    let result = stream
+      .context("failed to accept status connection")
+      .and_then(|mut stream| respond(&mut stream, &state));
+
+    if let Err(error) = result {
+      log::warn!("failed to answer status request: {error:#}");
+    }
+  }
+
+  log::error!("status socket loop exited unexpectedly");
+}
+
+fn respond(
+  stream: &mut UnixStream,
+  state: &RwLock<DaemonState>,
+) -> anyhow::Result<()> {
+  // A client that stops halfway shouldn't hold up the others.
+  stream
+    .set_read_timeout(Some(Duration::from_secs(1)))
+    .context("failed to set status connection timeout")?;
+
+  let response = match read_message::<Request>(stream) {
+    Ok(Request::Status) => {
+      Response::Status(Box::new(Status::new(&state.blocking_read())))
+    },
+    Err(error) => {
+      Response::Error {
+        message: format!("{error:#}"),
+      }
+    },
+  };
+
+  write_message(stream, &response)
+}
+
+/// Asks the daemon listening on `path` for its status.
+pub fn query(path: &Path) -> anyhow::Result<Status> {
+  let mut stream = UnixStream::connect(path).with_context(|| {
+    format!(
+      "failed to connect to '{path}', is the daemon running?",
+      path = path.display(),
+    )
+  })?;
+
+  write_message(&mut stream, &Request::Status)?;
+
+  match read_message(&mut stream)? {
+    Response::Status(status) => Ok(*status),
+    Response::Error { message } => bail!("daemon refused request: {message}"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    env,
+    process,
+  };
+
+  use super::*;
+
+  #[test]
+  fn query_returns_daemon_state() {
+    let path =
+      env::temp_dir().join(format!("watt-status-{}.sock", process::id()));
+
+    start(&path, Arc::new(RwLock::new(DaemonState::new(3)))).unwrap();
+
+    let status = query(&path).unwrap();
+    assert_eq!(status.rule_count, 3);
+    assert_eq!(status.last_applied_rules, Vec::<String>::new());
+    assert_eq!(status.poll_interval, None);
+    assert_eq!(status.sample, None);
+
+    // Garbage gets an error back instead of taking the listener down.
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream.write_all(&2u32.to_be_bytes()).unwrap();
+    stream.write_all(b"{}").unwrap();
+    assert!(matches!(
+      read_message(&mut stream).unwrap(),
+      Response::Error { .. },
+    ));
+
+    assert_eq!(query(&path).unwrap().rule_count, 3);
+
+    let _ = fs::remove_file(&path);
+  }
+}
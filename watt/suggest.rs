@@ -0,0 +1,73 @@
+//! "Did you mean" hints for values that aren't in a known set.
+
+/// Number of single character edits needed to turn `a` into `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+
+  let mut previous: Vec<usize> = (0..=b.len()).collect();
+  let mut current = vec![0; b.len() + 1];
+
+  for (i, a) in a.chars().enumerate() {
+    current[0] = i + 1;
+
+    for (j, &b) in b.iter().enumerate() {
+      let substitution = previous[j] + usize::from(a != b);
+      current[j + 1] =
+        substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+    }
+
+    std::mem::swap(&mut previous, &mut current);
+  }
+
+  previous[b.len()]
+}
+
+/// Returns the candidate closest to `value`, if it is close enough to make a
+/// typo the likely explanation.
+pub fn closest<'a>(
+  value: &str,
+  candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+  let limit = (value.chars().count() / 3).max(1);
+
+  candidates
+    .into_iter()
+    .map(|candidate| (levenshtein(value, candidate), candidate))
+    .filter(|&(distance, _)| distance <= limit)
+    .min_by_key(|&(distance, _)| distance)
+    .map(|(_, candidate)| candidate)
+}
+
+/// Formats a hint to put in an error message, starting with a space. Empty if
+/// no candidate is close enough.
+pub fn did_you_mean<'a>(
+  value: &str,
+  candidates: impl IntoIterator<Item = &'a String>,
+) -> String {
+  closest(value, candidates.into_iter().map(String::as_str))
+    .map(|candidate| format!(" did you mean '{candidate}'?"))
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn levenshtein_counts_edits() {
+    assert_eq!(levenshtein("", ""), 0);
+    assert_eq!(levenshtein("powersave", "powersave"), 0);
+    assert_eq!(levenshtein("powersafe", "powersave"), 1);
+    assert_eq!(levenshtein("kitten", "sitting"), 3);
+    assert_eq!(levenshtein("", "abc"), 3);
+  }
+
+  #[test]
+  fn closest_only_suggests_near_misses() {
+    let governors = ["performance", "powersave", "schedutil"];
+
+    assert_eq!(closest("powersafe", governors), Some("powersave"));
+    assert_eq!(closest("perfomance", governors), Some("performance"));
+    assert_eq!(closest("ondemand", governors), None);
+  }
+}
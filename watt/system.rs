@@ -5,7 +5,10 @@ use std::{
     VecDeque,
   },
   mem,
-  path::Path,
+  path::{
+    Path,
+    PathBuf,
+  },
   sync::Arc,
   time::{
     Duration,
@@ -17,9 +20,16 @@ use anyhow::{
   Context,
   bail,
 };
+use serde::{
+  Deserialize,
+  Serialize,
+};
 use tokio::{
-  signal,
-  sync::RwLock,
+  signal::unix,
+  sync::{
+    Notify,
+    RwLock,
+  },
 };
 
 use crate::{
@@ -29,8 +39,10 @@ use crate::{
   disk,
   fs,
   gpu,
+  percent::Percent,
   power_supply,
   profile,
+  systemd,
   uncore,
   usb,
   vm,
@@ -65,23 +77,94 @@ struct PowerSupplyLog {
   charge: f64,
 }
 
+/// How far back the CPU and power supply logs are looked at, and how long
+/// they are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LogWindows {
+  volatility: Duration,
+  idle:       Duration,
+  capacity:   usize,
+}
+
+impl Default for LogWindows {
+  fn default() -> Self {
+    Self::new(&config::DaemonConfig::default())
+  }
+}
+
+impl LogWindows {
+  fn new(config: &config::DaemonConfig) -> Self {
+    Self {
+      volatility: config.volatility_window,
+      idle:       config.idle_threshold,
+      capacity:   config.log_capacity(),
+    }
+  }
+}
+
 #[derive(Default, Debug, Clone)]
 struct System {
   is_ac: bool,
 
-  lid_closed:      bool,
-  virtual_machine: bool,
-  chassis_type:    Option<String>,
+  lid_closed:         Option<bool>,
+  lid_switch:         Option<LidSwitch>,
+  /// Whether the lid switch was looked for already.
+  lid_switch_scanned: bool,
+  virtual_machine:    bool,
+  chassis_type:       Option<String>,
+  /// Whether the chassis type and virtualization were detected already. They
+  /// don't change while the system runs, so they are only read once.
+  platform_scanned:   bool,
+  /// The current ACPI platform profile.
+  platform_profile:   Option<String>,
+  /// Whether this is a desktop, detected once when there is no power supply
+  /// reporting AC.
+  desktop:            Option<bool>,
 
   load_average_1min:  f64,
   load_average_5min:  f64,
   load_average_15min: f64,
 
+  /// The fraction of memory in use, from `/proc/meminfo`.
+  memory_usage: Option<f64>,
+
+  /// The CPU pressure stall information from `/proc/pressure/cpu`.
+  cpu_pressure: Option<Pressure>,
+
   /// All CPUs.
-  cpus:             HashSet<Arc<cpu::Cpu>>,
+  cpus:                              HashSet<Arc<cpu::Cpu>>,
   /// CPU usage and temperature log.
-  cpu_log:          VecDeque<CpuLog>,
-  cpu_temperatures: HashMap<u32, f64>,
+  cpu_log:                           VecDeque<CpuLog>,
+  /// The weight of the newest sample in the CPU usage moving average.
+  cpu_usage_smoothing:               f64,
+  /// How far back the CPU and power supply logs are looked at.
+  log_windows:                       LogWindows,
+  cpu_temperatures:                  HashMap<u32, f64>,
+  /// The sensors configured with `temperature-sensors`, read instead of
+  /// detecting CPU sensors if not empty.
+  temperature_sensors:               Vec<config::TemperatureSensor>,
+  /// Whether the lack of temperature sensors was reported already, so it
+  /// isn't repeated every poll.
+  temperatures_unavailable_reported: bool,
+  /// The names and paths of all hwmon devices, listed again every
+  /// [`HWMON_RELIST_SCANS`] scans.
+  hwmon_devices:                     Option<Vec<(String, PathBuf)>>,
+  /// How many scans used `hwmon_devices`.
+  hwmon_devices_scans:               u32,
+  /// The fastest fan in RPM, over every hwmon device.
+  fan_rpm_maximum:                   Option<f64>,
+  /// Whether the config reads the GPU sensors, which are only scanned then.
+  scan_gpu_sensors:                  bool,
+  /// The temperatures of the GPU hwmon devices, kept apart from the CPU
+  /// ones so neither skews the other.
+  gpu_temperatures:                  HashMap<u32, f64>,
+  /// The power draw of the GPUs reporting it, summed.
+  gpu_power_watts:                   Option<f64>,
+  /// Whether the config reads the disk temperatures, which are only scanned
+  /// then.
+  scan_disk_temperatures:            bool,
+  /// The hottest sensor of each storage device, keyed by device.
+  disk_temperatures:                 HashMap<String, f64>,
 
   /// All Intel uncore frequency devices.
   uncores: HashSet<Arc<uncore::Uncore>>,
@@ -100,52 +183,74 @@ struct System {
   /// Power supply status log.
   power_supply_log: VecDeque<PowerSupplyLog>,
 
-  /// Battery cycle count (aggregated average across all batteries).
+  /// Files read by rules during the current evaluation.
+  sysfs_reads: config::SysfsReads,
+
+  /// Battery cycle count (the highest across all batteries).
   battery_cycles: Option<f64>,
   /// Battery health (aggregated average across all batteries).
   battery_health: Option<f64>,
+
+  /// How long each phase of the last scan took, in order.
+  scan_timings: Vec<(&'static str, Duration)>,
 }
 
 impl System {
+  /// A system that scans and evaluates the way `config` asks for.
+  fn new(config: &config::DaemonConfig) -> Self {
+    Self {
+      temperature_sensors: config.temperature_sensors.clone(),
+      cpu_usage_smoothing: config.cpu_usage_smoothing,
+      log_windows: LogWindows::new(config),
+      scan_gpu_sensors: references_gpu_sensors(config),
+      scan_disk_temperatures: config.references("$disk-temperature-max"),
+      ..System::default()
+    }
+  }
+
+  fn record_scan_timing(&mut self, phase: &'static str, start: Instant) {
+    let elapsed = start.elapsed();
+
+    log::info!(
+      "scanned {phase} in {millis}ms",
+      millis = elapsed.as_millis(),
+    );
+
+    self.scan_timings.push((phase, elapsed));
+  }
+
   fn scan(&mut self) -> anyhow::Result<()> {
     log::info!("scanning view of system hardware...");
 
+    let scan_start = Instant::now();
+    self.scan_timings.clear();
+
+    if self.hwmon_devices_scans == HWMON_RELIST_SCANS {
+      self.hwmon_devices = None;
+      self.hwmon_devices_scans = 0;
+    }
+    self.hwmon_devices_scans += 1;
+
     {
       let start = Instant::now();
 
-      // Preserve previous stats for delta calculation
-      let previous_stats: HashMap<u32, cpu::CpuStat> = self
-        .cpus
-        .iter()
-        .map(|cpu| (cpu.number, cpu.stat.clone()))
-        .collect();
-
-      self.cpus = cpu::Cpu::all()
+      self.cpus = cpu::Cpu::rescan_all(&self.cpus)
         .context("failed to scan CPUs")?
         .into_iter()
-        .map(|mut cpu| {
-          // Transfer previous stat for this CPU
-          cpu.previous_stat = previous_stats.get(&cpu.number).cloned();
-          Arc::from(cpu)
-        })
+        .map(Arc::from)
         .collect();
-      log::info!(
-        "scanned all CPUs in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.record_scan_timing("CPUs", start);
     }
 
     {
       let start = Instant::now();
-      self.power_supplies = power_supply::PowerSupply::all()
-        .context("failed to scan power supplies")?
-        .into_iter()
-        .map(Arc::from)
-        .collect();
-      log::info!(
-        "scanned all power supplies in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.power_supplies =
+        power_supply::PowerSupply::rescan_all(&self.power_supplies)
+          .context("failed to scan power supplies")?
+          .into_iter()
+          .map(Arc::from)
+          .collect();
+      self.record_scan_timing("power supplies", start);
     }
 
     {
@@ -155,10 +260,7 @@ impl System {
         .into_iter()
         .map(Arc::from)
         .collect();
-      log::info!(
-        "scanned all uncore devices in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.record_scan_timing("uncore devices", start);
     }
 
     {
@@ -168,10 +270,7 @@ impl System {
         .into_iter()
         .map(Arc::from)
         .collect();
-      log::info!(
-        "scanned all disks in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.record_scan_timing("disks", start);
     }
 
     {
@@ -181,10 +280,7 @@ impl System {
         .into_iter()
         .map(Arc::from)
         .collect();
-      log::info!(
-        "scanned all USB devices in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.record_scan_timing("USB devices", start);
     }
 
     {
@@ -194,85 +290,131 @@ impl System {
         .into_iter()
         .map(Arc::from)
         .collect();
-      log::info!(
-        "scanned all GPUs in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.record_scan_timing("GPUs", start);
     }
 
-    self.is_ac = self
-      .power_supplies
-      .iter()
-      .any(|power_supply| power_supply.is_ac())
-      || {
-        log::debug!(
-          "checking whether if this device is a desktop to determine if it is \
-           AC as no power supplies are"
-        );
+    // Laptops keep their adapter around when it is unplugged, only offline.
+    self.is_ac = self.power_supplies.iter().any(|power_supply| {
+      power_supply.is_ac() && power_supply.online != Some(false)
+    }) || {
+      log::debug!(
+        "checking whether if this device is a desktop to determine if it is \
+         AC as no power supplies are"
+      );
 
-        let start = Instant::now();
-        let is_desktop = self.is_desktop()?;
-        log::debug!(
-          "checked if is a desktop in {millis}ms",
-          millis = start.elapsed().as_millis(),
-        );
+      let is_desktop = match self.desktop {
+        Some(is_desktop) => is_desktop,
 
-        log::debug!(
-          "scan result: {elaborate}",
-          elaborate = if is_desktop {
-            "is a desktop, therefore is AC"
-          } else {
-            "not a desktop, and not AC"
-          },
-        );
+        None => {
+          let start = Instant::now();
+          let is_desktop = self.is_desktop()?;
+          log::debug!(
+            "checked if is a desktop in {millis}ms",
+            millis = start.elapsed().as_millis(),
+          );
 
-        is_desktop
+          self.desktop = Some(is_desktop);
+          is_desktop
+        },
       };
 
+      log::debug!(
+        "scan result: {elaborate}",
+        elaborate = if is_desktop {
+          "is a desktop, therefore is AC"
+        } else {
+          "not a desktop, and not AC"
+        },
+      );
+
+      is_desktop
+    };
+
     {
       let start = Instant::now();
       self.scan_load_average()?;
-      log::info!(
-        "scanned load average in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.record_scan_timing("load average", start);
     }
 
     {
       let start = Instant::now();
-      self.scan_lid_state()?;
-      log::info!(
-        "scanned lid state in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.scan_memory()?;
+      self.record_scan_timing("memory usage", start);
+    }
+
+    {
+      let start = Instant::now();
+      self.scan_cpu_pressure();
+      self.record_scan_timing("CPU pressure", start);
     }
 
     {
+      let start = Instant::now();
+      self.scan_lid_state()?;
+      self.record_scan_timing("lid state", start);
+    }
+
+    if !self.platform_scanned {
       let start = Instant::now();
       self.chassis_type =
         read_chassis_type().context("failed to read chassis type")?;
       self.virtual_machine = detect_virtual_machine()
         .context("failed to detect virtualization status")?;
-      log::info!(
-        "scanned platform identity in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.platform_scanned = true;
+      self.record_scan_timing("platform identity", start);
     }
 
+    self.platform_profile = power_supply::PowerSupply::platform_profile()
+      .context("failed to read platform profile")?;
+
     {
       let start = Instant::now();
       self.scan_temperatures()?;
-      log::info!(
-        "scanned temperatures in {millis}ms",
-        millis = start.elapsed().as_millis(),
-      );
+      self.record_scan_timing("temperatures", start);
+    }
+
+    {
+      let start = Instant::now();
+      self.scan_fans()?;
+      self.record_scan_timing("fans", start);
+    }
+
+    if self.scan_gpu_sensors {
+      let start = Instant::now();
+      self.scan_gpu_sensors()?;
+      self.record_scan_timing("GPU sensors", start);
+    }
+
+    if self.scan_disk_temperatures {
+      let start = Instant::now();
+      self.scan_disk_temperatures()?;
+      self.record_scan_timing("disk temperatures", start);
+    }
+
+    match average_temperature(&self.cpu_temperatures) {
+      Some(_) => self.temperatures_unavailable_reported = false,
+
+      None if !self.temperatures_unavailable_reported => {
+        log::warn!(
+          "no valid CPU temperature readings found, temperature based \
+           conditions will be undefined"
+        );
+        self.temperatures_unavailable_reported = true;
+      },
+
+      None => {},
     }
 
+    log::info!(
+      "scanned system hardware in {millis}ms",
+      millis = scan_start.elapsed().as_millis(),
+    );
+
     log::debug!("appending to system logs...");
 
     let at = Instant::now();
 
-    while self.cpu_log.len() > 100 {
+    while self.cpu_log.len() >= self.log_windows.capacity {
       log::debug!("daemon CPU log was too long, popping element");
       self.cpu_log.pop_front();
     }
@@ -280,42 +422,35 @@ impl System {
     let cpu_log = CpuLog {
       at,
 
-      usage: self.cpus.iter().map(|cpu| cpu.current_usage()).sum::<f64>()
-        / self.cpus.len() as f64,
+      // Offline CPUs have no statistics, they would drag the average down.
+      usage: {
+        let (sum, count) = self
+          .cpus
+          .iter()
+          .filter(|cpu| cpu.online)
+          .fold((0.0, 0u32), |(sum, count), cpu| {
+            (sum + cpu.current_usage(), count + 1)
+          });
+
+        if count > 0 { sum / count as f64 } else { 0.0 }
+      },
 
-      temperature: (!self.cpu_temperatures.is_empty()).then(|| {
-        self.cpu_temperatures.values().sum::<f64>()
-          / self.cpu_temperatures.len() as f64
-      }),
+      temperature: average_temperature(&self.cpu_temperatures),
 
       load_average: self.load_average_1min,
     };
     log::debug!("appending CPU log item: {cpu_log:?}");
     self.cpu_log.push_back(cpu_log);
 
-    while self.power_supply_log.len() > 100 {
+    while self.power_supply_log.len() >= self.log_windows.capacity {
       log::debug!("daemon power supply log was too long, popping element");
       self.power_supply_log.pop_front();
     }
 
-    if !self.power_supplies.is_empty() {
-      let power_supply_log = PowerSupplyLog {
-        at,
-        charge: {
-          let (charge_sum, charge_nr) = self.power_supplies.iter().fold(
-            (0.0, 0u32),
-            |(sum, count), power_supply| {
-              if let Some(charge_percent) = power_supply.charge_percent {
-                (sum + charge_percent, count + 1)
-              } else {
-                (sum, count)
-              }
-            },
-          );
-
-          charge_sum / charge_nr as f64
-        },
-      };
+    if let Some(charge) =
+      battery_charge(&config::find_batteries(&self.power_supplies))
+    {
+      let power_supply_log = PowerSupplyLog { at, charge };
       log::debug!("appending power supply log item: {power_supply_log:?}");
       self.power_supply_log.push_back(power_supply_log);
     }
@@ -327,23 +462,12 @@ impl System {
       self.battery_cycles = None;
       self.battery_health = None;
     } else {
-      // Calculate average cycle count across all batteries
-      let (cycle_sum, cycles) =
-        batteries
-          .iter()
-          .fold((0u64, 0u32), |(sum, count), power_supply| {
-            if let Some(cycles) = power_supply.cycles {
-              (sum + cycles, count + 1)
-            } else {
-              (sum, count)
-            }
-          });
-
-      self.battery_cycles = if cycles > 0 {
-        Some(cycle_sum as f64 / cycles as f64)
-      } else {
-        None
-      };
+      // The most worn battery decides how conservative charging should be.
+      self.battery_cycles = batteries
+        .iter()
+        .filter_map(|power_supply| power_supply.cycles)
+        .max()
+        .map(|cycles| cycles as f64);
 
       // Calculate average health across all batteries
       let (health_sum, health_count) =
@@ -367,12 +491,16 @@ impl System {
     Ok(())
   }
 
-  fn scan_temperatures(&mut self) -> anyhow::Result<()> {
-    log::debug!("scanning CPU temperatures...");
-
+  /// Lists the names and paths of the hwmon devices, sorted by path. Later
+  /// calls reuse that list until it is dropped in [`Self::scan`].
+  fn hwmon_devices(&mut self) -> anyhow::Result<Vec<(String, PathBuf)>> {
     const PATH: &str = "/sys/class/hwmon";
 
-    let mut temperatures = HashMap::new();
+    if let Some(devices) = &self.hwmon_devices {
+      return Ok(devices.clone());
+    }
+
+    let mut devices = Vec::new();
 
     for entry in fs::read_dir(PATH)
       .context("failed to read hardware information")?
@@ -383,8 +511,8 @@ impl System {
 
       let entry_path = entry.path();
 
-      let Some(name) =
-        fs::read(entry_path.join("name")).with_context(|| {
+      let Some(name) = fs::read_first_line(entry_path.join("name"))
+        .with_context(|| {
           format!(
             "failed to read name of hardware entry at '{path}'",
             path = entry_path.display(),
@@ -394,15 +522,44 @@ impl System {
         continue;
       };
 
-      match &*name {
+      devices.push((name, entry_path));
+    }
+
+    devices.sort_unstable_by(|(_, a), (_, b)| a.cmp(b));
+
+    log::debug!("found {len} hwmon devices", len = devices.len());
+
+    self.hwmon_devices = Some(devices.clone());
+
+    Ok(devices)
+  }
+
+  fn scan_temperatures(&mut self) -> anyhow::Result<()> {
+    log::debug!("scanning CPU temperatures...");
+
+    let devices = self.hwmon_devices()?;
+
+    if !self.temperature_sensors.is_empty() {
+      self.cpu_temperatures = self.scan_configured_temperatures(&devices)?;
+      return Ok(());
+    }
+
+    let mut temperatures = HashMap::new();
+
+    for (name, device_path) in &devices {
+      match &**name {
         // TODO: 'zenergy' can also report those stats, I think?
-        "coretemp" | "k10temp" | "zenpower" | "amdgpu" => {
-          Self::get_temperatures(&entry_path, &mut temperatures)?;
+        "coretemp" | "k10temp" | "zenpower" => {
+          Self::get_temperatures(device_path, &mut temperatures)?;
         },
 
+        // Read on their own, `drivetemp` would pass for a CPU driver below.
+        _ if GPU_HWMON_NAMES.contains(&&**name)
+          || DISK_HWMON_NAMES.contains(&&**name) => {},
+
         // Other CPU temperature drivers.
         _ if name.contains("cpu") || name.contains("temp") => {
-          Self::get_temperatures(&entry_path, &mut temperatures)?;
+          Self::get_temperatures(device_path, &mut temperatures)?;
         },
 
         _ => {},
@@ -412,7 +569,7 @@ impl System {
     if temperatures.is_empty() {
       const PATH: &str = "/sys/devices/virtual/thermal";
 
-      log::warn!(
+      log::debug!(
         "failed to get CPU temperature information by using hwmon, falling \
          back to '{PATH}'"
       );
@@ -438,8 +595,8 @@ impl System {
           continue;
         }
 
-        let Some(entry_type) =
-          fs::read(entry_path.join("type")).with_context(|| {
+        let Some(entry_type) = fs::read_first_line(entry_path.join("type"))
+          .with_context(|| {
             format!(
               "failed to read type of zone at '{path}'",
               path = entry_path.display(),
@@ -478,6 +635,211 @@ impl System {
     Ok(())
   }
 
+  /// Reads the `fanN_input` sensors of every hwmon device, keeping the
+  /// fastest one.
+  fn scan_fans(&mut self) -> anyhow::Result<()> {
+    log::debug!("scanning fan speeds...");
+
+    let mut fan_rpm_maximum = None::<u64>;
+
+    for (_, device_path) in self.hwmon_devices()? {
+      let Some(entries) = fs::read_dir(&device_path)
+        .context("failed to read hardware device entries")?
+      else {
+        continue;
+      };
+
+      for entry in entries {
+        let entry = entry.with_context(|| {
+          format!(
+            "failed to read entry of '{path}'",
+            path = device_path.display(),
+          )
+        })?;
+
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        // Has to match "fan{N}_input".
+        let is_fan_input = file_name
+          .strip_prefix("fan")
+          .and_then(|rest| rest.strip_suffix("_input"))
+          .is_some_and(|number| number.parse::<u32>().is_ok());
+
+        if !is_fan_input {
+          continue;
+        }
+
+        let input_path = entry.path();
+
+        let Some(rpm) = read_sensor::<u64>(&input_path) else {
+          continue;
+        };
+
+        log::trace!(
+          "fan speed at '{path}': {rpm} RPM",
+          path = input_path.display(),
+        );
+
+        fan_rpm_maximum = fan_rpm_maximum.max(Some(rpm));
+      }
+    }
+
+    self.fan_rpm_maximum = fan_rpm_maximum.map(|rpm| rpm as f64);
+
+    Ok(())
+  }
+
+  /// Reads the temperature and `powerN_average` sensors of the GPU hwmon
+  /// devices. Every temperature sensor is kept, as GPUs label theirs by
+  /// location (`edge`, `junction`, `mem`) rather than by core.
+  fn scan_gpu_sensors(&mut self) -> anyhow::Result<()> {
+    log::debug!("scanning GPU sensors...");
+
+    let mut temperatures = HashMap::new();
+    let mut power_microwatts = None::<u64>;
+
+    for (name, device_path) in self.hwmon_devices()? {
+      if !GPU_HWMON_NAMES.contains(&&*name) {
+        continue;
+      }
+
+      for temperature in Self::read_temperature_inputs(&device_path) {
+        temperatures.insert(temperatures.len() as u32, temperature);
+      }
+
+      for i in 1..=4 {
+        let average_path = device_path.join(format!("power{i}_average"));
+
+        let Some(microwatts) = read_sensor::<u64>(&average_path) else {
+          continue;
+        };
+
+        *power_microwatts.get_or_insert(0) += microwatts;
+      }
+    }
+
+    log::debug!(
+      "found {len} GPU temperature sensors",
+      len = temperatures.len(),
+    );
+
+    self.gpu_temperatures = temperatures;
+    self.gpu_power_watts =
+      power_microwatts.map(|microwatts| microwatts as f64 / 1_000_000.0);
+
+    Ok(())
+  }
+
+  /// Reads the temperature sensors of the storage hwmon devices, `nvme` and
+  /// `drivetemp`, keeping the hottest sensor of each device.
+  fn scan_disk_temperatures(&mut self) -> anyhow::Result<()> {
+    log::debug!("scanning disk temperatures...");
+
+    let mut temperatures = HashMap::new();
+
+    for (name, device_path) in self.hwmon_devices()? {
+      if !DISK_HWMON_NAMES.contains(&&*name) {
+        continue;
+      }
+
+      let Some(temperature) = Self::read_temperature_inputs(&device_path)
+        .into_iter()
+        .filter(|temperature| !temperature.is_nan())
+        .reduce(f64::max)
+      else {
+        continue;
+      };
+
+      // Keyed by the device the sensor belongs to, such as `nvme0`.
+      let device = std::fs::canonicalize(device_path.join("device"))
+        .ok()
+        .and_then(|device| {
+          device
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| {
+          device_path.file_name().map_or_else(String::new, |name| {
+            name.to_string_lossy().into_owned()
+          })
+        });
+
+      log::trace!("disk temperature of '{device}': {temperature}°C");
+
+      temperatures.insert(device, temperature);
+    }
+
+    self.disk_temperatures = temperatures;
+
+    Ok(())
+  }
+
+  /// Reads every `tempN_input` sensor of a hwmon device, in °C.
+  fn read_temperature_inputs(device_path: &Path) -> Vec<f64> {
+    (1..=16)
+      .filter_map(|i| {
+        read_sensor::<i64>(&device_path.join(format!("temp{i}_input")))
+      })
+      .map(|temperature_mc| temperature_mc as f64 / 1000.0)
+      .collect()
+  }
+
+  /// Reads the sensors selected by `temperature-sensors`, numbered in the
+  /// order they are configured in.
+  fn scan_configured_temperatures(
+    &self,
+    devices: &[(String, PathBuf)],
+  ) -> anyhow::Result<HashMap<u32, f64>> {
+    let mut temperatures = HashMap::new();
+
+    for sensor in &self.temperature_sensors {
+      let count = temperatures.len();
+
+      for (name, device_path) in devices {
+        for i in 1..=96 {
+          let input_path = device_path.join(format!("temp{i}_input"));
+
+          if !fs::exists(&input_path) {
+            continue;
+          }
+
+          let label_path = device_path.join(format!("temp{i}_label"));
+          let label = fs::read_first_line(&label_path).with_context(|| {
+            format!(
+              "failed to read hardware device label from '{path}'",
+              path = label_path.display(),
+            )
+          })?;
+
+          if !sensor.matches(name, label.as_deref()) {
+            continue;
+          }
+
+          let Some(temperature_mc) = fs::read_n::<i64>(&input_path)
+            .with_context(|| {
+              format!(
+                "failed to read CPU temperature from '{path}'",
+                path = input_path.display(),
+              )
+            })?
+          else {
+            continue;
+          };
+
+          temperatures
+            .insert(temperatures.len() as u32, temperature_mc as f64 / 1000.0);
+        }
+      }
+
+      if temperatures.len() == count {
+        log::debug!("temperature sensor '{sensor}' didn't match any sensor");
+      }
+    }
+
+    Ok(temperatures)
+  }
+
   fn get_temperatures(
     device_path: &Path,
     temperatures: &mut HashMap<u32, f64>,
@@ -502,14 +864,15 @@ impl System {
         input_path = input_path.display(),
       );
 
-      let Some(label) = fs::read(&label_path).with_context(|| {
-        format!(
-          "failed to read hardware hardware device label from '{path}'",
-          path = label_path.display(),
-        )
-      })?
-      else {
-        continue;
+      let Some(label) =
+        fs::read_first_line(&label_path).with_context(|| {
+          format!(
+            "failed to read hardware hardware device label from '{path}'",
+            path = label_path.display(),
+          )
+        })?
+      else {
+        continue;
       };
       log::debug!("label content: {label}");
 
@@ -608,29 +971,64 @@ impl System {
     Ok(())
   }
 
-  // Scan and identify the current lid state.
+  fn scan_memory(&mut self) -> anyhow::Result<()> {
+    log::trace!("scanning memory usage");
+
+    let Some(content) = fs::read("/proc/meminfo")
+      .context("failed to read memory information from '/proc/meminfo'")?
+    else {
+      self.memory_usage = None;
+      return Ok(());
+    };
+
+    self.memory_usage = memory_usage(&content);
+
+    if self.memory_usage.is_none() {
+      log::debug!("failed to compute memory usage from '/proc/meminfo'");
+    }
+
+    Ok(())
+  }
+
+  fn scan_cpu_pressure(&mut self) {
+    log::trace!("scanning CPU pressure");
+
+    // The file is missing without `CONFIG_PSI`, and can't be read when PSI is
+    // disabled with `psi=0`. Either way, the pressure is undefined.
+    self.cpu_pressure = match fs::read("/proc/pressure/cpu") {
+      Ok(content) => content.as_deref().and_then(pressure),
+      Err(error) => {
+        log::debug!("failed to read CPU pressure: {error:#}");
+        None
+      },
+    };
+  }
+
+  /// Finds the lid switch, if there is one.
   // XXX: Most "uniform" APIs for identifying this data rely on some abstraction
   // library that *might or might not be installed*. The verbose fallback is,
   // unfortunately, necessary as there is no guarantee that we can use those
   // APIs.
-  fn scan_lid_state(&mut self) -> anyhow::Result<()> {
-    log::trace!("scanning lid state");
+  fn find_lid_switch() -> anyhow::Result<Option<LidSwitch>> {
+    log::trace!("looking for a lid switch");
 
     // Try ACPI button interface first
-    let acpi_lid_paths = [
-      "/proc/acpi/button/lid/LID/state", // most likely to exist
-      "/proc/acpi/button/lid/LID0/state",
-      "/proc/acpi/button/lid/LID1/state",
-    ];
-
-    for path in acpi_lid_paths {
-      if let Some(content) =
-        fs::read(path).context("failed to read lid state from ACPI")?
-      {
-        // Content is typically "state:      open" or "state:      closed"
-        self.lid_closed = content.contains("closed");
-        log::debug!("lid state from {path}: {content}");
-        return Ok(());
+    const ACPI_PATH: &str = "/proc/acpi/button/lid";
+
+    if let Some(entries) =
+      fs::read_dir(ACPI_PATH).context("failed to read ACPI lid entries")?
+    {
+      let mut paths = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path().join("state"))
+        .filter(|path| fs::exists(path))
+        .collect::<Vec<_>>();
+
+      // LID, LID0 and LID1 are the most common names, prefer them in order.
+      paths.sort_unstable();
+
+      if let Some(path) = paths.into_iter().next() {
+        return Ok(Some(LidSwitch::Acpi(path)));
       }
     }
 
@@ -649,12 +1047,13 @@ impl System {
         let entry_path = entry.path();
         let device_name_path = entry_path.join("device/name");
 
-        let Some(name) = fs::read(&device_name_path).with_context(|| {
-          format!(
-            "failed to read input device name from '{path}'",
-            path = device_name_path.display(),
-          )
-        })?
+        let Some(name) =
+          fs::read_first_line(&device_name_path).with_context(|| {
+            format!(
+              "failed to read input device name from '{path}'",
+              path = device_name_path.display(),
+            )
+          })?
         else {
           continue;
         };
@@ -664,58 +1063,62 @@ impl System {
           continue;
         };
 
-        // Read the lid switch state from the SW_LID capability
         let state_path = entry_path.join("device/capabilities/sw");
 
-        let Some(sw_caps) = fs::read(&state_path).with_context(|| {
-          format!(
-            "failed to read switch capabilities from '{path}'",
-            path = state_path.display(),
-          )
-        })?
-        else {
+        if !fs::exists(&state_path) {
           log::debug!(
             "found lid switch at {path} but no switch capabilities file",
             path = entry_path.display()
           );
           continue;
-        };
+        }
 
-        // SW_LID is bit 0 in the capabilities bitmask
-        // The state file shows the current state of switches as a hex bitmask
-        // If bit 0 is set, the lid is closed
-        if let Ok(caps) = u64::from_str_radix(sw_caps.trim(), 16) {
-          self.lid_closed = (caps & 0x1) != 0;
+        return Ok(Some(LidSwitch::Input(state_path)));
+      }
+    }
+
+    Ok(None)
+  }
+
+  /// Reads the lid state. The lid switch is looked for on the first scan
+  /// only, later scans just read its state. Without one, such as on a desktop,
+  /// the state is undefined.
+  fn scan_lid_state(&mut self) -> anyhow::Result<()> {
+    log::trace!("scanning lid state");
+
+    if !self.lid_switch_scanned {
+      self.lid_switch = Self::find_lid_switch()?;
+      self.lid_switch_scanned = true;
+
+      match &self.lid_switch {
+        Some(lid_switch) => log::debug!("found lid switch: {lid_switch:?}"),
+        None => {
           log::debug!(
-            "lid state from input device {path}: {state}",
-            path = entry_path.display(),
-            state = if self.lid_closed { "closed" } else { "open" }
+            "no lid switch found, assuming desktop or lid state unavailable"
           );
-          return Ok(());
-        }
+        },
       }
     }
 
-    // If we reach here, this is likely a desktop or the lid state is not
-    // available Default to lid open (false)
-    log::debug!(
-      "no lid switch found, assuming desktop or lid state unavailable"
-    );
-    self.lid_closed = false;
+    self.lid_closed = match &self.lid_switch {
+      Some(lid_switch) => lid_switch.read_closed()?,
+      None => None,
+    };
 
     Ok(())
   }
 
-  fn is_desktop(&mut self) -> anyhow::Result<bool> {
+  fn is_desktop(&self) -> anyhow::Result<bool> {
     log::debug!("checking chassis type to determine if system is a desktop");
-    if let Some(chassis_type) = fs::read("/sys/class/dmi/id/chassis_type")
-      .context("failed to read chassis type")?
+    if let Some(chassis_type) =
+      fs::read_first_line("/sys/class/dmi/id/chassis_type")
+        .context("failed to read chassis type")?
     {
       // 3=Desktop, 4=Low Profile Desktop, 5=Pizza Box, 6=Mini Tower,
       // 7=Tower, 8=Portable, 9=Laptop, 10=Notebook, 11=Hand Held, 13=All In
       // One, 14=Sub Notebook, 15=Space-saving, 16=Lunch Box, 17=Main
       // Server Chassis, 31=Convertible Laptop
-      match chassis_type.trim() {
+      match chassis_type.as_str() {
         // Desktop form factors.
         "3" | "4" | "5" | "6" | "7" | "15" | "16" | "17" => {
           log::debug!("chassis is a desktop form factor, short circuting true");
@@ -734,70 +1137,61 @@ impl System {
       }
     }
 
-    // Check battery-specific ACPI paths that laptops typically have
-    let laptop_acpi_paths = [
-      "/sys/class/power_supply/BAT0",
-      "/sys/class/power_supply/BAT1",
-      "/proc/acpi/battery",
-    ];
-
-    log::debug!("checking existence of ACPI paths");
-    for path in laptop_acpi_paths {
-      if fs::exists(path) {
-        log::debug!("path '{path}' exists, short circuting false");
-        return Ok(false); // Likely a laptop.
-      }
+    // Anything with a battery of its own, as opposed to those of peripherals
+    // such as mice, is portable. This doesn't rely on the battery names,
+    // which vary between vendors.
+    if !config::find_batteries(&self.power_supplies).is_empty() {
+      log::debug!("found a system battery, short circuting false");
+      return Ok(false);
     }
 
-    log::debug!("checking if power saving paths exists");
-    // Check CPU power policies, desktops often don't have these
-    let power_saving_exists =
-      fs::exists("/sys/module/intel_pstate/parameters/no_hwp")
-        || fs::exists("/sys/devices/system/cpu/cpufreq/conservative");
-
-    if !power_saving_exists {
-      log::debug!("power saving paths do not exist, short circuting true");
-      return Ok(true); // likely a desktop.
+    // Kernels with the deprecated ACPI battery interface.
+    if fs::exists("/proc/acpi/battery") {
+      log::debug!("path '/proc/acpi/battery' exists, short circuting false");
+      return Ok(false);
     }
 
-    // Default to assuming desktop if we can't determine.
-    log::debug!(
-      "cannot determine whether if we are a desktop, defaulting to true"
-    );
+    // Power saving paths such as `intel_pstate` parameters exist on desktops
+    // just as well, so they can't tell the two apart. Without a battery, the
+    // system can only be running on wall power.
+    log::debug!("no system battery found, assuming a desktop");
     Ok(true)
   }
 
-  fn cpu_volatility(&self) -> Option<CpuVolatility> {
+  /// The entries of the CPU log younger than `window`, oldest first.
+  fn recent_cpu_log(&self, window: Duration) -> impl Iterator<Item = &CpuLog> {
     let recent_log_count = self
       .cpu_log
       .iter()
       .rev()
-      .take_while(|log| log.at.elapsed() < Duration::from_secs(5 * 60))
+      .take_while(|log| log.at.elapsed() < window)
       .count();
 
-    if recent_log_count < 2 {
-      return None;
-    }
+    self
+      .cpu_log
+      .iter()
+      .skip(self.cpu_log.len() - recent_log_count)
+  }
+
+  fn cpu_volatility(&self) -> Option<CpuVolatility> {
+    let recent_log: Vec<&CpuLog> =
+      self.recent_cpu_log(self.log_windows.volatility).collect();
 
-    if self.cpu_log.len() < 2 {
+    if recent_log.len() < 2 {
       return None;
     }
 
-    let change_count = self.cpu_log.len() - 1;
+    let change_count = recent_log.len() - 1;
 
     let mut usage_change_sum = 0.0;
     let mut temperature_change_sum = 0.0;
     let mut temperature_change_count = 0;
 
-    for index in 0..change_count {
-      let usage_change =
-        self.cpu_log[index + 1].usage - self.cpu_log[index].usage;
+    for pair in recent_log.windows(2) {
+      let usage_change = pair[1].usage - pair[0].usage;
       usage_change_sum += usage_change.abs();
 
-      if let (Some(t1), Some(t2)) = (
-        self.cpu_log[index].temperature,
-        self.cpu_log[index + 1].temperature,
-      ) {
+      if let (Some(t1), Some(t2)) = (pair[0].temperature, pair[1].temperature) {
         temperature_change_sum += (t2 - t1).abs();
         temperature_change_count += 1;
       }
@@ -810,26 +1204,30 @@ impl System {
     })
   }
 
+  /// The exponential moving average of the CPU usage log, so that a single
+  /// spike moves it only by `cpu_usage_smoothing` of the spike.
+  fn cpu_usage_average(&self) -> Option<f64> {
+    let mut logs = self.cpu_log.iter();
+    let first = logs.next()?.usage;
+
+    Some(logs.fold(first, |average, log| {
+      self.cpu_usage_smoothing * log.usage
+        + (1.0 - self.cpu_usage_smoothing) * average
+    }))
+  }
+
   fn is_cpu_idle(&self) -> bool {
-    let recent_log_count = self
-      .cpu_log
-      .iter()
-      .rev()
-      .take_while(|log| log.at.elapsed() < Duration::from_secs(5 * 60))
-      .count();
+    let (usage_sum, recent_log_count) = self
+      .recent_cpu_log(self.log_windows.idle)
+      .fold((0.0, 0u32), |(sum, count), log| {
+        (sum + log.usage, count + 1)
+      });
 
     if recent_log_count < 2 {
       return false;
     }
 
-    let recent_average = self
-      .cpu_log
-      .iter()
-      .rev()
-      .take(recent_log_count)
-      .map(|log| log.usage)
-      .sum::<f64>()
-      / recent_log_count as f64;
+    let recent_average = usage_sum / recent_log_count as f64;
 
     recent_average < 0.1
       && self
@@ -838,9 +1236,29 @@ impl System {
   }
 
   fn is_discharging(&self) -> bool {
-    self.power_supplies.iter().any(|power_supply| {
-      power_supply.charge_state.as_deref() == Some("Discharging")
-    })
+    config::find_batteries(&self.power_supplies)
+      .into_iter()
+      .any(|battery| battery.charge_state.as_deref() == Some("Discharging"))
+  }
+
+  /// The total power drawn from the discharging batteries in watts, or
+  /// `None` if none of them reports it.
+  fn power_draw_watts(&self) -> Option<f64> {
+    config::find_batteries(&self.power_supplies)
+      .into_iter()
+      .filter(|battery| battery.charge_state.as_deref() == Some("Discharging"))
+      .filter_map(|battery| battery.drain_rate_watts)
+      .map(f64::abs)
+      .reduce(|total, watts| total + watts)
+  }
+
+  fn ac_power_limit_watts(&self) -> Option<f64> {
+    self
+      .power_supplies
+      .iter()
+      .filter(|power_supply| power_supply.is_ac())
+      .filter_map(|power_supply| power_supply.input_power_limit_watts)
+      .reduce(f64::max)
   }
 
   /// Calculates the discharge rate, returns a number between 0 and 1.
@@ -851,10 +1269,27 @@ impl System {
   fn power_supply_discharge_rate(&self) -> Option<f64> {
     log::trace!("calculating power supply discharge rate");
 
+    self.power_supply_rate(|older, newer| older > newer)
+  }
+
+  /// Like [`Self::power_supply_discharge_rate`], but for the charge gained
+  /// while charging.
+  fn power_supply_charge_rate(&self) -> Option<f64> {
+    log::trace!("calculating power supply charge rate");
+
+    self.power_supply_rate(|older, newer| older < newer)
+  }
+
+  /// The change in charge per hour over the latest samples that keep moving
+  /// in the direction `moving` accepts for each older and newer charge.
+  fn power_supply_rate(
+    &self,
+    moving: impl Fn(f64, f64) -> bool,
+  ) -> Option<f64> {
     let mut last_charge = None;
 
-    // A list of increasing charge percentages.
-    let discharging: Vec<&PowerSupplyLog> = self
+    // A list of charge percentages, from the newest to the oldest.
+    let moved: Vec<&PowerSupplyLog> = self
       .power_supply_log
       .iter()
       .rev()
@@ -866,24 +1301,36 @@ impl System {
 
         last_charge = Some(log.charge);
 
-        log.charge > last_charge_value
+        moving(log.charge, last_charge_value)
       })
       .collect();
 
-    if discharging.len() < 2 {
+    if moved.len() < 2 {
       return None;
     }
 
-    // Start of discharging. Has the most charge.
-    let start = discharging.last()?;
-    // End of discharging, very close to now. Has the least charge.
-    let end = discharging.first()?;
+    let start = moved.last()?;
+    // Very close to now.
+    let end = moved.first()?;
+
+    let duration_seconds = (end.at - start.at).as_secs_f64();
+    let duration_hours = duration_seconds / 60.0 / 60.0;
 
-    let discharging_duration_seconds = (start.at - end.at).as_secs_f64();
-    let discharging_duration_hours = discharging_duration_seconds / 60.0 / 60.0;
-    let discharged = start.charge - end.charge;
+    Some((start.charge - end.charge).abs() / duration_hours)
+  }
+
+  /// The minutes until the batteries are empty when `discharging`, or full
+  /// otherwise, at the current rate. `None` while the rate is unknown or zero.
+  fn battery_minutes_remaining(&self, discharging: bool) -> Option<f64> {
+    let charge = self.power_supply_log.back()?.charge;
+
+    let (rate, left) = if discharging {
+      (self.power_supply_discharge_rate()?, charge)
+    } else {
+      (self.power_supply_charge_rate()?, 1.0 - charge)
+    };
 
-    Some(discharged / discharging_duration_hours)
+    (rate > 0.0).then(|| left / rate * 60.0)
   }
 }
 
@@ -912,10 +1359,11 @@ fn idle_multiplier(idle_for: Duration) -> f64 {
 
 fn compute_poll_delay(
   system: &System,
+  profile_config: &config::ProfileConfig,
   last_polling_delay: Option<Duration>,
   last_user_activity: Instant,
 ) -> Duration {
-  let mut delay = Duration::from_secs(5);
+  let mut delay = profile_config.poll_interval_base();
 
   if system.is_discharging() {
     match system.power_supply_discharge_rate() {
@@ -964,7 +1412,121 @@ fn compute_poll_delay(
     None => delay,
   };
 
-  Duration::from_secs_f64(delay.as_secs_f64().clamp(1.0, 30.0))
+  delay.clamp(
+    profile_config.poll_interval_minimum(),
+    profile_config.poll_interval_maximum(),
+  )
+}
+
+/// Where the state of the lid switch is read from.
+#[derive(Debug, Clone)]
+enum LidSwitch {
+  /// `/proc/acpi/button/lid/*/state`, reading `open` or `closed`.
+  Acpi(PathBuf),
+  /// The switch capabilities of the `Lid Switch` input device.
+  Input(PathBuf),
+}
+
+impl LidSwitch {
+  fn read_closed(&self) -> anyhow::Result<Option<bool>> {
+    match self {
+      Self::Acpi(path) => {
+        let Some(content) = fs::read(path).with_context(|| {
+          format!(
+            "failed to read lid state from '{path}'",
+            path = path.display(),
+          )
+        })?
+        else {
+          return Ok(None);
+        };
+
+        // Content is typically "state:      open" or "state:      closed"
+        log::debug!("lid state from {path}: {content}", path = path.display());
+        Ok(Some(content.contains("closed")))
+      },
+
+      Self::Input(path) => {
+        let Some(sw_caps) = fs::read(path).with_context(|| {
+          format!(
+            "failed to read switch capabilities from '{path}'",
+            path = path.display(),
+          )
+        })?
+        else {
+          return Ok(None);
+        };
+
+        // SW_LID is bit 0 in the capabilities bitmask
+        // The state file shows the current state of switches as a hex bitmask
+        // If bit 0 is set, the lid is closed
+        Ok(
+          u64::from_str_radix(sw_caps.trim(), 16)
+            .ok()
+            .map(|caps| (caps & 0x1) != 0),
+        )
+      },
+    }
+  }
+}
+
+/// Computes the fraction of memory in use from the contents of
+/// `/proc/meminfo`. Kernels older than 3.14 don't report `MemAvailable`, it is
+/// estimated from the free memory and the caches there.
+fn memory_usage(meminfo: &str) -> Option<f64> {
+  let field = |name: &str| {
+    meminfo.lines().find_map(|line| {
+      let value = line.strip_prefix(name)?.strip_prefix(':')?;
+      value
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()
+    })
+  };
+
+  let total = field("MemTotal").filter(|&total| total > 0)?;
+
+  let available = match field("MemAvailable") {
+    Some(available) => available,
+    None => field("MemFree")? + field("Buffers")? + field("Cached")?,
+  };
+
+  Some(total.saturating_sub(available) as f64 / total as f64)
+}
+
+/// The share of time some tasks were stalled waiting for a resource, in
+/// percent, averaged over the last 10, 60 and 300 seconds.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Pressure {
+  pub avg10:  f64,
+  pub avg60:  f64,
+  pub avg300: f64,
+}
+
+/// Parses the `some` line of a `/proc/pressure` file, such as
+/// `some avg10=1.53 avg60=0.87 avg300=0.25 total=1234567`.
+fn pressure(content: &str) -> Option<Pressure> {
+  let line = content
+    .lines()
+    .find_map(|line| line.strip_prefix("some "))?;
+
+  let field = |name: &str| {
+    line.split_whitespace().find_map(|field| {
+      field
+        .strip_prefix(name)?
+        .strip_prefix('=')?
+        .parse::<f64>()
+        .ok()
+    })
+  };
+
+  Some(Pressure {
+    avg10:  field("avg10")?,
+    avg60:  field("avg60")?,
+    avg300: field("avg300")?,
+  })
 }
 
 fn detect_performance_degradation(_system: &System) -> Option<String> {
@@ -972,11 +1534,13 @@ fn detect_performance_degradation(_system: &System) -> Option<String> {
 }
 
 fn read_chassis_type() -> anyhow::Result<Option<String>> {
-  let Some(chassis_type) = fs::read("/sys/class/dmi/id/chassis_type")? else {
+  let Some(chassis_type) =
+    fs::read_first_line("/sys/class/dmi/id/chassis_type")?
+  else {
     return Ok(None);
   };
 
-  Ok(match chassis_type.trim() {
+  Ok(match chassis_type.as_str() {
     "3" | "4" | "5" | "6" | "7" | "15" | "16" | "17" => {
       Some("desktop".to_owned())
     },
@@ -1035,36 +1599,74 @@ pub struct DaemonState {
   profile:              profile::ProfileState,
   last_applied_rules:   Vec<String>,
   performance_degraded: Option<String>,
+  poll_interval:        Option<Duration>,
+
+  /// Wakes the polling loop up before its delay runs out.
+  wake: Arc<Notify>,
 }
 
 impl DaemonState {
-  fn new(rule_count: usize) -> Self {
+  pub(crate) fn new(rule_count: usize) -> Self {
     Self {
       system: System::default(),
       rule_count,
       profile: profile::ProfileState::new(),
       last_applied_rules: Vec::new(),
       performance_degraded: None,
+      poll_interval: None,
+      wake: Arc::new(Notify::new()),
     }
   }
 
+  /// Makes the daemon poll again right away instead of waiting for the
+  /// current polling delay to pass.
+  pub fn request_poll(&self) {
+    self.wake.notify_one();
+  }
+
   fn update_system(
     &mut self,
     system: &System,
     last_applied_rules: Vec<String>,
     performance_degraded: Option<String>,
+    poll_interval: Duration,
   ) {
     self.system = system.clone();
     self.last_applied_rules = last_applied_rules;
     self.performance_degraded = performance_degraded;
+    self.poll_interval = Some(poll_interval);
+  }
+
+  /// The state after a poll of the system `fs` resolves paths in, which
+  /// applied `last_applied_rules`.
+  #[cfg(all(test, feature = "metrics"))]
+  pub(crate) fn polled(
+    last_applied_rules: Vec<String>,
+    poll_interval: Duration,
+  ) -> anyhow::Result<Self> {
+    let mut system = System::new(&config::DaemonConfig::default());
+    system.scan()?;
+
+    let mut state = Self::new(last_applied_rules.len());
+    state.update_system(&system, last_applied_rules, None, poll_interval);
+    Ok(state)
   }
 
   pub fn active_profile(&self) -> profile::PowerProfile {
     self.profile.get_effective_profile()
   }
 
-  pub fn set_active_profile(&mut self, profile: profile::PowerProfile) {
+  /// Sets the preferred power profile and returns whether that changed the
+  /// active profile, in which case the daemon polls again right away.
+  pub fn set_active_profile(&mut self, profile: profile::PowerProfile) -> bool {
+    let previous = self.active_profile();
     self.profile.set_preference(profile);
+
+    let changed = self.active_profile() != previous;
+    if changed {
+      self.request_poll();
+    }
+    changed
   }
 
   pub fn profile_holds(&self) -> Vec<profile::ProfileHold> {
@@ -1077,11 +1679,15 @@ impl DaemonState {
     reason: String,
     application_id: String,
   ) -> u32 {
-    self.profile.add_hold(profile, reason, application_id)
+    let cookie = self.profile.add_hold(profile, reason, application_id);
+    self.request_poll();
+    cookie
   }
 
   pub fn release_profile_hold(&mut self, cookie: u32) -> anyhow::Result<()> {
-    self.profile.release_hold(cookie)
+    self.profile.release_hold(cookie)?;
+    self.request_poll();
+    Ok(())
   }
 
   pub fn rule_count(&self) -> usize {
@@ -1096,6 +1702,25 @@ impl DaemonState {
     self.system.cpu_log.back().cloned()
   }
 
+  /// The temperature of every core that has a sensor, sorted by core.
+  pub fn cpu_temperatures(&self) -> Vec<(u32, f64)> {
+    let mut temperatures: Vec<(u32, f64)> = self
+      .system
+      .cpu_temperatures
+      .iter()
+      .map(|(&core, &temperature)| (core, temperature))
+      .collect();
+    temperatures.sort_unstable_by_key(|&(core, _)| core);
+    temperatures
+  }
+
+  /// The system batteries, without those of peripherals, sorted by name.
+  pub fn batteries(&self) -> Vec<&power_supply::PowerSupply> {
+    let mut batteries = config::find_batteries(&self.system.power_supplies);
+    batteries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    batteries
+  }
+
   pub fn is_discharging(&self) -> bool {
     self.system.is_discharging()
   }
@@ -1107,358 +1732,1721 @@ impl DaemonState {
   pub fn last_applied_rules(&self) -> Vec<String> {
     self.last_applied_rules.clone()
   }
-}
 
-pub async fn run_daemon(config: config::DaemonConfig) -> anyhow::Result<()> {
-  if !config.rules.is_sorted_by_key(|rule| rule.priority) {
-    bail!("daemon config rules must be sorted by priority");
+  /// The delay until the next poll, `None` before the first one.
+  pub fn poll_interval(&self) -> Option<Duration> {
+    self.poll_interval
   }
 
-  log::info!("starting daemon...");
-
-  let state = Arc::new(RwLock::new(DaemonState::new(config.rules.len())));
-
-  #[cfg(feature = "metrics")]
-  if let Some(metrics_config) = &config.metrics {
-    crate::metrics::start(metrics_config, Arc::clone(&state))?;
+  /// The values the rules were last evaluated against, `None` before the
+  /// first poll.
+  pub fn monitor_sample(&self) -> Option<MonitorSample> {
+    self.system.monitor_sample().ok()
   }
+}
 
-  tokio::spawn({
-    let state = Arc::clone(&state);
-    async move {
-      if let Err(error) = crate::dbus::server::start(state).await {
-        log::error!("D-Bus server exited with error: {error}");
-      }
-    }
-  });
+/// The combined charge of the batteries, between 0 and 1. Weighted by the
+/// energy of each battery if they all report it, as a nearly full small
+/// battery and a nearly empty big one are far from half full together.
+fn battery_charge(batteries: &[&power_supply::PowerSupply]) -> Option<f64> {
+  let energy = batteries
+    .iter()
+    .try_fold((0.0, 0.0), |(now, full), battery| {
+      Some((now + battery.energy_now_wh?, full + battery.energy_full_wh?))
+    });
+
+  if let Some((now, full)) = energy
+    && full > 0.0
+  {
+    return Some((now / full).min(1.0));
+  }
 
-  let mut last_polling_delay = None::<Duration>;
-  let mut last_user_activity = Instant::now();
-  let mut system = System::default();
-  let mut dma_latency = cpu::DmaLatency::default();
-  let shutdown_signal = signal::ctrl_c();
-  tokio::pin!(shutdown_signal);
-  let mut sleep_for = Duration::ZERO;
+  let (sum, count) = batteries
+    .iter()
+    .filter_map(|battery| battery.charge_percent)
+    .fold((0.0, 0u32), |(sum, count), charge| {
+      (sum + charge.as_fraction(), count + 1)
+    });
 
-  loop {
-    tokio::select! {
-      result = &mut shutdown_signal => {
-        result.context("failed to listen for shutdown signal")?;
-        log::info!("received shutdown signal");
-        break;
-      },
-      () = tokio::time::sleep(sleep_for) => {},
-    }
+  (count > 0).then(|| sum / count as f64)
+}
 
-    log::debug!("starting main polling loop iteration");
-    let start = Instant::now();
+/// The average of the given temperatures, skipping NaN readings. `None` if
+/// there are no valid readings, rather than the NaN of dividing by zero.
+fn average_temperature(temperatures: &HashMap<u32, f64>) -> Option<f64> {
+  let (sum, count) = temperatures
+    .values()
+    .filter(|temperature| !temperature.is_nan())
+    .fold((0.0, 0u32), |(sum, count), temperature| {
+      (sum + temperature, count + 1)
+    });
+
+  (count > 0).then(|| sum / count as f64)
+}
 
-    system.scan()?;
+/// How many scans use the list of hwmon devices before it is listed again,
+/// so that devices of drivers that load later, like `drivetemp`, show up.
+const HWMON_RELIST_SCANS: u32 = 30;
+
+/// The hwmon device names of GPU drivers, whose temperatures are read into
+/// `$gpu-temperature` instead of the CPU temperatures.
+const GPU_HWMON_NAMES: &[&str] = &["amdgpu", "radeon", "i915", "xe", "nouveau"];
+
+/// The hwmon device names of storage drivers, whose temperatures are read into
+/// `$disk-temperature-max`.
+const DISK_HWMON_NAMES: &[&str] = &["nvme", "drivetemp"];
+
+/// The coolest and hottest of the given temperatures. NaN readings from broken
+/// sensors are skipped so they can't poison the result.
+fn temperature_range(temperatures: &HashMap<u32, f64>) -> Option<(f64, f64)> {
+  temperatures
+    .values()
+    .copied()
+    .filter(|temperature| !temperature.is_nan())
+    .fold(None, |range, temperature| {
+      Some(match range {
+        Some((minimum, maximum)) => {
+          (
+            f64::min(minimum, temperature),
+            f64::max(maximum, temperature),
+          )
+        },
+        None => (temperature, temperature),
+      })
+    })
+}
 
-    if !system.is_cpu_idle() {
-      last_user_activity = Instant::now();
-    }
+/// Reads the current CPU temperatures, keyed by CPU number.
+pub fn cpu_temperatures() -> anyhow::Result<HashMap<u32, f64>> {
+  let mut system = System::default();
+  system.scan_temperatures()?;
 
-    let power_profile_preference = state.read().await.active_profile();
-    let performance_degraded = detect_performance_degradation(&system);
+  Ok(system.cpu_temperatures)
+}
 
-    let delay = {
-      let eval_state = config::EvalState {
-        frequency_available: system
-          .cpus
-          .iter()
-          .any(|cpu| cpu.frequency_mhz.is_some()),
-        turbo_available: cpu::Cpu::turbo()
-          .context(
-            "failed to read CPU turbo boost status for `is-turbo-available`",
-          )?
-          .is_some(),
-
-        cpu_usage: system.cpu_log.back().context("CPU log is empty")?.usage,
-        cpu_usage_volatility: system.cpu_volatility().map(|vol| vol.usage),
-        cpu_temperature: system.cpu_log.back().and_then(|log| log.temperature),
-        cpu_temperature_volatility: system
-          .cpu_volatility()
-          .and_then(|vol| vol.temperature),
-        cpu_idle_seconds: last_user_activity.elapsed().as_secs_f64(),
-        cpu_frequency_maximum: cpu::Cpu::hardware_frequency_mhz_maximum()
-          .context("failed to read CPU hardware maximum frequency")?
-          .map(|u64| u64 as f64),
-        cpu_frequency_minimum: cpu::Cpu::hardware_frequency_mhz_minimum()
-          .context("failed to read CPU hardware minimum frequency")?
-          .map(|u64| u64 as f64),
-
-        lid_closed: system.lid_closed,
-        virtual_machine: system.virtual_machine,
-        chassis_type: system.chassis_type.as_deref(),
-
-        power_supply_charge: system
-          .power_supply_log
-          .back()
-          .map(|log| log.charge),
-        power_supply_discharge_rate: system.power_supply_discharge_rate(),
-
-        battery_cycles: system.battery_cycles,
-        battery_health: system.battery_health,
-
-        discharging: system.is_discharging(),
-        power_profile_preference,
-
-        context: config::EvalContext::WidestPossible,
-
-        cpus: &system.cpus,
-        uncores: &system.uncores,
-        disks: &system.disks,
-        usb_devices: &system.usb_devices,
-        gpus: &system.gpus,
-        power_supplies: &system.power_supplies,
-        cpu_log: &system.cpu_log,
-      };
+/// A battery as shown by `watt monitor`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct BatterySample {
+  pub name:             String,
+  pub charge_percent:   Option<f64>,
+  pub charge_state:     Option<String>,
+  pub drain_rate_watts: Option<f64>,
+}
 
-      let mut cpu_deltas: HashMap<Arc<cpu::Cpu>, cpu::Delta> = system
-        .cpus
-        .iter()
-        .map(|cpu| (Arc::clone(cpu), cpu::Delta::default()))
-        .collect();
-      let mut cpu_global_delta = cpu::GlobalDelta::default();
+/// The values rules are evaluated against, as printed by `watt monitor`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct MonitorSample {
+  pub cpu_usage:                   f64,
+  pub cpu_usage_average:           Option<f64>,
+  pub cpu_usage_volatility:        Option<f64>,
+  pub cpu_temperature:             Option<f64>,
+  pub cpu_temperature_volatility:  Option<f64>,
+  pub load_average:                [f64; 3],
+  pub memory_usage:                Option<f64>,
+  pub discharging:                 bool,
+  pub power_supply_charge:         Option<f64>,
+  pub power_supply_discharge_rate: Option<f64>,
+  pub power_draw_watts:            Option<f64>,
+  pub batteries:                   Vec<BatterySample>,
+}
 
-      let mut uncore_deltas: HashMap<Arc<uncore::Uncore>, uncore::Delta> =
-        system
-          .uncores
-          .iter()
-          .map(|uncore| (Arc::clone(uncore), uncore::Delta::default()))
-          .collect();
-      let mut vm_delta = vm::Delta::default();
-      let mut disk_deltas: HashMap<Arc<disk::Disk>, disk::Delta> = system
-        .disks
-        .iter()
-        .map(|disk| (Arc::clone(disk), disk::Delta::default()))
-        .collect();
-      let mut disk_global_delta = disk::GlobalDelta::default();
-      let mut usb_deltas: HashMap<Arc<usb::UsbDevice>, usb::Delta> = system
-        .usb_devices
-        .iter()
-        .map(|device| (Arc::clone(device), usb::Delta::default()))
-        .collect();
-      let mut audio_delta = audio::Delta::default();
-      let mut gpu_deltas: HashMap<Arc<gpu::Gpu>, gpu::Delta> = system
-        .gpus
-        .iter()
-        .map(|gpu| (Arc::clone(gpu), gpu::Delta::default()))
-        .collect();
+impl System {
+  fn monitor_sample(&self) -> anyhow::Result<MonitorSample> {
+    let system = self;
+    let volatility = system.cpu_volatility();
 
-      let mut power_deltas: HashMap<
-        Arc<power_supply::PowerSupply>,
-        power_supply::Delta,
-      > = system
-        .power_supplies
-        .iter()
-        .map(|power_supply| {
-          (Arc::clone(power_supply), power_supply::Delta::default())
+    let mut batteries: Vec<BatterySample> =
+      config::find_batteries(&system.power_supplies)
+        .into_iter()
+        .map(|battery| {
+          BatterySample {
+            name:             battery.name.clone(),
+            charge_percent:   battery.charge_percent.map(Percent::as_percent),
+            charge_state:     battery.charge_state.clone(),
+            drain_rate_watts: battery.drain_rate_watts,
+          }
         })
         .collect();
-      let mut power_platform_profile: Option<String> = None;
+    batteries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(MonitorSample {
+      cpu_usage: system.cpu_log.back().context("CPU log is empty")?.usage,
+      cpu_usage_average: system.cpu_usage_average(),
+      cpu_usage_volatility: volatility.as_ref().map(|vol| vol.usage),
+      cpu_temperature: system.cpu_log.back().and_then(|log| log.temperature),
+      cpu_temperature_volatility: volatility.and_then(|vol| vol.temperature),
+      load_average: [
+        system.load_average_1min,
+        system.load_average_5min,
+        system.load_average_15min,
+      ],
+      memory_usage: system.memory_usage,
+      discharging: system.is_discharging(),
+      power_supply_charge: system.power_supply_log.back().map(|log| log.charge),
+      power_supply_discharge_rate: system.power_supply_discharge_rate(),
+      power_draw_watts: system.power_draw_watts(),
+      batteries,
+    })
+  }
+}
 
-      // Higher priority rule first, so we can short-circuit.
-      let mut last_applied_rules = Vec::new();
+/// Scans the system repeatedly for `watt monitor`, keeping the logs that the
+/// averages, volatilities and discharge rate are computed from.
+pub struct Monitor {
+  system: System,
+}
 
-      for rule in config.rules.iter().rev() {
-        let Some(condition) = rule.condition.eval(&eval_state)? else {
-          continue;
-        };
+impl Monitor {
+  /// A monitor that sees the system the way the daemon running `config`
+  /// does, such as with its CPU usage smoothing and temperature sensors.
+  pub fn new(config: &config::DaemonConfig) -> Self {
+    Self {
+      system: System::new(config),
+    }
+  }
 
-        let condition = condition
-          .try_into_boolean()
-          .context("`if` was not a boolean")?;
+  pub fn scan(&mut self) -> anyhow::Result<()> {
+    self.system.scan()
+  }
 
-        if condition {
-          log::info!(
-            "rule '{name}' condition evaluated to true! evaluating members...",
-            name = rule.name,
-          );
+  pub fn sample(&self) -> anyhow::Result<MonitorSample> {
+    self.system.monitor_sample()
+  }
+}
 
-          last_applied_rules.push(rule.name.clone());
+/// How long a phase of the system scan took over the scans of `watt bench`,
+/// in milliseconds.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct BenchPhase {
+  pub phase:   &'static str,
+  /// How many scans the phase ran in. Some phases, like the platform
+  /// identity, only run in the first one.
+  pub samples: usize,
+  pub minimum: f64,
+  pub median:  f64,
+  pub p99:     f64,
+  pub maximum: f64,
+}
 
-          let cpu_some = {
-            let (cpu_deltas_lo, cpu_global_delta_lo) =
-              rule.cpu.eval(&eval_state)?;
+impl BenchPhase {
+  fn new(phase: &'static str, mut timings: Vec<Duration>) -> Self {
+    timings.sort();
 
-            for (cpu, delta) in cpu_deltas.iter_mut() {
-              if let Some(delta_lo) = cpu_deltas_lo.get(cpu) {
-                *delta = mem::take(delta).or(delta_lo);
-              }
-            }
+    // Nearest rank, so every value is one that was actually measured.
+    let percentile = |percentile: f64| {
+      let rank = (percentile * timings.len() as f64).ceil() as usize;
+      timings[rank.clamp(1, timings.len()) - 1].as_secs_f64() * 1000.0
+    };
 
-            cpu_global_delta =
-              mem::take(&mut cpu_global_delta).or(&cpu_global_delta_lo);
+    Self {
+      phase,
+      samples: timings.len(),
+      minimum: percentile(0.0),
+      median: percentile(0.5),
+      p99: percentile(0.99),
+      maximum: percentile(1.0),
+    }
+  }
+}
 
-            let deltas_some = cpu_deltas.values().all(|delta| delta.is_some());
-            deltas_some && cpu_global_delta.is_some()
-          };
+/// Scans the system `iterations` times, like the daemon does every poll, and
+/// summarizes how long each phase took. The last phase, `total`, is the whole
+/// scan.
+pub fn bench(iterations: usize) -> anyhow::Result<Vec<BenchPhase>> {
+  let mut system = System::default();
+  let mut phases = Vec::<(&'static str, Vec<Duration>)>::new();
+  let mut totals = Vec::with_capacity(iterations);
 
-          let power_some = {
-            let uncore_deltas_lo = rule.uncore.eval(&eval_state)?;
-            let vm_delta_lo = rule.vm.eval(&eval_state)?;
-            let (disk_deltas_lo, disk_global_delta_lo) =
-              rule.disk.eval(&eval_state)?;
-            let usb_deltas_lo = rule.usb.eval(&eval_state)?;
-            let audio_delta_lo = rule.audio.eval(&eval_state)?;
-            let gpu_deltas_lo = rule.gpu.eval(&eval_state)?;
-
-            for (uncore, delta) in uncore_deltas.iter_mut() {
-              if let Some(delta_lo) = uncore_deltas_lo.get(uncore) {
-                *delta = mem::take(delta).or(delta_lo);
-              }
-            }
-            vm_delta = mem::take(&mut vm_delta).or(&vm_delta_lo);
+  for _ in 0..iterations {
+    let start = Instant::now();
+    system.scan().context("failed to scan system")?;
+    totals.push(start.elapsed());
+
+    for &(phase, elapsed) in &system.scan_timings {
+      match phases.iter_mut().find(|(name, _)| *name == phase) {
+        Some((_, timings)) => timings.push(elapsed),
+        None => phases.push((phase, vec![elapsed])),
+      }
+    }
+  }
+
+  if iterations == 0 {
+    return Ok(Vec::new());
+  }
+
+  Ok(
+    phases
+      .into_iter()
+      .chain([("total", totals)])
+      .map(|(phase, timings)| BenchPhase::new(phase, timings))
+      .collect(),
+  )
+}
+
+/// The members of a single rule, evaluated. Kept by rules with an
+/// `apply-cooldown` to reuse until the cooldown is over.
+#[derive(Debug, Clone)]
+struct RuleDeltas {
+  cpu:        HashMap<Arc<cpu::Cpu>, cpu::Delta>,
+  cpu_global: cpu::GlobalDelta,
+
+  uncore: HashMap<Arc<uncore::Uncore>, uncore::Delta>,
+
+  vm: vm::Delta,
+
+  disk:        HashMap<Arc<disk::Disk>, disk::Delta>,
+  disk_global: disk::GlobalDelta,
+
+  usb: HashMap<Arc<usb::UsbDevice>, usb::Delta>,
+
+  audio: audio::Delta,
+
+  gpu: HashMap<Arc<gpu::Gpu>, gpu::Delta>,
+
+  power: HashMap<Arc<power_supply::PowerSupply>, power_supply::Delta>,
+  platform_profile: Option<String>,
+}
+
+impl RuleDeltas {
+  fn eval(
+    rule: &config::Rule,
+    state: &config::EvalState<'_, '_>,
+  ) -> anyhow::Result<Self> {
+    let (cpu, cpu_global) = rule.cpu.eval(state)?;
+    let uncore = rule.uncore.eval(state)?;
+    let vm = rule.vm.eval(state)?;
+    let (disk, disk_global) = rule.disk.eval(state)?;
+    let usb = rule.usb.eval(state)?;
+    let audio = rule.audio.eval(state)?;
+    let gpu = rule.gpu.eval(state)?;
+    let (power, platform_profile) = rule.power.eval(state)?;
+
+    Ok(Self {
+      cpu,
+      cpu_global,
+      uncore,
+      vm,
+      disk,
+      disk_global,
+      usb,
+      audio,
+      gpu,
+      power,
+      platform_profile,
+    })
+  }
+}
+
+/// The deltas collected from every rule that applied, merged by priority.
+#[derive(Default, Debug)]
+struct Deltas {
+  cpu:        HashMap<Arc<cpu::Cpu>, cpu::Delta>,
+  cpu_global: cpu::GlobalDelta,
+
+  uncore: HashMap<Arc<uncore::Uncore>, uncore::Delta>,
+
+  vm: vm::Delta,
+
+  disk:        HashMap<Arc<disk::Disk>, disk::Delta>,
+  disk_global: disk::GlobalDelta,
+
+  usb: HashMap<Arc<usb::UsbDevice>, usb::Delta>,
+
+  audio: audio::Delta,
+
+  gpu: HashMap<Arc<gpu::Gpu>, gpu::Delta>,
+
+  power: HashMap<Arc<power_supply::PowerSupply>, power_supply::Delta>,
+  platform_profile: Option<String>,
+}
+
+impl Deltas {
+  fn new(system: &System) -> Self {
+    fn empty<T: Clone + Eq + std::hash::Hash, D: Default>(
+      set: &HashSet<T>,
+    ) -> HashMap<T, D> {
+      set
+        .iter()
+        .map(|item| (item.clone(), D::default()))
+        .collect()
+    }
+
+    Self {
+      cpu: empty(&system.cpus),
+      uncore: empty(&system.uncores),
+      disk: empty(&system.disks),
+      usb: empty(&system.usb_devices),
+      gpu: empty(&system.gpus),
+      power: empty(&system.power_supplies),
+      ..Default::default()
+    }
+  }
+
+  /// Overrides the CPU settings of the rules with the most conservative ones:
+  /// the `powersave` governor, every CPU capped at its lowest frequency and
+  /// turbo off.
+  fn thermal_emergency(&mut self, turbo_available: bool) {
+    for (cpu, delta) in &mut self.cpu {
+      if cpu
+        .available_governors
+        .iter()
+        .any(|governor| governor == "powersave")
+      {
+        delta.governor = Some("powersave".to_owned());
+      }
+
+      // A pinned frequency replaces the governor.
+      delta.frequency_mhz = None;
+
+      if let Some(minimum) = cpu.frequency_mhz_minimum {
+        delta.frequency_mhz_minimum = Some(minimum);
+        delta.frequency_khz_minimum = None;
+        delta.frequency_mhz_maximum = Some(minimum);
+        delta.frequency_khz_maximum = None;
+      }
+    }
+
+    if turbo_available {
+      self.cpu_global.turbo = Some(false);
+    }
+  }
+
+  /// Evaluates the members of `rule` and fills in every setting that isn't
+  /// set yet. Returns whether every setting now has a value, in which case
+  /// lower priority rules can't change anything.
+  fn merge(
+    &mut self,
+    rule: &config::Rule,
+    state: &config::EvalState<'_, '_>,
+  ) -> anyhow::Result<bool> {
+    Ok(self.merge_evaluated(&RuleDeltas::eval(rule, state)?))
+  }
+
+  /// Fills in every setting that isn't set yet from the already evaluated
+  /// members of a rule. Returns the same as [`Self::merge`].
+  fn merge_evaluated(&mut self, rule: &RuleDeltas) -> bool {
+    let cpu_some = {
+      for (cpu, delta) in self.cpu.iter_mut() {
+        if let Some(delta_lo) = rule.cpu.get(cpu) {
+          *delta = mem::take(delta).or(delta_lo);
+        }
+      }
+
+      self.cpu_global = mem::take(&mut self.cpu_global).or(&rule.cpu_global);
+
+      let deltas_some = self.cpu.values().all(|delta| delta.is_some());
+      deltas_some && self.cpu_global.is_some()
+    };
+
+    let power_some = {
+      for (uncore, delta) in self.uncore.iter_mut() {
+        if let Some(delta_lo) = rule.uncore.get(uncore) {
+          *delta = mem::take(delta).or(delta_lo);
+        }
+      }
+      self.vm = mem::take(&mut self.vm).or(&rule.vm);
+
+      for (disk, delta) in self.disk.iter_mut() {
+        if let Some(delta_lo) = rule.disk.get(disk) {
+          *delta = mem::take(delta).or(delta_lo);
+        }
+      }
+      self.disk_global = mem::take(&mut self.disk_global).or(&rule.disk_global);
+
+      for (device, delta) in self.usb.iter_mut() {
+        if let Some(delta_lo) = rule.usb.get(device) {
+          *delta = mem::take(delta).or(delta_lo);
+        }
+      }
+
+      self.audio = mem::take(&mut self.audio).or(&rule.audio);
+
+      for (gpu, delta) in self.gpu.iter_mut() {
+        if let Some(delta_lo) = rule.gpu.get(gpu) {
+          *delta = mem::take(delta).or(delta_lo);
+        }
+      }
+
+      for (power, delta) in self.power.iter_mut() {
+        if let Some(delta_lo) = rule.power.get(power) {
+          *delta = mem::take(delta).or(delta_lo);
+        }
+      }
+
+      self.platform_profile = self
+        .platform_profile
+        .take()
+        .or_else(|| rule.platform_profile.clone());
+
+      let deltas_some = self.power.values().all(|delta| delta.is_some());
+      let uncore_some = self.uncore.values().all(|delta| delta.is_some());
+      let disk_some = self.disk.values().all(|delta| delta.is_some())
+        && self.disk_global.is_some();
+      let usb_some = self.usb.values().all(|delta| delta.is_some());
+      let gpu_some = self.gpu.values().all(|delta| delta.is_some());
+      deltas_some
+        && self.platform_profile.is_some()
+        && uncore_some
+        && self.vm.is_some()
+        && disk_some
+        && usb_some
+        && self.audio.is_some()
+        && gpu_some
+    };
+
+    cpu_some && power_some
+  }
+
+  /// Drops the settings that `rule` sets to the same value, so that a rule in
+  /// its `apply-cooldown` doesn't write its controls again.
+  fn skip_unchanged(&mut self, rule: &RuleDeltas) {
+    fn skip<K: Eq + std::hash::Hash, D: Default>(
+      deltas: &mut HashMap<K, D>,
+      rule: &HashMap<K, D>,
+      without: fn(D, &D) -> D,
+    ) {
+      for (key, delta) in deltas.iter_mut() {
+        if let Some(rule_delta) = rule.get(key) {
+          *delta = without(mem::take(delta), rule_delta);
+        }
+      }
+    }
+
+    skip(&mut self.cpu, &rule.cpu, cpu::Delta::without);
+    self.cpu_global = mem::take(&mut self.cpu_global).without(&rule.cpu_global);
+    skip(&mut self.uncore, &rule.uncore, uncore::Delta::without);
+    self.vm = mem::take(&mut self.vm).without(&rule.vm);
+    skip(&mut self.disk, &rule.disk, disk::Delta::without);
+    self.disk_global =
+      mem::take(&mut self.disk_global).without(&rule.disk_global);
+    skip(&mut self.usb, &rule.usb, usb::Delta::without);
+    self.audio = mem::take(&mut self.audio).without(&rule.audio);
+    skip(&mut self.gpu, &rule.gpu, gpu::Delta::without);
+    skip(&mut self.power, &rule.power, power_supply::Delta::without);
+
+    if self.platform_profile == rule.platform_profile {
+      self.platform_profile = None;
+    }
+  }
+
+  fn apply(
+    self,
+    context: &fs::ApplyContext,
+    dma_latency: &mut cpu::DmaLatency,
+    ignore_unsupported: bool,
+    force_discharge_floor: u8,
+  ) -> anyhow::Result<()> {
+    /// Whether `value` was written for the cpufreq policy already, recording
+    /// it otherwise.
+    fn written_for_policy<T: Clone + PartialEq>(
+      written: &mut HashMap<u32, T>,
+      policy_id: Option<u32>,
+      value: Option<&T>,
+    ) -> bool {
+      match (policy_id, value) {
+        (Some(policy_id), Some(value)) => {
+          written.insert(policy_id, value.clone()).as_ref() == Some(value)
+        },
+        _ => false,
+      }
+    }
+
+    let mut failures = 0;
+    let mut check = |result: anyhow::Result<()>| {
+      match result {
+        Err(error) if context.keep_going => {
+          log::error!("{error:#}");
+          failures += 1;
+          Ok(())
+        },
+        result => result,
+      }
+    };
+
+    // CPUs in the same cpufreq policy share a single governor and its
+    // tunables, so writing them once per policy is enough.
+    let mut policy_governors = HashMap::new();
+    let mut policy_rate_limits = HashMap::new();
+
+    for (cpu, delta) in &self.cpu {
+      let mut delta = delta.clone();
+
+      if written_for_policy(
+        &mut policy_governors,
+        cpu.policy_id,
+        delta.governor.as_ref(),
+      ) {
+        log::debug!(
+          "governor of {cpu} was already set through its cpufreq policy, \
+           skipping"
+        );
+        delta.governor = None;
+      }
+
+      if written_for_policy(
+        &mut policy_rate_limits,
+        cpu.policy_id,
+        delta.schedutil_rate_limit_us.as_ref(),
+      ) {
+        log::debug!(
+          "schedutil rate limit of {cpu} was already set through its cpufreq \
+           policy, skipping"
+        );
+        delta.schedutil_rate_limit_us = None;
+      }
+
+      check(
+        delta
+          .apply(context, &mut (**cpu).clone(), ignore_unsupported)
+          .with_context(|| format!("failed to apply delta to {cpu}")),
+      )?;
+    }
+
+    log::info!("applying CPU deltas to {len} CPUs", len = self.cpu.len());
+
+    check(
+      self
+        .cpu_global
+        .apply(
+          context,
+          self.cpu.keys().map(|arc| &**arc),
+          dma_latency,
+          ignore_unsupported,
+        )
+        .context("failed to apply global CPU delta"),
+    )?;
+
+    log::info!(
+      "applying uncore deltas to {len} devices",
+      len = self.uncore.len(),
+    );
+
+    for (uncore, delta) in self.uncore {
+      check(
+        delta
+          .apply(context, &uncore)
+          .with_context(|| format!("failed to apply delta to {uncore}")),
+      )?;
+    }
+
+    check(self.vm.apply(context).context("failed to apply VM delta"))?;
+
+    log::info!(
+      "applying disk deltas to {len} devices",
+      len = self.disk.len(),
+    );
+    for (disk, delta) in self.disk {
+      check(
+        delta
+          .apply(context, &disk)
+          .with_context(|| format!("failed to apply delta to {disk}")),
+      )?;
+    }
+    check(
+      self
+        .disk_global
+        .apply(context)
+        .context("failed to apply global disk delta"),
+    )?;
+
+    log::info!("applying USB deltas to {len} devices", len = self.usb.len());
+    for (device, delta) in self.usb {
+      check(
+        delta
+          .apply(context, &device)
+          .with_context(|| format!("failed to apply delta to {device}")),
+      )?;
+    }
+
+    check(
+      self
+        .audio
+        .apply(context)
+        .context("failed to apply audio delta"),
+    )?;
+
+    log::info!("applying GPU deltas to {len} devices", len = self.gpu.len());
+    for (gpu, delta) in self.gpu {
+      check(
+        delta
+          .apply(context, &gpu)
+          .with_context(|| format!("failed to apply delta to {gpu}")),
+      )?;
+    }
+
+    log::info!(
+      "applying power supply deltas to {len} devices",
+      len = self.power.len(),
+    );
+
+    for (power, delta) in self.power {
+      check(
+        delta
+          .apply(context, &mut (*power).clone(), force_discharge_floor)
+          .with_context(|| format!("failed to apply delta to {power}")),
+      )?;
+    }
+
+    if let Some(platform_profile) = self.platform_profile {
+      check(
+        power_supply::PowerSupply::set_platform_profile(
+          context,
+          &platform_profile,
+        )
+        .context("failed to set power supply platform profile"),
+      )?;
+    }
+
+    if failures > 0 {
+      bail!("failed to apply {failures} deltas");
+    }
+
+    Ok(())
+  }
+}
+
+impl System {
+  fn eval_state(
+    &self,
+    last_user_activity: Instant,
+    power_profile_preference: profile::PowerProfile,
+  ) -> anyhow::Result<config::EvalState<'_, 'static>> {
+    let cpu_temperature_range = temperature_range(&self.cpu_temperatures);
+
+    let turbo = cpu::Cpu::turbo().context(
+      "failed to read CPU turbo boost status for `?turbo-available` and \
+       `?turbo-enabled`",
+    )?;
+
+    self.sysfs_reads.clear();
+
+    Ok(config::EvalState {
+      frequency_available: self
+        .cpus
+        .iter()
+        .any(|cpu| cpu.frequency_mhz.is_some()),
+      turbo_available: turbo.is_some(),
+      turbo_enabled: turbo,
+      smt_available: cpu::Cpu::smt_available()
+        .context("failed to read CPU SMT control for `smt-available`")?,
+      hwp_dynamic_boost_available: cpu::Cpu::hwp_dynamic_boost()
+        .context(
+          "failed to read HWP dynamic boost for `hwp-dynamic-boost-available`",
+        )?
+        .is_some(),
+
+      cpu_usage: self.cpu_log.back().context("CPU log is empty")?.usage,
+      cpu_usage_volatility: self.cpu_volatility().map(|vol| vol.usage),
+      cpu_usage_average: self.cpu_usage_average(),
+      cpu_temperature: self.cpu_log.back().and_then(|log| log.temperature),
+      cpu_temperature_volatility: self
+        .cpu_volatility()
+        .and_then(|vol| vol.temperature),
+      cpu_temperature_maximum: cpu_temperature_range
+        .map(|(_, maximum)| maximum),
+      cpu_temperature_minimum: cpu_temperature_range
+        .map(|(minimum, _)| minimum),
+      fan_rpm_maximum: self.fan_rpm_maximum,
+      gpu_temperature: temperature_range(&self.gpu_temperatures)
+        .map(|(_, maximum)| maximum),
+      gpu_power_watts: self.gpu_power_watts,
+      disk_temperature_maximum: self
+        .disk_temperatures
+        .values()
+        .copied()
+        .reduce(f64::max),
+      cpu_idle_seconds: last_user_activity.elapsed().as_secs_f64(),
+      cpu_frequency_maximum: cpu::Cpu::hardware_frequency_mhz_maximum()
+        .context("failed to read CPU hardware maximum frequency")?
+        .map(|u64| u64 as f64),
+      cpu_frequency_minimum: cpu::Cpu::hardware_frequency_mhz_minimum()
+        .context("failed to read CPU hardware minimum frequency")?
+        .map(|u64| u64 as f64),
+
+      lid_closed: self.lid_closed,
+      virtual_machine: self.virtual_machine,
+      chassis_type: self.chassis_type.as_deref(),
+      platform_profile: self.platform_profile.as_deref(),
+
+      power_supply_charge: self.power_supply_log.back().map(|log| log.charge),
+      power_supply_discharge_rate: self.power_supply_discharge_rate(),
+      battery_minutes_remaining: self
+        .battery_minutes_remaining(self.is_discharging()),
+      power_draw_watts: self.power_draw_watts(),
+      ac_power_limit_watts: self.ac_power_limit_watts(),
+
+      memory_usage: self.memory_usage,
+
+      cpu_pressure: self.cpu_pressure,
+
+      battery_cycles: self.battery_cycles,
+      battery_health: self.battery_health,
+
+      discharging: self.is_discharging(),
+      on_ac: self.is_ac,
+      desktop: config::find_batteries(&self.power_supplies).is_empty(),
+
+      local_time: jiff::Zoned::now().datetime(),
+      power_profile_preference,
+
+      context: config::EvalContext::WidestPossible,
+
+      cpus: &self.cpus,
+      uncores: &self.uncores,
+      disks: &self.disks,
+      usb_devices: &self.usb_devices,
+      gpus: &self.gpus,
+      power_supplies: &self.power_supplies,
+      cpu_log: &self.cpu_log,
+      sysfs_reads: &self.sysfs_reads,
+    })
+  }
+}
+
+/// Resolves once the daemon is asked to stop, either by SIGINT or SIGTERM.
+struct ShutdownSignal {
+  interrupt: unix::Signal,
+  terminate: unix::Signal,
+}
+
+impl ShutdownSignal {
+  fn new() -> anyhow::Result<Self> {
+    Ok(Self {
+      interrupt: unix::signal(unix::SignalKind::interrupt())
+        .context("failed to listen for SIGINT")?,
+      terminate: unix::signal(unix::SignalKind::terminate())
+        .context("failed to listen for SIGTERM")?,
+    })
+  }
+
+  /// Waits for the next shutdown signal and returns its name.
+  async fn recv(&mut self) -> &'static str {
+    tokio::select! {
+      _ = self.interrupt.recv() => "SIGINT",
+      _ = self.terminate.recv() => "SIGTERM",
+    }
+  }
+}
+
+/// How often the daemon checks whether the system was suspended while it
+/// waits for the next poll.
+const SUSPEND_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Notices suspends through the time that passed on `CLOCK_BOOTTIME`, which
+/// keeps counting while the system is suspended, but not on
+/// `CLOCK_MONOTONIC`, which [`Instant`] and the polling timer use. After a
+/// resume, the polling timer would otherwise only run out as late as the
+/// whole polling delay, while the firmware may have reset the settings.
+#[derive(Debug)]
+struct SuspendDetector {
+  /// How far `CLOCK_BOOTTIME` is ahead of `CLOCK_MONOTONIC`, which is the
+  /// time spent suspended since boot.
+  suspended_since_boot: Option<Duration>,
+}
+
+impl SuspendDetector {
+  fn new() -> Self {
+    Self {
+      suspended_since_boot: Self::read_suspended_since_boot(),
+    }
+  }
+
+  fn read_suspended_since_boot() -> Option<Duration> {
+    use nix::time::{
+      ClockId,
+      clock_gettime,
+    };
+
+    let boottime = clock_gettime(ClockId::CLOCK_BOOTTIME).ok()?;
+    let monotonic = clock_gettime(ClockId::CLOCK_MONOTONIC).ok()?;
+
+    Some(Duration::from(boottime).saturating_sub(Duration::from(monotonic)))
+  }
+
+  /// How long the system was suspended since the last check.
+  fn suspended_for(&mut self) -> Duration {
+    self.advance(Self::read_suspended_since_boot())
+  }
+
+  fn advance(&mut self, suspended_since_boot: Option<Duration>) -> Duration {
+    let suspended = match (self.suspended_since_boot, suspended_since_boot) {
+      (Some(before), Some(now)) => now.saturating_sub(before),
+      _ => Duration::ZERO,
+    };
+
+    self.suspended_since_boot = suspended_since_boot;
+    suspended
+  }
+}
+
+/// Sleeps for the given duration, or until `wake` is notified.
+async fn sleep_or_wake(duration: Duration, wake: &Notify) {
+  tokio::select! {
+    () = tokio::time::sleep(duration) => {},
+    () = wake.notified() => log::debug!("woken up before the polling delay ran out"),
+  }
+}
+
+/// Ticks at the systemd watchdog interval, or never if there is no watchdog.
+/// Waits until `deadline`, or forever without one.
+async fn sleep_until(deadline: Option<Instant>) {
+  match deadline {
+    Some(deadline) => {
+      tokio::time::sleep_until(deadline.into()).await;
+    },
+    None => std::future::pending().await,
+  }
+}
+
+async fn watchdog_tick(watchdog: &mut Option<tokio::time::Interval>) {
+  match watchdog {
+    Some(interval) => {
+      interval.tick().await;
+    },
+    None => std::future::pending().await,
+  }
+}
+
+fn references_gpu_sensors(config: &config::DaemonConfig) -> bool {
+  config.references("$gpu-temperature") || config.references("$gpu-power-watts")
+}
+
+/// Reads a hwmon sensor. A sensor that fails to read, like one that returns EIO
+/// while its device sleeps, is logged and skipped like a missing one instead of
+/// failing the whole scan.
+fn read_sensor<N: std::str::FromStr>(path: &Path) -> Option<N>
+where
+  N::Err: std::error::Error + Send + Sync + 'static,
+{
+  fs::read_n::<N>(path).unwrap_or_else(|error| {
+    log::debug!(
+      "failed to read sensor at '{path}', skipping: {error:#}",
+      path = path.display(),
+    );
+    None
+  })
+}
+
+/// The CPU and power supply settings from before the daemon applied any rules.
+#[derive(Debug, Default)]
+struct Snapshot {
+  cpu:        HashMap<Arc<cpu::Cpu>, cpu::Delta>,
+  cpu_global: cpu::GlobalDelta,
+  power:      HashMap<Arc<power_supply::PowerSupply>, power_supply::Delta>,
+}
+
+impl Snapshot {
+  fn capture(system: &System) -> anyhow::Result<Self> {
+    let mut cpu = HashMap::with_capacity(system.cpus.len());
+    for each in &system.cpus {
+      cpu.insert(Arc::clone(each), each.snapshot()?);
+    }
+
+    let power = system
+      .power_supplies
+      .iter()
+      .map(|power_supply| (Arc::clone(power_supply), power_supply.snapshot()))
+      .collect();
+
+    Ok(Self {
+      cpu,
+      cpu_global: cpu::GlobalDelta::snapshot()?,
+      power,
+    })
+  }
+
+  /// Fills the settings the deltas leave unset with the captured ones. Devices
+  /// that disappeared since the capture are skipped.
+  fn fill(&self, deltas: &mut Deltas) {
+    for (cpu, snapshot) in &self.cpu {
+      if let Some(delta) = deltas.cpu.get_mut(cpu) {
+        *delta = mem::take(delta).or(snapshot);
+      }
+    }
+
+    deltas.cpu_global = mem::take(&mut deltas.cpu_global).or(&self.cpu_global);
+
+    for (power_supply, snapshot) in &self.power {
+      if let Some(delta) = deltas.power.get_mut(power_supply) {
+        *delta = mem::take(delta).or(snapshot);
+      }
+    }
+  }
+}
+
+/// Debounces the condition of a rule with a `hold-for` duration.
+#[derive(Debug, Default, Clone, Copy)]
+struct Hold {
+  applied:       bool,
+  pending_since: Option<Instant>,
+}
+
+impl Hold {
+  /// Feeds the current condition of the rule and returns whether it should be
+  /// applied. The result only flips once the condition has disagreed with it
+  /// for at least `hold_for`.
+  fn update(
+    &mut self,
+    condition: bool,
+    hold_for: Duration,
+    now: Instant,
+  ) -> bool {
+    if condition == self.applied {
+      self.pending_since = None;
+      return self.applied;
+    }
+
+    let pending_since = *self.pending_since.get_or_insert(now);
+
+    if now.duration_since(pending_since) >= hold_for {
+      self.applied = condition;
+      self.pending_since = None;
+    }
+
+    self.applied
+  }
+
+  /// Forgets the pending condition change of every rule that wasn't
+  /// evaluated, like after a short circuit, so that its `hold-for` doesn't
+  /// count the polls it was skipped in.
+  fn reset_skipped(holds: &mut HashMap<u16, Self>, evaluated: &HashSet<u16>) {
+    for (priority, hold) in holds {
+      if !evaluated.contains(priority) {
+        hold.pending_since = None;
+      }
+    }
+  }
+}
+
+/// Debounces a notification, so it is only sent when its condition becomes
+/// true and not more often than its cooldown.
+#[derive(Debug, Default, Clone, Copy)]
+struct Notified {
+  active:    bool,
+  last_sent: Option<Instant>,
+}
+
+impl Notified {
+  /// Feeds the current condition of the notification and returns whether it
+  /// should be sent now.
+  fn update(
+    &mut self,
+    condition: bool,
+    cooldown: Duration,
+    now: Instant,
+  ) -> bool {
+    let became_true = condition && !self.active;
+    self.active = condition;
+
+    if !became_true
+      || self
+        .last_sent
+        .is_some_and(|last_sent| now.duration_since(last_sent) < cooldown)
+    {
+      return false;
+    }
+
+    self.last_sent = Some(now);
+    true
+  }
+}
+
+struct Daemon {
+  config: config::DaemonConfig,
+
+  /// Hold state of rules with a `hold-for` duration, keyed by priority.
+  holds:     HashMap<u16, Hold>,
+  /// When rules with an `apply-cooldown` were last evaluated, the higher
+  /// priority rules that applied then and what they evaluated to, keyed by
+  /// priority.
+  cooldowns: HashMap<u16, (Instant, HashSet<u16>, RuleDeltas)>,
+
+  /// Debounce state of the notifications, keyed by summary.
+  notified:              HashMap<String, Notified>,
+  /// Notifications that became due in the last poll and weren't sent yet.
+  pending_notifications: Vec<config::Notification>,
+
+  /// Settings to restore when stopping, captured on the first poll.
+  snapshot:          Option<Snapshot>,
+  /// Whether the last poll forced the thermal emergency settings.
+  thermal_emergency: bool,
+
+  system:             System,
+  apply_context:      fs::ApplyContext,
+  dma_latency:        cpu::DmaLatency,
+  last_polling_delay: Option<Duration>,
+  last_user_activity: Instant,
+}
+
+impl Daemon {
+  fn new(
+    config: config::DaemonConfig,
+    apply_context: fs::ApplyContext,
+  ) -> Self {
+    let system = System::new(&config);
+
+    Self {
+      config,
+      holds: HashMap::new(),
+      cooldowns: HashMap::new(),
+      notified: HashMap::new(),
+      pending_notifications: Vec::new(),
+      snapshot: None,
+      thermal_emergency: false,
+      system,
+      apply_context,
+      dma_latency: cpu::DmaLatency::default(),
+      last_polling_delay: None,
+      last_user_activity: Instant::now(),
+    }
+  }
+
+  /// Switches to a newly loaded config. The system logs and the last user
+  /// activity are kept, so polling carries on where it left off.
+  fn reload(&mut self, config: config::DaemonConfig) -> anyhow::Result<()> {
+    if !config.rules.is_sorted_by_key(|rule| rule.priority) {
+      bail!("daemon config rules must be sorted by priority");
+    }
+
+    #[cfg(feature = "metrics")]
+    if config.metrics != self.config.metrics {
+      log::warn!("metrics settings only change when the daemon restarts");
+    }
+
+    fs::set_min_write_interval(config.min_write_interval);
+
+    // Rules are identified by their priority, a hold belongs to a rule that
+    // no longer exists if its priority is gone.
+    self.holds.retain(|priority, _| {
+      config.rules.iter().any(|rule| rule.priority == *priority)
+    });
+    // A changed rule is evaluated again right away.
+    self.cooldowns.retain(|priority, _| {
+      config.rules.iter().any(|rule| {
+        rule.priority == *priority
+          && self.config.rules.iter().any(|old| old == rule)
+      })
+    });
+
+    self.notified.retain(|summary, _| {
+      config
+        .notifications
+        .iter()
+        .any(|notification| notification.summary == *summary)
+    });
+
+    self.system.temperature_sensors = config.temperature_sensors.clone();
+    self.system.cpu_usage_smoothing = config.cpu_usage_smoothing;
+    self.system.log_windows = LogWindows::new(&config);
+    self.system.scan_gpu_sensors = references_gpu_sensors(&config);
+    self.system.scan_disk_temperatures =
+      config.references("$disk-temperature-max");
+    // Also lists the hwmon devices again on SIGHUP.
+    self.system.hwmon_devices = None;
+    self.config = config;
+
+    Ok(())
+  }
+
+  /// Fills the settings a thermal emergency pinned that no rule sets anymore
+  /// with the ones from before the daemon started, or the driver defaults
+  /// without a snapshot, so that they don't stay pinned.
+  fn restore_after_thermal_emergency(
+    &self,
+    deltas: &mut Deltas,
+    turbo_available: bool,
+  ) {
+    for (cpu, delta) in &mut deltas.cpu {
+      let baseline = match self
+        .snapshot
+        .as_ref()
+        .and_then(|snapshot| snapshot.cpu.get(cpu))
+      {
+        Some(snapshot) => snapshot.clone(),
+
+        None => {
+          match cpu.defaults() {
+            Ok(defaults) => defaults,
+            Err(error) => {
+              log::warn!("failed to read defaults of {cpu}: {error:#}");
+              continue;
+            },
+          }
+        },
+      };
+
+      let baseline = cpu::Delta {
+        // A pinned frequency replaces the governor.
+        governor: baseline.governor.filter(|_| delta.frequency_mhz.is_none()),
+        frequency_mhz_minimum: baseline.frequency_mhz_minimum,
+        frequency_mhz_maximum: baseline.frequency_mhz_maximum,
+        frequency_khz_minimum: baseline.frequency_khz_minimum,
+        frequency_khz_maximum: baseline.frequency_khz_maximum,
+        ..cpu::Delta::default()
+      };
+
+      *delta = mem::take(delta).or(&baseline);
+    }
+
+    if deltas.cpu_global.turbo.is_none() {
+      deltas.cpu_global.turbo = match &self.snapshot {
+        Some(snapshot) => snapshot.cpu_global.turbo,
+        None => turbo_available.then_some(true),
+      };
+    }
+  }
+
+  /// Scans the system, applies every matching rule and returns the delay
+  /// until the next poll along with the names of the rules that applied.
+  fn poll(
+    &mut self,
+    power_profile_preference: profile::PowerProfile,
+  ) -> anyhow::Result<(Duration, Vec<String>)> {
+    self.system.scan()?;
+
+    if self.config.restore_on_exit && self.snapshot.is_none() {
+      self.snapshot = Some(
+        Snapshot::capture(&self.system)
+          .context("failed to capture settings to restore on exit")?,
+      );
+    }
+
+    if !self.system.is_cpu_idle() {
+      self.last_user_activity = Instant::now();
+    }
+
+    let eval_state = self
+      .system
+      .eval_state(self.last_user_activity, power_profile_preference)?;
+
+    let now = Instant::now();
+
+    for notification in &self.config.notifications {
+      let condition = notification
+        .condition
+        .eval(&eval_state)
+        .and_then(|condition| {
+          condition.map(|condition| condition.try_into_boolean()).transpose()
+        })
+        .with_context(|| {
+          format!(
+            "`if` of notification '{summary}' was not a boolean",
+            summary = notification.summary,
+          )
+        })?
+        // Undefined counts as false, so that it is sent again once it is
+        // defined and true.
+        .unwrap_or(false);
+
+      if self
+        .notified
+        .entry(notification.summary.clone())
+        .or_default()
+        .update(condition, notification.cooldown(), now)
+      {
+        self.pending_notifications.push(notification.clone());
+      }
+    }
+
+    let mut deltas = Deltas::new(&self.system);
+
+    // Higher priority rule first, so we can short-circuit.
+    let mut last_applied_rules = Vec::new();
+    let mut applied_priorities = HashSet::new();
+    let mut held_priorities = HashSet::new();
+    let mut cooling_down = Vec::new();
+
+    let thermal_emergency =
+      eval_state
+        .cpu_temperature_maximum
+        .is_some_and(|temperature| {
+          temperature > self.config.thermal_emergency_celsius
+        });
+
+    // The rules write every setting again once the emergency is over, instead
+    // of leaving the emergency settings until their cooldown is over.
+    if self.thermal_emergency && !thermal_emergency {
+      self.cooldowns.clear();
+    }
+
+    for rule in self.config.rules.iter().rev() {
+      let condition = match rule.condition.eval(&eval_state)? {
+        Some(condition) => {
+          let condition = condition.try_into_boolean().with_context(|| {
+            format!("`if` of rule {rule} was not a boolean")
+          })?;
+
+          log::info!("rule {rule}: condition = {condition}");
+
+          condition
+        },
+
+        None if rule.hold_for.is_some() => {
+          log::info!(
+            "rule {rule}: condition is undefined, counting it as false for \
+             `hold-for`"
+          );
+
+          false
+        },
+
+        None => {
+          log::info!("rule {rule}: condition is undefined, skipping");
+          continue;
+        },
+      };
+
+      let condition = match rule.hold_for {
+        Some(hold_for) => {
+          held_priorities.insert(rule.priority);
+
+          let held = self
+            .holds
+            .entry(rule.priority)
+            .or_default()
+            .update(condition, hold_for, now);
+
+          if held != condition {
+            log::info!(
+              "rule {rule}: held at {held} for `hold-for` of {hold_for}",
+              hold_for = humantime::format_duration(hold_for),
+            );
+          }
+
+          held
+        },
+
+        None => condition,
+      };
+
+      if condition {
+        log::info!(
+          "rule {rule}: applying {sections}",
+          sections = match &*rule.sections() {
+            [] => "nothing".to_owned(),
+            sections => sections.join(", "),
+          },
+        );
+
+        last_applied_rules.push(rule.label());
+
+        // A higher priority rule starting or stopping to apply changes what
+        // this rule's settings are merged into, so the cooldown starts over
+        // and they're written again.
+        let applied_above = applied_priorities.clone();
+        applied_priorities.insert(rule.priority);
+
+        let rule_deltas =
+          match (rule.apply_cooldown, self.cooldowns.get(&rule.priority)) {
+            (Some(cooldown), Some((applied_at, above, rule_deltas)))
+              if now.duration_since(*applied_at) < cooldown
+                && *above == applied_above =>
+            {
+              log::info!(
+                "rule {rule}: not writing its settings again during \
+                 `apply-cooldown` of {cooldown}, they were written {elapsed} \
+                 ago",
+                cooldown = humantime::format_duration(cooldown),
+                elapsed = humantime::format_duration(Duration::from_secs(
+                  now.duration_since(*applied_at).as_secs()
+                )),
+              );
+
+              // Still merged, so that lower priority rules can't change them.
+              cooling_down.push(rule_deltas.clone());
+              rule_deltas.clone()
+            },
+
+            (cooldown, _) => {
+              let rule_deltas = RuleDeltas::eval(rule, &eval_state)?;
+
+              if cooldown.is_some() {
+                self.cooldowns.insert(
+                  rule.priority,
+                  (now, applied_above, rule_deltas.clone()),
+                );
+              }
+
+              rule_deltas
+            },
+          };
+
+        if deltas.merge_evaluated(&rule_deltas) {
+          log::debug!(
+            "got a full delta from rules, short circuting evaluation"
+          );
+          break;
+        }
+      }
+    }
+
+    // A rule that didn't apply starts its cooldown over the next time it does.
+    self
+      .cooldowns
+      .retain(|priority, _| applied_priorities.contains(priority));
+
+    Hold::reset_skipped(&mut self.holds, &held_priorities);
+
+    // Merged last, so that every rule takes precedence over it.
+    if let Some(default) = &self.config.default {
+      deltas.merge(&default.as_rule("default"), &eval_state)?;
+    }
+
+    // Checked after the rules, so that no rule can override it.
+    if thermal_emergency
+      && let Some(temperature) = eval_state.cpu_temperature_maximum
+    {
+      log::error!(
+        "THERMAL EMERGENCY: a CPU core is at {temperature}°C, above \
+         `thermal-emergency-celsius` of {threshold}°C. Forcing the powersave \
+         governor, the lowest frequency and turbo off regardless of the rules",
+        threshold = self.config.thermal_emergency_celsius,
+      );
+
+      deltas.thermal_emergency(eval_state.turbo_available);
+      self.thermal_emergency = true;
+    } else if mem::take(&mut self.thermal_emergency) {
+      log::warn!(
+        "thermal emergency is over, restoring the CPU settings no rule sets"
+      );
+
+      self.restore_after_thermal_emergency(
+        &mut deltas,
+        eval_state.turbo_available,
+      );
+    }
+
+    for rule_deltas in &cooling_down {
+      deltas.skip_unchanged(rule_deltas);
+    }
+
+    deltas.apply(
+      &self.apply_context,
+      &mut self.dma_latency,
+      self.config.ignore_unsupported,
+      self.config.force_discharge_floor,
+    )?;
+
+    let profile_config =
+      self.config.profile_config(Some(power_profile_preference));
+
+    let delay = compute_poll_delay(
+      &self.system,
+      &profile_config,
+      self.last_polling_delay,
+      self.last_user_activity,
+    );
+    self.last_polling_delay = Some(delay);
+
+    if let Some(stats_file) = &self.config.stats_file {
+      let stats = Stats {
+        variables:             &eval_state,
+        poll_interval_seconds: delay.as_secs_f64(),
+        applied_rules:         &last_applied_rules,
+      };
+
+      if let Err(error) = stats.write(stats_file) {
+        log::warn!("failed to write stats: {error:#}");
+      }
+    }
+
+    Ok((delay, last_applied_rules))
+  }
+
+  /// Takes the notifications that became due since the last call.
+  fn take_notifications(&mut self) -> Vec<config::Notification> {
+    mem::take(&mut self.pending_notifications)
+  }
+
+  /// Applies the `[on-stop]` actions and restores the settings from before
+  /// the daemon started, if configured.
+  fn stop(
+    &mut self,
+    power_profile_preference: profile::PowerProfile,
+  ) -> anyhow::Result<()> {
+    if self.config.on_stop.is_none() && self.snapshot.is_none() {
+      return Ok(());
+    }
+
+    self.system.scan()?;
+
+    let eval_state = self
+      .system
+      .eval_state(self.last_user_activity, power_profile_preference)?;
+
+    let mut deltas = Deltas::new(&self.system);
+
+    if let Some(on_stop) = &self.config.on_stop {
+      log::info!("applying on-stop actions");
+      deltas.merge(&on_stop.as_rule("on-stop"), &eval_state)?;
+    }
+
+    if let Some(snapshot) = &self.snapshot {
+      log::info!("restoring settings from before the daemon started");
+      snapshot.fill(&mut deltas);
+    }
+
+    // Nothing is left running to write queued values later or to retry.
+    let apply_context = fs::ApplyContext {
+      ignore_write_interval: true,
+      keep_going: true,
+      ..self.apply_context
+    };
+
+    deltas.apply(
+      &apply_context,
+      &mut self.dma_latency,
+      self.config.ignore_unsupported,
+      self.config.force_discharge_floor,
+    )
+  }
+}
+
+/// The contents of the `stats-file`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Stats<'a> {
+  variables:             &'a config::EvalState<'a, 'a>,
+  poll_interval_seconds: f64,
+  applied_rules:         &'a [String],
+}
 
-            for (disk, delta) in disk_deltas.iter_mut() {
-              if let Some(delta_lo) = disk_deltas_lo.get(disk) {
-                *delta = mem::take(delta).or(delta_lo);
-              }
-            }
-            disk_global_delta =
-              mem::take(&mut disk_global_delta).or(&disk_global_delta_lo);
+impl Stats<'_> {
+  /// Writes the stats as JSON to a temporary file that is then renamed over
+  /// `path`, so that readers never see a partial write.
+  fn write(&self, path: &Path) -> anyhow::Result<()> {
+    let json =
+      serde_json::to_vec_pretty(self).context("failed to serialize stats")?;
+
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    let temporary_path = PathBuf::from(temporary_path);
+
+    std::fs::write(&temporary_path, json).with_context(|| {
+      format!(
+        "failed to write stats to '{path}'",
+        path = temporary_path.display(),
+      )
+    })?;
 
-            for (device, delta) in usb_deltas.iter_mut() {
-              if let Some(delta_lo) = usb_deltas_lo.get(device) {
-                *delta = mem::take(delta).or(delta_lo);
-              }
-            }
+    std::fs::rename(&temporary_path, path).with_context(|| {
+      format!(
+        "failed to move stats from '{temporary}' to '{path}'",
+        temporary = temporary_path.display(),
+        path = path.display(),
+      )
+    })
+  }
+}
 
-            audio_delta = mem::take(&mut audio_delta).or(&audio_delta_lo);
+/// Applies the rules a single time and returns the names of the rules that
+/// applied, without starting the D-Bus server or restoring anything afterwards.
+#[cfg(test)]
+pub(crate) fn run_once(
+  mut config: config::DaemonConfig,
+) -> anyhow::Result<Vec<String>> {
+  if !config.rules.is_sorted_by_key(|rule| rule.priority) {
+    bail!("daemon config rules must be sorted by priority");
+  }
 
-            for (gpu, delta) in gpu_deltas.iter_mut() {
-              if let Some(delta_lo) = gpu_deltas_lo.get(gpu) {
-                *delta = mem::take(delta).or(delta_lo);
-              }
-            }
+  // Nothing runs afterwards to restore the settings.
+  config.restore_on_exit = false;
 
-            let (power_deltas_lo, power_platform_profile_lo) =
-              rule.power.eval(&eval_state)?;
+  let power_profile_preference =
+    profile::ProfileState::new().get_effective_profile();
 
-            for (power, delta) in power_deltas.iter_mut() {
-              if let Some(delta_lo) = power_deltas_lo.get(power) {
-                *delta = mem::take(delta).or(delta_lo);
-              }
-            }
+  let (_, applied_rules) = Daemon::new(config, fs::ApplyContext::default())
+    .poll(power_profile_preference)?;
 
-            power_platform_profile =
-              power_platform_profile.or(power_platform_profile_lo);
-
-            let deltas_some =
-              power_deltas.values().all(|delta| delta.is_some());
-            let uncore_some =
-              uncore_deltas.values().all(|delta| delta.is_some());
-            let disk_some = disk_deltas.values().all(|delta| delta.is_some())
-              && disk_global_delta.is_some();
-            let usb_some = usb_deltas.values().all(|delta| delta.is_some());
-            let gpu_some = gpu_deltas.values().all(|delta| delta.is_some());
-            deltas_some
-              && power_platform_profile.is_some()
-              && uncore_some
-              && vm_delta.is_some()
-              && disk_some
-              && usb_some
-              && audio_delta.is_some()
-              && gpu_some
-          };
+  Ok(applied_rules)
+}
 
-          if cpu_some && power_some {
-            log::debug!(
-              "got a full delta from rules, short circuting evaluation"
-            );
-            break;
-          }
-        }
-      }
+/// Runs the daemon until it receives SIGINT or SIGTERM. On SIGHUP, the config
+/// is loaded from `config_path` again.
+pub async fn run_daemon(
+  config: config::DaemonConfig,
+  config_path: Option<PathBuf>,
+  apply_context: fs::ApplyContext,
+) -> anyhow::Result<()> {
+  if !config.rules.is_sorted_by_key(|rule| rule.priority) {
+    bail!("daemon config rules must be sorted by priority");
+  }
 
-      for (cpu, delta) in &cpu_deltas {
-        delta
-          .apply(&mut (**cpu).clone())
-          .with_context(|| format!("failed to apply delta to {cpu}"))?;
-      }
+  log::info!("starting daemon...");
 
-      log::info!("applying CPU deltas to {len} CPUs", len = cpu_deltas.len());
+  fs::set_min_write_interval(config.min_write_interval);
 
-      cpu_global_delta
-        .apply(cpu_deltas.keys().map(|arc| &**arc), &mut dma_latency)
-        .context("failed to apply global CPU delta")?;
+  let state = Arc::new(RwLock::new(DaemonState::new(config.rules.len())));
 
-      log::info!(
-        "applying uncore deltas to {len} devices",
-        len = uncore_deltas.len(),
-      );
+  #[cfg(feature = "metrics")]
+  if let Some(metrics_config) = &config.metrics {
+    crate::metrics::start(metrics_config, Arc::clone(&state))?;
+  }
 
-      for (uncore, delta) in uncore_deltas {
-        delta
-          .apply(&uncore)
-          .with_context(|| format!("failed to apply delta to {uncore}"))?;
+  let status_socket = Path::new(crate::status::SOCKET_PATH);
+  if let Err(error) = crate::status::start(status_socket, Arc::clone(&state)) {
+    log::warn!("failed to start status socket: {error:#}");
+  }
+
+  tokio::spawn({
+    let state = Arc::clone(&state);
+    async move {
+      if let Err(error) = crate::dbus::server::start(state).await {
+        log::error!("D-Bus server exited with error: {error}");
       }
+    }
+  });
 
-      vm_delta.apply().context("failed to apply VM delta")?;
+  let mut config_modified = config_path
+    .as_deref()
+    .and_then(|path| config.files_modified(path));
 
-      log::info!(
-        "applying disk deltas to {len} devices",
-        len = disk_deltas.len(),
-      );
-      for (disk, delta) in disk_deltas {
-        delta
-          .apply(&disk)
-          .with_context(|| format!("failed to apply delta to {disk}"))?;
-      }
-      disk_global_delta
-        .apply()
-        .context("failed to apply global disk delta")?;
+  let mut daemon = Daemon::new(config, apply_context);
+  let mut shutdown = ShutdownSignal::new()?;
+  let mut hangup = unix::signal(unix::SignalKind::hangup())
+    .context("failed to listen for SIGHUP")?;
+  let mut reload = false;
+  let wake = Arc::clone(&state.read().await.wake);
+  let mut sleep_for = Duration::ZERO;
+  let mut ready = false;
 
-      log::info!(
-        "applying USB deltas to {len} devices",
-        len = usb_deltas.len(),
-      );
-      for (device, delta) in usb_deltas {
-        delta
-          .apply(&device)
-          .with_context(|| format!("failed to apply delta to {device}"))?;
-      }
+  let mut result = Ok(());
 
-      audio_delta.apply().context("failed to apply audio delta")?;
+  let mut suspend_detector = SuspendDetector::new();
+  let mut suspend_check = tokio::time::interval(SUSPEND_CHECK_INTERVAL);
+  suspend_check
+    .set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-      log::info!(
-        "applying GPU deltas to {len} devices",
-        len = gpu_deltas.len(),
-      );
-      for (gpu, delta) in gpu_deltas {
-        delta
-          .apply(&gpu)
-          .with_context(|| format!("failed to apply delta to {gpu}"))?;
+  let mut watchdog = systemd::watchdog_interval().map(|period| {
+    log::debug!(
+      "pinging the systemd watchdog every {seconds} seconds",
+      seconds = period.as_secs_f64(),
+    );
+
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval
+  });
+
+  'poll: loop {
+    let deadline = Instant::now() + sleep_for;
+
+    loop {
+      tokio::select! {
+        signal = shutdown.recv() => {
+          log::info!("received {signal}, shutting down");
+          break 'poll;
+        },
+        () = sleep_or_wake(deadline.saturating_duration_since(Instant::now()), &wake) => break,
+        _ = hangup.recv() => {
+          log::info!("received SIGHUP, reloading config");
+          reload = true;
+          break;
+        },
+        () = sleep_until(fs::next_queued_write()) => {
+          if let Err(error) = fs::flush_queued_writes() {
+            log::warn!("failed to write queued value: {error:#}");
+          }
+        },
+        () = watchdog_tick(&mut watchdog) => {
+          if let Err(error) = systemd::notify("WATCHDOG=1") {
+            log::warn!("failed to ping the systemd watchdog: {error:#}");
+          }
+        },
+        _ = suspend_check.tick() => {
+          let suspended = suspend_detector.suspended_for();
+
+          if suspended > sleep_for * 2 {
+            log::info!(
+              "detected wakeup from suspend after {seconds}s, re-applying rules",
+              seconds = suspended.as_secs(),
+            );
+            break;
+          }
+        },
       }
+    }
 
+    if daemon.config.watch_config
+      && let Some(path) = &config_path
+      && daemon.config.files_modified(path) != config_modified
+    {
       log::info!(
-        "applying power supply deltas to {len} devices",
-        len = power_deltas.len(),
+        "config at '{path}' changed, reloading",
+        path = path.display(),
       );
+      reload = true;
+    }
 
-      for (power, delta) in power_deltas {
-        delta
-          .apply(&mut (*power).clone())
-          .with_context(|| format!("failed to apply delta to {power}"))?;
-      }
+    if mem::take(&mut reload) {
+      // Also on SIGHUP, so that the change isn't loaded a second time. A
+      // config that fails to load is only retried after it changes again.
+      config_modified = config_path
+        .as_deref()
+        .and_then(|path| daemon.config.files_modified(path));
+
+      match config::DaemonConfig::load_from(config_path.as_deref()).and_then(
+        |config| {
+          let rule_count = config.rules.len();
+          daemon.reload(config)?;
+          Ok(rule_count)
+        },
+      ) {
+        Ok(rule_count) => {
+          state.write().await.rule_count = rule_count;
+          log::info!("reloaded config with {rule_count} rules");
+        },
 
-      if let Some(platform_profile) = power_platform_profile {
-        power_supply::PowerSupply::set_platform_profile(&platform_profile)
-          .context("failed to set power supply platform profile")?;
+        Err(error) => {
+          log::error!(
+            "failed to reload config, keeping the old one: {error:#}"
+          );
+        },
       }
+    }
 
-      let delay =
-        compute_poll_delay(&system, last_polling_delay, last_user_activity);
-      state.write().await.update_system(
-        &system,
-        last_applied_rules,
-        performance_degraded,
-      );
-      last_polling_delay = Some(delay);
-      delay
-    };
+    log::debug!("starting main polling loop iteration");
+    let start = Instant::now();
+
+    let power_profile_preference = state.read().await.active_profile();
+
+    // Stops the same way as on a signal, so that the settings are still
+    // restored and the on-stop actions applied.
+    let (delay, last_applied_rules) =
+      match daemon.poll(power_profile_preference) {
+        Ok(polled) => polled,
+
+        Err(error) => {
+          // Reported by the caller once the daemon has stopped.
+          log::error!("failed to poll, stopping the daemon");
+          result = Err(error);
+          break 'poll;
+        },
+      };
+
+    let notifications = daemon.take_notifications();
+    if !notifications.is_empty() {
+      tokio::spawn(async move {
+        for notification in notifications {
+          crate::dbus::notifications::send(
+            &notification.summary,
+            &notification.body,
+          )
+          .await;
+        }
+      });
+    }
+
+    let performance_degraded = detect_performance_degradation(&daemon.system);
+    state.write().await.update_system(
+      &daemon.system,
+      last_applied_rules,
+      performance_degraded,
+      delay,
+    );
 
     let elapsed = start.elapsed();
     log::info!(
@@ -1474,10 +3462,767 @@ pub async fn run_daemon(config: config::DaemonConfig) -> anyhow::Result<()> {
       minutes = delay.as_secs_f64() / 60.0,
     );
 
+    if !ready {
+      ready = true;
+
+      if let Err(error) = systemd::notify("READY=1") {
+        log::warn!("failed to notify systemd of readiness: {error:#}");
+      }
+    }
+
     sleep_for = delay.saturating_sub(elapsed);
   }
 
   log::info!("stopping polling loop and shutting down");
 
-  Ok(())
+  let _ = std::fs::remove_file(status_socket);
+
+  if let Err(error) = systemd::notify("STOPPING=1") {
+    log::debug!("failed to notify systemd of shutdown: {error:#}");
+  }
+
+  let power_profile_preference = state.read().await.active_profile();
+  // Exit regardless, there is nothing left to retry with.
+  if let Err(error) = daemon.stop(power_profile_preference) {
+    log::error!("failed to apply on-stop actions: {error:#}");
+  }
+
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use nix::sys::signal;
+
+  use super::*;
+
+  #[test]
+  fn average_temperature_is_undefined_without_valid_readings() {
+    assert_eq!(average_temperature(&HashMap::new()), None);
+    assert_eq!(average_temperature(&HashMap::from([(0, f64::NAN)])), None);
+    assert_eq!(
+      average_temperature(&HashMap::from([
+        (0, 60.0),
+        (1, f64::NAN),
+        (2, 70.0)
+      ])),
+      Some(65.0),
+    );
+  }
+
+  #[test]
+  fn temperature_range_skips_nan() {
+    assert_eq!(temperature_range(&HashMap::new()), None);
+    assert_eq!(temperature_range(&HashMap::from([(0, f64::NAN)])), None,);
+    assert_eq!(
+      temperature_range(&HashMap::from([
+        (0, 70.0),
+        (1, f64::NAN),
+        (2, 95.0),
+        (3, 45.5),
+      ])),
+      Some((45.5, 95.0)),
+    );
+  }
+
+  #[test]
+  fn cpu_usage_average_damps_spikes() {
+    let usage = |usage| {
+      CpuLog {
+        at: Instant::now(),
+        usage,
+        temperature: None,
+        load_average: 0.0,
+      }
+    };
+
+    let mut system = System {
+      cpu_usage_smoothing: 0.5,
+      ..System::default()
+    };
+    assert_eq!(system.cpu_usage_average(), None);
+
+    system.cpu_log = VecDeque::from([usage(0.2), usage(0.2), usage(1.0)]);
+    assert_eq!(system.cpu_usage_average(), Some(0.6));
+
+    system.cpu_usage_smoothing = 1.0;
+    assert_eq!(system.cpu_usage_average(), Some(1.0));
+  }
+
+  #[test]
+  fn volatility_and_idle_only_look_within_their_windows() {
+    let now = Instant::now();
+    let usage = |seconds_ago, usage| {
+      CpuLog {
+        at: now - Duration::from_secs(seconds_ago),
+        usage,
+        temperature: None,
+        load_average: 0.0,
+      }
+    };
+
+    let mut system = System {
+      cpu_log: VecDeque::from([
+        usage(50, 0.9),
+        usage(40, 0.1),
+        usage(20, 0.02),
+        usage(10, 0.02),
+      ]),
+      log_windows: LogWindows {
+        volatility: Duration::from_secs(30),
+        idle:       Duration::from_secs(30),
+        capacity:   100,
+      },
+      ..System::default()
+    };
+
+    assert_eq!(
+      system.cpu_volatility().map(|volatility| volatility.usage),
+      Some(0.0),
+    );
+    assert!(system.is_cpu_idle());
+
+    // The spike from 50 seconds ago is now in both windows.
+    system.log_windows.volatility = Duration::from_secs(60);
+    system.log_windows.idle = Duration::from_secs(60);
+    assert!(system.cpu_volatility().unwrap().usage > 0.05);
+    assert!(!system.is_cpu_idle());
+  }
+
+  #[test]
+  fn battery_minutes_remaining_follows_the_charge_direction() {
+    let now = Instant::now();
+    let charge = |minutes_ago: u64, charge| {
+      PowerSupplyLog {
+        at: now - Duration::from_secs(minutes_ago * 60),
+        charge,
+      }
+    };
+
+    // Losing 10% over 30 minutes, 20% per hour.
+    let system = System {
+      power_supply_log: VecDeque::from([charge(30, 0.5), charge(0, 0.4)]),
+      ..System::default()
+    };
+    let minutes = system.battery_minutes_remaining(true).unwrap();
+    assert!((minutes - 120.0).abs() < 0.01, "{minutes}");
+    // Not charging, so there is no time to full.
+    assert_eq!(system.battery_minutes_remaining(false), None);
+
+    // Gaining 30% per hour with 60% left to charge.
+    let system = System {
+      power_supply_log: VecDeque::from([charge(20, 0.3), charge(0, 0.4)]),
+      ..System::default()
+    };
+    let minutes = system.battery_minutes_remaining(false).unwrap();
+    assert!((minutes - 120.0).abs() < 0.01, "{minutes}");
+
+    // A single sample has no rate.
+    let system = System {
+      power_supply_log: VecDeque::from([charge(0, 0.4)]),
+      ..System::default()
+    };
+    assert_eq!(system.battery_minutes_remaining(true), None);
+  }
+
+  #[test]
+  fn suspend_detector_measures_the_time_since_the_last_check() {
+    let mut detector = SuspendDetector {
+      suspended_since_boot: Some(Duration::from_secs(100)),
+    };
+
+    assert_eq!(
+      detector.advance(Some(Duration::from_secs(100))),
+      Duration::ZERO,
+    );
+    assert_eq!(
+      detector.advance(Some(Duration::from_secs(160))),
+      Duration::from_secs(60),
+    );
+    // Only the time since the previous check counts.
+    assert_eq!(
+      detector.advance(Some(Duration::from_secs(160))),
+      Duration::ZERO,
+    );
+    assert_eq!(detector.advance(None), Duration::ZERO);
+    assert_eq!(
+      detector.advance(Some(Duration::from_secs(200))),
+      Duration::ZERO,
+    );
+
+    // The clocks are readable on Linux.
+    assert!(SuspendDetector::new().suspended_since_boot.is_some());
+  }
+
+  #[test]
+  fn pressure_reads_the_some_line() {
+    assert_eq!(
+      pressure(
+        "some avg10=1.53 avg60=0.87 avg300=0.25 total=1234567\nfull \
+         avg10=0.00 avg60=0.00 avg300=0.00 total=0\n"
+      ),
+      Some(Pressure {
+        avg10:  1.53,
+        avg60:  0.87,
+        avg300: 0.25,
+      }),
+    );
+    assert_eq!(pressure("full avg10=0.00 avg60=0.00 avg300=0.00\n"), None);
+    assert_eq!(pressure("some avg10=1.53 total=1234567\n"), None);
+  }
+
+  #[test]
+  fn memory_usage_falls_back_without_mem_available() {
+    assert_eq!(
+      memory_usage(
+        "MemTotal:       16000000 kB\nMemFree:         2000000 \
+         kB\nMemAvailable:    4000000 kB\n"
+      ),
+      Some(0.75),
+    );
+    assert_eq!(
+      memory_usage(
+        "MemTotal: 1000 kB\nMemFree: 100 kB\nBuffers: 50 kB\nCached: 250 \
+         kB\nSwapCached: 999 kB\n"
+      ),
+      Some(0.6),
+    );
+    assert_eq!(memory_usage("MemTotal: 1000 kB\nMemFree: 100 kB\n"), None);
+    assert_eq!(memory_usage("MemTotal: 0 kB\nMemAvailable: 0 kB\n"), None);
+  }
+
+  #[tokio::test]
+  async fn sigterm_triggers_shutdown() {
+    let mut shutdown = ShutdownSignal::new().unwrap();
+
+    signal::raise(signal::Signal::SIGTERM).unwrap();
+
+    let received =
+      tokio::time::timeout(Duration::from_secs(5), shutdown.recv())
+        .await
+        .expect("SIGTERM should resolve the shutdown signal");
+    assert_eq!(received, "SIGTERM");
+  }
+
+  #[tokio::test]
+  async fn wake_interrupts_long_sleep() {
+    let wake = Arc::new(Notify::new());
+
+    tokio::spawn({
+      let wake = Arc::clone(&wake);
+      async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        wake.notify_one();
+      }
+    });
+
+    tokio::time::timeout(
+      Duration::from_secs(5),
+      sleep_or_wake(Duration::from_secs(60 * 60), &wake),
+    )
+    .await
+    .expect("notifying should end the sleep early");
+  }
+
+  #[tokio::test]
+  async fn sleep_lasts_the_whole_delay_without_wake() {
+    let wake = Notify::new();
+    let delay = Duration::from_millis(100);
+
+    let start = Instant::now();
+    sleep_or_wake(delay, &wake).await;
+
+    assert!(start.elapsed() >= delay);
+  }
+
+  #[test]
+  fn reload_keeps_history_and_drops_holds_of_removed_rules() {
+    let config = |priorities: &[u16]| {
+      config::DaemonConfig {
+        rules: priorities
+          .iter()
+          .map(|&priority| {
+            config::Rule {
+              priority,
+              ..config::Rule::default()
+            }
+          })
+          .collect(),
+        ..config::DaemonConfig::default()
+      }
+    };
+
+    let mut daemon =
+      Daemon::new(config(&[10, 20]), fs::ApplyContext::default());
+    daemon.holds.insert(10, Hold::default());
+    daemon.holds.insert(20, Hold::default());
+    daemon.system.cpu_log.push_back(CpuLog {
+      at:           Instant::now(),
+      usage:        0.5,
+      temperature:  None,
+      load_average: 1.0,
+    });
+    let last_user_activity = daemon.last_user_activity;
+
+    daemon.reload(config(&[20, 30])).unwrap();
+
+    assert_eq!(daemon.holds.keys().copied().collect::<Vec<_>>(), [20]);
+    assert_eq!(daemon.system.cpu_log.len(), 1);
+    assert_eq!(daemon.last_user_activity, last_user_activity);
+    assert_eq!(daemon.config.rules.len(), 2);
+
+    assert!(daemon.reload(config(&[30, 20])).is_err());
+    assert_eq!(daemon.config.rules[1].priority, 30);
+  }
+
+  #[test]
+  fn on_stop_actions_are_merged_into_deltas() {
+    let config: config::DaemonConfig = toml::from_str(
+      r#"
+        [on-stop]
+        cpu.governor = "powersave"
+        power.platform-profile = "balanced"
+      "#,
+    )
+    .unwrap();
+    let on_stop = config.on_stop.expect("on-stop should be parsed");
+
+    let system = System {
+      cpus: HashSet::from([
+        Arc::new(cpu::Cpu {
+          number: 0,
+          ..Default::default()
+        }),
+        Arc::new(cpu::Cpu {
+          number: 1,
+          ..Default::default()
+        }),
+      ]),
+      cpu_log: VecDeque::from([CpuLog {
+        at:           Instant::now(),
+        usage:        0.0,
+        temperature:  None,
+        load_average: 0.0,
+      }]),
+      ..Default::default()
+    };
+    // Evaluated against an empty fake system instead of the host.
+    let deltas = crate::harness::FakeSystem::new().with_root(|| {
+      let state = system
+        .eval_state(Instant::now(), profile::PowerProfile::Balanced)
+        .unwrap();
+
+      let mut deltas = Deltas::new(&system);
+      deltas.merge(&on_stop.as_rule("on-stop"), &state).unwrap();
+      deltas
+    });
+
+    assert_eq!(deltas.cpu.len(), 2);
+    assert!(
+      deltas
+        .cpu
+        .values()
+        .all(|delta| delta.governor.as_deref() == Some("powersave"))
+    );
+    assert_eq!(deltas.platform_profile.as_deref(), Some("balanced"));
+  }
+
+  #[test]
+  fn keep_going_applies_the_remaining_devices() {
+    let system = crate::harness::FakeSystem::new()
+      .file("sys/bus/usb/devices/1-2/power/control", "on");
+
+    let mut deltas = Deltas::new(&System::default());
+    // The first device has no autosuspend control to write to.
+    for name in ["1-1", "1-2"] {
+      deltas.usb.insert(
+        Arc::new(usb::UsbDevice {
+          name: name.to_owned(),
+          path: PathBuf::from(format!("/sys/bus/usb/devices/{name}")),
+        }),
+        usb::Delta {
+          autosuspend: Some(true),
+          ..usb::Delta::default()
+        },
+      );
+    }
+
+    let context = fs::ApplyContext {
+      keep_going: true,
+      ..fs::ApplyContext::default()
+    };
+    let result = system.with_root(|| {
+      deltas.apply(&context, &mut cpu::DmaLatency::default(), true, 0)
+    });
+
+    assert!(result.is_err());
+    assert_eq!(system.read("sys/bus/usb/devices/1-2/power/control"), "auto");
+  }
+
+  #[test]
+  fn hold_debounces_condition_changes() {
+    let hold_for = Duration::from_secs(30);
+    let start = Instant::now();
+    let at = |seconds| start + Duration::from_secs(seconds);
+
+    let mut hold = Hold::default();
+
+    // Has to stay true for the whole duration before applying.
+    assert!(!hold.update(true, hold_for, at(0)));
+    assert!(!hold.update(true, hold_for, at(20)));
+    assert!(!hold.update(false, hold_for, at(25)));
+    assert!(!hold.update(true, hold_for, at(40)));
+    assert!(!hold.update(true, hold_for, at(60)));
+    assert!(hold.update(true, hold_for, at(70)));
+
+    // And false for the whole duration before releasing.
+    assert!(hold.update(false, hold_for, at(80)));
+    assert!(hold.update(true, hold_for, at(90)));
+    assert!(hold.update(false, hold_for, at(100)));
+    assert!(!hold.update(false, hold_for, at(130)));
+  }
+
+  #[test]
+  fn hwmon_devices_are_listed_again() {
+    let mut system = System {
+      scan_disk_temperatures: true,
+      ..System::default()
+    };
+
+    let fake = crate::harness::FakeSystem::new()
+      .cpu(0, "performance")
+      .cpu_temperature(50.0);
+    fake.with_root(|| system.scan()).unwrap();
+
+    // The nvme driver loads after the first scan.
+    let fake = fake.hwmon(1, "nvme", &[("Composite", 48.0)]);
+    for _ in 1..HWMON_RELIST_SCANS {
+      fake.with_root(|| system.scan()).unwrap();
+    }
+    assert!(system.disk_temperatures.is_empty());
+
+    fake.with_root(|| system.scan()).unwrap();
+    assert_eq!(
+      system
+        .disk_temperatures
+        .values()
+        .copied()
+        .collect::<Vec<_>>(),
+      [48.0]
+    );
+  }
+
+  #[test]
+  fn skipped_rules_forget_their_pending_hold() {
+    let pending = Hold {
+      applied:       true,
+      pending_since: Some(Instant::now()),
+    };
+    let mut holds = HashMap::from([(10, pending), (20, pending)]);
+
+    Hold::reset_skipped(&mut holds, &HashSet::from([20]));
+
+    assert!(holds[&10].applied);
+    assert_eq!(holds[&10].pending_since, None);
+    assert_eq!(holds[&20].pending_since, pending.pending_since);
+  }
+
+  #[test]
+  fn notification_is_sent_when_condition_becomes_true() {
+    let cooldown = Duration::from_secs(300);
+    let start = Instant::now();
+    let at = |seconds| start + Duration::from_secs(seconds);
+
+    let mut notified = Notified::default();
+
+    // Once when it becomes true, not on every poll while it holds.
+    assert!(notified.update(true, cooldown, at(0)));
+    assert!(!notified.update(true, cooldown, at(10)));
+
+    // Flapping within the cooldown stays quiet.
+    assert!(!notified.update(false, cooldown, at(20)));
+    assert!(!notified.update(true, cooldown, at(30)));
+
+    assert!(!notified.update(false, cooldown, at(400)));
+    assert!(notified.update(true, cooldown, at(410)));
+  }
+
+  #[test]
+  fn snapshot_fills_settings_left_unset() {
+    let cpu = |number| {
+      Arc::new(cpu::Cpu {
+        number,
+        ..Default::default()
+      })
+    };
+
+    let system = System {
+      cpus: HashSet::from([cpu(0), cpu(1)]),
+      ..Default::default()
+    };
+
+    let mut deltas = Deltas::new(&system);
+    deltas.cpu.insert(cpu(0), cpu::Delta {
+      governor: Some("powersave".to_owned()),
+      ..Default::default()
+    });
+
+    let snapshot = Snapshot {
+      cpu:        HashMap::from([
+        (cpu(0), cpu::Delta {
+          governor: Some("performance".to_owned()),
+          energy_performance_preference: Some("balance_power".to_owned()),
+          ..Default::default()
+        }),
+        (cpu(1), cpu::Delta {
+          governor: Some("schedutil".to_owned()),
+          frequency_khz_maximum: Some(3_200_000),
+          ..Default::default()
+        }),
+        // Went offline since the capture.
+        (cpu(2), cpu::Delta {
+          governor: Some("schedutil".to_owned()),
+          ..Default::default()
+        }),
+      ]),
+      cpu_global: cpu::GlobalDelta {
+        turbo: Some(false),
+        smt: Some(true),
+        ..Default::default()
+      },
+      power:      HashMap::new(),
+    };
+    deltas.cpu_global.turbo = Some(true);
+    snapshot.fill(&mut deltas);
+
+    assert_eq!(deltas.cpu_global, cpu::GlobalDelta {
+      turbo: Some(true),
+      smt: Some(true),
+      ..Default::default()
+    });
+
+    assert_eq!(deltas.cpu.len(), 2);
+    assert_eq!(deltas.cpu[&cpu(0)], cpu::Delta {
+      governor: Some("powersave".to_owned()),
+      energy_performance_preference: Some("balance_power".to_owned()),
+      ..Default::default()
+    });
+    assert_eq!(deltas.cpu[&cpu(1)], cpu::Delta {
+      governor: Some("schedutil".to_owned()),
+      frequency_khz_maximum: Some(3_200_000),
+      ..Default::default()
+    });
+  }
+
+  #[test]
+  fn profile_poll_intervals_bound_delay() {
+    let config: config::DaemonConfig = toml::from_str(
+      r#"
+        [profiles.power-saver]
+        poll-interval-base = "20s"
+        poll-interval-minimum = "60s"
+        poll-interval-maximum = "2min"
+
+        [profiles.performance]
+        poll-interval-maximum = "2s"
+      "#,
+    )
+    .unwrap();
+
+    let system = System::default();
+    let delay = |profile| {
+      compute_poll_delay(
+        &system,
+        &config.profiles.get(&profile).copied().unwrap_or_default(),
+        None,
+        Instant::now(),
+      )
+    };
+
+    assert_eq!(
+      delay(profile::PowerProfile::PowerSaver),
+      Duration::from_secs(60),
+    );
+    assert_eq!(
+      delay(profile::PowerProfile::Performance),
+      Duration::from_secs(2),
+    );
+    assert_eq!(
+      delay(profile::PowerProfile::Balanced),
+      Duration::from_secs(5),
+    );
+  }
+
+  #[test]
+  fn bench_phase_uses_nearest_rank_percentiles() {
+    let phase = BenchPhase::new(
+      "CPUs",
+      (1..=100).rev().map(Duration::from_millis).collect(),
+    );
+
+    assert_eq!(phase, BenchPhase {
+      phase:   "CPUs",
+      samples: 100,
+      minimum: 1.0,
+      median:  50.0,
+      p99:     99.0,
+      maximum: 100.0,
+    });
+
+    let phase = BenchPhase::new("lid state", vec![Duration::from_millis(3)]);
+    assert_eq!((phase.minimum, phase.p99), (3.0, 3.0));
+  }
+
+  #[test]
+  fn apply_cooldown_skips_writes_until_the_rule_stops_applying() {
+    const GOVERNOR: &str =
+      "sys/devices/system/cpu/cpu0/cpufreq/scaling_governor";
+
+    // Polls at 90°C, marks the governor as written by someone else and polls
+    // again at each of `temperatures`.
+    let governor_after = |apply_cooldown: &str, temperatures: &[f64]| {
+      let config: config::DaemonConfig = toml::from_str(&format!(
+        r#"
+          [[rule]]
+          priority = 10
+          if = {{ is-more-than = 40.0, value = "$cpu-temperature" }}
+          {apply_cooldown}
+          cpu.governor = {{ if = {{ is-more-than = 80.0, value = "$cpu-temperature" }}, then = "powersave", else = "performance" }}
+        "#
+      ))
+      .unwrap();
+      let mut daemon = Daemon::new(config, fs::ApplyContext::default());
+
+      let mut system = crate::harness::FakeSystem::new()
+        .cpu(0, "performance")
+        .cpu_temperature(90.0);
+      system
+        .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+        .unwrap();
+      assert_eq!(system.read(GOVERNOR), "powersave");
+
+      system = system.file(GOVERNOR, "untouched");
+
+      for temperature in temperatures {
+        system = system.cpu_temperature(*temperature);
+        system
+          .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+          .unwrap();
+      }
+
+      system.read(GOVERNOR)
+    };
+
+    let cooldown = r#"apply-cooldown = "1h""#;
+
+    assert_eq!(governor_after(cooldown, &[50.0]), "untouched");
+    assert_eq!(governor_after("", &[50.0]), "performance");
+    // Not applying at 30°C ends the cooldown.
+    assert_eq!(governor_after(cooldown, &[30.0, 50.0]), "performance");
+  }
+
+  #[test]
+  fn apply_cooldown_writes_again_once_a_higher_priority_rule_stops() {
+    const GOVERNOR: &str =
+      "sys/devices/system/cpu/cpu0/cpufreq/scaling_governor";
+
+    let config: config::DaemonConfig = toml::from_str(
+      r#"
+        [[rule]]
+        priority = 10
+        apply-cooldown = "1h"
+        cpu.governor = "performance"
+
+        [[rule]]
+        priority = 20
+        if = { is-more-than = 80.0, value = "$cpu-temperature" }
+        cpu.governor = "powersave"
+      "#,
+    )
+    .unwrap();
+    let mut daemon = Daemon::new(config, fs::ApplyContext::default());
+
+    let mut system = crate::harness::FakeSystem::new()
+      .cpu(0, "performance")
+      .cpu_temperature(90.0);
+    system
+      .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+      .unwrap();
+    assert_eq!(system.read(GOVERNOR), "powersave");
+
+    system = system.cpu_temperature(50.0);
+    system
+      .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+      .unwrap();
+    assert_eq!(system.read(GOVERNOR), "performance");
+  }
+
+  #[test]
+  fn thermal_emergency_settings_are_restored_once_it_is_over() {
+    const CPU: &str = "sys/devices/system/cpu";
+
+    let mut daemon =
+      Daemon::new(config::DaemonConfig::default(), fs::ApplyContext::default());
+
+    let system = crate::harness::FakeSystem::new()
+      .cpu(0, "performance")
+      .intel_turbo()
+      .cpu_temperature(110.0);
+    system
+      .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+      .unwrap();
+    assert_eq!(
+      system.read(&format!("{CPU}/cpu0/cpufreq/scaling_governor")),
+      "powersave"
+    );
+    assert_eq!(
+      system.read(&format!("{CPU}/cpu0/cpufreq/scaling_max_freq")),
+      "400000"
+    );
+    assert_eq!(system.read(&format!("{CPU}/intel_pstate/no_turbo")), "1");
+
+    let system = system.cpu_temperature(50.0);
+    system
+      .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+      .unwrap();
+    assert_eq!(
+      system.read(&format!("{CPU}/cpu0/cpufreq/scaling_governor")),
+      "performance"
+    );
+    assert_eq!(
+      system.read(&format!("{CPU}/cpu0/cpufreq/scaling_max_freq")),
+      "4000000"
+    );
+    assert_eq!(system.read(&format!("{CPU}/intel_pstate/no_turbo")), "0");
+  }
+
+  #[test]
+  fn thermal_emergency_restores_turbo_from_the_snapshot() {
+    const NO_TURBO: &str = "sys/devices/system/cpu/intel_pstate/no_turbo";
+
+    let mut daemon =
+      Daemon::new(config::DaemonConfig::default(), fs::ApplyContext::default());
+
+    // Turbo was off before the daemon started.
+    let system = crate::harness::FakeSystem::new()
+      .cpu(0, "performance")
+      .file(NO_TURBO, "1")
+      .cpu_temperature(50.0);
+    system
+      .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+      .unwrap();
+
+    let system = system.cpu_temperature(110.0);
+    system
+      .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+      .unwrap();
+
+    assert_eq!(system.read(NO_TURBO), "1");
+
+    let system = system.cpu_temperature(50.0);
+    system
+      .with_root(|| daemon.poll(profile::PowerProfile::Balanced))
+      .unwrap();
+    assert_eq!(system.read(NO_TURBO), "1");
+  }
 }
@@ -0,0 +1,118 @@
+//! The systemd notify protocol, so that watt can run as a `Type=notify`
+//! service with a watchdog. See `sd_notify(3)`.
+
+use std::{
+  env,
+  os::{
+    linux::net::SocketAddrExt as _,
+    unix::net::{
+      SocketAddr,
+      UnixDatagram,
+    },
+  },
+  process,
+  time::Duration,
+};
+
+use anyhow::Context as _;
+
+/// Parses `$NOTIFY_SOCKET`, where a leading `@` means an abstract socket.
+fn socket_addr(socket: &str) -> anyhow::Result<SocketAddr> {
+  match socket.strip_prefix('@') {
+    Some(name) => SocketAddr::from_abstract_name(name),
+    None => SocketAddr::from_pathname(socket),
+  }
+  .with_context(|| format!("invalid notify socket '{socket}'"))
+}
+
+fn notify_to(socket: &str, state: &str) -> anyhow::Result<()> {
+  let address = socket_addr(socket)?;
+
+  UnixDatagram::unbound()
+    .context("failed to create notify socket")?
+    .send_to_addr(state.as_bytes(), &address)
+    .with_context(|| {
+      format!("failed to send '{state}' to notify socket '{socket}'")
+    })?;
+
+  Ok(())
+}
+
+/// Sends `state`, such as `READY=1`, to the service manager. Does nothing if
+/// watt isn't running as a `Type=notify` service.
+pub fn notify(state: &str) -> anyhow::Result<()> {
+  let Ok(socket) = env::var("NOTIFY_SOCKET") else {
+    return Ok(());
+  };
+
+  log::debug!("notifying service manager: {state}");
+
+  notify_to(&socket, state)
+}
+
+/// Half of the watchdog timeout, as recommended by `sd_watchdog_enabled(3)`.
+/// `None` if the watchdog is disabled or meant for another process.
+fn parse_watchdog_interval(
+  usec: Option<&str>,
+  pid: Option<&str>,
+  own_pid: u32,
+) -> Option<Duration> {
+  if let Some(pid) = pid
+    && pid.parse::<u32>().ok() != Some(own_pid)
+  {
+    return None;
+  }
+
+  let usec = usec?.parse::<u64>().ok().filter(|&usec| usec > 0)?;
+
+  Some(Duration::from_micros(usec) / 2)
+}
+
+/// How often to send `WATCHDOG=1`, from `$WATCHDOG_USEC` and `$WATCHDOG_PID`.
+pub fn watchdog_interval() -> Option<Duration> {
+  parse_watchdog_interval(
+    env::var("WATCHDOG_USEC").ok().as_deref(),
+    env::var("WATCHDOG_PID").ok().as_deref(),
+    process::id(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn watchdog_interval_is_half_the_timeout() {
+    assert_eq!(
+      parse_watchdog_interval(Some("30000000"), None, 1),
+      Some(Duration::from_secs(15)),
+    );
+    assert_eq!(
+      parse_watchdog_interval(Some("30000000"), Some("1"), 1),
+      Some(Duration::from_secs(15)),
+    );
+    assert_eq!(
+      parse_watchdog_interval(Some("30000000"), Some("2"), 1),
+      None
+    );
+    assert_eq!(parse_watchdog_interval(Some("0"), None, 1), None);
+    assert_eq!(parse_watchdog_interval(None, None, 1), None);
+  }
+
+  #[test]
+  fn notify_sends_state_as_datagram() {
+    let path =
+      env::temp_dir().join(format!("watt-systemd-notify-{}", process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let receiver = UnixDatagram::bind(&path).unwrap();
+
+    notify_to(path.to_str().unwrap(), "READY=1").unwrap();
+
+    let mut buffer = [0; 64];
+    let length = receiver.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..length], b"READY=1");
+
+    let _ = std::fs::remove_file(&path);
+  }
+}
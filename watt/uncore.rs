@@ -91,7 +91,11 @@ impl Uncore {
     })
   }
 
-  pub fn set_min_khz(&self, value: u64) -> anyhow::Result<()> {
+  pub fn set_min_khz(
+    &self,
+    context: &fs::ApplyContext,
+    value: u64,
+  ) -> anyhow::Result<()> {
     if value < self.initial_min_khz {
       bail!(
         "new uncore minimum frequency ({value} kHz) cannot be lower than the \
@@ -99,14 +103,19 @@ impl Uncore {
         min = self.initial_min_khz,
       );
     }
-    fs::write(self.path.join("min_freq_khz"), &value.to_string())
+    context
+      .write(self.path.join("min_freq_khz"), &value.to_string())
       .with_context(|| format!("failed to set minimum frequency for {self}"))?;
 
     log::info!("{self} minimum frequency set to {value} kHz");
     Ok(())
   }
 
-  pub fn set_max_khz(&self, value: u64) -> anyhow::Result<()> {
+  pub fn set_max_khz(
+    &self,
+    context: &fs::ApplyContext,
+    value: u64,
+  ) -> anyhow::Result<()> {
     if value > self.initial_max_khz {
       bail!(
         "new uncore maximum frequency ({value} kHz) cannot be higher than the \
@@ -114,7 +123,8 @@ impl Uncore {
         max = self.initial_max_khz,
       );
     }
-    fs::write(self.path.join("max_freq_khz"), &value.to_string())
+    context
+      .write(self.path.join("max_freq_khz"), &value.to_string())
       .with_context(|| format!("failed to set maximum frequency for {self}"))?;
 
     log::info!("{self} maximum frequency set to {value} kHz");
@@ -150,7 +160,22 @@ impl Delta {
     }
   }
 
-  pub fn apply(&self, uncore: &Uncore) -> anyhow::Result<()> {
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      frequency_khz_minimum: self
+        .frequency_khz_minimum
+        .filter(|value| that.frequency_khz_minimum.as_ref() != Some(value)),
+      frequency_khz_maximum: self
+        .frequency_khz_maximum
+        .filter(|value| that.frequency_khz_maximum.as_ref() != Some(value)),
+    }
+  }
+
+  pub fn apply(
+    &self,
+    context: &fs::ApplyContext,
+    uncore: &Uncore,
+  ) -> anyhow::Result<()> {
     let target_min = self.frequency_khz_minimum.unwrap_or(uncore.min_khz);
     let target_max = self.frequency_khz_maximum.unwrap_or(uncore.max_khz);
 
@@ -163,17 +188,17 @@ impl Delta {
 
     if target_min > uncore.max_khz {
       if let Some(value) = self.frequency_khz_maximum {
-        uncore.set_max_khz(value)?;
+        uncore.set_max_khz(context, value)?;
       }
       if let Some(value) = self.frequency_khz_minimum {
-        uncore.set_min_khz(value)?;
+        uncore.set_min_khz(context, value)?;
       }
     } else {
       if let Some(value) = self.frequency_khz_minimum {
-        uncore.set_min_khz(value)?;
+        uncore.set_min_khz(context, value)?;
       }
       if let Some(value) = self.frequency_khz_maximum {
-        uncore.set_max_khz(value)?;
+        uncore.set_max_khz(context, value)?;
       }
     }
 
@@ -59,18 +59,28 @@ impl UsbDevice {
     Ok(devices)
   }
 
-  pub fn set_autosuspend(&self, enabled: bool) -> anyhow::Result<()> {
+  pub fn set_autosuspend(
+    &self,
+    context: &fs::ApplyContext,
+    enabled: bool,
+  ) -> anyhow::Result<()> {
     let value = if enabled { "auto" } else { "on" };
-    fs::write(self.path.join("power/control"), value)
+    context
+      .write(self.path.join("power/control"), value)
       .with_context(|| format!("failed to set autosuspend for {self}"))
   }
 
-  pub fn set_autosuspend_delay_ms(&self, delay_ms: u64) -> anyhow::Result<()> {
-    fs::write(
-      self.path.join("power/autosuspend_delay_ms"),
-      &delay_ms.to_string(),
-    )
-    .with_context(|| format!("failed to set autosuspend delay for {self}"))
+  pub fn set_autosuspend_delay_ms(
+    &self,
+    context: &fs::ApplyContext,
+    delay_ms: u64,
+  ) -> anyhow::Result<()> {
+    context
+      .write(
+        self.path.join("power/autosuspend_delay_ms"),
+        &delay_ms.to_string(),
+      )
+      .with_context(|| format!("failed to set autosuspend delay for {self}"))
   }
 }
 
@@ -95,12 +105,27 @@ impl Delta {
     }
   }
 
-  pub fn apply(&self, device: &UsbDevice) -> anyhow::Result<()> {
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      autosuspend:          self
+        .autosuspend
+        .filter(|value| that.autosuspend.as_ref() != Some(value)),
+      autosuspend_delay_ms: self
+        .autosuspend_delay_ms
+        .filter(|value| that.autosuspend_delay_ms.as_ref() != Some(value)),
+    }
+  }
+
+  pub fn apply(
+    &self,
+    context: &fs::ApplyContext,
+    device: &UsbDevice,
+  ) -> anyhow::Result<()> {
     if let Some(enabled) = self.autosuspend {
-      device.set_autosuspend(enabled)?;
+      device.set_autosuspend(context, enabled)?;
     }
     if let Some(delay_ms) = self.autosuspend_delay_ms {
-      device.set_autosuspend_delay_ms(delay_ms)?;
+      device.set_autosuspend_delay_ms(context, delay_ms)?;
     }
 
     Ok(())
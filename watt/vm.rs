@@ -47,6 +47,29 @@ impl Delta {
     }
   }
 
+  pub fn without(self, that: &Self) -> Self {
+    Self {
+      dirty_bytes:                 self
+        .dirty_bytes
+        .filter(|value| that.dirty_bytes.as_ref() != Some(value)),
+      dirty_ratio:                 self
+        .dirty_ratio
+        .filter(|value| that.dirty_ratio.as_ref() != Some(value)),
+      dirty_background_bytes:      self
+        .dirty_background_bytes
+        .filter(|value| that.dirty_background_bytes.as_ref() != Some(value)),
+      dirty_background_ratio:      self
+        .dirty_background_ratio
+        .filter(|value| that.dirty_background_ratio.as_ref() != Some(value)),
+      transparent_hugepages:       self
+        .transparent_hugepages
+        .filter(|value| that.transparent_hugepages.as_ref() != Some(value)),
+      transparent_hugepage_defrag: self.transparent_hugepage_defrag.filter(
+        |value| that.transparent_hugepage_defrag.as_ref() != Some(value),
+      ),
+    }
+  }
+
   pub fn validate(&self) -> anyhow::Result<()> {
     if self.dirty_bytes.is_some() && self.dirty_ratio.is_some() {
       bail!("`vm.dirty-bytes` conflicts with `vm.dirty-ratio`");
@@ -78,27 +101,27 @@ impl Delta {
     Ok(())
   }
 
-  pub fn apply(&self) -> anyhow::Result<()> {
+  pub fn apply(&self, context: &fs::ApplyContext) -> anyhow::Result<()> {
     self.validate()?;
 
     if let Some(value) = self.dirty_bytes {
-      write_proc_sys_vm("dirty_bytes", value)?;
+      write_proc_sys_vm(context, "dirty_bytes", value)?;
     }
     if let Some(value) = self.dirty_ratio {
-      write_proc_sys_vm("dirty_ratio", value)?;
+      write_proc_sys_vm(context, "dirty_ratio", value)?;
     }
     if let Some(value) = self.dirty_background_bytes {
-      write_proc_sys_vm("dirty_background_bytes", value)?;
+      write_proc_sys_vm(context, "dirty_background_bytes", value)?;
     }
     if let Some(value) = self.dirty_background_ratio {
-      write_proc_sys_vm("dirty_background_ratio", value)?;
+      write_proc_sys_vm(context, "dirty_background_ratio", value)?;
     }
 
     if let Some(value) = &self.transparent_hugepages {
-      write_transparent_hugepage("enabled", value)?;
+      write_transparent_hugepage(context, "enabled", value)?;
     }
     if let Some(value) = &self.transparent_hugepage_defrag {
-      write_transparent_hugepage("defrag", value)?;
+      write_transparent_hugepage(context, "defrag", value)?;
     }
 
     Ok(())
@@ -106,17 +129,24 @@ impl Delta {
 }
 
 fn write_proc_sys_vm(
+  context: &fs::ApplyContext,
   name: &str,
   value: impl std::fmt::Display,
 ) -> anyhow::Result<()> {
-  fs::write(format!("/proc/sys/vm/{name}"), &value.to_string())
+  context
+    .write(format!("/proc/sys/vm/{name}"), &value.to_string())
     .with_context(|| format!("failed to set vm sysctl '{name}'"))
 }
 
-fn write_transparent_hugepage(name: &str, value: &str) -> anyhow::Result<()> {
+fn write_transparent_hugepage(
+  context: &fs::ApplyContext,
+  name: &str,
+  value: &str,
+) -> anyhow::Result<()> {
   let base = transparent_hugepage_path()
     .context("transparent hugepage control is not available")?;
-  fs::write(base.join(name), value)
+  context
+    .write(base.join(name), value)
     .with_context(|| format!("failed to set transparent hugepage '{name}'"))
 }
 
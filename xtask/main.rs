@@ -8,10 +8,8 @@ use std::{
   process,
 };
 
-use clap::{
-  CommandFactory,
-  Parser as _,
-};
+use clap::Parser as _;
+use watt::cli::Shell;
 
 #[derive(clap::Parser)]
 #[command(version, about)]
@@ -30,38 +28,14 @@ enum Command {
   },
 }
 
-#[expect(clippy::enum_variant_names)]
-#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
-enum Shell {
-  Bash,
-  Elvish,
-  Fish,
-  PowerShell,
-  Zsh,
-  Nushell,
-}
-
-impl Shell {
-  fn generator(&self) -> &'static dyn clap_complete::Generator {
-    match self {
-      Self::Bash => &clap_complete::Shell::Bash,
-      Self::Elvish => &clap_complete::Shell::Elvish,
-      Self::Fish => &clap_complete::Shell::Fish,
-      Self::PowerShell => &clap_complete::Shell::PowerShell,
-      Self::Zsh => &clap_complete::Shell::Zsh,
-      Self::Nushell => &clap_complete_nushell::Nushell,
-    }
-  }
-
-  fn file_parts(&self) -> (&'static str, &'static str) {
-    match self {
-      Self::Bash => ("", ""),
-      Self::Elvish => ("", "elv"),
-      Self::Fish => ("", "fish"),
-      Self::PowerShell => ("_", "ps1"),
-      Self::Zsh => ("_", ""),
-      Self::Nushell => ("", "nu"),
-    }
+fn file_parts(shell: Shell) -> (&'static str, &'static str) {
+  match shell {
+    Shell::Bash => ("", ""),
+    Shell::Elvish => ("", "elv"),
+    Shell::Fish => ("", "fish"),
+    Shell::PowerShell => ("_", "ps1"),
+    Shell::Zsh => ("_", ""),
+    Shell::Nushell => ("", "nu"),
   }
 }
 
@@ -99,17 +73,11 @@ fn create_dist_files(
   ];
 
   for shell in shells {
-    let (prefix, ext) = shell.file_parts();
+    let (prefix, ext) = file_parts(shell);
     let mut path = completions_dir.join(format!("{prefix}watt"));
     path.set_extension(ext);
 
-    let mut command = watt::Cli::command();
-    command.set_bin_name("watt");
-    command.build();
-
-    shell
-      .generator()
-      .generate(&command, &mut fs::File::create(&path)?);
+    shell.generate(&mut fs::File::create(&path)?);
 
     println!("  created: {path}", path = path.display());
   }